@@ -14,18 +14,23 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use adw::prelude::*;
 use gettextrs::*;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{gdk, gio, glib};
 use log::debug;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
 use std::sync::Mutex;
 
 use crate::config;
 use crate::database;
+use crate::report_export::{ExportFormat, ReportDocument};
+use crate::reporting;
 use crate::settings_manager;
-use crate::ui::{FurPreferencesWindow, FurReport, rusttimetrackWindow};
+use crate::ui::{FurImport, FurInvoice, FurPreferencesWindow, FurReport, FurTimeline, FurTimesheet, rusttimetrackWindow};
 
 mod imp {
     use super::*;
@@ -49,19 +54,31 @@ mod imp {
 
             obj.setup_gactions();
             obj.setup_application();
+            obj.setup_options();
             obj.set_accels_for_action("app.quit", &["<primary>Q", "<primary>W"]);
         }
     }
 
     impl ApplicationImpl for rusttimetrackApplication {
+        // Saved reports can be run without opening a window
+        fn handle_local_options(&self, options: &glib::VariantDict) -> ControlFlow<glib::ExitCode> {
+            if options.contains("list-reports") {
+                return ControlFlow::Break(self.obj().list_reports());
+            }
+            if let Ok(Some(name)) = options.lookup::<String>("report") {
+                let output = options.lookup::<PathBuf>("output").ok().flatten();
+                return ControlFlow::Break(self.obj().run_report(&name, output));
+            }
+            self.parent_handle_local_options(options)
+        }
+
         // We connect to the activate callback to create a window when the application
         // has been launched. Additionally, this callback notifies us when the user
         // tries to launch a "second instance" of the application. When they try
         // to do that, we'll just present any existing window.
         fn activate(&self) {
             // Initialize the database
-            let _ = database::db_init();
-            let _ = database::upgrade_old_db();
+            let _ = database::migrate();
 
             // Get the current window or create one if necessary
             let application = self.obj();
@@ -108,6 +125,98 @@ impl rusttimetrackApplication {
             .build()
     }
 
+    fn setup_options(&self) {
+        self.add_main_option(
+            "report",
+            glib::Char::from(b'r'),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::String,
+            &gettext("Run a saved report and print it as Markdown"),
+            Some(&gettext("NAME")),
+        );
+        self.add_main_option(
+            "output",
+            glib::Char::from(b'o'),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::Filename,
+            &gettext("Write the report to FILE instead, as HTML, Markdown or PDF by its extension"),
+            Some(&gettext("FILE")),
+        );
+        self.add_main_option(
+            "list-reports",
+            glib::Char::from(b'l'),
+            glib::OptionFlags::NONE,
+            glib::OptionArg::None,
+            &gettext("List saved reports"),
+            None,
+        );
+    }
+
+    fn list_reports(&self) -> glib::ExitCode {
+        if let Err(e) = database::migrate() {
+            eprintln!("{}", e);
+            return glib::ExitCode::FAILURE;
+        }
+        match database::get_report_presets() {
+            Ok(presets) => {
+                for preset in presets {
+                    println!("{}", preset.name);
+                }
+                glib::ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                glib::ExitCode::FAILURE
+            }
+        }
+    }
+
+    fn run_report(&self, name: &str, output: Option<PathBuf>) -> glib::ExitCode {
+        if let Err(e) = database::migrate() {
+            eprintln!("{}", e);
+            return glib::ExitCode::FAILURE;
+        }
+        let preset = database::get_report_presets()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|preset| preset.name.to_lowercase() == name.to_lowercase());
+        let preset = match preset {
+            Some(preset) => preset,
+            None => {
+                eprintln!("{}", gettext("No saved report named \"{}\"").replace("{}", name));
+                return glib::ExitCode::FAILURE;
+            }
+        };
+
+        let today = chrono::Local::now().naive_local().date();
+        let query = preset.query(
+            today,
+            settings_manager::week_starts_on(),
+            settings_manager::fiscal_year_start(),
+        );
+        let result = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| reporting::run(&conn, &query))
+            .and_then(|report| {
+                let document = ReportDocument::new(&query, &report);
+                match &output {
+                    Some(path) => document.write(path, ExportFormat::from_path(path)),
+                    None => {
+                        print!("{}", document.to_markdown());
+                        Ok(())
+                    }
+                }
+            });
+
+        match result {
+            Ok(_) => glib::ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e);
+                glib::ExitCode::FAILURE
+            }
+        }
+    }
+
     fn setup_gactions(&self) {
         let quit_action = gio::SimpleAction::new("quit", None);
         quit_action.connect_activate(clone!(@weak self as app => move |_, _| {
@@ -129,6 +238,25 @@ impl rusttimetrackApplication {
         self.set_accels_for_action("app.report", &["<primary>R"]);
         self.add_action(&report_action);
 
+        let invoice_action = gio::SimpleAction::new("invoice", None);
+        invoice_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            FurInvoice::new().show();
+        }));
+        self.add_action(&invoice_action);
+
+        let timeline_action = gio::SimpleAction::new("timeline", None);
+        timeline_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            FurTimeline::new().show();
+        }));
+        self.set_accels_for_action("app.timeline", &["<primary>T"]);
+        self.add_action(&timeline_action);
+
+        let timesheet_action = gio::SimpleAction::new("timesheet", None);
+        timesheet_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            FurTimesheet::new().show();
+        }));
+        self.add_action(&timesheet_action);
+
         let about_action = gio::SimpleAction::new("about", None);
         about_action.connect_activate(clone!(@weak self as app => move |_, _| {
             app.show_about();
@@ -141,6 +269,12 @@ impl rusttimetrackApplication {
         }));
         self.add_action(&import_database_action);
 
+        let import_entries_action = gio::SimpleAction::new("import-entries", None);
+        import_entries_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            app.import_entries();
+        }));
+        self.add_action(&import_entries_action);
+
         let discard_idle_action = gio::SimpleAction::new("discard-idle-action", None);
         discard_idle_action.connect_activate(clone!(@weak self as app => move |_, _| {
             let window = rusttimetrackWindow::default();
@@ -173,6 +307,22 @@ impl rusttimetrackApplication {
             imp.pomodoro_dialog.lock().unwrap().response(gtk::ResponseType::Reject);
         }));
         self.add_action(&stop_pomodoro_action);
+
+        let start_last_task_action = gio::SimpleAction::new("start-last-task", None);
+        start_last_task_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            let window = rusttimetrackWindow::default();
+            window.present();
+            window.start_last_task();
+        }));
+        self.add_action(&start_last_task_action);
+
+        let stop_timer_at_action = gio::SimpleAction::new("stop-timer-at", None);
+        stop_timer_at_action.connect_activate(clone!(@weak self as app => move |_, _| {
+            let window = rusttimetrackWindow::default();
+            window.present();
+            window.open_stop_at_dialog();
+        }));
+        self.add_action(&stop_timer_at_action);
     }
 
     fn setup_application(&self) {
@@ -318,6 +468,59 @@ impl rusttimetrackApplication {
         self.send_notification(Some("idle"), &notification);
     }
 
+    pub fn system_goal_notification(&self, title: &str, body: &str) {
+        let notification = gio::Notification::new(title);
+        notification.set_body(Some(body));
+
+        match gio::Icon::for_string("emblem-ok-symbolic") {
+            Ok(gicon) => notification.set_icon(&gicon),
+            Err(err) => debug!("Unable to display notification: {:?}", err),
+        }
+
+        self.send_notification(Some("goal"), &notification);
+    }
+
+    pub fn system_budget_notification(&self, title: &str, body: &str) {
+        let notification = gio::Notification::new(title);
+        notification.set_body(Some(body));
+
+        match gio::Icon::for_string("dialog-warning-symbolic") {
+            Ok(gicon) => notification.set_icon(&gicon),
+            Err(err) => debug!("Unable to display notification: {:?}", err),
+        }
+
+        self.send_notification(Some("budget"), &notification);
+    }
+
+    pub fn system_start_reminder(&self, body: &str) {
+        let notification = gio::Notification::new(&gettext("Start tracking?"));
+        notification.set_body(Some(body));
+
+        match gio::Icon::for_string("alarm-symbolic") {
+            Ok(gicon) => notification.set_icon(&gicon),
+            Err(err) => debug!("Unable to display notification: {:?}", err),
+        }
+
+        notification.add_button(&gettext("Start Last Task"), "app.start-last-task");
+
+        self.send_notification(Some("reminder"), &notification);
+    }
+
+    pub fn system_stop_reminder(&self, body: &str) {
+        let notification = gio::Notification::new(&gettext("Timer still running"));
+        notification.set_body(Some(body));
+
+        match gio::Icon::for_string("alarm-symbolic") {
+            Ok(gicon) => notification.set_icon(&gicon),
+            Err(err) => debug!("Unable to display notification: {:?}", err),
+        }
+
+        notification.add_button(&gettext("Stop at…"), "app.stop-timer-at");
+        notification.set_priority(gio::NotificationPriority::High);
+
+        self.send_notification(Some("reminder"), &notification);
+    }
+
     pub fn system_pomodoro_notification(&self, dialog: gtk::MessageDialog) {
         let imp = imp::rusttimetrackApplication::from_obj(self);
         *imp.pomodoro_dialog.lock().unwrap() = dialog;
@@ -402,11 +605,7 @@ impl rusttimetrackApplication {
             clone!(@strong dialog, @weak self as this => move |filechooser, resp| {
                 if resp == gtk::ResponseType::Accept {
                     if let Some(path) = filechooser.file().and_then(|file| file.path()) {
-                        let path = &path.to_string_lossy();
-                        let _bkup = database::import_db(path.to_string());
-
-                        let window = rusttimetrackWindow::default();
-                        window.reset_history_box();
+                        this.open_merge_dialog(path.to_string_lossy().to_string());
                     }
                     dialog.close();
                 } else {
@@ -417,6 +616,126 @@ impl rusttimetrackApplication {
 
         dialog.show();
     }
+
+    /// Shows what merging `path` would add, and asks whether to merge it or
+    /// replace this database with it.
+    fn open_merge_dialog(&self, path: String) {
+        let window = rusttimetrackWindow::default();
+        let report = match database::merge_db(&path, true) {
+            Ok(report) => report,
+            Err(e) => {
+                log::error!("reading {} for a merge failed, Err {}", path, e);
+                // Shows the error dialog for files that aren't a database
+                let _ = database::import_db(path);
+                return;
+            }
+        };
+
+        let dialog = gtk::MessageDialog::with_markup(
+            Some(&window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            Some(&format!("<span size='x-large' weight='bold'>{}</span>", &gettext("Import Database"))),
+        );
+        dialog.set_secondary_text(Some(&format!(
+            "{}\n\n{}",
+            report.summary(),
+            gettext("Merging adds the new entries and keeps everything here. Replacing deletes this history.")
+        )));
+
+        if !report.conflicts.is_empty() {
+            let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+            let conflict_list = gtk::ListBox::new();
+            conflict_list.set_selection_mode(gtk::SelectionMode::None);
+            conflict_list.add_css_class("boxed-list");
+            for conflict in &report.conflicts {
+                let start = chrono::DateTime::parse_from_rfc3339(&conflict.local.start_time)
+                    .map(|start| start.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default();
+                let row = adw::ActionRow::new();
+                row.set_title(&glib::markup_escape_text(&format!("{} – {}", start, conflict.local.task_name)));
+                row.set_subtitle(&glib::markup_escape_text(&conflict.differences().join("\n")));
+                conflict_list.append(&row);
+            }
+            let scrolled = gtk::ScrolledWindow::builder()
+                .hscrollbar_policy(gtk::PolicyType::Never)
+                .max_content_height(240)
+                .propagate_natural_height(true)
+                .child(&conflict_list)
+                .build();
+            message_area.append(&scrolled);
+        }
+
+        dialog.add_button(&gettext("Cancel"), gtk::ResponseType::Cancel);
+        let replace_btn = dialog.add_button(&gettext("Replace"), gtk::ResponseType::Reject);
+        replace_btn.add_css_class("destructive-action");
+        let merge_btn = dialog.add_button(&gettext("Merge"), gtk::ResponseType::Accept);
+        merge_btn.add_css_class("suggested-action");
+        dialog.set_default_response(gtk::ResponseType::Accept);
+
+        dialog.connect_response(move |dialog, resp| {
+            let window = rusttimetrackWindow::default();
+            match resp {
+                gtk::ResponseType::Accept => match database::merge_db(&path, false) {
+                    Ok(report) => {
+                        window.display_toast(
+                            &ngettext("Merged {} entry.", "Merged {} entries.", report.added.len() as u32)
+                                .replace("{}", &report.added.len().to_string()),
+                        );
+                    }
+                    Err(e) => {
+                        log::error!("merging {} failed, Err {}", path, e);
+                        window.display_toast(&gettext("Could not merge the database. Nothing was changed."));
+                    }
+                },
+                gtk::ResponseType::Reject => {
+                    let _bkup = database::import_db(path.clone());
+                }
+                _ => {}
+            }
+            window.reset_history_box();
+            dialog.close();
+        });
+
+        dialog.show();
+    }
+
+    /// Asks for a CSV export or a file from another tracker, then previews
+    /// its entries.
+    pub fn import_entries(&self) {
+        let window = self.active_window().unwrap();
+        let dialog = gtk::FileChooserDialog::new(
+            Some(&gettext("Import Entries")),
+            Some(&window),
+            gtk::FileChooserAction::Open,
+            &[
+                (&gettext("Cancel"), gtk::ResponseType::Reject),
+                (&gettext("Open"), gtk::ResponseType::Accept),
+            ]
+        );
+        dialog.set_modal(true);
+
+        let filter = gtk::FileFilter::new();
+        gtk::FileFilter::set_name(&filter, Some(&gettext("Time Tracker Exports")));
+        for pattern in ["*.csv", "*.tsv", "*.txt", "*.json", "frames", "*.data", "*.org"] {
+            filter.add_pattern(pattern);
+        }
+        dialog.add_filter(&filter);
+
+        dialog.connect_response(
+            clone!(@strong dialog => move |filechooser, resp| {
+                if resp == gtk::ResponseType::Accept {
+                    if let Some(path) = filechooser.file().and_then(|file| file.path()) {
+                        FurImport::new(&path).show();
+                    }
+                }
+                dialog.close();
+            }),
+        );
+
+        dialog.show();
+    }
 }
 
 impl Default for rusttimetrackApplication {
@@ -0,0 +1,169 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Hourly rates and billable time. Like `reporting`, this stays free of GTK.
+
+use gettextrs::*;
+use std::collections::BTreeMap;
+
+/// What a rate or budget applies to. For rates, more specific kinds win: a
+/// task rate beats a tag rate, which beats a project rate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RateKind {
+    Task,
+    Tag,
+    Project,
+}
+
+impl RateKind {
+    pub fn to_sqlite(&self) -> &str {
+        match self {
+            RateKind::Task => "task",
+            RateKind::Tag => "tag",
+            RateKind::Project => "project",
+        }
+    }
+
+    pub fn from_sqlite(value: &str) -> Option<Self> {
+        match value {
+            "task" => Some(RateKind::Task),
+            "tag" => Some(RateKind::Tag),
+            "project" => Some(RateKind::Project),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            RateKind::Task => gettext("Task"),
+            RateKind::Tag => gettext("Tag"),
+            RateKind::Project => gettext("Project"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rate {
+    pub kind: RateKind,
+    /// Task name, tag without the # or project, compared case-insensitively
+    pub name: String,
+    pub hourly_rate: f64,
+    pub currency: String,
+    /// Whether matching entries are billable unless marked otherwise
+    pub billable: bool,
+}
+
+impl Rate {
+    pub fn describe(&self) -> String {
+        gettext("{amount} per hour").replace("{amount}", &format_money(self.hourly_rate, &self.currency))
+    }
+}
+
+/// All configured rates, for looking up the one that applies to an entry.
+#[derive(Clone, Debug, Default)]
+pub struct RateBook {
+    rates: Vec<Rate>,
+}
+
+impl RateBook {
+    pub fn new(rates: Vec<Rate>) -> Self {
+        RateBook { rates }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// The most specific rate for an entry. `tags` are lowercase, without #.
+    pub fn rate_for(&self, task_name: &str, tags: &[String], project: &str) -> Option<&Rate> {
+        let find = |kind: RateKind, matches: &dyn Fn(&str) -> bool| {
+            self.rates
+                .iter()
+                .find(|rate| rate.kind == kind && matches(&rate.name.to_lowercase()))
+        };
+        let task_name = task_name.trim().to_lowercase();
+        let project = project.trim().to_lowercase();
+
+        find(RateKind::Task, &|name| name == task_name)
+            .or_else(|| find(RateKind::Tag, &|name| tags.iter().any(|tag| tag == name)))
+            .or_else(|| find(RateKind::Project, &|name| !project.is_empty() && name == project))
+    }
+}
+
+/// An entry's own flag wins, then its rate's. Without either it isn't billable.
+pub fn is_billable(billable: Option<bool>, rate: Option<&Rate>) -> bool {
+    billable.unwrap_or_else(|| rate.map_or(false, |rate| rate.billable))
+}
+
+/// Money earned, kept apart per currency.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Earnings(BTreeMap<String, f64>);
+
+impl Earnings {
+    pub fn for_time(seconds: i64, rate: &Rate) -> Self {
+        let mut earnings = Earnings::default();
+        earnings.add(&rate.currency, seconds as f64 / 3600.0 * rate.hourly_rate);
+        earnings
+    }
+
+    pub fn add(&mut self, currency: &str, amount: f64) {
+        *self.0.entry(currency.to_string()).or_insert(0.0) += amount;
+    }
+
+    pub fn merge(&mut self, other: &Earnings) {
+        for (currency, amount) in &other.0 {
+            self.add(currency, *amount);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Each currency's total, like "120.00 EUR + 40.00 USD".
+    pub fn format(&self) -> String {
+        self.0
+            .iter()
+            .map(|(currency, amount)| format_money(*amount, currency))
+            .collect::<Vec<String>>()
+            .join(" + ")
+    }
+}
+
+pub fn format_money(amount: f64, currency: &str) -> String {
+    if currency.is_empty() {
+        format!("{:.2}", amount)
+    } else {
+        format!("{:.2} {}", amount, currency)
+    }
+}
+
+/// How a per-entry billable flag is stored: empty when it follows the rates.
+pub fn billable_to_sqlite(billable: Option<bool>) -> &'static str {
+    match billable {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "",
+    }
+}
+
+pub fn billable_from_sqlite(value: &str) -> Option<bool> {
+    match value {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
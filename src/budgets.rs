@@ -0,0 +1,243 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Time budgets and estimates for a task, tag or project. Like `reporting`,
+//! this stays free of GTK.
+
+use chrono::{NaiveDate, Weekday};
+use gettextrs::*;
+use rusqlite::Connection;
+
+use crate::billing::RateKind;
+use crate::store;
+use crate::goals;
+use crate::reporting::{self, DateRange};
+
+/// How much of a budget gets used before an alert, in percent.
+pub const ALERT_PERCENTS: [u32; 2] = [80, 100];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BudgetPeriod {
+    /// An estimate for all time
+    Total,
+    Day,
+    Week,
+    Month,
+}
+
+pub const BUDGET_PERIODS: [BudgetPeriod; 4] = [
+    BudgetPeriod::Total,
+    BudgetPeriod::Day,
+    BudgetPeriod::Week,
+    BudgetPeriod::Month,
+];
+
+impl BudgetPeriod {
+    pub fn to_sqlite(&self) -> &str {
+        match self {
+            BudgetPeriod::Total => "total",
+            BudgetPeriod::Day => "day",
+            BudgetPeriod::Week => "week",
+            BudgetPeriod::Month => "month",
+        }
+    }
+
+    pub fn from_sqlite(value: &str) -> Option<Self> {
+        match value {
+            "total" => Some(BudgetPeriod::Total),
+            "day" => Some(BudgetPeriod::Day),
+            "week" => Some(BudgetPeriod::Week),
+            "month" => Some(BudgetPeriod::Month),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            BudgetPeriod::Total => gettext("In Total"),
+            BudgetPeriod::Day => gettext("Per Day"),
+            BudgetPeriod::Week => gettext("Per Week"),
+            BudgetPeriod::Month => gettext("Per Month"),
+        }
+    }
+
+    /// The period today falls in, or None for a total that never starts over.
+    pub fn range(&self, today: NaiveDate, week_starts_on: Weekday) -> Option<DateRange> {
+        match self {
+            BudgetPeriod::Total => None,
+            BudgetPeriod::Day => Some(DateRange::new(today, today)),
+            BudgetPeriod::Week => Some(DateRange::week_of(today, week_starts_on)),
+            BudgetPeriod::Month => Some(DateRange::month_of(today)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub kind: RateKind,
+    /// Task name, tag without the # or project, compared case-insensitively
+    pub name: String,
+    pub seconds: i64,
+    pub period: BudgetPeriod,
+}
+
+impl Budget {
+    /// Unlike rates, every matching budget counts an entry, so time on a
+    /// tagged task uses up both the task's and the tag's budget.
+    pub fn matches(&self, task_name: &str, tags: &[String], project: &str) -> bool {
+        let name = self.name.trim().to_lowercase();
+        match self.kind {
+            RateKind::Task => task_name.trim().to_lowercase() == name,
+            RateKind::Tag => tags.iter().any(|tag| *tag == name),
+            RateKind::Project => !name.is_empty() && project.trim().to_lowercase() == name,
+        }
+    }
+
+    /// Like "#rust" or "Website".
+    pub fn label(&self) -> String {
+        match self.kind {
+            RateKind::Tag => format!("#{}", self.name),
+            _ => self.name.clone(),
+        }
+    }
+
+    /// Like "10:00 per week".
+    pub fn describe(&self) -> String {
+        let hours = goals::format_hours(self.seconds);
+        match self.period {
+            BudgetPeriod::Total => gettext("{} in total").replace("{}", &hours),
+            BudgetPeriod::Day => gettext("{} per day").replace("{}", &hours),
+            BudgetPeriod::Week => gettext("{} per week").replace("{}", &hours),
+            BudgetPeriod::Month => gettext("{} per month").replace("{}", &hours),
+        }
+    }
+
+    /// The budgeted time for a range. Weekly and monthly budgets are spread
+    /// over their days, so a full week or month gets the whole budget.
+    pub fn estimate_for(&self, range: &DateRange) -> i64 {
+        let per_day = |day: &NaiveDate| match self.period {
+            BudgetPeriod::Total => 0.0,
+            BudgetPeriod::Day => self.seconds as f64,
+            BudgetPeriod::Week => self.seconds as f64 / 7.0,
+            BudgetPeriod::Month => self.seconds as f64 / DateRange::month_of(*day).num_days() as f64,
+        };
+        match self.period {
+            BudgetPeriod::Total => self.seconds,
+            _ => range.days().iter().map(per_day).sum::<f64>().round() as i64,
+        }
+    }
+}
+
+/// How much of a budget is used in its current period.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BudgetUsage {
+    pub budget: Budget,
+    pub seconds: i64,
+}
+
+impl BudgetUsage {
+    pub fn remaining(&self) -> i64 {
+        self.budget.seconds - self.seconds
+    }
+
+    pub fn fraction(&self) -> f64 {
+        if self.budget.seconds <= 0 {
+            1.0
+        } else {
+            self.seconds as f64 / self.budget.seconds as f64
+        }
+    }
+
+    /// The usage with a running timer's time added.
+    pub fn with_running(&self, seconds: i64) -> Self {
+        BudgetUsage {
+            budget: self.budget.clone(),
+            seconds: self.seconds + seconds,
+        }
+    }
+
+    /// The highest alert threshold reached, if any.
+    pub fn alert_percent(&self) -> Option<u32> {
+        let percent = self.fraction() * 100.0;
+        ALERT_PERCENTS.iter().rev().find(|alert| percent >= **alert as f64).copied()
+    }
+
+    /// Like "Website: 3:20 left of 10:00 per week".
+    pub fn status_text(&self) -> String {
+        let text = if self.remaining() >= 0 {
+            gettext("{label}: {time} left of {budget}")
+        } else {
+            gettext("{label}: {time} over {budget}")
+        };
+        text.replace("{label}", &self.budget.label())
+            .replace("{time}", &goals::format_hours(self.remaining().abs()))
+            .replace("{budget}", &self.budget.describe())
+    }
+}
+
+/// How much of each budget is used in the period `today` falls in.
+pub fn usage(
+    conn: &Connection,
+    budgets: &[Budget],
+    today: NaiveDate,
+    week_starts_on: Weekday,
+) -> anyhow::Result<Vec<BudgetUsage>> {
+    if budgets.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Load everything once, back to the earliest period's start
+    let mut start = budgets
+        .iter()
+        .filter_map(|budget| budget.period.range(today, week_starts_on))
+        .map(|range| range.start)
+        .min()
+        .unwrap_or(today);
+    if budgets.iter().any(|budget| budget.period == BudgetPeriod::Total) {
+        if let Some(first) = reporting::first_tracked_day(conn)? {
+            start = start.min(first);
+        }
+    }
+    let end = DateRange::month_of(today).end.max(DateRange::week_of(today, week_starts_on).end);
+    let buckets = reporting::load_buckets(conn, &DateRange::new(start, end))?;
+
+    Ok(budgets
+        .iter()
+        .map(|budget| {
+            let range = budget.period.range(today, week_starts_on);
+            let seconds = buckets
+                .iter()
+                .filter(|bucket| range.map_or(true, |range| range.start <= bucket.day && bucket.day <= range.end))
+                .filter(|bucket| {
+                    budget.matches(&bucket.task_name, &store::split_tags(&bucket.tags), &bucket.project)
+                })
+                .map(|bucket| bucket.seconds)
+                .sum();
+            BudgetUsage {
+                budget: budget.clone(),
+                seconds,
+            }
+        })
+        .collect())
+}
+
+/// The budget in `budgets` for a report line, if its group has one.
+pub fn budget_for<'a>(budgets: &'a [Budget], kind: RateKind, key: &str) -> Option<&'a Budget> {
+    let key = key.trim().to_lowercase();
+    budgets
+        .iter()
+        .find(|budget| budget.kind == kind && budget.name.trim().to_lowercase() == key)
+}
@@ -0,0 +1,570 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Report charts drawn with Cairo, so the same code paints the report
+//! window and writes PNG and SVG files.
+
+use cairo::{Context, FontSlant, FontWeight};
+use chrono::{NaiveDate, Weekday};
+use gettextrs::*;
+use itertools::Itertools;
+use rusqlite::Connection;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+use crate::reporting::{self, DateRange, GroupBy, ReportNode, ReportQuery};
+
+// GNOME palette
+pub const PALETTE: [(f64, f64, f64); 8] = [
+    (0.21, 0.52, 0.89),
+    (0.20, 0.82, 0.48),
+    (0.96, 0.83, 0.18),
+    (1.00, 0.47, 0.00),
+    (0.88, 0.11, 0.14),
+    (0.57, 0.25, 0.67),
+    (0.60, 0.42, 0.27),
+    (0.13, 0.56, 0.64),
+];
+const OTHER_COLOR: (f64, f64, f64) = (0.60, 0.60, 0.60);
+const GOAL_COLOR: (f64, f64, f64) = (0.88, 0.11, 0.14);
+
+const MARGIN: f64 = 12.0;
+const AXIS_WIDTH: f64 = 44.0;
+const LABEL_HEIGHT: f64 = 20.0;
+const LEGEND_ROW: f64 = 20.0;
+const SWATCH_SIZE: f64 = 10.0;
+
+/// Picks a stable palette color for a name, so the same project or tag
+/// keeps its color between views.
+pub fn color_for_key(key: &str) -> (f64, f64, f64) {
+    let hash = key.bytes().fold(0u32, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as u32));
+    PALETTE[(hash % PALETTE.len() as u32) as usize]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChartKind {
+    /// Stacked bars of time per day or week
+    Bars,
+    /// A donut of each task's, tag's or project's share
+    Share,
+    /// Daily totals against the daily goal
+    Trend,
+}
+
+struct Series {
+    /// None for the catch-all "Other" series
+    key: Option<String>,
+    label: String,
+    seconds: i64,
+    color: (f64, f64, f64),
+}
+
+#[derive(Clone, Copy)]
+struct Area {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Chart {
+    pub kind: ChartKind,
+    pub range: DateRange,
+    pub period: GroupBy,
+    pub week_starts_on: Weekday,
+    pub report: ReportNode,
+    /// In seconds, 0 for none
+    pub daily_goal: i64,
+}
+
+impl Chart {
+    /// Runs the report behind a chart. `share_by` is what the bars are
+    /// stacked by and what the donut is divided by.
+    pub fn load(
+        conn: &Connection,
+        kind: ChartKind,
+        query: &ReportQuery,
+        share_by: GroupBy,
+        daily_goal: i64,
+    ) -> anyhow::Result<Self> {
+        // Long ranges get one bar per week so the bars stay readable
+        let period = if query.range.num_days() > 31 {
+            GroupBy::Week
+        } else {
+            GroupBy::Day
+        };
+        let group_by = match kind {
+            ChartKind::Bars => vec![period, share_by],
+            ChartKind::Share => vec![share_by],
+            ChartKind::Trend => vec![GroupBy::Day],
+        };
+        let query = ReportQuery {
+            group_by,
+            compare: None,
+            ..query.clone()
+        };
+
+        Ok(Chart {
+            kind,
+            range: query.range,
+            period,
+            week_starts_on: query.week_starts_on,
+            report: reporting::run(conn, &query)?,
+            daily_goal,
+        })
+    }
+
+    /// Draws the chart with `fg` as the color for text and axes.
+    pub fn draw(&self, cr: &Context, width: f64, height: f64, fg: (f64, f64, f64)) {
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+        cr.set_font_size(11.0);
+        cr.set_line_width(1.0);
+
+        if self.report.seconds == 0 && !(self.kind == ChartKind::Trend && self.daily_goal > 0) {
+            Chart::draw_centered(cr, width / 2.0, height / 2.0, fg, &gettext("No Results"));
+            return;
+        }
+
+        match self.kind {
+            ChartKind::Bars => self.draw_bars(cr, width, height, fg),
+            ChartKind::Share => self.draw_share(cr, width, height, fg),
+            ChartKind::Trend => self.draw_trend(cr, width, height, fg),
+        }
+    }
+
+    pub fn write_png(&self, path: &Path, width: i32, height: i32) -> anyhow::Result<()> {
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)?;
+        let cr = Context::new(&surface)?;
+        self.draw_page(&cr, width as f64, height as f64);
+        drop(cr);
+
+        let mut file = File::create(path)?;
+        surface.write_to_png(&mut file)?;
+        Ok(())
+    }
+
+    pub fn write_svg(&self, path: &Path, width: f64, height: f64) -> anyhow::Result<()> {
+        let surface = cairo::SvgSurface::new(width, height, Some(path))?;
+        let cr = Context::new(&surface)?;
+        self.draw_page(&cr, width, height);
+        drop(cr);
+
+        surface.finish();
+        Ok(())
+    }
+
+    /// Exported charts don't follow the app's theme, so give them a
+    /// plain white page.
+    fn draw_page(&self, cr: &Context, width: f64, height: f64) {
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        let _ = cr.paint();
+        self.draw(cr, width, height, (0.2, 0.2, 0.2));
+    }
+
+    fn draw_bars(&self, cr: &Context, width: f64, height: f64, fg: (f64, f64, f64)) {
+        let series = self.bar_series();
+        let legend_height = Chart::draw_legend(cr, &series, width, height, fg, None);
+        let area = Area {
+            x: AXIS_WIDTH,
+            y: MARGIN,
+            width: width - AXIS_WIDTH - MARGIN,
+            height: height - MARGIN * 2.0 - LABEL_HEIGHT - legend_height,
+        };
+
+        let periods = self.periods();
+        let by_key: HashMap<&str, &ReportNode> = self
+            .report
+            .children
+            .iter()
+            .map(|node| (node.key.as_str(), node))
+            .collect();
+        let max = periods
+            .iter()
+            .filter_map(|(key, _)| by_key.get(key.as_str()))
+            // Per-tag stacks can add up to more than the period's total
+            .map(|node| node.children.iter().map(|child| child.seconds).sum::<i64>())
+            .max()
+            .unwrap_or(0);
+        let top = Chart::draw_axis(cr, area, max, fg);
+
+        let slot = area.width / periods.len().max(1) as f64;
+        let bar_width = (slot * 0.7).min(48.0);
+        let label_every = self.label_every(cr, slot);
+        for (index, (key, date)) in periods.iter().enumerate() {
+            let x = area.x + index as f64 * slot + (slot - bar_width) / 2.0;
+
+            if let Some(node) = by_key.get(key.as_str()) {
+                // Stack in legend order so colors line up between bars
+                let mut amounts = vec![0; series.len()];
+                for child in &node.children {
+                    amounts[Chart::series_index(&series, &child.key)] += child.seconds;
+                }
+                let mut y = area.y + area.height;
+                for (amount, entry) in amounts.iter().zip(&series) {
+                    if *amount == 0 {
+                        continue;
+                    }
+                    let bar_height = *amount as f64 / top as f64 * area.height;
+                    let (r, g, b) = entry.color;
+                    cr.set_source_rgb(r, g, b);
+                    cr.rectangle(x, y - bar_height, bar_width, bar_height);
+                    let _ = cr.fill();
+                    y -= bar_height;
+                }
+            }
+
+            if index % label_every == 0 {
+                let label = self.period_label(*date, periods.len());
+                Chart::draw_centered(cr, x + bar_width / 2.0, area.y + area.height + 14.0, fg, &label);
+            }
+        }
+    }
+
+    fn draw_share(&self, cr: &Context, width: f64, height: f64, fg: (f64, f64, f64)) {
+        let series = self.share_series();
+        let total: i64 = series.iter().map(|entry| entry.seconds).sum();
+        let legend_height = Chart::draw_legend(cr, &series, width, height, fg, Some(total));
+        let available = height - MARGIN * 2.0 - legend_height;
+        let radius = (width - MARGIN * 2.0).min(available) / 2.0;
+        if radius <= 0.0 {
+            return;
+        }
+        let (cx, cy) = (width / 2.0, MARGIN + available / 2.0);
+        let inner_radius = radius * 0.55;
+
+        let mut angle = -PI / 2.0;
+        cr.new_path();
+        for entry in &series {
+            let sweep = entry.seconds as f64 / total as f64 * 2.0 * PI;
+            let (r, g, b) = entry.color;
+            cr.set_source_rgb(r, g, b);
+            cr.arc(cx, cy, radius, angle, angle + sweep);
+            cr.arc_negative(cx, cy, inner_radius, angle + sweep, angle);
+            cr.close_path();
+            let _ = cr.fill();
+            angle += sweep;
+        }
+
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        cr.set_font_size(16.0);
+        Chart::draw_centered(cr, cx, cy + 6.0, fg, &Chart::format_hours(self.report.seconds));
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+        cr.set_font_size(11.0);
+    }
+
+    fn draw_trend(&self, cr: &Context, width: f64, height: f64, fg: (f64, f64, f64)) {
+        let mut legend = vec![Series {
+            key: None,
+            label: gettext("Time tracked"),
+            seconds: self.report.seconds,
+            color: PALETTE[0],
+        }];
+        if self.daily_goal > 0 {
+            legend.push(Series {
+                key: None,
+                label: gettext("Daily goal"),
+                seconds: self.daily_goal,
+                color: GOAL_COLOR,
+            });
+        }
+        let legend_height = Chart::draw_legend(cr, &legend, width, height, fg, None);
+        let area = Area {
+            x: AXIS_WIDTH,
+            y: MARGIN,
+            width: width - AXIS_WIDTH - MARGIN,
+            height: height - MARGIN * 2.0 - LABEL_HEIGHT - legend_height,
+        };
+
+        let by_day: HashMap<&str, i64> = self
+            .report
+            .children
+            .iter()
+            .map(|node| (node.key.as_str(), node.seconds))
+            .collect();
+        let days: Vec<(NaiveDate, i64)> = self
+            .range
+            .days()
+            .into_iter()
+            .map(|day| {
+                let key = day.format("%F").to_string();
+                (day, by_day.get(key.as_str()).copied().unwrap_or(0))
+            })
+            .collect();
+        let max = days.iter().map(|(_, seconds)| *seconds).max().unwrap_or(0).max(self.daily_goal);
+        let top = Chart::draw_axis(cr, area, max, fg);
+
+        let slot = area.width / days.len().max(1) as f64;
+        let point = |index: usize, seconds: i64| {
+            (
+                area.x + index as f64 * slot + slot / 2.0,
+                area.y + area.height - seconds as f64 / top as f64 * area.height,
+            )
+        };
+
+        if self.daily_goal > 0 {
+            let (_, y) = point(0, self.daily_goal);
+            let (r, g, b) = GOAL_COLOR;
+            cr.set_source_rgb(r, g, b);
+            cr.set_line_width(2.0);
+            cr.set_dash(&[6.0, 4.0], 0.0);
+            cr.move_to(area.x, y.round());
+            cr.line_to(area.x + area.width, y.round());
+            let _ = cr.stroke();
+            cr.set_dash(&[], 0.0);
+        }
+
+        let (r, g, b) = PALETTE[0];
+        cr.set_source_rgb(r, g, b);
+        cr.set_line_width(2.0);
+        for (index, (_, seconds)) in days.iter().enumerate() {
+            let (x, y) = point(index, *seconds);
+            if index == 0 {
+                cr.move_to(x, y);
+            } else {
+                cr.line_to(x, y);
+            }
+        }
+        let _ = cr.stroke();
+        cr.set_line_width(1.0);
+
+        // Days that met the goal get a green dot
+        let label_every = self.label_every(cr, slot);
+        for (index, (day, seconds)) in days.iter().enumerate() {
+            let (x, y) = point(index, *seconds);
+            let (r, g, b) = if self.daily_goal > 0 && *seconds >= self.daily_goal {
+                PALETTE[1]
+            } else {
+                PALETTE[0]
+            };
+            cr.set_source_rgb(r, g, b);
+            cr.new_sub_path();
+            cr.arc(x, y, 3.5, 0.0, 2.0 * PI);
+            let _ = cr.fill();
+
+            if index % label_every == 0 {
+                let label = self.period_label(*day, days.len());
+                Chart::draw_centered(cr, x, area.y + area.height + 14.0, fg, &label);
+            }
+        }
+    }
+
+    /// Draws horizontal grid lines with hour labels and returns the value
+    /// at the top of the area.
+    fn draw_axis(cr: &Context, area: Area, max: i64, fg: (f64, f64, f64)) -> i64 {
+        let step = Chart::axis_step(max);
+        let lines = ((max + step - 1) / step).max(1);
+        let top = lines * step;
+        let (r, g, b) = fg;
+
+        for line in 0..=lines {
+            let y = (area.y + area.height - (line * step) as f64 / top as f64 * area.height).round() + 0.5;
+            cr.set_source_rgba(r, g, b, if line == 0 { 0.4 } else { 0.12 });
+            cr.move_to(area.x, y);
+            cr.line_to(area.x + area.width, y);
+            let _ = cr.stroke();
+
+            let label = if step % 3600 == 0 {
+                format!("{}h", line * step / 3600)
+            } else {
+                Chart::format_hours(line * step)
+            };
+            cr.set_source_rgba(r, g, b, 0.6);
+            if let Ok(extents) = cr.text_extents(&label) {
+                cr.move_to(area.x - extents.x_advance() - 6.0, y + 4.0);
+                let _ = cr.show_text(&label);
+            }
+        }
+        top
+    }
+
+    /// Picks a grid step that gives at most five lines.
+    fn axis_step(max: i64) -> i64 {
+        const STEPS: [i64; 10] = [900, 1800, 3600, 7200, 14400, 28800, 43200, 86400, 172800, 360000];
+        STEPS
+            .iter()
+            .copied()
+            .find(|step| max <= step * 5)
+            .unwrap_or_else(|| (max / 5 / 360000 + 1) * 360000)
+    }
+
+    /// Draws the legend along the bottom and returns its height. With a
+    /// total, each entry also shows its percentage.
+    fn draw_legend(
+        cr: &Context,
+        series: &[Series],
+        width: f64,
+        height: f64,
+        fg: (f64, f64, f64),
+        total: Option<i64>,
+    ) -> f64 {
+        let labels: Vec<String> = series
+            .iter()
+            .map(|entry| match total {
+                Some(total) => format!(
+                    "{} {:.0}%",
+                    entry.label,
+                    entry.seconds as f64 / total.max(1) as f64 * 100.0
+                ),
+                None => entry.label.clone(),
+            })
+            .collect();
+
+        // Lay entries out left to right, wrapping onto new rows
+        let mut positions: Vec<(f64, f64)> = Vec::new();
+        let (mut x, mut row) = (MARGIN, 0.0);
+        for label in &labels {
+            let text_width = cr.text_extents(label).map(|e| e.x_advance()).unwrap_or(0.0);
+            let item_width = SWATCH_SIZE + 6.0 + text_width + 16.0;
+            if x > MARGIN && x + item_width > width - MARGIN {
+                x = MARGIN;
+                row += 1.0;
+            }
+            positions.push((x, row));
+            x += item_width;
+        }
+        let rows = if labels.is_empty() { 0.0 } else { row + 1.0 };
+        let legend_height = rows * LEGEND_ROW;
+        let top = height - MARGIN - legend_height;
+
+        let (r, g, b) = fg;
+        for ((label, entry), (x, row)) in labels.iter().zip(series).zip(positions) {
+            let y = top + row * LEGEND_ROW;
+            let (sr, sg, sb) = entry.color;
+            cr.set_source_rgb(sr, sg, sb);
+            cr.rectangle(x, y + 4.0, SWATCH_SIZE, SWATCH_SIZE);
+            let _ = cr.fill();
+            cr.set_source_rgb(r, g, b);
+            cr.move_to(x + SWATCH_SIZE + 6.0, y + 13.0);
+            let _ = cr.show_text(label);
+        }
+        legend_height
+    }
+
+    fn draw_centered(cr: &Context, x: f64, y: f64, fg: (f64, f64, f64), text: &str) {
+        let (r, g, b) = fg;
+        cr.set_source_rgb(r, g, b);
+        if let Ok(extents) = cr.text_extents(text) {
+            cr.move_to(x - extents.x_advance() / 2.0, y);
+            let _ = cr.show_text(text);
+        }
+    }
+
+    /// The largest groups get their own color and the rest share "Other",
+    /// so the palette never repeats within a chart.
+    fn build_series(totals: Vec<(String, String, i64)>) -> Vec<Series> {
+        let totals: Vec<(String, String, i64)> = totals
+            .into_iter()
+            .sorted_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)))
+            .collect();
+        let own_colors = if totals.len() > PALETTE.len() {
+            PALETTE.len() - 1
+        } else {
+            totals.len()
+        };
+
+        let mut series: Vec<Series> = totals
+            .iter()
+            .take(own_colors)
+            .enumerate()
+            .map(|(index, (key, label, seconds))| Series {
+                key: Some(key.clone()),
+                label: label.clone(),
+                seconds: *seconds,
+                color: PALETTE[index],
+            })
+            .collect();
+        if totals.len() > own_colors {
+            series.push(Series {
+                key: None,
+                label: gettext("Other"),
+                seconds: totals.iter().skip(own_colors).map(|(_, _, seconds)| seconds).sum(),
+                color: OTHER_COLOR,
+            });
+        }
+        series
+    }
+
+    fn bar_series(&self) -> Vec<Series> {
+        let mut totals: Vec<(String, String, i64)> = Vec::new();
+        for child in self.report.children.iter().flat_map(|period| &period.children) {
+            match totals.iter_mut().find(|(key, _, _)| *key == child.key) {
+                Some(total) => total.2 += child.seconds,
+                None => totals.push((child.key.clone(), child.label(), child.seconds)),
+            }
+        }
+        Chart::build_series(totals)
+    }
+
+    fn share_series(&self) -> Vec<Series> {
+        Chart::build_series(
+            self.report
+                .children
+                .iter()
+                .map(|node| (node.key.clone(), node.label(), node.seconds))
+                .collect(),
+        )
+    }
+
+    fn series_index(series: &[Series], key: &str) -> usize {
+        series
+            .iter()
+            .position(|entry| entry.key.as_deref() == Some(key))
+            .unwrap_or(series.len() - 1)
+    }
+
+    /// Every bar in the range with the key the report uses for it, so
+    /// empty days and weeks still get a slot.
+    fn periods(&self) -> Vec<(String, NaiveDate)> {
+        let days = self.range.days();
+        let starts: Vec<NaiveDate> = if self.period == GroupBy::Week {
+            days.into_iter()
+                .map(|day| reporting::week_start(day, self.week_starts_on))
+                .dedup()
+                .collect()
+        } else {
+            days
+        };
+        starts
+            .into_iter()
+            .map(|day| (day.format("%F").to_string(), day))
+            .collect()
+    }
+
+    fn period_label(&self, date: NaiveDate, count: usize) -> String {
+        if self.period == GroupBy::Week || count > 7 {
+            date.format("%b %d").to_string()
+        } else {
+            date.format("%a %d").to_string()
+        }
+    }
+
+    /// How many slots to skip between x axis labels so they don't overlap.
+    fn label_every(&self, cr: &Context, slot: f64) -> usize {
+        let label_width = cr
+            .text_extents(&self.period_label(self.range.start, 8))
+            .map(|e| e.x_advance())
+            .unwrap_or(40.0)
+            + 8.0;
+        (label_width / slot).ceil().max(1.0) as usize
+    }
+
+    fn format_hours(seconds: i64) -> String {
+        format!("{}:{:02}", seconds / 3600, seconds % 3600 / 60)
+    }
+}
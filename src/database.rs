@@ -20,33 +20,29 @@ use gettextrs::*;
 use glib::clone;
 use gtk::prelude::*;
 use gtk::glib;
-use rusqlite::{Connection, Result, backup};
+use rusqlite::{Connection, OptionalExtension, Result, backup};
 use std::convert::TryFrom;
 use std::fs::create_dir_all;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use crate::billing::{self, Rate, RateKind};
+use crate::budgets::Budget;
+use crate::filter::Expr;
+use crate::importers::NewTask;
+use crate::invoicing::Invoice;
+use crate::merging;
+use crate::reporting::{self, ReportPreset, Rounding};
+use crate::store::{
+    add_billable_column, add_identity_columns, create_budget_table, create_changes_table,
+    create_invoice_tables, create_rates_table, create_tasks_table, delete_with, find_overlapping_with,
+    insert_task, task_from_row, tasks_to_csv, update_field_with,
+};
 use crate::ui::rusttimetrackWindow;
 use crate::settings_manager;
 
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-pub struct Task {
-    pub id: i32,
-    pub task_name: String,
-    pub start_time: String,
-    pub stop_time: String,
-    pub tags: String,
-}
-
-impl ToString for Task {
-    fn to_string(&self) -> String {
-        if !self.tags.is_empty() {
-            format!("{} #{}", self.task_name, self.tags)
-        } else {
-            self.task_name.to_string()
-        }
-    }
-}
+pub use crate::merging::{MergeConflict, MergeReport};
+pub use crate::store::{budgets_from, new_uuid, rates_from, split_tags, Change, ChangeOrigin, Task};
 
 #[derive(
     Debug,
@@ -155,26 +151,38 @@ pub fn get_directory() -> PathBuf {
     PathBuf::new()
 }
 
-pub fn db_init() -> Result<()> {
+pub fn open_db() -> Result<Connection> {
+    Connection::open(get_directory())
+}
+
+/// Brings the database up to date, creating the tables that are missing
+/// and adding the columns older versions didn't have.
+pub fn migrate() -> Result<()> {
     let conn = Connection::open(get_directory())?;
+    migrate_with(&conn)
+}
+
+fn migrate_with(conn: &Connection) -> Result<()> {
+    // These fail if the table or column is already there
+    let _ = create_tasks_table(conn);
+    // Update from old DBs w/o tags, projects or billable flags
+    let _ = conn.execute("ALTER TABLE tasks ADD COLUMN tags TEXT DEFAULT ' '", []);
+    let _ = conn.execute("ALTER TABLE tasks ADD COLUMN project TEXT DEFAULT ''", []);
+    let _ = add_billable_column(conn);
+
+    create_changes_table(conn)?;
+    // Update from old DBs w/o UUIDs and modification times
+    add_identity_columns(conn)?;
     conn.execute(
-        "CREATE TABLE tasks (
+        "CREATE TABLE IF NOT EXISTS report_presets (
                     id integer primary key,
-                    task_name text,
-                    start_time timestamp,
-                    stop_time timestamp,
-                    tags text)",
+                    name text UNIQUE,
+                    preset text)",
         [],
     )?;
-
-    Ok(())
-}
-
-pub fn upgrade_old_db() -> Result<()> {
-    // Update from old DB w/o tags
-    let conn = Connection::open(get_directory())?;
-
-    conn.execute("ALTER TABLE tasks ADD COLUMN tags TEXT DEFAULT ' '", [])?;
+    create_rates_table(conn)?;
+    create_invoice_tables(conn)?;
+    create_budget_table(conn)?;
 
     Ok(())
 }
@@ -184,23 +192,45 @@ pub fn db_write(
     start_time: DateTime<Local>,
     stop_time: DateTime<Local>,
     tags: String,
+    project: String,
+    origin: ChangeOrigin,
 ) -> Result<()> {
     // Write data into database
     let conn = Connection::open(get_directory())?;
-
-    conn.execute(
-        "INSERT INTO tasks (task_name, start_time, stop_time, tags) values (?1, ?2, ?3, ?4)",
-        &[
-            &task_name.to_string(),
-            &start_time.to_rfc3339(),
-            &stop_time.to_rfc3339(),
-            &tags,
-        ],
+    insert_task(
+        &conn,
+        task_name,
+        &start_time.to_rfc3339(),
+        &stop_time.to_rfc3339(),
+        &tags,
+        &project,
+        origin,
     )?;
 
     Ok(())
 }
 
+/// Adds tasks read from another tracker in one transaction, and returns how
+/// many were added.
+pub fn import_tasks(tasks: &[NewTask]) -> Result<usize> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    for task in tasks {
+        insert_task(
+            &tx,
+            &task.task_name,
+            &task.start.to_rfc3339(),
+            &task.stop.to_rfc3339(),
+            &task.tags,
+            &task.project,
+            ChangeOrigin::Import,
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(tasks.len())
+}
+
 pub fn write_autosave(
     task_name: &str,
     start_time: &str,
@@ -209,11 +239,8 @@ pub fn write_autosave(
 ) -> Result<()> {
     // Write data into database
     let conn = Connection::open(get_directory())?;
-
-    conn.execute(
-        "INSERT INTO tasks (task_name, start_time, stop_time, tags) values (?1, ?2, ?3, ?4)",
-        &[&task_name, &start_time, &stop_time, &tags],
-    )?;
+    let project = last_project_with(&conn, task_name)?;
+    insert_task(&conn, task_name, start_time, stop_time, tags, &project, ChangeOrigin::Autosave)?;
 
     Ok(())
 }
@@ -230,15 +257,7 @@ pub fn retrieve(sort: TaskSort, order: SortOrder) -> Result<Vec<Task>, rusqlite:
         )
         .as_str(),
     )?;
-    let task_iter = query.query_map([], |row| {
-        Ok(Task {
-            id: row.get(0)?,
-            task_name: row.get(1)?,
-            start_time: row.get(2)?,
-            stop_time: row.get(3)?,
-            tags: row.get(4)?,
-        })
-    })?;
+    let task_iter = query.query_map([], task_from_row)?;
 
     let mut tasks_vec: Vec<Task> = Vec::new();
     for task_item in task_iter {
@@ -248,102 +267,407 @@ pub fn retrieve(sort: TaskSort, order: SortOrder) -> Result<Vec<Task>, rusqlite:
     Ok(tasks_vec)
 }
 
-/// Exports the database as CSV.
+/// Exports the database as CSV, keeping only tasks matching `filter` if given.
+/// With `rounding`, each entry also gets a rounded_seconds column.
 /// The delimiter parameter is interpreted as a ASCII character.
-pub fn export_as_csv(sort: TaskSort, order: SortOrder, delimiter: u8) -> anyhow::Result<String> {
-    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
-    struct CSVTask {
-        pub id: i32,
-        pub task_name: String,
-        pub start_time: String,
-        pub stop_time: String,
-        pub tags: String,
-        pub seconds: i64,
-    }
-
-    let mut csv_writer = csv::WriterBuilder::new()
-        .delimiter(delimiter)
-        .from_writer(vec![]);
-    let tasks = retrieve(sort, order)?;
+pub fn export_as_csv(
+    sort: TaskSort,
+    order: SortOrder,
+    delimiter: u8,
+    filter: Option<&Expr>,
+    rounding: Option<Rounding>,
+) -> anyhow::Result<String> {
+    let mut tasks = retrieve(sort, order)?;
+    if let Some(filter) = filter {
+        tasks.retain(|task| filter.matches(task));
+    }
+    tasks_to_csv(tasks, delimiter, rounding)
+}
 
-    for task in tasks {
-        let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap();
-        let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap();
-        let duration = stop_time - start_time;
-        csv_writer.serialize(CSVTask{
-            id: task.id,
-            task_name: task.task_name,
-            start_time: task.start_time,
-            stop_time: task.stop_time,
-            tags: task.tags,
-            seconds: duration.num_seconds(),
-        })?;
+/// Exports only the given tasks as CSV, in the same format as `export_as_csv`.
+pub fn export_ids_as_csv(id_list: Vec<i32>, delimiter: u8) -> anyhow::Result<String> {
+    let mut tasks = get_list_by_id(id_list)?;
+    tasks.sort_by(|a, b| a.start_time.cmp(&b.start_time));
+    tasks_to_csv(tasks, delimiter, None)
+}
+
+pub fn update_start_time(id: i32, start_time: String) -> anyhow::Result<()> {
+    update_field(id, "start_time", &start_time)
+}
+
+pub fn update_stop_time(id: i32, stop_time: String) -> anyhow::Result<()> {
+    update_field(id, "stop_time", &stop_time)
+}
+
+pub fn update_task_name(id: i32, task_name: String) -> anyhow::Result<()> {
+    update_field(id, "task_name", &task_name)
+}
+
+pub fn update_tags(id: i32, tags: String) -> anyhow::Result<()> {
+    update_field(id, "tags", &tags)
+}
+
+pub fn update_project(id: i32, project: String) -> anyhow::Result<()> {
+    update_field(id, "project", &project)
+}
+
+pub fn update_billable(id: i32, billable: Option<bool>) -> anyhow::Result<()> {
+    update_field(id, "billable", billing::billable_to_sqlite(billable))
+}
+
+pub fn update_times(id: i32, start_time: String, stop_time: String) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    check_editable(&tx, id)?;
+    update_field_with(&tx, id, "start_time", &start_time, ChangeOrigin::Manual)?;
+    update_field_with(&tx, id, "stop_time", &stop_time, ChangeOrigin::Manual)?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn update_field(id: i32, field: &str, value: &str) -> anyhow::Result<()> {
+    // The old value is read, replaced and logged together
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    check_editable(&tx, id)?;
+    update_field_with(&tx, id, field, value, ChangeOrigin::Manual)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Gives every task in `id_list` the same name and tags, or changes none
+/// of them if one is invoiced.
+pub fn rename_tasks(id_list: &[i32], task_name: &str, tags: &str) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    for id in id_list {
+        check_editable(&tx, *id)?;
+        update_field_with(&tx, *id, "task_name", task_name, ChangeOrigin::Manual)?;
+        update_field_with(&tx, *id, "tags", tags, ChangeOrigin::Manual)?;
     }
+    tx.commit()?;
+    Ok(())
+}
+
+/// An edit applied to several tasks at once by `bulk_edit`.
+#[derive(Debug, Clone)]
+pub enum BulkEdit {
+    Rename(String),
+    AddTags(Vec<String>),
+    RemoveTags(Vec<String>),
+    SetProject(String),
+    ShiftTimes(chrono::Duration),
+    Merge,
+    Delete,
+}
 
-    csv_writer.flush()?;
+/// Applies one edit to all tasks in `id_list` inside a single transaction,
+/// so either every task is changed or none is.
+pub fn bulk_edit(id_list: Vec<i32>, edit: BulkEdit) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    let origin = ChangeOrigin::Manual;
+
+    let mut tasks: Vec<Task> = Vec::new();
+    {
+        let mut query = tx.prepare("SELECT * FROM tasks WHERE id = ?1")?;
+        for id in &id_list {
+            if let Some(task) = query.query_map([id], task_from_row)?.next() {
+                tasks.push(task?);
+            }
+        }
+    }
+    if !matches!(edit, BulkEdit::Delete) {
+        check_not_invoiced(&tx, &tasks)?;
+    }
 
-    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+    match edit {
+        BulkEdit::Rename(task_name) => {
+            for task in &tasks {
+                update_field_with(&tx, task.id, "task_name", &task_name, origin)?;
+            }
+        }
+        BulkEdit::AddTags(new_tags) => {
+            for task in &tasks {
+                let mut tag_list = split_tags(&task.tags);
+                for tag in split_tags(&new_tags.join(" #")) {
+                    if !tag_list.contains(&tag) {
+                        tag_list.push(tag);
+                    }
+                }
+                update_field_with(&tx, task.id, "tags", &tag_list.join(" #"), origin)?;
+            }
+        }
+        BulkEdit::RemoveTags(old_tags) => {
+            let old_tags = split_tags(&old_tags.join(" #"));
+            for task in &tasks {
+                let mut tag_list = split_tags(&task.tags);
+                tag_list.retain(|tag| !old_tags.contains(tag));
+                update_field_with(&tx, task.id, "tags", &tag_list.join(" #"), origin)?;
+            }
+        }
+        BulkEdit::SetProject(project) => {
+            for task in &tasks {
+                update_field_with(&tx, task.id, "project", project.trim(), origin)?;
+            }
+        }
+        BulkEdit::ShiftTimes(offset) => {
+            for task in &tasks {
+                let start_time = DateTime::parse_from_rfc3339(&task.start_time)?
+                    .with_timezone(&Local) + offset;
+                let stop_time = DateTime::parse_from_rfc3339(&task.stop_time)?
+                    .with_timezone(&Local) + offset;
+                if stop_time > Local::now() {
+                    anyhow::bail!(gettext("Time cannot be in the future."));
+                }
+                update_field_with(&tx, task.id, "start_time", &start_time.to_rfc3339(), origin)?;
+                update_field_with(&tx, task.id, "stop_time", &stop_time.to_rfc3339(), origin)?;
+            }
+        }
+        BulkEdit::Merge => {
+            check_merge(&tx, &tasks, &id_list)?;
+            merge_tasks_with(&tx, tasks, origin)?;
+        }
+        BulkEdit::Delete => {
+            for task in &tasks {
+                delete_with(&tx, task.id, origin)?;
+            }
+        }
+    }
+
+    tx.commit()?;
+
+    Ok(())
 }
 
-pub fn update_start_time(id: i32, start_time: String) -> Result<()> {
-    let conn = Connection::open(get_directory())?;
+/// Merges tasks into the earliest one, which then spans from the first start
+/// time to the last stop time. The other tasks are deleted.
+fn merge_tasks_with(conn: &Connection, mut tasks: Vec<Task>, origin: ChangeOrigin) -> anyhow::Result<()> {
+    if tasks.len() < 2 {
+        return Ok(());
+    }
 
-    conn.execute(
-        "UPDATE tasks SET start_time = (?1) WHERE id = (?2)",
-        &[&start_time, &id.to_string()],
-    )?;
+    tasks.sort_by_key(|task| DateTime::parse_from_rfc3339(&task.start_time).ok());
+    let first = tasks.remove(0);
+    let mut stop_time = DateTime::parse_from_rfc3339(&first.stop_time)?;
+    for task in &tasks {
+        let task_stop = DateTime::parse_from_rfc3339(&task.stop_time)?;
+        if task_stop > stop_time {
+            stop_time = task_stop;
+        }
+        delete_with(conn, task.id, origin)?;
+    }
+    update_field_with(conn, first.id, "stop_time", &stop_time.to_rfc3339(), origin)?;
 
     Ok(())
 }
 
-pub fn update_stop_time(id: i32, stop_time: String) -> Result<()> {
-    let conn = Connection::open(get_directory())?;
+/// Splits a task in two at `split_time`. The original task keeps the first
+/// part and a new task with the given name and tags gets the rest.
+pub fn split_task(id: i32, split_time: DateTime<Local>, task_name: &str, tags: &str) -> anyhow::Result<i32> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    let origin = ChangeOrigin::Manual;
+
+    let task = tx.query_row("SELECT * FROM tasks WHERE id = ?1", [id], task_from_row)?;
+    check_not_invoiced(&tx, std::slice::from_ref(&task))?;
+    let start_time = DateTime::parse_from_rfc3339(&task.start_time)?;
+    let stop_time = DateTime::parse_from_rfc3339(&task.stop_time)?;
+    if split_time <= start_time || split_time >= stop_time {
+        anyhow::bail!(gettext("Split time must be between the start and stop time."));
+    }
 
-    conn.execute(
-        "UPDATE tasks SET stop_time = (?1) WHERE id = (?2)",
-        &[&stop_time, &id.to_string()],
-    )?;
+    let split_time = split_time.to_rfc3339();
+    update_field_with(&tx, id, "stop_time", &split_time, origin)?;
+    let new_id = insert_task(&tx, task_name, &split_time, &task.stop_time, tags, &task.project, origin)?;
+
+    tx.commit()?;
+
+    Ok(new_id)
+}
+
+/// Merges the given tasks into one, unless they can't be, see `check_merge`.
+pub fn merge_tasks(id_list: Vec<i32>) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+
+    let mut tasks: Vec<Task> = Vec::new();
+    {
+        let mut query = tx.prepare("SELECT * FROM tasks WHERE id = ?1")?;
+        for id in &id_list {
+            if let Some(task) = query.query_map([id], task_from_row)?.next() {
+                tasks.push(task?);
+            }
+        }
+    }
+    if tasks.len() < 2 {
+        return Ok(());
+    }
+
+    check_not_invoiced(&tx, &tasks)?;
+    check_merge(&tx, &tasks, &id_list)?;
+    merge_tasks_with(&tx, tasks, ChangeOrigin::Manual)?;
+    tx.commit()?;
 
     Ok(())
 }
 
-pub fn update_task_name(id: i32, task_name: String) -> Result<()> {
-    let conn = Connection::open(get_directory())?;
+/// Invoiced time stays as it was billed, so invoiced tasks can't be edited,
+/// split or merged. They can still be deleted.
+/// Like `check_not_invoiced`, for the one task about to be edited.
+fn check_editable(conn: &Connection, id: i32) -> anyhow::Result<()> {
+    let task = conn
+        .query_row("SELECT * FROM tasks WHERE id = ?1", [id], task_from_row)
+        .optional()?;
+    check_not_invoiced(conn, task.as_slice())
+}
 
-    conn.execute(
-        "UPDATE tasks SET task_name = (?1) WHERE id = (?2)",
-        &[&task_name, &id.to_string()],
-    )?;
+fn check_not_invoiced(conn: &Connection, tasks: &[Task]) -> anyhow::Result<()> {
+    let mut query = conn.prepare("SELECT EXISTS(SELECT 1 FROM invoice_entries WHERE task_uuid = ?1)")?;
+    for task in tasks {
+        if query.query_row([&task.uuid], |row| row.get::<_, bool>(0))? {
+            anyhow::bail!(gettext("{} is on an invoice and can't be changed.").replace("{}", &task.task_name));
+        }
+    }
 
     Ok(())
 }
 
-pub fn update_tags(id: i32, tags: String) -> Result<()> {
-    let conn = Connection::open(get_directory())?;
+/// Only entries of the same task on the same day can be merged, and the
+/// merged time span can't overlap another task.
+fn check_merge(conn: &Connection, tasks: &[Task], id_list: &[i32]) -> anyhow::Result<()> {
+    let first = match tasks.first() {
+        Some(task) => task,
+        None => return Ok(()),
+    };
+    let day_of = |time: &str| -> anyhow::Result<chrono::NaiveDate> {
+        Ok(DateTime::parse_from_rfc3339(time)?.with_timezone(&Local).date_naive())
+    };
+    let day = day_of(&first.start_time)?;
 
-    conn.execute(
-        "UPDATE tasks SET tags = (?1) WHERE id = (?2)",
-        &[&tags, &id.to_string()],
+    let mut start_time = DateTime::parse_from_rfc3339(&first.start_time)?;
+    let mut stop_time = DateTime::parse_from_rfc3339(&first.stop_time)?;
+    for task in tasks {
+        if task.task_name.trim() != first.task_name.trim() {
+            anyhow::bail!(gettext("Only entries of the same task can be merged."));
+        }
+        if day_of(&task.start_time)? != day {
+            anyhow::bail!(gettext("Only entries on the same day can be merged."));
+        }
+        start_time = start_time.min(DateTime::parse_from_rfc3339(&task.start_time)?);
+        stop_time = stop_time.max(DateTime::parse_from_rfc3339(&task.stop_time)?);
+    }
+
+    let overlapping = find_overlapping_with(
+        conn,
+        start_time.with_timezone(&Local),
+        stop_time.with_timezone(&Local),
+        id_list,
     )?;
+    if !overlapping.is_empty() {
+        anyhow::bail!(gettext("The merged task would overlap {}.")
+            .replace("{}", &overlapping[0].task_name));
+    }
+
+    Ok(())
+}
+
+/// Adds or removes time so the given tasks add up to `total` for the day.
+/// Extra time is appended after the last task, removed time is taken from
+/// the latest tasks first. Added time has to fit into the day without
+/// overlapping other tasks.
+pub fn set_day_total(
+    id_list: Vec<i32>,
+    day_start: DateTime<Local>,
+    task_name: &str,
+    tags: &str,
+    project: &str,
+    total: chrono::Duration,
+) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    let origin = ChangeOrigin::Manual;
+
+    let mut tasks: Vec<Task> = Vec::new();
+    {
+        let mut query = tx.prepare("SELECT * FROM tasks WHERE id = ?1")?;
+        for id in &id_list {
+            if let Some(task) = query.query_map([id], task_from_row)?.next() {
+                tasks.push(task?);
+            }
+        }
+    }
+    tasks.sort_by_key(|task| DateTime::parse_from_rfc3339(&task.start_time).ok());
+
+    let mut current = chrono::Duration::zero();
+    for task in &tasks {
+        current = current + (DateTime::parse_from_rfc3339(&task.stop_time)?
+            - DateTime::parse_from_rfc3339(&task.start_time)?);
+    }
+
+    if total > current {
+        let extra = total - current;
+        let mut start_time = match tasks.last() {
+            Some(task) => DateTime::parse_from_rfc3339(&task.stop_time)?.with_timezone(&Local),
+            None => day_start + chrono::Duration::hours(9),
+        };
+        let day_end = reporting::local_midnight(day_start.date_naive() + chrono::Duration::days(1));
+        if start_time + extra > Local::now() {
+            start_time = Local::now() - extra;
+        }
+        if start_time + extra > day_end {
+            start_time = day_end - extra;
+        }
+        if start_time < day_start {
+            anyhow::bail!(gettext("There is not enough time left in this day."));
+        }
+        let stop_time = start_time + extra;
+        let overlapping = find_overlapping_with(&tx, start_time, stop_time, &[])?;
+        if let Some(task) = overlapping.first() {
+            anyhow::bail!(gettext("The added time would overlap {}.").replace("{}", &task.task_name));
+        }
+        insert_task(&tx, task_name, &start_time.to_rfc3339(), &stop_time.to_rfc3339(), tags, project, origin)?;
+    } else if total < current {
+        // Removed time comes out of the existing entries
+        check_not_invoiced(&tx, &tasks)?;
+        let mut remove = current - total;
+        for task in tasks.iter().rev() {
+            let start_time = DateTime::parse_from_rfc3339(&task.start_time)?;
+            let stop_time = DateTime::parse_from_rfc3339(&task.stop_time)?;
+            if stop_time - start_time <= remove {
+                remove = remove - (stop_time - start_time);
+                delete_with(&tx, task.id, origin)?;
+            } else {
+                update_field_with(&tx, task.id, "stop_time", &(stop_time - remove).to_rfc3339(), origin)?;
+                break;
+            }
+            if remove <= chrono::Duration::zero() {
+                break;
+            }
+        }
+    }
+
+    tx.commit()?;
 
     Ok(())
 }
 
+pub fn find_overlapping(
+    start_time: DateTime<Local>,
+    stop_time: DateTime<Local>,
+    exclude: &[i32],
+) -> anyhow::Result<Vec<Task>> {
+    let conn = Connection::open(get_directory())?;
+    find_overlapping_with(&conn, start_time, stop_time, exclude)
+}
+
 pub fn get_list_by_id(id_list: Vec<i32>) -> Result<Vec<Task>, rusqlite::Error> {
     let conn = Connection::open(get_directory())?;
     let mut tasks_vec: Vec<Task> = Vec::new();
 
     for id in id_list {
         let mut query = conn.prepare("SELECT * FROM tasks WHERE id = :id;")?;
-        let task_iter = query.query_map(&[(":id", &id.to_string())], |row| {
-            Ok(Task {
-                id: row.get(0)?,
-                task_name: row.get(1)?,
-                start_time: row.get(2)?,
-                stop_time: row.get(3)?,
-                tags: row.get(4)?,
-            })
-        })?;
+        let task_iter = query.query_map(&[(":id", &id.to_string())], task_from_row)?;
 
         for task_item in task_iter {
             tasks_vec.push(task_item.unwrap());
@@ -353,6 +677,18 @@ pub fn get_list_by_id(id_list: Vec<i32>) -> Result<Vec<Task>, rusqlite::Error> {
     Ok(tasks_vec)
 }
 
+/// The entries with exactly this name and these tags that start on `day`,
+/// like "2023-03-06", as the task details group them.
+pub fn get_group(task_name: &str, tags: &str, day: &str) -> Result<Vec<Task>> {
+    let conn = Connection::open(get_directory())?;
+    let mut query = conn.prepare(
+        "SELECT * FROM tasks WHERE task_name = ?1 AND tags = ?2 AND substr(start_time, 1, 10) = ?3
+            ORDER BY start_time ASC",
+    )?;
+    let tasks = query.query_map([task_name, tags, day], task_from_row)?.collect();
+    tasks
+}
+
 pub fn get_list_by_name_and_tags(task_name: String, tag_list: Vec<String>) -> Result<Vec<Task>, rusqlite::Error> {
     let conn = Connection::open(get_directory())?;
 
@@ -369,19 +705,54 @@ pub fn get_list_by_name_and_tags(task_name: String, tag_list: Vec<String>) -> Re
         query.raw_bind_parameter(i + 2, tag)?;
     }
 
-    let tasks_vec = query.raw_query().mapped(|row| {
-        Ok(Task {
-            id: row.get(0)?,
-            task_name: row.get(1)?,
-            start_time: row.get(2)?,
-            stop_time: row.get(3)?,
-            tags: row.get(4)?,
-        })
-    }).map(|task_item| task_item.unwrap()).collect();
+    let tasks_vec = query.raw_query().mapped(task_from_row).map(|task_item| task_item.unwrap()).collect();
 
     Ok(tasks_vec)
 }
 
+/// The entry that ended last, for starting it again.
+pub fn last_task() -> Result<Option<Task>> {
+    let conn = Connection::open(get_directory())?;
+    conn.query_row(
+        "SELECT * FROM tasks ORDER BY julianday(stop_time) DESC LIMIT 1",
+        [],
+        task_from_row,
+    )
+    .optional()
+}
+
+/// The project of the latest entry with this name, which the timer's entries
+/// get since there is nowhere to enter one while it runs.
+pub fn last_project(task_name: &str) -> Result<String> {
+    let conn = Connection::open(get_directory())?;
+    last_project_with(&conn, task_name)
+}
+
+fn last_project_with(conn: &Connection, task_name: &str) -> Result<String> {
+    let project: Option<Option<String>> = conn
+        .query_row(
+            "SELECT project FROM tasks WHERE lower(trim(task_name)) = lower(trim(?1))
+                ORDER BY julianday(stop_time) DESC LIMIT 1",
+            [task_name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(project.flatten().unwrap_or_default())
+}
+
+/// The task name and tags of the project's latest entry, for time added to
+/// the project without naming a task.
+pub fn last_task_in_project(project: &str) -> Result<Option<(String, String)>> {
+    let conn = Connection::open(get_directory())?;
+    conn.query_row(
+        "SELECT task_name, tags FROM tasks WHERE lower(trim(project)) = lower(trim(?1))
+            ORDER BY julianday(stop_time) DESC LIMIT 1",
+        [project],
+        |row| Ok((row.get(0)?, row.get::<_, Option<String>>(1)?.unwrap_or_default())),
+    )
+    .optional()
+}
+
 pub fn check_for_tasks() -> Result<String> {
     let conn = Connection::open(get_directory())?;
 
@@ -406,7 +777,7 @@ pub fn delete_by_ids(id_list: Vec<i32>) -> Result<()> {
     let conn = Connection::open(get_directory())?;
 
     for id in id_list {
-        conn.execute("delete FROM tasks WHERE id = (?1)", &[&id.to_string()])?;
+        delete_with(&conn, id, ChangeOrigin::Manual)?;
     }
 
     Ok(())
@@ -414,17 +785,204 @@ pub fn delete_by_ids(id_list: Vec<i32>) -> Result<()> {
 
 pub fn delete_by_id(id: i32) -> Result<()> {
     let conn = Connection::open(get_directory())?;
-
-    conn.execute("delete FROM tasks WHERE id = (?1)", &[&id.to_string()])?;
-
-    Ok(())
+    delete_with(&conn, id, ChangeOrigin::Manual)
 }
 
 pub fn delete_all() -> Result<()> {
     // Delete everything from the database
     let conn = Connection::open(get_directory())?;
 
+    conn.execute(
+        "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+            SELECT id, uuid, 'deleted', task_name, '', ?1, ?2 FROM tasks",
+        (Local::now().to_rfc3339(), ChangeOrigin::Manual.to_sqlite()),
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_deleted (uuid, deleted_at, device)
+            SELECT uuid, ?1, NULL FROM tasks WHERE uuid IS NOT NULL AND uuid != ''",
+        [Local::now().to_rfc3339()],
+    )?;
     conn.execute("delete from tasks", [])?;
+    conn.execute("delete from invoice_entries", [])?;
+
+    Ok(())
+}
+
+pub fn get_changes_by_ids(id_list: Vec<i32>) -> Result<Vec<Change>, rusqlite::Error> {
+    let conn = Connection::open(get_directory())?;
+    let mut changes_vec: Vec<Change> = Vec::new();
+
+    let mut query = conn.prepare(
+        "SELECT id, task_id, task_uuid, field, old_value, new_value, changed_at, origin
+            FROM changes WHERE task_uuid = (SELECT uuid FROM tasks WHERE id = :id) ORDER BY id ASC",
+    )?;
+    for id in id_list {
+        let change_iter = query.query_map(&[(":id", &id)], change_from_row)?;
+
+        for change_item in change_iter {
+            changes_vec.push(change_item?);
+        }
+    }
+
+    changes_vec.sort_by_key(|change| change.id);
+    Ok(changes_vec)
+}
+
+fn change_from_row(row: &rusqlite::Row) -> Result<Change> {
+    let origin: String = row.get(7)?;
+    Ok(Change {
+        id: row.get(0)?,
+        task_id: row.get(1)?,
+        task_uuid: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+        field: row.get(3)?,
+        old_value: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+        new_value: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+        changed_at: row.get(6)?,
+        origin: ChangeOrigin::from_sqlite(&origin),
+    })
+}
+
+pub fn get_report_presets() -> anyhow::Result<Vec<ReportPreset>> {
+    let conn = Connection::open(get_directory())?;
+    let mut query = conn.prepare("SELECT preset FROM report_presets ORDER BY name COLLATE NOCASE")?;
+    let preset_iter = query.query_map([], |row| row.get::<_, String>(0))?;
+
+    let mut presets: Vec<ReportPreset> = Vec::new();
+    for preset in preset_iter {
+        // Skip presets saved by a version that had different fields
+        match serde_json::from_str(&preset?) {
+            Ok(preset) => presets.push(preset),
+            Err(e) => log::warn!("skipping unreadable report preset, Err {}", e),
+        }
+    }
+    Ok(presets)
+}
+
+/// Saves a preset, replacing any existing one with the same name.
+pub fn save_report_preset(preset: &ReportPreset) -> anyhow::Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute(
+        "INSERT INTO report_presets (name, preset) VALUES (?1, ?2)
+            ON CONFLICT(name) DO UPDATE SET preset = excluded.preset",
+        (&preset.name, serde_json::to_string(preset)?),
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_report_preset(name: &str) -> Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute("DELETE FROM report_presets WHERE name = ?1", [name])?;
+
+    Ok(())
+}
+
+pub fn get_rates() -> Result<Vec<Rate>> {
+    let conn = Connection::open(get_directory())?;
+    rates_from(&conn)
+}
+
+/// Saves a rate, replacing any existing one for the same task, tag or project.
+pub fn save_rate(rate: &Rate) -> Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute(
+        "INSERT INTO rates (kind, name, hourly_rate, currency, billable) VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(kind, name) DO UPDATE SET hourly_rate = excluded.hourly_rate,
+                currency = excluded.currency, billable = excluded.billable",
+        (rate.kind.to_sqlite(), &rate.name, rate.hourly_rate, &rate.currency, rate.billable),
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_rate(kind: RateKind, name: &str) -> Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute(
+        "DELETE FROM rates WHERE kind = ?1 AND name = ?2",
+        (kind.to_sqlite(), name),
+    )?;
+
+    Ok(())
+}
+
+pub fn get_budgets() -> Result<Vec<Budget>> {
+    let conn = Connection::open(get_directory())?;
+    budgets_from(&conn)
+}
+
+/// Saves a budget, replacing any existing one for the same task, tag or project.
+pub fn save_budget(budget: &Budget) -> Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute(
+        "INSERT INTO budgets (kind, name, seconds, period) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(kind, name) DO UPDATE SET seconds = excluded.seconds,
+                period = excluded.period",
+        (budget.kind.to_sqlite(), &budget.name, budget.seconds, budget.period.to_sqlite()),
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_budget(kind: RateKind, name: &str) -> Result<()> {
+    let conn = Connection::open(get_directory())?;
+    conn.execute(
+        "DELETE FROM budgets WHERE kind = ?1 AND name = ?2",
+        (kind.to_sqlite(), name),
+    )?;
+
+    Ok(())
+}
+
+/// Remembers an invoice and marks its entries as invoiced, then saves it
+/// with `write`. Nothing is recorded if the number is taken, an entry is
+/// already invoiced or `write` fails, and nothing is written in the first
+/// two cases.
+pub fn record_invoice(invoice: &Invoice, write: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let mut conn = Connection::open(get_directory())?;
+    let tx = conn.transaction()?;
+    let exists: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM invoices WHERE number = ?1)",
+        [&invoice.number],
+        |row| row.get(0),
+    )?;
+    if exists {
+        anyhow::bail!(gettext("An invoice numbered {} already exists").replace("{}", &invoice.number));
+    }
+
+    tx.execute(
+        "INSERT INTO invoices (number, client, project, start_date, end_date, issued_at,
+                currency, subtotal, tax_rate, total)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        (
+            &invoice.number,
+            &invoice.client,
+            &invoice.project,
+            invoice.range.start.to_string(),
+            invoice.range.end.to_string(),
+            Local::now().to_rfc3339(),
+            &invoice.currency,
+            invoice.subtotal,
+            invoice.tax_rate,
+            invoice.total,
+        ),
+    )?;
+    let invoice_id = tx.last_insert_rowid();
+    for task_uuid in invoice.task_uuids() {
+        let invoiced: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM invoice_entries WHERE task_uuid = ?1)",
+            [&task_uuid],
+            |row| row.get(0),
+        )?;
+        if invoiced {
+            anyhow::bail!(gettext("Some of these entries were invoiced in the meantime. Build the invoice again."));
+        }
+        tx.execute(
+            "INSERT INTO invoice_entries (invoice_id, task_uuid) VALUES (?1, ?2)",
+            (invoice_id, &task_uuid),
+        )?;
+    }
+    write()?;
+    tx.commit()?;
 
     Ok(())
 }
@@ -445,8 +1003,58 @@ pub fn import_db(new_db: String) -> Result<()> {
 
     if valid {
         let mut conn = Connection::open(get_directory())?;
-        let backup = backup::Backup::new(&new_conn, &mut conn)?;
-        backup.run_to_completion(5, Duration::from_millis(250), None)
+
+        // The change log is append-only, so it outlives the replaced tasks
+        // and carries on in the imported file
+        conn.execute(
+            "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+                SELECT id, uuid, 'deleted', task_name, '', ?1, ?2 FROM tasks",
+            (Local::now().to_rfc3339(), ChangeOrigin::Import.to_sqlite()),
+        )?;
+        let history: Vec<Change> = conn
+            .prepare(
+                "SELECT id, task_id, task_uuid, field, old_value, new_value, changed_at, origin
+                    FROM changes ORDER BY id ASC",
+            )?
+            .query_map([], change_from_row)?
+            .collect::<Result<_>>()?;
+
+        {
+            let backup = backup::Backup::new(&new_conn, &mut conn)?;
+            backup.run_to_completion(5, Duration::from_millis(250), None)?;
+        }
+
+        // The imported file may be from an older version, and brings its
+        // own history if it has one
+        migrate_with(&conn)?;
+
+        let tx = conn.transaction()?;
+        for change in &history {
+            // A backup of this database already has most of them
+            tx.execute(
+                "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+                    SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7 WHERE NOT EXISTS (SELECT 1 FROM changes
+                        WHERE COALESCE(task_uuid, '') = ?2 AND field = ?3 AND COALESCE(old_value, '') = ?4
+                            AND COALESCE(new_value, '') = ?5 AND changed_at = ?6 AND origin = ?7)",
+                (
+                    change.task_id,
+                    &change.task_uuid,
+                    &change.field,
+                    &change.old_value,
+                    &change.new_value,
+                    &change.changed_at,
+                    change.origin.to_sqlite(),
+                ),
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+                SELECT id, uuid, 'imported', '', task_name, ?1, ?2 FROM tasks",
+            (Local::now().to_rfc3339(), ChangeOrigin::Import.to_sqlite()),
+        )?;
+        tx.commit()?;
+
+        Ok(())
     } else {
         let window = rusttimetrackWindow::default();
         let dialog = gtk::MessageDialog::with_markup(
@@ -469,3 +1077,13 @@ pub fn import_db(new_db: String) -> Result<()> {
         Ok(())
     }
 }
+
+/// Adds the entries of another database that aren't here yet, unlike
+/// `import_db` which replaces this one. With `dry_run`, or if anything
+/// fails, nothing is written.
+pub fn merge_db(other_db: &str, dry_run: bool) -> anyhow::Result<MergeReport> {
+    check_db_validity(other_db.to_string())?;
+    let other_conn = Connection::open_with_flags(other_db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut conn = Connection::open(get_directory())?;
+    merging::merge_into(&mut conn, &other_conn, dry_run)
+}
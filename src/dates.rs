@@ -0,0 +1,159 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Dates written the way the user's locale writes them, for entering and
+//! showing date ranges.
+
+use chrono::{Locale, NaiveDate, TimeZone, Utc};
+use gettextrs::*;
+use std::env;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Part {
+    Year,
+    Month,
+    Day,
+}
+
+/// The order of year, month and day in the locale's short dates, what goes
+/// between them and what comes after, like "年", "月" and "日".
+struct DateOrder {
+    parts: [Part; 3],
+    separators: [String; 2],
+    suffix: String,
+}
+
+impl DateOrder {
+    fn join(&self, part: impl Fn(Part) -> String) -> String {
+        format!(
+            "{}{}{}{}{}{}",
+            part(self.parts[0]),
+            self.separators[0],
+            part(self.parts[1]),
+            self.separators[1],
+            part(self.parts[2]),
+            self.suffix
+        )
+    }
+}
+
+/// The locale for dates, from LC_ALL, LC_TIME or LANG, like "de_DE.UTF-8".
+pub fn user_locale() -> Locale {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|key| env::var(key).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| value.split(|c| c == '.' || c == '@').next().map(|name| name.to_string()))
+        .and_then(|name| Locale::try_from(name.as_str()).ok())
+        .unwrap_or(Locale::en_US)
+}
+
+/// Finds the order by writing February 3rd, 2001 as the locale would.
+fn date_order() -> DateOrder {
+    let sample = NaiveDate::from_ymd_opt(2001, 2, 3)
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|time| Utc.from_utc_datetime(&time).format_localized("%x", user_locale()).to_string())
+        .unwrap_or_default();
+
+    let mut parts: Vec<Part> = Vec::new();
+    for number in sample.split(|c: char| !c.is_ascii_digit()).filter(|number| !number.is_empty()) {
+        match number.trim_start_matches('0') {
+            "2001" | "1" => parts.push(Part::Year),
+            "2" => parts.push(Part::Month),
+            "3" => parts.push(Part::Day),
+            _ => (),
+        }
+    }
+    let between: Vec<&str> = sample
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .split(|c: char| c.is_ascii_digit())
+        .filter(|text| !text.is_empty())
+        .collect();
+
+    match (&parts[..], &between[..]) {
+        ([first, second, third], [separator, separator_2, suffix @ ..])
+            if first != second && second != third && first != third =>
+        {
+            DateOrder {
+                parts: [*first, *second, *third],
+                separators: [separator.to_string(), separator_2.to_string()],
+                suffix: suffix.concat(),
+            }
+        }
+        // Fall back to ISO 8601 for locales without a numeric short date
+        _ => DateOrder {
+            parts: [Part::Year, Part::Month, Part::Day],
+            separators: ["-".to_string(), "-".to_string()],
+            suffix: String::new(),
+        },
+    }
+}
+
+/// Whether the locale writes the day before the month, so 03/04 is April 3rd.
+pub fn day_first() -> bool {
+    let parts = date_order().parts;
+    parts.iter().position(|part| *part == Part::Day) < parts.iter().position(|part| *part == Part::Month)
+}
+
+/// Writes a date in the locale's order, always with four digit years.
+pub fn format_date(day: NaiveDate) -> String {
+    date_order().join(|part| match part {
+        Part::Year => day.format("%Y").to_string(),
+        Part::Month => day.format("%m").to_string(),
+        Part::Day => day.format("%d").to_string(),
+    })
+}
+
+/// How dates should be typed, like "DD.MM.YYYY", for placeholders and errors.
+pub fn date_pattern() -> String {
+    date_order().join(|part| match part {
+        Part::Year => gettext("YYYY"),
+        Part::Month => gettext("MM"),
+        Part::Day => gettext("DD"),
+    })
+}
+
+/// Reads a date in the locale's order, with any separator. Dates starting
+/// with a four digit year are read as year, month, day, and two digit years
+/// are in this century.
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    let numbers: Vec<&str> = text
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|number| !number.is_empty())
+        .collect();
+    if numbers.len() != 3 {
+        return None;
+    }
+    let parts = if numbers[0].len() == 4 {
+        [Part::Year, Part::Month, Part::Day]
+    } else {
+        date_order().parts
+    };
+
+    let (mut year, mut month, mut day) = (0, 0, 0);
+    for (part, number) in parts.iter().zip(numbers) {
+        let value: i32 = number.parse().ok()?;
+        match part {
+            Part::Year => year = value,
+            Part::Month => month = value as u32,
+            Part::Day => day = value as u32,
+        }
+    }
+    if year < 100 {
+        year += 2000;
+    }
+    NaiveDate::from_ymd_opt(year, month, day)
+}
@@ -0,0 +1,607 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Filter expressions shared by reports, history search and exports, like
+//! `tag:client-a AND NOT tag:internal AND name~"review" AND duration>15m`.
+//!
+//! Terms are `field op value`, where field is name, tag, project, duration
+//! or date. Text fields take `:` or `=` (equals), `~` (contains) and `!=`.
+//! Duration and date also take `<`, `<=`, `>` and `>=`. A bare word or
+//! quoted string searches names and `#word` matches a tag. Terms combine
+//! with AND, OR, NOT and parentheses, and AND may be left out.
+
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use gettextrs::*;
+use std::fmt;
+
+use crate::store::{self, Task};
+use crate::reporting::Bucket;
+
+/// Something a filter can be checked against.
+pub trait Filterable {
+    fn name(&self) -> &str;
+    /// Lowercase tags without the leading #
+    fn tag_list(&self) -> Vec<String>;
+    fn project(&self) -> &str;
+    fn seconds(&self) -> i64;
+    /// The local day it started on
+    fn date(&self) -> NaiveDate;
+}
+
+impl Filterable for Task {
+    fn name(&self) -> &str {
+        &self.task_name
+    }
+
+    fn tag_list(&self) -> Vec<String> {
+        store::split_tags(&self.tags)
+    }
+
+    fn project(&self) -> &str {
+        &self.project
+    }
+
+    fn seconds(&self) -> i64 {
+        let start_time = DateTime::parse_from_rfc3339(&self.start_time).unwrap();
+        let stop_time = DateTime::parse_from_rfc3339(&self.stop_time).unwrap();
+        (stop_time - start_time).num_seconds()
+    }
+
+    fn date(&self) -> NaiveDate {
+        let start_time = DateTime::parse_from_rfc3339(&self.start_time).unwrap();
+        start_time.with_timezone(&Local).naive_local().date()
+    }
+}
+
+impl Filterable for Bucket {
+    fn name(&self) -> &str {
+        &self.task_name
+    }
+
+    fn tag_list(&self) -> Vec<String> {
+        store::split_tags(&self.tags)
+    }
+
+    fn project(&self) -> &str {
+        &self.project
+    }
+
+    fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    fn date(&self) -> NaiveDate {
+        self.day
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Name,
+    Tag,
+    Project,
+    Duration,
+    Date,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Is,
+    IsNot,
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Seconds(i64),
+    Date(NaiveDate),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Term(Field, Op, Value),
+}
+
+impl Expr {
+    pub fn matches<T: Filterable>(&self, item: &T) -> bool {
+        match self {
+            Expr::And(left, right) => left.matches(item) && right.matches(item),
+            Expr::Or(left, right) => left.matches(item) || right.matches(item),
+            Expr::Not(inner) => !inner.matches(item),
+            Expr::Term(field, op, value) => match (field, value) {
+                (Field::Name, Value::Text(text)) => compare_text(item.name(), *op, text),
+                (Field::Project, Value::Text(text)) => compare_text(item.project(), *op, text),
+                (Field::Tag, Value::Text(text)) => {
+                    let tags = item.tag_list();
+                    match op {
+                        Op::Contains => tags.iter().any(|tag| tag.contains(text.as_str())),
+                        Op::IsNot => !tags.contains(text),
+                        _ => tags.contains(text),
+                    }
+                }
+                (Field::Duration, Value::Seconds(seconds)) => compare(item.seconds(), *op, *seconds),
+                (Field::Date, Value::Date(date)) => compare(item.date(), *op, *date),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Text terms are already lowercase.
+fn compare_text(actual: &str, op: Op, text: &str) -> bool {
+    let actual = actual.trim().to_lowercase();
+    match op {
+        Op::Contains => actual.contains(text),
+        Op::IsNot => actual != text,
+        _ => actual == text,
+    }
+}
+
+fn compare<T: PartialOrd>(actual: T, op: Op, value: T) -> bool {
+    match op {
+        Op::Is | Op::Contains => actual == value,
+        Op::IsNot => actual != value,
+        Op::Less => actual < value,
+        Op::LessOrEqual => actual <= value,
+        Op::Greater => actual > value,
+        Op::GreaterOrEqual => actual >= value,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    /// Character offset into the expression
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            gettext("{message} (at character {position})")
+                .replace("{message}", &self.message)
+                .replace("{position}", &(self.position + 1).to_string())
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+    };
+    parser.skip_whitespace();
+    if parser.at_end() {
+        return Err(parser.error(gettext("The filter is empty")));
+    }
+
+    let expr = parser.parse_or()?;
+    parser.skip_whitespace();
+    if !parser.at_end() {
+        return Err(parser.error(gettext("Unexpected “)”")));
+    }
+    Ok(expr)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            position: self.pos,
+            message,
+        }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.chars.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes `keyword` if it is next as a whole word, in any case.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.pos + keyword.len();
+        if end > self.chars.len() {
+            return false;
+        }
+        let word: String = self.chars[self.pos..end].iter().collect();
+        let word_ends = self
+            .chars
+            .get(end)
+            .map_or(true, |next| next.is_whitespace() || *next == '(' || *next == ')');
+        if word.eq_ignore_ascii_case(keyword) && word_ends {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            self.skip_whitespace();
+            if self.at_end() || self.peek() == Some(')') {
+                break;
+            }
+            let start = self.pos;
+            if self.eat_keyword("OR") {
+                self.pos = start;
+                break;
+            }
+            // AND is optional between terms
+            self.eat_keyword("AND");
+            let right = self.parse_unary()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        if self.eat_keyword("NOT") {
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.peek() == Some('!') && self.chars.get(self.pos + 1) != Some(&'=') {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error(gettext("Expected a term after this"))),
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                self.skip_whitespace();
+                if self.peek() != Some(')') {
+                    return Err(self.error(gettext("Missing closing “)”")));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(')') => Err(self.error(gettext("Expected a term before “)”"))),
+            Some('#') => {
+                self.pos += 1;
+                let tag = self.parse_value()?;
+                Ok(Expr::Term(Field::Tag, Op::Is, Value::Text(tag.to_lowercase())))
+            }
+            Some('"') => {
+                let text = self.parse_value()?;
+                Ok(Expr::Term(Field::Name, Op::Contains, Value::Text(text.to_lowercase())))
+            }
+            Some(_) => self.parse_term(),
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Expr, ParseError> {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_alphabetic() || c == '_') {
+            self.pos += 1;
+        }
+        let field_name: String = self.chars[start..self.pos].iter().collect();
+
+        let op = match self.parse_op() {
+            Some(op) => op,
+            None => {
+                // Not a field, so search names for the whole word
+                self.pos = start;
+                let word = self.parse_value()?;
+                return Ok(Expr::Term(Field::Name, Op::Contains, Value::Text(word.to_lowercase())));
+            }
+        };
+
+        let field = match field_name.to_lowercase().as_str() {
+            "name" | "task" => Field::Name,
+            "tag" | "tags" => Field::Tag,
+            "project" => Field::Project,
+            "duration" => Field::Duration,
+            "date" => Field::Date,
+            _ => {
+                self.pos = start;
+                return Err(self.error(
+                    gettext("Unknown field “{}”. Use name, tag, project, duration or date.")
+                        .replace("{}", &field_name),
+                ));
+            }
+        };
+
+        let op_position = self.pos;
+        let value_position = {
+            self.skip_whitespace();
+            self.pos
+        };
+        let text = self.parse_value()?;
+
+        let value = match field {
+            Field::Name | Field::Tag | Field::Project => {
+                if !matches!(op, Op::Is | Op::IsNot | Op::Contains) {
+                    self.pos = op_position;
+                    return Err(self.error(
+                        gettext("Text can only be compared with “:”, “~” or “!=”"),
+                    ));
+                }
+                let text = text.to_lowercase();
+                match field {
+                    Field::Tag => Value::Text(text.trim_start_matches('#').to_string()),
+                    _ => Value::Text(text),
+                }
+            }
+            Field::Duration => match parse_duration(&text) {
+                Some(seconds) if op != Op::Contains => Value::Seconds(seconds),
+                Some(_) => {
+                    self.pos = op_position;
+                    return Err(self.error(gettext("Durations can't use “~”")));
+                }
+                None => {
+                    self.pos = value_position;
+                    return Err(self.error(
+                        gettext("“{}” is not a duration. Try 15m, 1h30m or 1:30.")
+                            .replace("{}", &text),
+                    ));
+                }
+            },
+            Field::Date => match parse_date(&text) {
+                Some(date) if op != Op::Contains => Value::Date(date),
+                Some(_) => {
+                    self.pos = op_position;
+                    return Err(self.error(gettext("Dates can't use “~”")));
+                }
+                None => {
+                    self.pos = value_position;
+                    return Err(self.error(
+                        gettext("“{}” is not a date. Use YYYY-MM-DD, today or yesterday.")
+                            .replace("{}", &text),
+                    ));
+                }
+            },
+        };
+
+        Ok(Expr::Term(field, op, value))
+    }
+
+    fn parse_op(&mut self) -> Option<Op> {
+        let next = self.chars.get(self.pos + 1).copied();
+        let (op, len) = match (self.peek()?, next) {
+            ('>', Some('=')) => (Op::GreaterOrEqual, 2),
+            ('<', Some('=')) => (Op::LessOrEqual, 2),
+            ('!', Some('=')) => (Op::IsNot, 2),
+            ('>', _) => (Op::Greater, 1),
+            ('<', _) => (Op::Less, 1),
+            (':', _) | ('=', _) => (Op::Is, 1),
+            ('~', _) => (Op::Contains, 1),
+            _ => return None,
+        };
+        self.pos += len;
+        Some(op)
+    }
+
+    /// A quoted string, or a bare word up to whitespace or a parenthesis.
+    fn parse_value(&mut self) -> Result<String, ParseError> {
+        if self.peek() == Some('"') {
+            let start = self.pos;
+            self.pos += 1;
+            let mut text = String::new();
+            loop {
+                match self.peek() {
+                    None => {
+                        self.pos = start;
+                        return Err(self.error(gettext("Missing closing quote")));
+                    }
+                    Some('"') => {
+                        self.pos += 1;
+                        return Ok(text);
+                    }
+                    Some('\\') if self.chars.get(self.pos + 1) == Some(&'"') => {
+                        text.push('"');
+                        self.pos += 2;
+                    }
+                    Some(c) => {
+                        text.push(c);
+                        self.pos += 1;
+                    }
+                }
+            }
+        }
+
+        let start = self.pos;
+        while self
+            .peek()
+            .map_or(false, |c| !c.is_whitespace() && c != '(' && c != ')' && c != '"')
+        {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error(gettext("Expected a value")));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+}
+
+/// Reads durations like `15m`, `1h30m`, `1.5h`, `90s`, `1:30` or a plain
+/// number of minutes.
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let text = text.trim().to_lowercase();
+    if let Some((hours, minutes)) = text.split_once(':') {
+        let hours: i64 = hours.parse().ok()?;
+        let minutes: i64 = minutes.parse().ok()?;
+        return Some(hours * 3600 + minutes * 60);
+    }
+    if let Ok(minutes) = text.parse::<f64>() {
+        return Some((minutes * 60.0).round() as i64);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        total += amount
+            * match c {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return None,
+            };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(total.round() as i64)
+}
+
+fn parse_date(text: &str) -> Option<NaiveDate> {
+    let today = Local::now().naive_local().date();
+    match text.to_lowercase().as_str() {
+        "today" => Some(today),
+        "yesterday" => Some(today - Duration::days(1)),
+        _ => NaiveDate::parse_from_str(text, "%Y-%m-%d").ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(field: Field, op: Op, value: &str) -> Expr {
+        Expr::Term(field, op, Value::Text(value.to_string()))
+    }
+
+    fn and(left: Expr, right: Expr) -> Expr {
+        Expr::And(Box::new(left), Box::new(right))
+    }
+
+    fn or(left: Expr, right: Expr) -> Expr {
+        Expr::Or(Box::new(left), Box::new(right))
+    }
+
+    fn not(inner: Expr) -> Expr {
+        Expr::Not(Box::new(inner))
+    }
+
+    fn error_at(input: &str) -> usize {
+        parse(input).unwrap_err().position
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            parse("tag:a OR tag:b AND project:x").unwrap(),
+            or(text(Field::Tag, Op::Is, "a"), and(text(Field::Tag, Op::Is, "b"), text(Field::Project, Op::Is, "x")))
+        );
+        assert_eq!(
+            parse("(tag:a or tag:b) and project:x").unwrap(),
+            and(or(text(Field::Tag, Op::Is, "a"), text(Field::Tag, Op::Is, "b")), text(Field::Project, Op::Is, "x"))
+        );
+    }
+
+    #[test]
+    fn and_may_be_left_out() {
+        assert_eq!(
+            parse("#Client review").unwrap(),
+            and(text(Field::Tag, Op::Is, "client"), text(Field::Name, Op::Contains, "review"))
+        );
+    }
+
+    #[test]
+    fn not_and_bang_negate() {
+        let internal = text(Field::Tag, Op::Is, "internal");
+        assert_eq!(parse("NOT tag:internal").unwrap(), not(internal.clone()));
+        assert_eq!(parse("!tag:internal").unwrap(), not(internal));
+        assert_eq!(parse("tag!=internal").unwrap(), text(Field::Tag, Op::IsNot, "internal"));
+        // A word starting with "not" is no keyword
+        assert_eq!(parse("notes").unwrap(), text(Field::Name, Op::Contains, "notes"));
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(parse(r#"name~"Code Review""#).unwrap(), text(Field::Name, Op::Contains, "code review"));
+        assert_eq!(parse(r#""say \"hi\"""#).unwrap(), text(Field::Name, Op::Contains, "say \"hi\""));
+    }
+
+    #[test]
+    fn durations_and_dates() {
+        assert_eq!(parse_duration("1h30m"), Some(5400));
+        assert_eq!(parse_duration("1.5h"), Some(5400));
+        assert_eq!(parse_duration("1:30"), Some(5400));
+        assert_eq!(parse_duration("90"), Some(5400));
+        assert_eq!(parse_duration("45s"), Some(45));
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("2d"), None);
+        assert_eq!(
+            parse("duration>=15m").unwrap(),
+            Expr::Term(Field::Duration, Op::GreaterOrEqual, Value::Seconds(900))
+        );
+        assert_eq!(
+            parse("date<2023-03-06").unwrap(),
+            Expr::Term(Field::Date, Op::Less, Value::Date(NaiveDate::from_ymd_opt(2023, 3, 6).unwrap()))
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error_at("   "), 3);
+        assert_eq!(error_at("colour:red"), 0);
+        assert_eq!(error_at("tag:a AND duration>soon"), 19);
+        assert_eq!(error_at("date~2023-03-06"), 5);
+        assert_eq!(error_at("name<abc"), 5);
+        assert_eq!(error_at("tag:a AND \"open"), 10);
+        assert_eq!(error_at("(tag:a"), 6);
+        assert_eq!(error_at("tag:a)"), 5);
+        assert_eq!(error_at("tag:a OR"), 8);
+    }
+}
@@ -0,0 +1,159 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Daily and weekly time goals, and the over- or undertime carried from one
+//! week to the next. Like `reporting`, this stays free of GTK.
+
+use chrono::{Duration, NaiveDate, Weekday};
+use gettextrs::*;
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+use crate::reporting::{self, DateRange};
+
+/// Goals in seconds. A goal of zero is switched off.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Goals {
+    pub daily: i64,
+    pub weekly: i64,
+    pub week_starts_on: Weekday,
+    /// The first day counted in the balance, set when the weekly goal is
+    /// switched on. Without it there is no balance yet.
+    pub balance_since: Option<NaiveDate>,
+}
+
+impl Goals {
+    pub fn is_set(&self) -> bool {
+        self.daily > 0 || self.weekly > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub seconds: i64,
+    pub goal: i64,
+}
+
+impl Progress {
+    pub fn new(seconds: i64, goal: i64) -> Self {
+        Progress { seconds, goal }
+    }
+
+    /// How far along the goal is, from 0.0 to 1.0.
+    pub fn fraction(&self) -> f64 {
+        if self.goal <= 0 {
+            0.0
+        } else {
+            (self.seconds as f64 / self.goal as f64).clamp(0.0, 1.0)
+        }
+    }
+
+    pub fn is_reached(&self) -> bool {
+        self.goal > 0 && self.seconds >= self.goal
+    }
+
+    /// Like "6:30 of 8:00".
+    pub fn text(&self) -> String {
+        gettext("{done} of {goal}")
+            .replace("{done}", &format_hours(self.seconds))
+            .replace("{goal}", &format_hours(self.goal))
+    }
+
+    /// Like "1:30 left" or "0:20 over".
+    pub fn remaining_text(&self) -> String {
+        if self.seconds >= self.goal {
+            gettext("{} over").replace("{}", &format_hours(self.seconds - self.goal))
+        } else {
+            gettext("{} left").replace("{}", &format_hours(self.goal - self.seconds))
+        }
+    }
+}
+
+/// Progress for today and this week, and the balance of earlier weeks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GoalStatus {
+    pub today: NaiveDate,
+    pub day: Progress,
+    pub week: Progress,
+    /// Time over the weekly goal in the finished weeks, negative if under
+    pub balance: i64,
+}
+
+impl GoalStatus {
+    /// The status with a running timer's time added to today and this week.
+    pub fn with_running(&self, seconds: i64) -> Self {
+        GoalStatus {
+            day: Progress::new(self.day.seconds + seconds, self.day.goal),
+            week: Progress::new(self.week.seconds + seconds, self.week.goal),
+            ..*self
+        }
+    }
+}
+
+pub fn status(conn: &Connection, goals: &Goals, today: NaiveDate) -> anyhow::Result<GoalStatus> {
+    let week = DateRange::week_of(today, goals.week_starts_on);
+
+    // The balance only counts finished weeks, from the week it starts in,
+    // so only those are loaded
+    let first_week = goals
+        .balance_since
+        .map(|day| reporting::week_start(day, goals.week_starts_on))
+        .filter(|start| *start < week.start && goals.weekly > 0)
+        .unwrap_or(week.start);
+
+    let totals = day_totals(conn, &DateRange::new(first_week, week.end))?;
+    let day_seconds = totals.get(&today).copied().unwrap_or(0);
+    let week_seconds: i64 = week.days().iter().filter_map(|day| totals.get(day)).sum();
+
+    let mut balance = 0;
+    let mut start = first_week;
+    while start < week.start {
+        let finished = DateRange::new(start, start + Duration::days(6));
+        let worked: i64 = finished.days().iter().filter_map(|day| totals.get(day)).sum();
+        balance += worked - goals.weekly;
+        start += Duration::days(7);
+    }
+
+    Ok(GoalStatus {
+        today,
+        day: Progress::new(day_seconds, goals.daily),
+        week: Progress::new(week_seconds, goals.weekly),
+        balance,
+    })
+}
+
+/// Time tracked per local day.
+pub fn day_totals(conn: &Connection, range: &DateRange) -> anyhow::Result<HashMap<NaiveDate, i64>> {
+    let mut totals: HashMap<NaiveDate, i64> = HashMap::new();
+    for bucket in reporting::load_buckets(conn, range)? {
+        *totals.entry(bucket.day).or_insert(0) += bucket.seconds;
+    }
+    Ok(totals)
+}
+
+/// Hours and minutes, like "7:30".
+pub fn format_hours(seconds: i64) -> String {
+    format!("{}:{:02}", seconds / 3600, seconds % 3600 / 60)
+}
+
+/// Hours and minutes with a sign, like "+2:15" or "−0:45".
+pub fn format_balance(seconds: i64) -> String {
+    if seconds < 0 {
+        format!("−{}", format_hours(-seconds))
+    } else {
+        format!("+{}", format_hours(seconds))
+    }
+}
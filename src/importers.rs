@@ -0,0 +1,974 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Reading entries from our own CSV export or from other time trackers, and
+//! turning them into tasks. Like `reporting`, this stays free of GTK.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use gettextrs::*;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::store::{self, Task};
+use crate::dates;
+use crate::goals;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// What `database::export_as_csv` writes
+    Csv,
+    Toggl,
+    Clockify,
+    Watson,
+    Timewarrior,
+    OrgMode,
+}
+
+pub const IMPORT_FORMATS: [ImportFormat; 6] = [
+    ImportFormat::Csv,
+    ImportFormat::Toggl,
+    ImportFormat::Clockify,
+    ImportFormat::Watson,
+    ImportFormat::Timewarrior,
+    ImportFormat::OrgMode,
+];
+
+impl ImportFormat {
+    pub fn title(&self) -> String {
+        match self {
+            ImportFormat::Csv => gettext("rusttimetrack CSV"),
+            ImportFormat::Toggl => gettext("Toggl Track CSV"),
+            ImportFormat::Clockify => gettext("Clockify CSV"),
+            ImportFormat::Watson => gettext("Watson Frames"),
+            ImportFormat::Timewarrior => gettext("Timewarrior Data"),
+            ImportFormat::OrgMode => gettext("Org Mode Clocks"),
+        }
+    }
+
+    /// Guesses the format from the file name and its first lines.
+    pub fn detect(path: &Path, text: &str) -> Option<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let first_line = text.trim_start_matches('\u{feff}').lines().next().unwrap_or("");
+
+        if first_line.contains("task_name") && first_line.contains("start_time") {
+            Some(ImportFormat::Csv)
+        } else if extension == "org" || text.lines().any(|line| line.trim_start().starts_with("CLOCK:")) {
+            Some(ImportFormat::OrgMode)
+        } else if extension == "json" || file_name == "frames" || text.trim_start().starts_with('[') {
+            Some(ImportFormat::Watson)
+        } else if extension == "data" || text.lines().any(|line| line.starts_with("inc ")) {
+            Some(ImportFormat::Timewarrior)
+        } else if first_line.contains("Start Date") || first_line.contains("Duration (h)") {
+            // Toggl writes "Start date", Clockify "Start Date"
+            Some(ImportFormat::Clockify)
+        } else if first_line.to_lowercase().contains("start date") {
+            Some(ImportFormat::Toggl)
+        } else {
+            None
+        }
+    }
+}
+
+/// One entry as the other tracker stored it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedEntry {
+    pub description: String,
+    pub project: String,
+    pub tags: Vec<String>,
+    pub notes: String,
+    pub start: DateTime<Local>,
+    pub stop: DateTime<Local>,
+}
+
+/// The entries read from a file, and why any lines were left out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Parsed {
+    pub entries: Vec<ImportedEntry>,
+    pub skipped: Vec<String>,
+}
+
+impl Parsed {
+    fn skip(&mut self, line: usize, reason: &str) {
+        self.skipped.push(
+            gettext("Line {line}: {reason}")
+                .replace("{line}", &line.to_string())
+                .replace("{reason}", reason),
+        );
+    }
+}
+
+/// How our own CSV is laid out, since it may have been edited in a
+/// spreadsheet that saved it differently.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub task_name: String,
+    pub start_time: String,
+    pub stop_time: String,
+    pub tags: String,
+    pub project: String,
+    /// Only used for a missing stop time, and otherwise checked
+    pub seconds: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            task_name: "task_name".to_string(),
+            start_time: "start_time".to_string(),
+            stop_time: "stop_time".to_string(),
+            tags: "tags".to_string(),
+            project: "project".to_string(),
+            seconds: "seconds".to_string(),
+        }
+    }
+}
+
+pub const CSV_DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+pub fn delimiter_title(delimiter: u8) -> String {
+    match delimiter {
+        b',' => gettext("Comma"),
+        b';' => gettext("Semicolon"),
+        b'\t' => gettext("Tab"),
+        b'|' => gettext("Vertical Bar"),
+        _ => (delimiter as char).to_string(),
+    }
+}
+
+/// The delimiter used most in the header line.
+pub fn detect_delimiter(text: &str) -> u8 {
+    let header = text.trim_start_matches('\u{feff}').lines().next().unwrap_or("");
+    CSV_DELIMITERS
+        .iter()
+        .copied()
+        .max_by_key(|delimiter| header.bytes().filter(|byte| byte == delimiter).count())
+        .unwrap_or(b',')
+}
+
+pub fn parse(format: ImportFormat, text: &str, options: &CsvOptions) -> anyhow::Result<Parsed> {
+    let text = text.trim_start_matches('\u{feff}');
+    match format {
+        ImportFormat::Csv => parse_own_csv(text, options),
+        ImportFormat::Toggl | ImportFormat::Clockify => parse_csv(text, dates::day_first()),
+        ImportFormat::Watson => parse_watson(text),
+        ImportFormat::Timewarrior => Ok(parse_timewarrior(text)),
+        ImportFormat::OrgMode => Ok(parse_org(text)),
+    }
+}
+
+/// Reads rows like `export_as_csv` writes them, checking each one. Unlike
+/// `import_db`, this adds to the history instead of replacing it.
+fn parse_own_csv(text: &str, options: &CsvOptions) -> anyhow::Result<Parsed> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let columns: HashMap<String, usize> = reader
+        .headers()?
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.trim().to_lowercase(), i))
+        .collect();
+    let column = |name: &str| columns.get(&name.trim().to_lowercase()).copied();
+    for required in [&options.task_name, &options.start_time] {
+        if column(required).is_none() {
+            anyhow::bail!(gettext("The file has no \"{}\" column.").replace("{}", required));
+        }
+    }
+    if column(&options.stop_time).is_none() && column(&options.seconds).is_none() {
+        anyhow::bail!(gettext("The file has no \"{}\" column.").replace("{}", &options.stop_time));
+    }
+
+    let mut parsed = Parsed::default();
+    let mut record = csv::StringRecord::new();
+    loop {
+        let line = reader.position().line() as usize;
+        match reader.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                parsed.skip(line, &e.to_string());
+                continue;
+            }
+        }
+        let field = |name: &str| {
+            column(name)
+                .and_then(|i| record.get(i))
+                .unwrap_or("")
+                .trim()
+                .to_string()
+        };
+
+        let task_name = field(&options.task_name);
+        if task_name.is_empty() {
+            parsed.skip(line, &gettext("the task name is empty"));
+            continue;
+        }
+        let start = match own_csv_time(&field(&options.start_time)) {
+            Some(start) => start,
+            None => {
+                parsed.skip(
+                    line,
+                    &gettext("\"{}\" is not a start time").replace("{}", &field(&options.start_time)),
+                );
+                continue;
+            }
+        };
+        let seconds = field(&options.seconds);
+        let seconds = if seconds.is_empty() {
+            None
+        } else {
+            match seconds.parse::<i64>() {
+                Ok(seconds) => Some(seconds),
+                Err(_) => {
+                    parsed.skip(line, &gettext("\"{}\" is not a number of seconds").replace("{}", &seconds));
+                    continue;
+                }
+            }
+        };
+        let stop = match (field(&options.stop_time), seconds) {
+            (stop, Some(seconds)) if stop.is_empty() => start + chrono::Duration::seconds(seconds),
+            (stop, _) => match own_csv_time(&stop) {
+                Some(stop) => stop,
+                None => {
+                    parsed.skip(line, &gettext("\"{}\" is not a stop time").replace("{}", &stop));
+                    continue;
+                }
+            },
+        };
+        if stop <= start {
+            parsed.skip(line, &gettext("the stop time is not after the start time"));
+            continue;
+        }
+        if seconds.map_or(false, |seconds| (seconds - (stop - start).num_seconds()).abs() > 1) {
+            parsed.skip(line, &gettext("the seconds don't match the start and stop time"));
+            continue;
+        }
+
+        parsed.entries.push(ImportedEntry {
+            description: task_name,
+            project: field(&options.project),
+            tags: store::split_tags(&field(&options.tags)),
+            notes: String::new(),
+            start,
+            stop,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Reads the RFC 3339 times the export writes, or local times like
+/// "2024-03-04 09:00" that a spreadsheet may have turned them into.
+fn own_csv_time(text: &str) -> Option<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Local));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+}
+
+/// Toggl and Clockify both name their columns "Description", "Project",
+/// "Tags", "Start Date" and so on, with only the case and the date format
+/// told apart. Dates with slashes are read day first when `day_first` is set.
+fn parse_csv(text: &str, day_first: bool) -> anyhow::Result<Parsed> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(text.as_bytes());
+    let columns: HashMap<String, usize> = reader
+        .headers()?
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.trim().to_lowercase(), i))
+        .collect();
+    for required in ["start date", "start time", "end date", "end time"] {
+        if !columns.contains_key(required) {
+            anyhow::bail!(gettext("The file has no \"{}\" column.").replace("{}", required));
+        }
+    }
+
+    let mut parsed = Parsed::default();
+    for (i, record) in reader.records().enumerate() {
+        // The header is line 1
+        let line = i + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                parsed.skip(line, &e.to_string());
+                continue;
+            }
+        };
+        let field = |name: &str| {
+            columns
+                .get(name)
+                .and_then(|i| record.get(*i))
+                .unwrap_or("")
+                .trim()
+                .to_string()
+        };
+
+        let start = csv_time(&field("start date"), &field("start time"), day_first);
+        let stop = csv_time(&field("end date"), &field("end time"), day_first);
+        let (start, stop) = match (start, stop) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => {
+                parsed.skip(line, &gettext("unreadable start or end time"));
+                continue;
+            }
+        };
+
+        // Both trackers also have tasks below projects, kept here as notes
+        let mut description = field("description");
+        let mut notes = field("task");
+        if description.is_empty() {
+            description = std::mem::take(&mut notes);
+        }
+        parsed.entries.push(ImportedEntry {
+            description,
+            project: field("project"),
+            tags: field("tags").split(',').map(|tag| tag.trim().to_string()).collect(),
+            notes,
+            start,
+            stop,
+        });
+    }
+    Ok(parsed)
+}
+
+fn csv_time(date: &str, time: &str, day_first: bool) -> Option<DateTime<Local>> {
+    let slashed = if day_first { "%d/%m/%Y" } else { "%m/%d/%Y" };
+    let date = ["%Y-%m-%d", slashed, "%d.%m.%Y"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date, format).ok())?;
+    let time = ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(time, format).ok())?;
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
+
+/// Watson keeps every frame as [start, stop, project, id, tags, updated_at],
+/// with Unix times. Frames have no description, so the project names the task.
+fn parse_watson(text: &str) -> anyhow::Result<Parsed> {
+    let frames: Vec<serde_json::Value> = serde_json::from_str(text)?;
+
+    let mut parsed = Parsed::default();
+    for (i, frame) in frames.iter().enumerate() {
+        let line = i + 1;
+        let time = |index: usize| {
+            frame
+                .get(index)
+                .and_then(|value| value.as_i64())
+                .and_then(|secs| Local.timestamp_opt(secs, 0).single())
+        };
+        let (start, stop) = match (time(0), time(1)) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => {
+                parsed.skip(line, &gettext("unreadable start or end time"));
+                continue;
+            }
+        };
+        let field = |index: usize| frame.get(index).and_then(|value| value.as_str()).unwrap_or("").to_string();
+
+        parsed.entries.push(ImportedEntry {
+            description: field(2),
+            project: String::new(),
+            tags: frame
+                .get(4)
+                .and_then(|tags| tags.as_array())
+                .map(|tags| tags.iter().filter_map(|tag| tag.as_str()).map(String::from).collect())
+                .unwrap_or_default(),
+            notes: field(6),
+            start,
+            stop,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Timewarrior writes one interval per line, like
+/// `inc 20240304T080000Z - 20240304T093000Z # coding "big project" # notes`.
+/// Intervals only have tags, so the first tag names the task.
+fn parse_timewarrior(text: &str) -> Parsed {
+    let utc = |value: &str| {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%SZ")
+            .ok()
+            .map(|time| Utc.from_utc_datetime(&time).with_timezone(&Local))
+    };
+
+    let mut parsed = Parsed::default();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let rest = match line.trim().strip_prefix("inc ") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let mut parts = rest.splitn(3, " # ");
+        let times = parts.next().unwrap_or("").trim();
+        let mut tags = split_words(parts.next().unwrap_or(""));
+        let notes = split_words(parts.next().unwrap_or("")).join(" ");
+
+        let (start, stop) = match times.split_once(" - ") {
+            Some((start, stop)) => (utc(start.trim()), utc(stop.trim())),
+            None => {
+                parsed.skip(line_number, &gettext("the interval is still open"));
+                continue;
+            }
+        };
+        let (start, stop) = match (start, stop) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => {
+                parsed.skip(line_number, &gettext("unreadable start or end time"));
+                continue;
+            }
+        };
+        if tags.is_empty() {
+            parsed.skip(line_number, &gettext("no tag to name the task"));
+            continue;
+        }
+
+        parsed.entries.push(ImportedEntry {
+            description: tags.remove(0),
+            project: String::new(),
+            tags,
+            notes,
+            start,
+            stop,
+        });
+    }
+    parsed
+}
+
+/// Splits `tag "two words" "with \"quotes\""` into its words.
+fn split_words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut chars = text.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut word = String::new();
+        if c == '"' {
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => word.extend(chars.next()),
+                    '"' => break,
+                    _ => word.push(c),
+                }
+            }
+        } else {
+            word.push(c);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                word.push(c);
+            }
+        }
+        words.push(word);
+    }
+    words
+}
+
+/// Org mode keeps `CLOCK: [2024-03-04 Mon 09:00]--[2024-03-04 Mon 10:30]`
+/// lines under the heading they were clocked on. The heading names the task,
+/// its top-level heading the project, and tags are inherited as in Org.
+fn parse_org(text: &str) -> Parsed {
+    const KEYWORDS: [&str; 8] = ["TODO", "DONE", "NEXT", "STARTED", "WAITING", "HOLD", "CANCELLED", "CANCELED"];
+
+    // Each open heading, as (level, title, tags)
+    let mut headings: Vec<(usize, String, Vec<String>)> = Vec::new();
+    let mut parsed = Parsed::default();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let level = line.chars().take_while(|c| *c == '*').count();
+        if level > 0 && line[level..].starts_with(' ') {
+            let mut title = line[level..].trim();
+            let mut tags: Vec<String> = Vec::new();
+            if let Some((start, end)) = title.rsplit_once(char::is_whitespace) {
+                if end.len() > 1 && end.starts_with(':') && end.ends_with(':') {
+                    tags = end.split(':').filter(|tag| !tag.is_empty()).map(String::from).collect();
+                    title = start.trim_end();
+                }
+            }
+            if let Some((keyword, rest)) = title.split_once(' ') {
+                if KEYWORDS.contains(&keyword) {
+                    title = rest.trim_start();
+                }
+            }
+            if title.starts_with("[#") && title.get(3..5) == Some("] ") {
+                title = title[5..].trim_start();
+            }
+
+            headings.retain(|(open, _, _)| *open < level);
+            headings.push((level, title.to_string(), tags));
+            continue;
+        }
+
+        let clock = match line.trim().strip_prefix("CLOCK:") {
+            Some(clock) => clock.trim(),
+            None => continue,
+        };
+        let (start, stop) = match clock.split_once("--") {
+            Some((start, stop)) => (org_time(start), org_time(stop.split("=>").next().unwrap_or(""))),
+            None => {
+                parsed.skip(line_number, &gettext("the clock is still running"));
+                continue;
+            }
+        };
+        let (start, stop) = match (start, stop) {
+            (Some(start), Some(stop)) => (start, stop),
+            _ => {
+                parsed.skip(line_number, &gettext("unreadable start or end time"));
+                continue;
+            }
+        };
+        let (description, project) = match (headings.last(), headings.first()) {
+            (Some((_, title, _)), Some((level, top, _))) => {
+                let project = if headings.len() > 1 && *level == 1 { top.clone() } else { String::new() };
+                (title.clone(), project)
+            }
+            _ => {
+                parsed.skip(line_number, &gettext("the clock is not under a heading"));
+                continue;
+            }
+        };
+
+        parsed.entries.push(ImportedEntry {
+            description,
+            project,
+            tags: headings.iter().flat_map(|(_, _, tags)| tags.clone()).collect(),
+            notes: String::new(),
+            start,
+            stop,
+        });
+    }
+    parsed
+}
+
+/// Reads "[2024-03-04 Mon 09:00]", ignoring the day name.
+fn org_time(text: &str) -> Option<DateTime<Local>> {
+    let text = text.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let date = NaiveDate::parse_from_str(text.split_whitespace().next()?, "%Y-%m-%d").ok()?;
+    let time = NaiveTime::parse_from_str(text.split_whitespace().last()?, "%H:%M").ok()?;
+    Local.from_local_datetime(&date.and_time(time)).earliest()
+}
+
+/// Where a field of the other tracker ends up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapTo {
+    Project,
+    Tags,
+    TaskName,
+    Ignore,
+}
+
+pub const MAP_TARGETS: [MapTo; 4] = [MapTo::Project, MapTo::Tags, MapTo::TaskName, MapTo::Ignore];
+
+impl MapTo {
+    pub fn title(&self) -> String {
+        match self {
+            MapTo::Project => gettext("Project"),
+            MapTo::Tags => gettext("Tags"),
+            MapTo::TaskName => gettext("Task Name"),
+            MapTo::Ignore => gettext("Ignore"),
+        }
+    }
+}
+
+/// Where the project, tags and notes go. The description always names the
+/// task.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldMapping {
+    pub project: MapTo,
+    pub tags: MapTo,
+    pub notes: MapTo,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            project: MapTo::Project,
+            tags: MapTo::Tags,
+            notes: MapTo::Ignore,
+        }
+    }
+}
+
+/// A task ready for the tasks table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewTask {
+    pub task_name: String,
+    /// Stored like the tasks table does, "tag #tag 2"
+    pub tags: String,
+    pub project: String,
+    pub start: DateTime<Local>,
+    pub stop: DateTime<Local>,
+}
+
+impl NewTask {
+    pub fn seconds(&self) -> i64 {
+        (self.stop - self.start).num_seconds()
+    }
+
+    /// The same task name and times, to the second.
+    fn key(&self) -> (String, i64, i64) {
+        (self.task_name.trim().to_lowercase(), self.start.timestamp(), self.stop.timestamp())
+    }
+}
+
+impl FieldMapping {
+    pub fn apply(&self, entry: &ImportedEntry) -> NewTask {
+        let mut names: Vec<String> = vec![entry.description.trim().to_string()];
+        let mut projects: Vec<String> = Vec::new();
+        let mut tags: Vec<String> = Vec::new();
+
+        let fields = [
+            (self.project, vec![entry.project.clone()]),
+            (self.tags, entry.tags.clone()),
+            (self.notes, vec![entry.notes.clone()]),
+        ];
+        for (map_to, values) in fields {
+            let values = values.iter().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+            match map_to {
+                MapTo::Project => projects.extend(values),
+                MapTo::Tags => tags.extend(values.map(|tag| tag.replace('#', ""))),
+                MapTo::TaskName => names.extend(values),
+                MapTo::Ignore => {}
+            }
+        }
+        names.retain(|name| !name.is_empty());
+
+        NewTask {
+            task_name: names.join(" - "),
+            tags: store::split_tags(&tags.join(" #")).join(" #"),
+            project: projects.join(" / "),
+            start: entry.start,
+            stop: entry.stop,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportRow {
+    pub task: NewTask,
+    /// Already tracked, or earlier in the same file
+    pub duplicate: bool,
+}
+
+/// What an import would do, worked out without writing anything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportPlan {
+    pub rows: Vec<ImportRow>,
+    pub skipped: Vec<String>,
+}
+
+impl ImportPlan {
+    pub fn new_tasks(&self, skip_duplicates: bool) -> Vec<NewTask> {
+        self.rows
+            .iter()
+            .filter(|row| !(skip_duplicates && row.duplicate))
+            .map(|row| row.task.clone())
+            .collect()
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.rows.iter().filter(|row| row.duplicate).count()
+    }
+
+    /// Like "12 entries to import, 14:30 in total. 3 duplicates skipped.
+    /// 1 line can't be read."
+    pub fn summary(&self, skip_duplicates: bool) -> String {
+        let tasks = self.new_tasks(skip_duplicates);
+        let seconds: i64 = tasks.iter().map(|task| task.seconds()).sum();
+        let mut lines: Vec<String> = vec![ngettext(
+            "{count} entry to import, {time} in total.",
+            "{count} entries to import, {time} in total.",
+            tasks.len() as u32,
+        )
+        .replace("{count}", &tasks.len().to_string())
+        .replace("{time}", &goals::format_hours(seconds))];
+
+        let duplicates = self.duplicates();
+        if duplicates > 0 {
+            let text = if skip_duplicates {
+                ngettext("{} duplicate skipped.", "{} duplicates skipped.", duplicates as u32)
+            } else {
+                ngettext("{} duplicate included.", "{} duplicates included.", duplicates as u32)
+            };
+            lines.push(text.replace("{}", &duplicates.to_string()));
+        }
+        if !self.skipped.is_empty() {
+            lines.push(
+                ngettext("{} line can't be read.", "{} lines can't be read.", self.skipped.len() as u32)
+                    .replace("{}", &self.skipped.len().to_string()),
+            );
+        }
+        lines.join(" ")
+    }
+}
+
+/// Maps every entry, and marks the ones already among `existing` or earlier
+/// in the file.
+pub fn plan(parsed: &Parsed, mapping: FieldMapping, existing: &[Task]) -> ImportPlan {
+    let mut seen: HashSet<(String, i64, i64)> = existing
+        .iter()
+        .filter_map(|task| {
+            let start = DateTime::parse_from_rfc3339(&task.start_time).ok()?;
+            let stop = DateTime::parse_from_rfc3339(&task.stop_time).ok()?;
+            Some((task.task_name.trim().to_lowercase(), start.timestamp(), stop.timestamp()))
+        })
+        .collect();
+
+    let mut plan = ImportPlan {
+        rows: Vec::new(),
+        skipped: parsed.skipped.clone(),
+    };
+    for entry in &parsed.entries {
+        let task = mapping.apply(entry);
+        if task.task_name.is_empty() {
+            plan.skipped.push(gettext("An entry from {} has no task name.").replace(
+                "{}",
+                &entry.start.format("%Y-%m-%d %H:%M").to_string(),
+            ));
+            continue;
+        }
+        if task.stop <= task.start {
+            plan.skipped.push(gettext("An entry from {} ends before it starts.").replace(
+                "{}",
+                &entry.start.format("%Y-%m-%d %H:%M").to_string(),
+            ));
+            continue;
+        }
+        let duplicate = !seen.insert(task.key());
+        plan.rows.push(ImportRow { task, duplicate });
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporting::{Rounding, RoundingMode, RoundingScope};
+
+    fn local(text: &str) -> DateTime<Local> {
+        let time = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap();
+        Local.from_local_datetime(&time).unwrap()
+    }
+
+    fn existing(task_name: &str, start: &str, stop: &str) -> Task {
+        Task {
+            id: 1,
+            task_name: task_name.to_string(),
+            start_time: local(start).to_rfc3339(),
+            stop_time: local(stop).to_rfc3339(),
+            tags: String::new(),
+            project: String::new(),
+            billable: None,
+            uuid: String::new(),
+            created_at: String::new(),
+            updated_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn own_csv_round_trip() {
+        let mut header = existing("Header, \"v2\"", "2024-03-04 09:00", "2024-03-04 10:30");
+        header.tags = "design #web".to_string();
+        let footer = existing("Footer", "2024-03-04 11:00", "2024-03-04 11:10");
+        let rounding = Rounding {
+            increment: 15 * 60,
+            mode: RoundingMode::Up,
+            scope: RoundingScope::Entry,
+        };
+
+        for delimiter in CSV_DELIMITERS {
+            let text = store::tasks_to_csv(vec![header.clone(), footer.clone()], delimiter, Some(rounding)).unwrap();
+            let options = CsvOptions {
+                delimiter: detect_delimiter(&text),
+                ..CsvOptions::default()
+            };
+            let parsed = parse(ImportFormat::Csv, &text, &options).unwrap();
+            assert!(parsed.skipped.is_empty(), "{:?}", parsed.skipped);
+            let entries: Vec<(&str, Vec<String>)> = parsed
+                .entries
+                .iter()
+                .map(|entry| (entry.description.as_str(), entry.tags.clone()))
+                .collect();
+            assert_eq!(
+                entries,
+                [("Header, \"v2\"", vec!["design".to_string(), "web".to_string()]), ("Footer", vec![])]
+            );
+            let times: Vec<(DateTime<Local>, DateTime<Local>)> =
+                parsed.entries.iter().map(|entry| (entry.start, entry.stop)).collect();
+            assert_eq!(
+                times,
+                [
+                    (local("2024-03-04 09:00"), local("2024-03-04 10:30")),
+                    (local("2024-03-04 11:00"), local("2024-03-04 11:10")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn own_csv_bad_rows_name_their_line() {
+        let text = "task_name,start_time,stop_time,seconds\n\
+                    Header,2024-03-04T09:00:00+00:00,2024-03-04T10:00:00+00:00,3600\n\
+                    Footer,yesterday,2024-03-04T11:00:00+00:00,3600\n\
+                    Logo,2024-03-04 12:00,,600\n\
+                    Menu,2024-03-04 13:00,2024-03-04 13:30,60\n";
+        let parsed = parse(ImportFormat::Csv, text, &CsvOptions::default()).unwrap();
+        let names: Vec<&str> = parsed.entries.iter().map(|entry| entry.description.as_str()).collect();
+        assert_eq!(names, ["Header", "Logo"]);
+        assert_eq!(parsed.entries[1].stop, local("2024-03-04 12:10"));
+        assert_eq!(parsed.skipped.len(), 2);
+        assert!(parsed.skipped[0].starts_with("Line 3:"), "{}", parsed.skipped[0]);
+        assert!(parsed.skipped[0].contains("yesterday"));
+        assert!(parsed.skipped[1].starts_with("Line 5:"), "{}", parsed.skipped[1]);
+
+        let missing = parse(ImportFormat::Csv, "task_name,stop_time\nHeader,2024-03-04 10:00\n", &CsvOptions::default());
+        assert!(missing.unwrap_err().to_string().contains("start_time"));
+    }
+
+    #[test]
+    fn toggl_csv() {
+        let text = "Project,Description,Tags,Start date,Start time,End date,End time\n\
+                    Website,Header,\"design, web\",2024-03-04,09:00:00,2024-03-04,10:30:00\n\
+                    Website,Footer,,2024-03-04,soon,2024-03-04,12:00:00\n";
+        let parsed = parse(ImportFormat::Toggl, text, &CsvOptions::default()).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.description, "Header");
+        assert_eq!(entry.project, "Website");
+        assert_eq!(entry.tags, ["design", "web"]);
+        assert_eq!((entry.start, entry.stop), (local("2024-03-04 09:00"), local("2024-03-04 10:30")));
+        assert_eq!(parsed.skipped.len(), 1);
+        assert!(parsed.skipped[0].contains('3'));
+    }
+
+    #[test]
+    fn clockify_csv_follows_the_day_order() {
+        let text = "Project,Task,Description,Tags,Start Date,Start Time,End Date,End Time\n\
+                    Website,Layout,,,03/04/2024,09:00 AM,03/04/2024,01:15 PM\n";
+        let month_first = parse_csv(text, false).unwrap();
+        assert_eq!(month_first.entries[0].start, local("2024-03-04 09:00"));
+        let day_first = parse_csv(text, true).unwrap();
+        assert_eq!(day_first.entries[0].start, local("2024-04-03 09:00"));
+        assert_eq!(day_first.entries[0].stop, local("2024-04-03 13:15"));
+        // Without a description, the task names the entry
+        assert_eq!(day_first.entries[0].description, "Layout");
+        assert_eq!(day_first.entries[0].notes, "");
+    }
+
+    #[test]
+    fn watson_frames() {
+        let start = local("2024-03-04 09:00").timestamp();
+        let stop = local("2024-03-04 10:00").timestamp();
+        let text = format!(
+            "[[{start}, {stop}, \"writing\", \"abc\", [\"book\", \"draft\"], {stop}, \"chapter 2\"], \
+              [\"soon\", {stop}, \"reading\", \"def\", [], {stop}]]"
+        );
+        let parsed = parse(ImportFormat::Watson, &text, &CsvOptions::default()).unwrap();
+        assert_eq!(parsed.entries.len(), 1);
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.description, "writing");
+        assert_eq!(entry.tags, ["book", "draft"]);
+        assert_eq!(entry.notes, "chapter 2");
+        assert_eq!(entry.stop, local("2024-03-04 10:00"));
+        assert_eq!(parsed.skipped.len(), 1);
+    }
+
+    #[test]
+    fn timewarrior_intervals() {
+        let text = "inc 20240304T080000Z - 20240304T093000Z # coding \"big project\" # \"fixed \\\"it\\\"\"\n\
+                    inc 20240305T080000Z # coding\n\
+                    inc 20240306T080000Z - 20240306T090000Z\n";
+        let parsed = parse_timewarrior(text);
+        assert_eq!(parsed.entries.len(), 1);
+        let entry = &parsed.entries[0];
+        assert_eq!(entry.description, "coding");
+        assert_eq!(entry.tags, ["big project"]);
+        assert_eq!(entry.notes, "fixed \"it\"");
+        assert_eq!(entry.start, Utc.ymd(2024, 3, 4).and_hms(8, 0, 0));
+        assert_eq!(parsed.skipped.len(), 2);
+        assert!(parsed.skipped[0].contains("still open"));
+        assert!(parsed.skipped[1].contains("no tag"));
+    }
+
+    #[test]
+    fn org_headings_and_tags_are_inherited() {
+        let text = "* Website :client:\n\
+                    ** TODO [#A] Header :design:\n\
+                    CLOCK: [2024-03-04 Mon 09:00]--[2024-03-04 Mon 10:30] =>  1:30\n\
+                    *** Logo\n\
+                    CLOCK: [2024-03-04 Mon 11:00]--[2024-03-04 Mon 11:45] =>  0:45\n\
+                    ** Footer\n\
+                    CLOCK: [2024-03-05 Tue 09:00]\n\
+                    * Errands\n\
+                    CLOCK: [2024-03-05 Tue 12:00]--[2024-03-05 Tue 12:30] =>  0:30\n";
+        let parsed = parse_org(text);
+        let entries: Vec<(&str, &str, Vec<String>)> = parsed
+            .entries
+            .iter()
+            .map(|entry| (entry.description.as_str(), entry.project.as_str(), entry.tags.clone()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("Header", "Website", vec!["client".to_string(), "design".to_string()]),
+                ("Logo", "Website", vec!["client".to_string(), "design".to_string()]),
+                ("Errands", "", vec![]),
+            ]
+        );
+        assert_eq!(parsed.entries[1].start, local("2024-03-04 11:00"));
+        assert_eq!(parsed.skipped.len(), 1);
+        assert!(parsed.skipped[0].contains("still running"));
+    }
+
+    #[test]
+    fn plan_marks_duplicates() {
+        let entry = |description: &str, start: &str, stop: &str| ImportedEntry {
+            description: description.to_string(),
+            project: String::new(),
+            tags: Vec::new(),
+            notes: String::new(),
+            start: local(start),
+            stop: local(stop),
+        };
+        let parsed = Parsed {
+            entries: vec![
+                entry("Header", "2024-03-04 09:00", "2024-03-04 10:00"),
+                entry("Footer", "2024-03-04 10:00", "2024-03-04 11:00"),
+                entry("footer ", "2024-03-04 10:00", "2024-03-04 11:00"),
+                entry("Logo", "2024-03-04 12:00", "2024-03-04 11:00"),
+            ],
+            skipped: Vec::new(),
+        };
+        let tracked = [existing("header", "2024-03-04 09:00", "2024-03-04 10:00")];
+
+        let plan = plan(&parsed, FieldMapping::default(), &tracked);
+        let duplicates: Vec<bool> = plan.rows.iter().map(|row| row.duplicate).collect();
+        assert_eq!(duplicates, [true, false, true]);
+        assert_eq!(plan.new_tasks(true).len(), 1);
+        assert_eq!(plan.new_tasks(false).len(), 3);
+        assert_eq!(plan.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,541 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Invoices built from billable time that hasn't been invoiced yet.
+//!
+//! Templates are plain text, one block per line: `# ` starts a heading,
+//! `## ` a smaller one, `{lines}` and `{totals}` on their own line place the
+//! line items and the totals, and an empty line adds space. Placeholders like
+//! `{number}` or `{client}` are filled in anywhere else.
+
+use anyhow::bail;
+use cairo::{Context, FontSlant, FontWeight};
+use chrono::{Local, NaiveDate};
+use gettextrs::*;
+use rusqlite::Connection;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::billing::{self, RateBook};
+use crate::store;
+use crate::report_export::{self, ExportFormat, PAGE_HEIGHT, PAGE_MARGIN, PAGE_WIDTH};
+use crate::reporting::{DateRange, Rounding, RoundingScope};
+
+/// Width of each right-aligned number column in the PDF
+const COLUMN_WIDTH: f64 = 90.0;
+const ROW_HEIGHT: f64 = 18.0;
+
+/// What to put on an invoice.
+#[derive(Clone, Debug)]
+pub struct InvoiceRequest {
+    pub number: String,
+    pub client: String,
+    /// Only entries in this project, or all projects when empty
+    pub project: String,
+    pub range: DateRange,
+    pub rounding: Option<Rounding>,
+    /// Percent added on top of the subtotal
+    pub tax_rate: f64,
+}
+
+/// Time on one task at one rate.
+#[derive(Clone, Debug)]
+pub struct LineItem {
+    pub description: String,
+    pub seconds: i64,
+    pub hourly_rate: f64,
+    pub amount: f64,
+    pub task_uuids: Vec<String>,
+}
+
+impl LineItem {
+    pub fn hours(&self) -> String {
+        format!("{:.2}", self.seconds as f64 / 3600.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Invoice {
+    pub number: String,
+    pub client: String,
+    pub project: String,
+    pub range: DateRange,
+    pub issued: NaiveDate,
+    pub currency: String,
+    pub lines: Vec<LineItem>,
+    pub tax_rate: f64,
+    pub subtotal: f64,
+    pub tax: f64,
+    pub total: f64,
+    /// Billable entries left out because no rate applies to them
+    pub unpriced: usize,
+}
+
+/// One uninvoiced entry, as stored.
+struct Entry {
+    uuid: String,
+    task_name: String,
+    tags: String,
+    project: String,
+    billable: Option<bool>,
+    day: NaiveDate,
+    seconds: i64,
+}
+
+/// One line of a template.
+enum Block {
+    Heading(String),
+    Subheading(String),
+    Text(String),
+    Space,
+    Lines,
+    Totals,
+}
+
+/// Fills `{year}` and `{number}` in the invoice number format, padding the
+/// number to four digits.
+pub fn format_number(format: &str, number: i32, year: i32) -> String {
+    format
+        .replace("{year}", &year.to_string())
+        .replace("{number}", &format!("{:04}", number))
+}
+
+pub fn default_template() -> String {
+    gettext(
+        "# Invoice {number}
+
+Date: {date}
+Bill to: {client}
+Project: {project}
+Period: {period}
+
+{lines}
+
+{totals}
+
+Thank you for your business.",
+    )
+}
+
+/// Groups the billable entries in the range that aren't on an invoice yet
+/// into line items, one per task and rate.
+pub fn build(conn: &Connection, request: &InvoiceRequest) -> anyhow::Result<Invoice> {
+    let rates = RateBook::new(store::rates_from(conn)?);
+    let project = request.project.trim().to_lowercase();
+
+    let mut lines: Vec<LineItem> = Vec::new();
+    let mut pieces: Vec<Vec<(NaiveDate, i64)>> = Vec::new();
+    let mut currencies: BTreeSet<String> = BTreeSet::new();
+    let mut unpriced = 0;
+    for entry in load_uninvoiced(conn, &request.range)? {
+        if !project.is_empty() && entry.project.trim().to_lowercase() != project {
+            continue;
+        }
+        let rate = rates.rate_for(&entry.task_name, &store::split_tags(&entry.tags), &entry.project);
+        if !billing::is_billable(entry.billable, rate) {
+            continue;
+        }
+        let rate = match rate {
+            Some(rate) => rate,
+            None => {
+                unpriced += 1;
+                continue;
+            }
+        };
+        currencies.insert(rate.currency.clone());
+
+        let position = lines.iter().position(|line| {
+            line.description.to_lowercase() == entry.task_name.trim().to_lowercase()
+                && line.hourly_rate == rate.hourly_rate
+        });
+        let index = position.unwrap_or_else(|| {
+            lines.push(LineItem {
+                description: entry.task_name.trim().to_string(),
+                seconds: 0,
+                hourly_rate: rate.hourly_rate,
+                amount: 0.0,
+                task_uuids: Vec::new(),
+            });
+            pieces.push(Vec::new());
+            lines.len() - 1
+        });
+        lines[index].task_uuids.push(entry.uuid);
+        pieces[index].push((entry.day, entry.seconds));
+    }
+
+    if currencies.len() > 1 {
+        bail!(gettext("These entries are billed in more than one currency ({}). Invoice each currency separately.")
+            .replace("{}", &currencies.into_iter().collect::<Vec<String>>().join(", ")));
+    }
+
+    let mut subtotal = 0.0;
+    for (line, pieces) in lines.iter_mut().zip(pieces.iter()) {
+        line.seconds = line_seconds(pieces, request.rounding);
+        line.amount = round_cents(line.seconds as f64 / 3600.0 * line.hourly_rate);
+        subtotal += line.amount;
+    }
+    let tax = round_cents(subtotal * request.tax_rate / 100.0);
+
+    Ok(Invoice {
+        number: request.number.trim().to_string(),
+        client: request.client.trim().to_string(),
+        project: request.project.trim().to_string(),
+        range: request.range,
+        issued: Local::now().naive_local().date(),
+        currency: currencies.into_iter().next().unwrap_or_default(),
+        lines,
+        tax_rate: request.tax_rate,
+        subtotal,
+        tax,
+        total: subtotal + tax,
+        unpriced,
+    })
+}
+
+fn load_uninvoiced(conn: &Connection, range: &DateRange) -> anyhow::Result<Vec<Entry>> {
+    let mut query = conn.prepare(
+        "SELECT uuid, task_name, tags, project, billable, date(start_time, 'localtime'),
+                CAST(round((julianday(stop_time) - julianday(start_time)) * 86400) AS INTEGER)
+            FROM tasks
+            WHERE julianday(start_time) >= julianday(?1) AND julianday(start_time) < julianday(?2)
+                AND uuid NOT IN (SELECT task_uuid FROM invoice_entries WHERE task_uuid IS NOT NULL)
+            ORDER BY julianday(start_time)",
+    )?;
+
+    let rows = query.query_map(
+        [range.start_time().to_rfc3339(), range.end_time().to_rfc3339()],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                row.get::<_, String>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        },
+    )?;
+
+    let mut entries: Vec<Entry> = Vec::new();
+    for row in rows {
+        let (uuid, task_name, tags, project, billable, day, seconds) = row?;
+        entries.push(Entry {
+            uuid,
+            task_name,
+            tags,
+            project,
+            billable: billing::billable_from_sqlite(&billable),
+            day: NaiveDate::parse_from_str(&day, "%Y-%m-%d")?,
+            seconds,
+        });
+    }
+    Ok(entries)
+}
+
+/// The time billed for one line, rounded the way the request asks.
+fn line_seconds(pieces: &[(NaiveDate, i64)], rounding: Option<Rounding>) -> i64 {
+    let rounding = match rounding {
+        Some(rounding) => rounding,
+        None => return pieces.iter().map(|(_, seconds)| seconds).sum(),
+    };
+    match rounding.scope {
+        RoundingScope::Entry => pieces.iter().map(|(_, seconds)| rounding.round(*seconds)).sum(),
+        RoundingScope::TaskDay => {
+            let mut days: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+            for (day, seconds) in pieces {
+                *days.entry(*day).or_insert(0) += seconds;
+            }
+            days.values().map(|seconds| rounding.round(*seconds)).sum()
+        }
+        RoundingScope::Total => rounding.round(pieces.iter().map(|(_, seconds)| seconds).sum()),
+    }
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
+
+impl Invoice {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn task_uuids(&self) -> Vec<String> {
+        self.lines.iter().flat_map(|line| line.task_uuids.iter().cloned()).collect()
+    }
+
+    pub fn money(&self, amount: f64) -> String {
+        billing::format_money(amount, &self.currency)
+    }
+
+    pub fn period_text(&self) -> String {
+        if self.range.start == self.range.end {
+            self.range.start.format("%B %-d, %Y").to_string()
+        } else {
+            format!(
+                "{} – {}",
+                self.range.start.format("%B %-d, %Y"),
+                self.range.end.format("%B %-d, %Y")
+            )
+        }
+    }
+
+    /// Label and amount of each totals row, leaving out tax when there is none.
+    pub fn totals(&self) -> Vec<(String, String)> {
+        let mut totals = Vec::new();
+        if self.tax_rate > 0.0 {
+            totals.push((gettext("Subtotal"), self.money(self.subtotal)));
+            totals.push((
+                gettext("Tax ({}%)").replace("{}", &format!("{}", self.tax_rate)),
+                self.money(self.tax),
+            ));
+        }
+        totals.push((gettext("Total"), self.money(self.total)));
+        totals
+    }
+
+    fn headings(&self) -> [String; 4] {
+        [gettext("Description"), gettext("Hours"), gettext("Rate"), gettext("Amount")]
+    }
+
+    fn cells(&self, line: &LineItem) -> [String; 3] {
+        [line.hours(), self.money(line.hourly_rate), self.money(line.amount)]
+    }
+
+    fn fill(&self, text: &str) -> String {
+        text.replace("{number}", &self.number)
+            .replace("{date}", &self.issued.format("%B %-d, %Y").to_string())
+            .replace("{client}", &self.client)
+            .replace("{project}", &self.project)
+            .replace("{period}", &self.period_text())
+            .replace("{currency}", &self.currency)
+            .replace("{subtotal}", &self.money(self.subtotal))
+            .replace("{tax}", &self.money(self.tax))
+            .replace("{total}", &self.money(self.total))
+    }
+
+    fn blocks(&self, template: &str) -> Vec<Block> {
+        template
+            .lines()
+            .map(|line| {
+                let line = line.trim_end();
+                match line.trim() {
+                    "" => Block::Space,
+                    "{lines}" => Block::Lines,
+                    "{totals}" => Block::Totals,
+                    _ => {
+                        if let Some(heading) = line.strip_prefix("## ") {
+                            Block::Subheading(self.fill(heading))
+                        } else if let Some(heading) = line.strip_prefix("# ") {
+                            Block::Heading(self.fill(heading))
+                        } else {
+                            Block::Text(self.fill(line))
+                        }
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Writes HTML, or PDF when the file ends in .pdf.
+    pub fn write(&self, path: &Path, template: &str) -> anyhow::Result<()> {
+        match ExportFormat::from_path(path) {
+            ExportFormat::Pdf => self.write_pdf(path, template)?,
+            _ => std::fs::write(path, self.to_html(template))?,
+        }
+        Ok(())
+    }
+
+    pub fn to_html(&self, template: &str) -> String {
+        let escape = report_export::escape_html;
+
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!(
+            "<title>{}</title>\n",
+            escape(&gettext("Invoice {}").replace("{}", &self.number))
+        ));
+        out.push_str(
+            "<style>
+body { font-family: sans-serif; color: #222; max-width: 48em; margin: 2em auto; padding: 0 1em; }
+p { margin: 0.2em 0; }
+.space { height: 1em; }
+table { width: 100%; border-collapse: collapse; }
+th, td { padding: 0.35em 0.5em; border-bottom: 1px solid #ddd; }
+th { text-align: left; border-bottom: 2px solid #999; }
+.num { text-align: right; font-variant-numeric: tabular-nums; }
+.totals td { border-bottom: none; }
+.totals tr:last-child td { font-weight: bold; border-top: 2px solid #999; }
+</style>
+</head>
+<body>
+",
+        );
+
+        for block in self.blocks(template) {
+            match block {
+                Block::Heading(text) => out.push_str(&format!("<h1>{}</h1>\n", escape(&text))),
+                Block::Subheading(text) => out.push_str(&format!("<h2>{}</h2>\n", escape(&text))),
+                Block::Text(text) => out.push_str(&format!("<p>{}</p>\n", escape(&text))),
+                Block::Space => out.push_str("<div class=\"space\"></div>\n"),
+                Block::Lines => {
+                    let [description, hours, rate, amount] = self.headings();
+                    out.push_str(&format!(
+                        "<table>\n<thead><tr><th>{}</th><th class=\"num\">{}</th><th class=\"num\">{}</th><th class=\"num\">{}</th></tr></thead>\n<tbody>\n",
+                        escape(&description),
+                        escape(&hours),
+                        escape(&rate),
+                        escape(&amount)
+                    ));
+                    for line in &self.lines {
+                        let cells: String = self
+                            .cells(line)
+                            .iter()
+                            .map(|cell| format!("<td class=\"num\">{}</td>", escape(cell)))
+                            .collect();
+                        out.push_str(&format!("<tr><td>{}</td>{}</tr>\n", escape(&line.description), cells));
+                    }
+                    out.push_str("</tbody>\n</table>\n");
+                }
+                Block::Totals => {
+                    out.push_str("<table class=\"totals\">\n");
+                    for (label, amount) in self.totals() {
+                        out.push_str(&format!(
+                            "<tr><td class=\"num\">{}</td><td class=\"num\" style=\"width: 8em\">{}</td></tr>\n",
+                            escape(&label),
+                            escape(&amount)
+                        ));
+                    }
+                    out.push_str("</table>\n");
+                }
+            }
+        }
+
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    pub fn write_pdf(&self, path: &Path, template: &str) -> anyhow::Result<()> {
+        let surface = cairo::PdfSurface::new(PAGE_WIDTH, PAGE_HEIGHT, path)?;
+        let cr = Context::new(&surface)?;
+        let mut page = PdfPage {
+            cr: &cr,
+            y: PAGE_MARGIN,
+            number: 1,
+        };
+
+        for block in self.blocks(template) {
+            match block {
+                Block::Heading(text) => page.text(&text, 20.0, FontWeight::Bold, 28.0)?,
+                Block::Subheading(text) => page.text(&text, 14.0, FontWeight::Bold, 20.0)?,
+                Block::Text(text) => page.text(&text, 11.0, FontWeight::Normal, 15.0)?,
+                Block::Space => page.y += 10.0,
+                Block::Lines => {
+                    let [description, hours, rate, amount] = self.headings();
+                    page.table_header(&description, &[hours, rate, amount])?;
+                    for line in &self.lines {
+                        page.break_if_needed(ROW_HEIGHT)?;
+                        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+                        page.row(&line.description, &self.cells(line))?;
+                    }
+                }
+                Block::Totals => {
+                    page.break_if_needed(ROW_HEIGHT * self.totals().len() as f64 + 8.0)?;
+                    page.y += 8.0;
+                    let totals = self.totals();
+                    for (i, (label, amount)) in totals.iter().enumerate() {
+                        let weight = if i + 1 == totals.len() {
+                            FontWeight::Bold
+                        } else {
+                            FontWeight::Normal
+                        };
+                        cr.select_font_face("Sans", FontSlant::Normal, weight);
+                        page.row("", &[label.clone(), amount.clone()])?;
+                    }
+                }
+            }
+        }
+
+        report_export::draw_page_number(&cr, page.number)?;
+        drop(cr);
+        surface.finish();
+        Ok(())
+    }
+}
+
+/// Where the PDF writer is on the current page.
+struct PdfPage<'a> {
+    cr: &'a Context,
+    y: f64,
+    number: i32,
+}
+
+impl<'a> PdfPage<'a> {
+    /// Starts a new page unless `height` still fits on this one.
+    fn break_if_needed(&mut self, height: f64) -> anyhow::Result<()> {
+        if self.y + height > PAGE_HEIGHT - PAGE_MARGIN {
+            report_export::draw_page_number(self.cr, self.number)?;
+            self.cr.show_page()?;
+            self.number += 1;
+            self.y = PAGE_MARGIN;
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str, size: f64, weight: FontWeight, height: f64) -> anyhow::Result<()> {
+        self.break_if_needed(height)?;
+        self.y += height;
+        self.cr.set_source_rgb(0.13, 0.13, 0.13);
+        self.cr.select_font_face("Sans", FontSlant::Normal, weight);
+        self.cr.set_font_size(size);
+        let text = report_export::fit_text(self.cr, text, PAGE_WIDTH - PAGE_MARGIN * 2.0)?;
+        self.cr.move_to(PAGE_MARGIN, self.y);
+        self.cr.show_text(&text)?;
+        Ok(())
+    }
+
+    fn table_header(&mut self, label: &str, headings: &[String]) -> anyhow::Result<()> {
+        self.break_if_needed(ROW_HEIGHT * 2.0)?;
+        self.cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        self.row(label, headings)?;
+        self.cr.set_source_rgb(0.6, 0.6, 0.6);
+        self.cr.set_line_width(1.0);
+        self.cr.move_to(PAGE_MARGIN, self.y + 6.0);
+        self.cr.line_to(PAGE_WIDTH - PAGE_MARGIN, self.y + 6.0);
+        self.cr.stroke()?;
+        self.y += 4.0;
+        Ok(())
+    }
+
+    /// A label on the left and number cells lined up from the right margin.
+    fn row(&mut self, label: &str, cells: &[String]) -> anyhow::Result<()> {
+        self.y += ROW_HEIGHT;
+        self.cr.set_source_rgb(0.13, 0.13, 0.13);
+        self.cr.set_font_size(10.0);
+        let first_right = PAGE_WIDTH - PAGE_MARGIN - (cells.len() as f64 - 1.0) * COLUMN_WIDTH;
+        let label = report_export::fit_text(self.cr, label, first_right - COLUMN_WIDTH - PAGE_MARGIN)?;
+        self.cr.move_to(PAGE_MARGIN, self.y);
+        self.cr.show_text(&label)?;
+        for (i, text) in cells.iter().enumerate() {
+            let extents = self.cr.text_extents(text)?;
+            self.cr
+                .move_to(first_right + i as f64 * COLUMN_WIDTH - extents.x_advance(), self.y);
+            self.cr.show_text(text)?;
+        }
+        Ok(())
+    }
+}
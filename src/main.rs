@@ -15,9 +15,25 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod application;
+mod billing;
+mod budgets;
+mod charts;
 mod config;
 mod database;
+mod dates;
+mod filter;
+mod goals;
+mod importers;
+mod invoicing;
+mod merging;
+mod reminders;
+mod remote_sync;
+mod report_export;
+mod reporting;
+mod secrets;
 mod settings_manager;
+mod store;
+mod sync;
 mod ui;
 
 use self::application::rusttimetrackApplication;
@@ -0,0 +1,283 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Merging the entries of another database into this one. Like `store`,
+//! this only needs open connections and stays free of GTK.
+
+use chrono::{DateTime, Local};
+use gettextrs::*;
+use rusqlite::Connection;
+use std::collections::HashMap;
+
+use crate::billing;
+use crate::store::{all_tasks, insert_task_as, split_tags, update_field_with, ChangeOrigin, Task};
+
+/// An entry both databases have, edited differently on each side.
+#[derive(Clone, Debug)]
+pub struct MergeConflict {
+    pub local: Task,
+    pub other: Task,
+}
+
+impl MergeConflict {
+    /// Each field that differs, like "Task name: Code here, Coding in the file".
+    pub fn differences(&self) -> Vec<String> {
+        let billable = |task: &Task| match task.billable {
+            Some(true) => gettext("billable"),
+            Some(false) => gettext("not billable"),
+            None => gettext("by rate"),
+        };
+        let time = |time: &str| match DateTime::parse_from_rfc3339(time) {
+            Ok(time) => time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            Err(_) => time.to_string(),
+        };
+        let fields = [
+            (gettext("Task name"), self.local.task_name.clone(), self.other.task_name.clone()),
+            (gettext("Start time"), time(&self.local.start_time), time(&self.other.start_time)),
+            (gettext("Stop time"), time(&self.local.stop_time), time(&self.other.stop_time)),
+            (gettext("Tags"), self.local.tags.clone(), self.other.tags.clone()),
+            (gettext("Project"), self.local.project.clone(), self.other.project.clone()),
+            (gettext("Billable"), billable(&self.local), billable(&self.other)),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, local, other)| local.trim() != other.trim())
+            .map(|(field, local, other)| {
+                gettext("{field}: {local} here, {other} in the file")
+                    .replace("{field}", &field)
+                    .replace("{local}", &local)
+                    .replace("{other}", &other)
+            })
+            .collect()
+    }
+}
+
+/// What merging another database adds, and what it leaves alone.
+#[derive(Clone, Debug, Default)]
+pub struct MergeReport {
+    pub added: Vec<Task>,
+    pub unchanged: usize,
+    /// Kept as they are here
+    pub conflicts: Vec<MergeConflict>,
+    /// Entries in the file without a readable start time
+    pub invalid: usize,
+}
+
+impl MergeReport {
+    pub fn summary(&self) -> String {
+        let mut lines: Vec<String> = vec![
+            ngettext("{} new entry.", "{} new entries.", self.added.len() as u32)
+                .replace("{}", &self.added.len().to_string()),
+            ngettext("{} entry is already here.", "{} entries are already here.", self.unchanged as u32)
+                .replace("{}", &self.unchanged.to_string()),
+        ];
+        if !self.conflicts.is_empty() {
+            lines.push(
+                ngettext(
+                    "{} entry was edited differently and stays as it is here.",
+                    "{} entries were edited differently and stay as they are here.",
+                    self.conflicts.len() as u32,
+                )
+                .replace("{}", &self.conflicts.len().to_string()),
+            );
+        }
+        if self.invalid > 0 {
+            lines.push(
+                ngettext("{} entry can't be read.", "{} entries can't be read.", self.invalid as u32)
+                    .replace("{}", &self.invalid.to_string()),
+            );
+        }
+        lines.join(" ")
+    }
+}
+
+/// Adds the entries of `other_conn` that `conn` doesn't have yet. Entries
+/// are matched by their uuid,
+/// or by their start time for rows that got different uuids in each copy.
+/// With `dry_run`, or if anything fails, nothing is written.
+pub fn merge_into(conn: &mut Connection, other_conn: &Connection, dry_run: bool) -> anyhow::Result<MergeReport> {
+    let other_tasks = all_tasks(other_conn)?;
+
+    let start_of = |task: &Task| {
+        DateTime::parse_from_rfc3339(&task.start_time)
+            .ok()
+            .map(|start| start.timestamp())
+    };
+    let mut known: HashMap<i64, Vec<Task>> = HashMap::new();
+    let mut known_uuids: HashMap<String, Task> = HashMap::new();
+    for task in all_tasks(conn)? {
+        if !task.uuid.is_empty() {
+            known_uuids.insert(task.uuid.clone(), task.clone());
+        }
+        if let Some(start) = start_of(&task) {
+            known.entry(start).or_default().push(task);
+        }
+    }
+
+    let mut report = MergeReport::default();
+    for task in other_tasks {
+        let start = match start_of(&task) {
+            Some(start) => start,
+            None => {
+                report.invalid += 1;
+                continue;
+            }
+        };
+        if let Some(local) = known_uuids.get(&task.uuid) {
+            if same_entry(local, &task) {
+                report.unchanged += 1;
+            } else {
+                report.conflicts.push(MergeConflict {
+                    local: local.clone(),
+                    other: task,
+                });
+            }
+            continue;
+        }
+
+        let same_start = known.entry(start).or_default();
+        if same_start.iter().any(|local| same_entry(local, &task)) {
+            report.unchanged += 1;
+        } else if let Some(local) = same_start.first() {
+            report.conflicts.push(MergeConflict {
+                local: local.clone(),
+                other: task,
+            });
+        } else {
+            same_start.push(task.clone());
+            if !task.uuid.is_empty() {
+                known_uuids.insert(task.uuid.clone(), task.clone());
+            }
+            report.added.push(task);
+        }
+    }
+
+    if !dry_run {
+        let tx = conn.transaction()?;
+        for task in &report.added {
+            let id = insert_task_as(
+                &tx,
+                Some(task),
+                &task.task_name,
+                &task.start_time,
+                &task.stop_time,
+                &task.tags,
+                &task.project,
+                ChangeOrigin::Import,
+            )?;
+            if task.billable.is_some() {
+                update_field_with(&tx, id, "billable", billing::billable_to_sqlite(task.billable), ChangeOrigin::Import)?;
+                // Still the same edit as in the other database
+                if !task.updated_at.is_empty() {
+                    tx.execute("UPDATE tasks SET updated_at = ?1 WHERE id = ?2", (&task.updated_at, id))?;
+                }
+            }
+        }
+        tx.commit()?;
+    }
+
+    Ok(report)
+}
+
+fn same_entry(local: &Task, other: &Task) -> bool {
+    let timestamp = |time: &str| DateTime::parse_from_rfc3339(time).map(|time| time.timestamp()).ok();
+    local.task_name.trim() == other.task_name.trim()
+        && timestamp(&local.start_time) == timestamp(&other.start_time)
+        && timestamp(&local.stop_time) == timestamp(&other.stop_time)
+        && split_tags(&local.tags) == split_tags(&other.tags)
+        && local.project.trim() == other.project.trim()
+        && local.billable == other.billable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_util::{add, empty_db, names};
+
+    #[test]
+    fn adds_new_entries_and_keeps_known_ones() {
+        let mut conn = empty_db();
+        add(&conn, "Code", 9);
+        let other = empty_db();
+        add(&other, "Code", 9);
+        add(&other, "Review", 10);
+
+        let report = merge_into(&mut conn, &other, true).unwrap();
+        assert_eq!((report.added.len(), report.unchanged), (1, 1));
+        assert_eq!(names(&conn), vec!["Code"]);
+
+        merge_into(&mut conn, &other, false).unwrap();
+        assert_eq!(names(&conn), vec!["Code", "Review"]);
+    }
+
+    #[test]
+    fn a_moved_start_is_a_conflict() {
+        let mut conn = empty_db();
+        add(&conn, "Code", 9);
+        let other = empty_db();
+        add(&other, "Code", 8);
+        let uuid: String = conn.query_row("SELECT uuid FROM tasks", [], |row| row.get(0)).unwrap();
+        other.execute("UPDATE tasks SET uuid = ?1", [&uuid]).unwrap();
+
+        let report = merge_into(&mut conn, &other, false).unwrap();
+        assert_eq!((report.added.len(), report.unchanged, report.conflicts.len()), (0, 0, 1));
+        let differences = report.conflicts[0].differences();
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].starts_with("Start time"));
+        assert_eq!(names(&conn), vec!["Code"]);
+    }
+
+    #[test]
+    fn reads_databases_from_before_projects() {
+        let mut conn = empty_db();
+        let other = Connection::open_in_memory().unwrap();
+        other
+            .execute_batch(
+                "CREATE TABLE tasks (id integer primary key, task_name text, start_time timestamp,
+                                     stop_time timestamp, tags text);
+                 INSERT INTO tasks (task_name, start_time, stop_time, tags)
+                     VALUES ('Code', '2023-03-06T09:00:00+00:00', '2023-03-06T10:00:00+00:00', 'work');",
+            )
+            .unwrap();
+
+        let report = merge_into(&mut conn, &other, false).unwrap();
+        assert_eq!(report.added.len(), 1);
+        let task = &all_tasks(&conn).unwrap()[0];
+        assert_eq!((task.task_name.as_str(), task.tags.as_str(), task.project.as_str()), ("Code", "work", ""));
+    }
+
+    #[test]
+    fn leaves_the_local_database_untouched_on_failure() {
+        let mut conn = empty_db();
+        add(&conn, "Code", 9);
+        conn.execute_batch(
+            "CREATE TRIGGER refuse BEFORE INSERT ON tasks WHEN NEW.task_name = 'Broken'
+                 BEGIN SELECT RAISE(ABORT, 'refused'); END;",
+        )
+        .unwrap();
+        let other = empty_db();
+        add(&other, "Review", 10);
+        add(&other, "Broken", 11);
+        let changes = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM changes", [], |row| row.get(0)).unwrap()
+        };
+        let changes_before = changes(&conn);
+
+        assert!(merge_into(&mut conn, &other, false).is_err());
+        assert_eq!(names(&conn), vec!["Code"]);
+        assert_eq!(changes(&conn), changes_before);
+    }
+}
@@ -0,0 +1,154 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Working hours, and when to remind the user to start or stop the timer.
+//! Like `reporting`, this stays free of GTK.
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorkHours {
+    /// Monday first
+    pub days: [bool; 7],
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl WorkHours {
+    /// From one bit per day, Monday in the lowest, and minutes after midnight.
+    pub fn new(day_bits: i32, start_minutes: i32, end_minutes: i32) -> Self {
+        let time = |minutes: i32| {
+            let minutes = minutes.clamp(0, 24 * 60 - 1) as u32;
+            NaiveTime::from_hms_opt(minutes / 60, minutes % 60, 0).unwrap()
+        };
+        let mut days = [false; 7];
+        for (i, day) in days.iter_mut().enumerate() {
+            *day = day_bits & (1 << i) != 0;
+        }
+        WorkHours {
+            days,
+            start: time(start_minutes),
+            end: time(end_minutes),
+        }
+    }
+
+    pub fn is_work_day(&self, day: NaiveDate) -> bool {
+        self.days[day.weekday().num_days_from_monday() as usize]
+    }
+
+    pub fn start_on(&self, day: NaiveDate) -> NaiveDateTime {
+        day.and_time(self.start)
+    }
+
+    pub fn end_on(&self, day: NaiveDate) -> NaiveDateTime {
+        day.and_time(self.end)
+    }
+
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        self.is_work_day(time.date()) && self.start <= time.time() && time.time() < self.end
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReminderSettings {
+    pub hours: WorkHours,
+    /// Remind to start after this long without a timer in working hours
+    pub start_after: Option<Duration>,
+    /// Remind to stop after the timer runs this long, or past the end of
+    /// the working day
+    pub stop_after: Option<Duration>,
+}
+
+/// A reminder, told apart from others of its kind by its time, so each one
+/// is only shown once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reminder {
+    /// Nothing has been tracked since this time in working hours
+    Start(NaiveDateTime),
+    /// The timer started at this time and has run longer than allowed
+    RunningLong(NaiveDateTime),
+    /// The timer is still running after the working day ended at this time
+    PastWorkday(NaiveDateTime),
+}
+
+impl Reminder {
+    /// Where a timer reminded to stop should probably have stopped.
+    pub fn suggested_stop(&self, stop_after: Option<Duration>) -> Option<NaiveDateTime> {
+        match *self {
+            Reminder::Start(_) => None,
+            Reminder::RunningLong(since) => stop_after.map(|limit| since + limit),
+            Reminder::PastWorkday(end) => Some(end),
+        }
+    }
+}
+
+/// The reminders due at `now`. `running_since` is when the running timer
+/// started, and `last_stop` is when the last entry ended.
+pub fn due(
+    settings: &ReminderSettings,
+    now: NaiveDateTime,
+    running_since: Option<NaiveDateTime>,
+    last_stop: Option<NaiveDateTime>,
+) -> Vec<Reminder> {
+    let mut reminders: Vec<Reminder> = Vec::new();
+    let hours = &settings.hours;
+
+    match running_since {
+        None => {
+            if let Some(start_after) = settings.start_after {
+                // Count from the start of the working day or the last entry
+                let mut idle_since = hours.start_on(now.date());
+                if let Some(last_stop) = last_stop {
+                    idle_since = idle_since.max(last_stop);
+                }
+                if hours.contains(now) && now - idle_since >= start_after {
+                    reminders.push(Reminder::Start(idle_since));
+                }
+            }
+        }
+        Some(since) => {
+            if let Some(stop_after) = settings.stop_after {
+                if now - since >= stop_after {
+                    reminders.push(Reminder::RunningLong(since));
+                }
+                let end = hours.end_on(since.date());
+                if hours.is_work_day(since.date()) && since < end && now >= end {
+                    reminders.push(Reminder::PastWorkday(end));
+                }
+            }
+        }
+    }
+    reminders
+}
+
+/// Reads "17:30" as a time of day.
+pub fn parse_time(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text.trim(), "%H:%M").ok()
+}
+
+/// The last time `time` of day happened between `after` and `now`, for
+/// stopping a timer that may have run overnight.
+pub fn latest_time_between(time: NaiveTime, after: NaiveDateTime, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let mut candidate = now.date().and_time(time);
+    if candidate > now {
+        candidate -= Duration::days(1);
+    }
+    if candidate > after {
+        Some(candidate)
+    } else {
+        None
+    }
+}
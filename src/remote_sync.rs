@@ -0,0 +1,306 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The client side of the sync protocol described in
+//! `sync-server/PROTOCOL.md`. The same journal entries as in folder sync are
+//! encrypted here before they are sent, so the server only ever sees which
+//! device sent how many changes. Like `reporting`, this stays free of GTK.
+//!
+//! The local database stays the source of truth: when the server can't be
+//! reached nothing changes, and everything edited in the meantime is sent
+//! with the next sync that gets through.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rusqlite::{Connection, OptionalExtension};
+use std::fmt;
+use std::time::Duration;
+
+use crate::sync::{self, JournalEntry, SyncReport};
+
+/// Every device derives the same key from the same passphrase, so the salt
+/// is fixed by the protocol.
+const KEY_SALT: &[u8] = b"rusttimetrack sync v1";
+const NONCE_LEN: usize = 24;
+/// Changes sent in one request
+const PUSH_LIMIT: usize = 500;
+/// Changes asked for in one request
+const PULL_LIMIT: usize = 500;
+
+/// The key changes are encrypted with, derived from a passphrase shared by
+/// the devices and never sent anywhere.
+#[derive(Clone)]
+pub struct SyncKey([u8; 32]);
+
+impl SyncKey {
+    pub fn from_passphrase(passphrase: &str) -> anyhow::Result<Self> {
+        let mut key = [0u8; 32];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), KEY_SALT, &mut key)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(SyncKey(key))
+    }
+
+    /// For storing the key instead of the passphrase.
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.0)
+    }
+
+    pub fn from_base64(text: &str) -> anyhow::Result<Self> {
+        let key: [u8; 32] = BASE64
+            .decode(text.trim())?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("The sync key has the wrong length"))?;
+        Ok(SyncKey(key))
+    }
+
+    /// Encrypts a change. The device id is authenticated along with it, so
+    /// the server can't pass one device's change off as another's.
+    fn seal(&self, device: &str, plaintext: &[u8]) -> anyhow::Result<String> {
+        let cipher = XChaCha20Poly1305::new(&self.0.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: device.as_bytes() })
+            .map_err(|_| anyhow::anyhow!("Couldn't encrypt a change"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(BASE64.encode(sealed))
+    }
+
+    fn open(&self, device: &str, payload: &str) -> anyhow::Result<Vec<u8>> {
+        let sealed = BASE64.decode(payload)?;
+        if sealed.len() < NONCE_LEN {
+            anyhow::bail!("A change from {} is too short", device);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = XChaCha20Poly1305::new(&self.0.into());
+        cipher
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: device.as_bytes() })
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Can't decrypt a change from {}. Is the passphrase the same on every device?",
+                    device
+                )
+            })
+    }
+}
+
+impl fmt::Debug for SyncKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SyncKey(..)")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Server {
+    /// Where the server is, like "https://sync.example.com"
+    pub url: String,
+    pub token: String,
+    pub key: SyncKey,
+}
+
+#[derive(serde::Serialize)]
+struct PushRequest<'a> {
+    device: &'a str,
+    changes: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct PulledChange {
+    cursor: i64,
+    device: String,
+    payload: String,
+}
+
+#[derive(serde::Deserialize)]
+struct PullResponse {
+    changes: Vec<PulledChange>,
+    more: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+impl Server {
+    fn endpoint(&self) -> String {
+        format!("{}/v1/changes", self.url.trim_end_matches('/'))
+    }
+
+    fn agent() -> ureq::Agent {
+        ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout(Duration::from_secs(60))
+            .build()
+    }
+
+    fn pull(&self, since: i64) -> anyhow::Result<PullResponse> {
+        let response = Server::agent()
+            .get(&self.endpoint())
+            .query("since", &since.to_string())
+            .query("limit", &PULL_LIMIT.to_string())
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .call()
+            .map_err(request_error)?;
+        Ok(response.into_json()?)
+    }
+
+    fn push(&self, device: &str, changes: Vec<String>) -> anyhow::Result<()> {
+        Server::agent()
+            .post(&self.endpoint())
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .send_json(PushRequest { device, changes })
+            .map_err(request_error)?;
+        Ok(())
+    }
+}
+
+/// Turns the server's error message, or the reason it couldn't be reached,
+/// into an error.
+fn request_error(error: ureq::Error) -> anyhow::Error {
+    match error {
+        ureq::Error::Status(code, response) => match response.into_json::<ErrorResponse>() {
+            Ok(body) => anyhow::anyhow!("{} ({})", body.error, code),
+            Err(_) => anyhow::anyhow!("The server answered with status {}", code),
+        },
+        ureq::Error::Transport(transport) => anyhow::anyhow!("Can't reach the server: {}", transport),
+    }
+}
+
+/// Applies the changes other devices sent to the server since the last sync,
+/// then sends the ones made here.
+pub fn sync(conn: &mut Connection, server: &Server, device: &str) -> anyhow::Result<SyncReport> {
+    sync::create_sync_tables(conn)?;
+    let target = server.url.as_str();
+    let mut report = SyncReport::default();
+
+    // Each page is committed on its own, so a dropped connection doesn't
+    // lose what was already applied
+    loop {
+        let tx = conn.transaction()?;
+        let cursor: i64 = tx
+            .query_row("SELECT cursor FROM sync_cursors WHERE target = ?1", [target], |row| row.get(0))
+            .optional()?
+            .unwrap_or(0);
+
+        let page = server.pull(cursor)?;
+        let mut last_cursor = cursor;
+        for change in &page.changes {
+            if change.device != device {
+                // A change that can't be read is skipped, so it doesn't hold
+                // back everything after it
+                let entry = server
+                    .key
+                    .open(&change.device, &change.payload)
+                    .and_then(|plaintext| Ok(serde_json::from_slice::<JournalEntry>(&plaintext)?));
+                match entry {
+                    Ok(entry) => {
+                        if sync::apply(&tx, target, &entry, &change.device, device)? {
+                            report.applied += 1;
+                        } else {
+                            report.outdated += 1;
+                        }
+                    }
+                    Err(e) => report.errors.push(format!("{}: {}", server.url, e)),
+                }
+            }
+            last_cursor = last_cursor.max(change.cursor);
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO sync_cursors (target, cursor) VALUES (?1, ?2)",
+            (target, last_cursor),
+        )?;
+        tx.commit()?;
+
+        if !page.more || page.changes.is_empty() {
+            break;
+        }
+    }
+
+    // Only recorded as sent once the server has them. Sending a change twice
+    // after a failure is harmless.
+    let tx = conn.transaction()?;
+    let changes = sync::local_changes(&tx, target, 1)?;
+    for chunk in changes.chunks(PUSH_LIMIT) {
+        let payloads = chunk
+            .iter()
+            .map(|entry| server.key.seal(device, &serde_json::to_vec(entry)?))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        server.push(device, payloads)?;
+    }
+    sync::mark_exported(&tx, target, &changes)?;
+    tx.commit()?;
+    report.written = changes.len();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_util::{add, empty_db, names};
+    use rusttimetrack_sync_server::store::Store;
+
+    /// Starts the reference server on localhost with an empty store.
+    fn start_server() -> Server {
+        let http = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", http.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            let mut store = Store::open(std::path::Path::new(":memory:")).unwrap();
+            rusttimetrack_sync_server::serve(&http, &mut store, "token");
+        });
+        // A fixed key, since deriving one is slow
+        Server { url, token: String::from("token"), key: SyncKey([7; 32]) }
+    }
+
+    #[test]
+    fn tasks_reach_the_other_device() {
+        let server = start_server();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        add(&b, "Review", 9);
+
+        assert_eq!(sync(&mut a, &server, "device-a").unwrap().written, 1);
+        let report = sync(&mut b, &server, "device-b").unwrap();
+        assert_eq!((report.applied, report.written), (1, 1));
+        assert_eq!(sync(&mut a, &server, "device-a").unwrap().applied, 1);
+
+        assert_eq!(names(&a), vec!["Code", "Review"]);
+        assert_eq!(names(&b), vec!["Code", "Review"]);
+    }
+
+    #[test]
+    fn unreadable_changes_are_skipped() {
+        let server = start_server();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        let other_key = Server { key: SyncKey([8; 32]), ..server.clone() };
+        server.push("device-c", vec![other_key.key.seal("device-c", b"{}").unwrap()]).unwrap();
+        add(&a, "Code", 9);
+        sync(&mut a, &server, "device-a").unwrap();
+
+        let report = sync(&mut b, &server, "device-b").unwrap();
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(names(&b), vec!["Code"]);
+
+        // The cursor moved past it, so it isn't tried again
+        let report = sync(&mut b, &server, "device-b").unwrap();
+        assert!(report.errors.is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Writes a finished report as HTML, Markdown or PDF for sharing.
+
+use cairo::{Context, FontSlant, FontWeight};
+use chrono::{DateTime, Local};
+use gettextrs::*;
+use std::path::Path;
+
+use crate::reporting::{self, ReportNode, ReportQuery};
+
+// A4 in points
+pub(crate) const PAGE_WIDTH: f64 = 595.0;
+pub(crate) const PAGE_HEIGHT: f64 = 842.0;
+pub(crate) const PAGE_MARGIN: f64 = 48.0;
+const ROW_HEIGHT: f64 = 18.0;
+const INDENT: f64 = 14.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Html,
+    Markdown,
+    Pdf,
+}
+
+impl ExportFormat {
+    /// Picks the format from a file name, HTML unless it ends in .md or .pdf.
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("md") | Some("markdown") => ExportFormat::Markdown,
+            Some("pdf") => ExportFormat::Pdf,
+            _ => ExportFormat::Html,
+        }
+    }
+}
+
+/// One line of the report table.
+struct Row<'a> {
+    depth: usize,
+    node: &'a ReportNode,
+    percent: f64,
+}
+
+/// A report together with what produced it, ready to be written out.
+pub struct ReportDocument<'a> {
+    pub query: &'a ReportQuery,
+    pub report: &'a ReportNode,
+    pub generated: DateTime<Local>,
+}
+
+impl<'a> ReportDocument<'a> {
+    pub fn new(query: &'a ReportQuery, report: &'a ReportNode) -> Self {
+        ReportDocument {
+            query,
+            report,
+            generated: Local::now(),
+        }
+    }
+
+    pub fn write(&self, path: &Path, format: ExportFormat) -> anyhow::Result<()> {
+        match format {
+            ExportFormat::Html => std::fs::write(path, self.to_html())?,
+            ExportFormat::Markdown => std::fs::write(path, self.to_markdown())?,
+            ExportFormat::Pdf => self.write_pdf(path)?,
+        }
+        Ok(())
+    }
+
+    pub fn title(&self) -> String {
+        gettext("Time Report")
+    }
+
+    pub fn range_text(&self) -> String {
+        let range = &self.query.range;
+        if range.start == range.end {
+            range.start.format("%B %-d, %Y").to_string()
+        } else {
+            format!(
+                "{} – {}",
+                range.start.format("%B %-d, %Y"),
+                range.end.format("%B %-d, %Y")
+            )
+        }
+    }
+
+    pub fn generated_text(&self) -> String {
+        gettext("Generated {}").replace("{}", &self.generated.format("%Y-%m-%d %H:%M").to_string())
+    }
+
+    /// Filters and grouping, one line each, skipping what wasn't used.
+    pub fn details(&self) -> Vec<String> {
+        let mut details: Vec<String> = self.query.filters.iter().map(|filter| filter.describe()).collect();
+        if !self.query.group_by.is_empty() {
+            let levels: Vec<String> = self.query.group_by.iter().map(|group| group.title()).collect();
+            details.push(format!("{}: {}", gettext("Grouped by"), levels.join(" → ")));
+        }
+        if let Some(rounding) = self.query.rounding {
+            details.push(rounding.describe());
+        }
+        if let Some(compare) = self.query.compare {
+            let range = compare.range_for(&self.query.range);
+            details.push(format!(
+                "{}: {} ({} – {})",
+                gettext("Compared with"),
+                compare.title(),
+                range.start.format("%B %-d, %Y"),
+                range.end.format("%B %-d, %Y")
+            ));
+        }
+        details
+    }
+
+    fn has_billing(&self) -> bool {
+        self.report.billable_seconds > 0
+    }
+
+    /// Titles of the number columns, with the rounded time when rounding,
+    /// billing columns when anything is billable, the change when comparing
+    /// and estimates when anything has a budget.
+    fn headings(&self) -> Vec<String> {
+        let mut headings = vec![gettext("Duration")];
+        if self.query.rounding.is_some() {
+            headings.push(gettext("Rounded"));
+        }
+        if self.has_billing() {
+            headings.push(gettext("Billable"));
+            headings.push(gettext("Earnings"));
+        }
+        if self.query.compare.is_some() {
+            headings.push(gettext("Previous"));
+            headings.push(gettext("Change"));
+        }
+        if self.report.has_estimates() {
+            headings.push(gettext("Estimate"));
+        }
+        headings.push(gettext("Share"));
+        headings
+    }
+
+    /// The number columns for one line, matching `headings`.
+    fn cells(&self, node: &ReportNode, percent: Option<f64>) -> Vec<String> {
+        let mut cells = vec![reporting::format_duration(node.seconds)];
+        if self.query.rounding.is_some() {
+            cells.push(reporting::format_duration(node.rounded_seconds));
+        }
+        if self.has_billing() {
+            cells.push(reporting::format_duration(node.billable_seconds));
+            cells.push(node.earnings.format());
+        }
+        if self.query.compare.is_some() {
+            cells.push(node.previous_seconds.map(reporting::format_duration).unwrap_or_default());
+            cells.push(node.change_text());
+        }
+        if self.report.has_estimates() {
+            cells.push(node.estimate_text());
+        }
+        cells.push(percent.map(|percent| format!("{:.1}%", percent)).unwrap_or_default());
+        cells
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        fn add_rows<'a>(rows: &mut Vec<Row<'a>>, parent: &'a ReportNode, depth: usize) {
+            for node in &parent.children {
+                rows.push(Row {
+                    depth,
+                    node,
+                    percent: node.percent_of(parent.seconds),
+                });
+                add_rows(rows, node, depth + 1);
+            }
+        }
+
+        let mut rows: Vec<Row> = Vec::new();
+        add_rows(&mut rows, self.report, 0);
+        rows
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let escape = |text: &str| text.replace('|', "\\|");
+
+        let mut out = format!("# {}\n\n", self.title());
+        out.push_str(&format!("**{}**  \n", self.range_text()));
+        for detail in self.details() {
+            out.push_str(&format!("{}  \n", escape(&detail)));
+        }
+        out.push_str(&format!("*{}*\n\n", self.generated_text()));
+
+        let headings = self.headings();
+        out.push_str(&format!(
+            "| {} | {} |\n|---|{}\n",
+            gettext("Name"),
+            headings.join(" | "),
+            "---:|".repeat(headings.len())
+        ));
+        for row in self.rows() {
+            // Markdown tables have no nesting, so mark the depth instead
+            out.push_str(&format!(
+                "| {}{} | {} |\n",
+                "› ".repeat(row.depth),
+                escape(&row.node.label()),
+                self.cells(row.node, Some(row.percent)).join(" | ")
+            ));
+        }
+        let total = self
+            .cells(self.report, None)
+            .iter()
+            .map(|cell| if cell.is_empty() { String::new() } else { format!("**{}**", cell) })
+            .collect::<Vec<String>>();
+        out.push_str(&format!("| **{}** | {} |\n", gettext("Total"), total.join(" | ")));
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!("<title>{}</title>\n", escape_html(&self.title())));
+        out.push_str(
+            "<style>
+body { font-family: sans-serif; color: #222; max-width: 48em; margin: 2em auto; padding: 0 1em; }
+h1 { margin-bottom: 0.2em; }
+p { margin: 0.2em 0; }
+.generated { color: #777; font-size: 0.9em; }
+table { width: 100%; border-collapse: collapse; margin-top: 1.5em; }
+th, td { padding: 0.35em 0.5em; border-bottom: 1px solid #ddd; }
+th { text-align: left; border-bottom: 2px solid #999; }
+.num { text-align: right; font-variant-numeric: tabular-nums; }
+.level-0 { font-weight: bold; }
+tfoot td { font-weight: bold; border-top: 2px solid #999; border-bottom: none; }
+</style>
+</head>
+<body>
+",
+        );
+        out.push_str(&format!("<h1>{}</h1>\n", escape_html(&self.title())));
+        out.push_str(&format!("<p><strong>{}</strong></p>\n", escape_html(&self.range_text())));
+        for detail in self.details() {
+            out.push_str(&format!("<p>{}</p>\n", escape_html(&detail)));
+        }
+        out.push_str(&format!(
+            "<p class=\"generated\">{}</p>\n",
+            escape_html(&self.generated_text())
+        ));
+
+        let number_cells = |cells: Vec<String>, tag: &str| -> String {
+            cells
+                .iter()
+                .map(|cell| format!("<{} class=\"num\">{}</{}>", tag, escape_html(cell), tag))
+                .collect()
+        };
+        out.push_str(&format!(
+            "<table>\n<thead><tr><th>{}</th>{}</tr></thead>\n<tbody>\n",
+            escape_html(&gettext("Name")),
+            number_cells(self.headings(), "th")
+        ));
+        for row in self.rows() {
+            out.push_str(&format!(
+                "<tr class=\"level-{}\"><td style=\"padding-left: {:.1}em\">{}</td>{}</tr>\n",
+                row.depth,
+                0.5 + row.depth as f64 * 1.5,
+                escape_html(&row.node.label()),
+                number_cells(self.cells(row.node, Some(row.percent)), "td")
+            ));
+        }
+        out.push_str(&format!(
+            "</tbody>\n<tfoot><tr><td>{}</td>{}</tr></tfoot>\n</table>\n</body>\n</html>\n",
+            escape_html(&gettext("Total")),
+            number_cells(self.cells(self.report, None), "td")
+        ));
+        out
+    }
+
+    /// Width of each number column, fitting its widest cell.
+    fn column_widths(&self, cr: &Context, headings: &[String]) -> anyhow::Result<Vec<f64>> {
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        cr.set_font_size(10.0);
+        let mut widths = Vec::new();
+        for heading in headings {
+            widths.push(cr.text_extents(heading)?.x_advance());
+        }
+        let mut lines: Vec<Vec<String>> = self
+            .rows()
+            .iter()
+            .map(|row| self.cells(row.node, Some(row.percent)))
+            .collect();
+        lines.push(self.cells(self.report, None));
+        for cells in lines {
+            for (width, cell) in widths.iter_mut().zip(cells.iter()) {
+                *width = width.max(cr.text_extents(cell)?.x_advance());
+            }
+        }
+        Ok(widths
+            .into_iter()
+            .map(|width| (width + COLUMN_GAP).max(MIN_COLUMN_WIDTH))
+            .collect())
+    }
+
+    pub fn write_pdf(&self, path: &Path) -> anyhow::Result<()> {
+        let surface = cairo::PdfSurface::new(PAGE_WIDTH, PAGE_HEIGHT, path)?;
+        let cr = Context::new(&surface)?;
+        cr.set_source_rgb(0.13, 0.13, 0.13);
+
+        // Title block, on the first page only
+        let mut y = PAGE_MARGIN + 18.0;
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        cr.set_font_size(20.0);
+        cr.move_to(PAGE_MARGIN, y);
+        cr.show_text(&self.title())?;
+        y += 24.0;
+        cr.set_font_size(12.0);
+        cr.move_to(PAGE_MARGIN, y);
+        cr.show_text(&self.range_text())?;
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+        cr.set_font_size(10.0);
+        for detail in self.details() {
+            y += 15.0;
+            cr.move_to(PAGE_MARGIN, y);
+            cr.show_text(&detail)?;
+        }
+        y += 15.0;
+        cr.set_source_rgb(0.47, 0.47, 0.47);
+        cr.move_to(PAGE_MARGIN, y);
+        cr.show_text(&self.generated_text())?;
+        y += 30.0;
+
+        let mut page = 1;
+        let headings = self.headings();
+        let widths = self.column_widths(&cr, &headings)?;
+        y = draw_table_header(&cr, y, &headings, &widths)?;
+        for row in self.rows() {
+            if y + ROW_HEIGHT > PAGE_HEIGHT - PAGE_MARGIN {
+                draw_page_number(&cr, page)?;
+                cr.show_page()?;
+                page += 1;
+                y = draw_table_header(&cr, PAGE_MARGIN + 12.0, &headings, &widths)?;
+            }
+            let weight = if row.depth == 0 {
+                FontWeight::Bold
+            } else {
+                FontWeight::Normal
+            };
+            cr.select_font_face("Sans", FontSlant::Normal, weight);
+            cr.set_source_rgb(0.13, 0.13, 0.13);
+            draw_table_row(
+                &cr,
+                y,
+                PAGE_MARGIN + row.depth as f64 * INDENT,
+                &row.node.label(),
+                &self.cells(row.node, Some(row.percent)),
+                &widths,
+            )?;
+            y += ROW_HEIGHT;
+        }
+
+        // Total
+        if y + ROW_HEIGHT > PAGE_HEIGHT - PAGE_MARGIN {
+            draw_page_number(&cr, page)?;
+            cr.show_page()?;
+            page += 1;
+            y = PAGE_MARGIN + 12.0 + ROW_HEIGHT;
+        }
+        cr.set_source_rgb(0.6, 0.6, 0.6);
+        cr.move_to(PAGE_MARGIN, y - 12.0);
+        cr.line_to(PAGE_WIDTH - PAGE_MARGIN, y - 12.0);
+        cr.stroke()?;
+        cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        cr.set_source_rgb(0.13, 0.13, 0.13);
+        draw_table_row(
+            &cr,
+            y + 4.0,
+            PAGE_MARGIN,
+            &gettext("Total"),
+            &self.cells(self.report, None),
+            &widths,
+        )?;
+
+        draw_page_number(&cr, page)?;
+        drop(cr);
+        surface.finish();
+        Ok(())
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Narrowest right-aligned number column, and the space left of its text
+const MIN_COLUMN_WIDTH: f64 = 62.0;
+const COLUMN_GAP: f64 = 12.0;
+
+fn draw_table_header(cr: &Context, y: f64, headings: &[String], widths: &[f64]) -> anyhow::Result<f64> {
+    cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+    cr.set_font_size(10.0);
+    cr.set_source_rgb(0.13, 0.13, 0.13);
+    draw_table_row(cr, y, PAGE_MARGIN, &gettext("Name"), headings, widths)?;
+    cr.set_source_rgb(0.6, 0.6, 0.6);
+    cr.set_line_width(1.0);
+    cr.move_to(PAGE_MARGIN, y + 6.0);
+    cr.line_to(PAGE_WIDTH - PAGE_MARGIN, y + 6.0);
+    cr.stroke()?;
+    Ok(y + ROW_HEIGHT + 4.0)
+}
+
+fn draw_table_row(
+    cr: &Context,
+    y: f64,
+    x: f64,
+    label: &str,
+    cells: &[String],
+    widths: &[f64],
+) -> anyhow::Result<()> {
+    // Number columns line up from the right margin
+    let mut left = PAGE_WIDTH - PAGE_MARGIN - widths.iter().sum::<f64>();
+    let label = fit_text(cr, label, left - x)?;
+    cr.move_to(x, y);
+    cr.show_text(&label)?;
+    for (text, width) in cells.iter().zip(widths.iter()) {
+        left += width;
+        let text = fit_text(cr, text, width - COLUMN_GAP)?;
+        let extents = cr.text_extents(&text)?;
+        cr.move_to(left - extents.x_advance(), y);
+        cr.show_text(&text)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn draw_page_number(cr: &Context, page: i32) -> anyhow::Result<()> {
+    cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Normal);
+    cr.set_font_size(9.0);
+    cr.set_source_rgb(0.47, 0.47, 0.47);
+    let text = gettext("Page {}").replace("{}", &page.to_string());
+    let extents = cr.text_extents(&text)?;
+    cr.move_to((PAGE_WIDTH - extents.x_advance()) / 2.0, PAGE_HEIGHT - PAGE_MARGIN / 2.0);
+    cr.show_text(&text)?;
+    cr.set_font_size(10.0);
+    Ok(())
+}
+
+/// Shortens text with an ellipsis so it fits in `width`.
+pub(crate) fn fit_text(cr: &Context, text: &str, width: f64) -> anyhow::Result<String> {
+    if cr.text_extents(text)?.x_advance() <= width {
+        return Ok(text.to_string());
+    }
+    let mut chars: Vec<char> = text.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let shortened = format!("{}…", chars.iter().collect::<String>());
+        if cr.text_extents(&shortened)?.x_advance() <= width {
+            return Ok(shortened);
+        }
+    }
+    Ok(String::new())
+}
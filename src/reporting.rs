@@ -0,0 +1,986 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Report aggregation, kept free of GTK so the report window, exports and
+//! the command line can share it.
+
+use chrono::{offset::TimeZone, DateTime, Datelike, Duration, Local, NaiveDate, Weekday};
+use gettextrs::*;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::billing::{self, Earnings, RateBook, RateKind};
+use crate::budgets::{self, Budget};
+use crate::store;
+use crate::filter::{self, Expr};
+
+/// An inclusive range of local dates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateRange {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+}
+
+impl DateRange {
+    pub fn new(start: NaiveDate, end: NaiveDate) -> Self {
+        DateRange { start, end }
+    }
+
+    pub fn week_of(day: NaiveDate, week_starts_on: Weekday) -> Self {
+        let start = week_start(day, week_starts_on);
+        DateRange::new(start, start + Duration::days(6))
+    }
+
+    pub fn month_of(day: NaiveDate) -> Self {
+        let start = day.with_day(1).unwrap();
+        let next_month = if start.month() == 12 {
+            NaiveDate::from_ymd_opt(start.year() + 1, 1, 1).unwrap()
+        } else {
+            NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1).unwrap()
+        };
+        DateRange::new(start, next_month - Duration::days(1))
+    }
+
+    /// The last `days` days, including `today`.
+    pub fn last_days(today: NaiveDate, days: i64) -> Self {
+        DateRange::new(today - Duration::days(days - 1), today)
+    }
+
+    pub fn start_time(&self) -> DateTime<Local> {
+        local_midnight(self.start)
+    }
+
+    /// Midnight after the last day, so the range is half-open in time.
+    pub fn end_time(&self) -> DateTime<Local> {
+        local_midnight(self.end + Duration::days(1))
+    }
+
+    pub fn num_days(&self) -> i64 {
+        (self.end - self.start).num_days() + 1
+    }
+
+    pub fn days(&self) -> Vec<NaiveDate> {
+        (0..self.num_days()).map(|i| self.start + Duration::days(i)).collect()
+    }
+}
+
+pub fn week_start(day: NaiveDate, week_starts_on: Weekday) -> NaiveDate {
+    let offset = match week_starts_on {
+        Weekday::Sun => day.weekday().num_days_from_sunday(),
+        _ => day.weekday().num_days_from_monday(),
+    };
+    day - Duration::days(offset as i64)
+}
+
+/// The first moment of `day`. Where daylight saving time skips midnight,
+/// the day starts when the clocks resume.
+pub fn local_midnight(day: NaiveDate) -> DateTime<Local> {
+    let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+    (0..=24 * 4)
+        .map(|quarter| midnight + Duration::minutes(quarter * 15))
+        .find_map(|time| Local.from_local_datetime(&time).earliest())
+        .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+}
+
+/// A range relative to today, so a saved preset keeps meaning "this week".
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RangePreset {
+    Yesterday,
+    ThisWeek,
+    LastWeek,
+    PastWeek,
+    ThisMonth,
+    LastMonth,
+    PastMonth,
+    ThisQuarter,
+    PastHalfYear,
+    ThisYear,
+    YearToDate,
+    PastYear,
+    /// The fiscal year today falls in
+    FiscalYear,
+    Custom(NaiveDate, NaiveDate),
+}
+
+impl RangePreset {
+    /// `fiscal_year_start` is the month the fiscal year starts in, from 1 to 12.
+    pub fn resolve(&self, today: NaiveDate, week_starts_on: Weekday, fiscal_year_start: u32) -> DateRange {
+        match *self {
+            RangePreset::Yesterday => DateRange::last_days(today - Duration::days(1), 1),
+            RangePreset::ThisWeek => DateRange::week_of(today, week_starts_on),
+            RangePreset::LastWeek => DateRange::week_of(today - Duration::days(7), week_starts_on),
+            RangePreset::PastWeek => DateRange::last_days(today, 7),
+            RangePreset::ThisMonth => DateRange::new(today.with_day(1).unwrap(), today),
+            RangePreset::LastMonth => DateRange::month_of(today.with_day(1).unwrap() - Duration::days(1)),
+            RangePreset::PastMonth => DateRange::last_days(today, 30),
+            RangePreset::ThisQuarter => {
+                let start = NaiveDate::from_ymd_opt(today.year(), today.month0() / 3 * 3 + 1, 1).unwrap();
+                DateRange::new(start, DateRange::month_of(months_after(start, 2)).end)
+            }
+            RangePreset::PastHalfYear => DateRange::last_days(today, 180),
+            RangePreset::ThisYear => DateRange::new(
+                NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(today.year(), 12, 31).unwrap(),
+            ),
+            RangePreset::YearToDate => DateRange::new(NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap(), today),
+            RangePreset::PastYear => DateRange::last_days(today, 365),
+            RangePreset::FiscalYear => {
+                let month = fiscal_year_start.clamp(1, 12);
+                let year = if today.month() >= month { today.year() } else { today.year() - 1 };
+                let start = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+                DateRange::new(start, months_after(start, 12) - Duration::days(1))
+            }
+            RangePreset::Custom(start, end) => DateRange::new(start, end),
+        }
+    }
+}
+
+/// The same day `months` months later, or earlier when negative, moved to
+/// the end of the month if that month is shorter.
+pub fn months_after(day: NaiveDate, months: i32) -> NaiveDate {
+    let month_index = day.year() * 12 + day.month0() as i32 + months;
+    let first = NaiveDate::from_ymd_opt(month_index.div_euclid(12), month_index.rem_euclid(12) as u32 + 1, 1).unwrap();
+    let last = DateRange::month_of(first).end;
+    first.with_day(day.day().min(last.day())).unwrap()
+}
+
+/// Limits a report to matching tasks. Names are compared case-insensitively.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Filter {
+    Tasks(Vec<String>),
+    Tags(Vec<String>),
+    Projects(Vec<String>),
+    /// A filter expression, see the `filter` module
+    Expression(String),
+}
+
+impl Filter {
+    pub fn describe(&self) -> String {
+        match self {
+            Filter::Tasks(names) => format!("{}: {}", gettext("Tasks"), names.join(", ")),
+            Filter::Tags(tags) => format!(
+                "{}: {}",
+                gettext("Tags"),
+                tags.iter().map(|tag| format!("#{}", tag)).collect::<Vec<String>>().join(", ")
+            ),
+            Filter::Projects(projects) => format!("{}: {}", gettext("Projects"), projects.join(", ")),
+            Filter::Expression(text) => format!("{}: {}", gettext("Filter"), text),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GroupBy {
+    Task,
+    /// The whole set of tags on a task
+    Tags,
+    /// Each tag on its own, so a task counts toward every tag it has
+    Tag,
+    Project,
+    Day,
+    Week,
+    Month,
+}
+
+impl GroupBy {
+    pub fn title(&self) -> String {
+        match self {
+            GroupBy::Task => gettext("Task"),
+            GroupBy::Tags => gettext("Tag Combination"),
+            GroupBy::Tag => gettext("Tag"),
+            GroupBy::Project => gettext("Project"),
+            GroupBy::Day => gettext("Day"),
+            GroupBy::Week => gettext("Week"),
+            GroupBy::Month => gettext("Month"),
+        }
+    }
+
+    fn is_time(&self) -> bool {
+        matches!(self, GroupBy::Day | GroupBy::Week | GroupBy::Month)
+    }
+
+    /// The kind of budget that can apply to lines of this group.
+    fn budget_kind(&self) -> Option<RateKind> {
+        match self {
+            GroupBy::Task => Some(RateKind::Task),
+            GroupBy::Tag => Some(RateKind::Tag),
+            GroupBy::Project => Some(RateKind::Project),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RoundingMode {
+    Up,
+    Down,
+    Nearest,
+}
+
+/// What gets rounded. Rounding smaller pieces adds up to more time when
+/// rounding up.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RoundingScope {
+    /// Every tracked entry on its own
+    Entry,
+    /// The time spent on each task on each day
+    TaskDay,
+    /// Each total shown in the report
+    Total,
+}
+
+/// Increments in minutes offered for rounding, in the order the dropdowns
+/// list them. 0 is off.
+pub const ROUNDING_INCREMENTS: [i64; 8] = [0, 1, 5, 6, 10, 15, 30, 60];
+pub const ROUNDING_MODES: [RoundingMode; 3] = [RoundingMode::Up, RoundingMode::Down, RoundingMode::Nearest];
+pub const ROUNDING_SCOPES: [RoundingScope; 3] = [RoundingScope::Entry, RoundingScope::TaskDay, RoundingScope::Total];
+
+/// Rounding to billable increments, like 6 or 15 minutes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rounding {
+    pub increment: i64,
+    pub mode: RoundingMode,
+    pub scope: RoundingScope,
+}
+
+impl Rounding {
+    /// Rounds `seconds` to a multiple of the increment, which is in seconds.
+    pub fn round(&self, seconds: i64) -> i64 {
+        if self.increment <= 0 {
+            return seconds;
+        }
+        let below = seconds.div_euclid(self.increment) * self.increment;
+        let rest = seconds - below;
+        if rest == 0 {
+            return seconds;
+        }
+        match self.mode {
+            RoundingMode::Up => below + self.increment,
+            RoundingMode::Down => below,
+            RoundingMode::Nearest if rest * 2 >= self.increment => below + self.increment,
+            RoundingMode::Nearest => below,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mode = match self.mode {
+            RoundingMode::Up => gettext("Rounded up to {} minutes"),
+            RoundingMode::Down => gettext("Rounded down to {} minutes"),
+            RoundingMode::Nearest => gettext("Rounded to the nearest {} minutes"),
+        };
+        let scope = match self.scope {
+            RoundingScope::Entry => gettext("per entry"),
+            RoundingScope::TaskDay => gettext("per task and day"),
+            RoundingScope::Total => gettext("on totals"),
+        };
+        format!("{} {}", mode.replace("{}", &(self.increment / 60).to_string()), scope)
+    }
+}
+
+/// An earlier range to compare a report with.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+    /// The period just before, like last month for this month
+    PreviousPeriod,
+    /// The same dates a year earlier
+    PreviousYear,
+}
+
+pub const COMPARISONS: [Comparison; 2] = [Comparison::PreviousPeriod, Comparison::PreviousYear];
+
+impl Comparison {
+    pub fn title(&self) -> String {
+        match self {
+            Comparison::PreviousPeriod => gettext("Previous Period"),
+            Comparison::PreviousYear => gettext("Same Period Last Year"),
+        }
+    }
+
+    pub fn range_for(&self, range: &DateRange) -> DateRange {
+        match self {
+            Comparison::PreviousPeriod => {
+                // Whole months compare with as many whole months before, so
+                // a quarter compares with a quarter
+                if range.start.day() == 1 && range.end == DateRange::month_of(range.end).end {
+                    let months = (range.end.year() - range.start.year()) * 12 + range.end.month() as i32
+                        - range.start.month() as i32
+                        + 1;
+                    DateRange::new(months_after(range.start, -months), range.start - Duration::days(1))
+                } else {
+                    let days = Duration::days(range.num_days());
+                    DateRange::new(range.start - days, range.end - days)
+                }
+            }
+            Comparison::PreviousYear => DateRange::new(year_earlier(range.start), year_earlier(range.end)),
+        }
+    }
+}
+
+/// The same date a year before, or February 28th for the 29th.
+fn year_earlier(day: NaiveDate) -> NaiveDate {
+    day.with_year(day.year() - 1)
+        .or_else(|| (day - Duration::days(1)).with_year(day.year() - 1))
+        .unwrap_or(day)
+}
+
+#[derive(Clone, Debug)]
+pub struct ReportQuery {
+    pub range: DateRange,
+    pub filters: Vec<Filter>,
+    pub group_by: Vec<GroupBy>,
+    pub week_starts_on: Weekday,
+    pub rounding: Option<Rounding>,
+    pub compare: Option<Comparison>,
+}
+
+/// Report settings saved under a name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReportPreset {
+    pub name: String,
+    pub range: RangePreset,
+    pub filters: Vec<Filter>,
+    pub group_by: Vec<GroupBy>,
+    /// Missing from presets saved before rounding existed
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+    #[serde(default)]
+    pub compare: Option<Comparison>,
+}
+
+impl ReportPreset {
+    pub fn query(&self, today: NaiveDate, week_starts_on: Weekday, fiscal_year_start: u32) -> ReportQuery {
+        ReportQuery {
+            range: self.range.resolve(today, week_starts_on, fiscal_year_start),
+            filters: self.filters.clone(),
+            group_by: self.group_by.clone(),
+            week_starts_on,
+            rounding: self.rounding,
+            compare: self.compare,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ReportNode {
+    pub key: String,
+    pub group: Option<GroupBy>,
+    pub seconds: i64,
+    /// The same as `seconds` unless the query rounds
+    pub rounded_seconds: i64,
+    /// Billable part of `rounded_seconds`
+    pub billable_seconds: i64,
+    pub earnings: Earnings,
+    pub entries: i64,
+    /// Time in the compared range, when comparing. Time periods have none,
+    /// as they don't repeat.
+    pub previous_seconds: Option<i64>,
+    /// Budgeted time for the report's range, for tasks, tags and projects
+    /// with a budget
+    pub estimate: Option<i64>,
+    pub children: Vec<ReportNode>,
+}
+
+impl ReportNode {
+    pub fn label(&self) -> String {
+        match self.group {
+            Some(GroupBy::Tags) | Some(GroupBy::Tag) if self.key.is_empty() => gettext("no tags"),
+            Some(GroupBy::Tags) | Some(GroupBy::Tag) => format!("#{}", self.key),
+            Some(GroupBy::Project) if self.key.is_empty() => gettext("No project"),
+            Some(GroupBy::Week) => gettext("Week of {}").replace("{}", &self.key),
+            _ => self.key.clone(),
+        }
+    }
+
+    /// The change from the compared range, like "+01:30:00 (+25%)".
+    pub fn change_text(&self) -> String {
+        let previous = match self.previous_seconds {
+            Some(previous) => previous,
+            None => return String::new(),
+        };
+        let change = self.seconds - previous;
+        let sign = if change < 0 { "−" } else { "+" };
+        let duration = format!("{}{}", sign, format_duration(change.abs()));
+        if previous == 0 && change > 0 {
+            format!("{} ({})", duration, gettext("new"))
+        } else if previous == 0 {
+            duration
+        } else {
+            format!("{} ({}{:.0}%)", duration, sign, change.abs() as f64 / previous as f64 * 100.0)
+        }
+    }
+
+    /// The estimate and how much of it was used, like "10:00:00 (82%)".
+    pub fn estimate_text(&self) -> String {
+        match self.estimate {
+            Some(estimate) if estimate > 0 => format!(
+                "{} ({:.0}%)",
+                format_duration(estimate),
+                self.seconds as f64 / estimate as f64 * 100.0
+            ),
+            Some(estimate) => format_duration(estimate),
+            None => String::new(),
+        }
+    }
+
+    pub fn has_estimates(&self) -> bool {
+        self.estimate.is_some() || self.children.iter().any(|child| child.has_estimates())
+    }
+
+    /// Share of the parent's time, from 0 to 100.
+    pub fn percent_of(&self, total_seconds: i64) -> f64 {
+        if total_seconds == 0 {
+            0.0
+        } else {
+            self.seconds as f64 / total_seconds as f64 * 100.0
+        }
+    }
+}
+
+pub fn format_duration(total_time: i64) -> String {
+    let h = total_time / 3600;
+    let m = total_time % 3600 / 60;
+    let s = total_time % 60;
+    format!("{:02}:{:02}:{:02}", h, m, s)
+}
+
+/// Time per task name, tags, project, billable flag and local day, as
+/// summed by SQLite.
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    pub task_name: String,
+    pub tags: String,
+    pub project: String,
+    pub billable: Option<bool>,
+    pub day: NaiveDate,
+    pub seconds: i64,
+    /// Set by `run` when rounding per entry or per task and day
+    pub rounded_seconds: i64,
+    /// Set by `run` from the rates
+    pub billable_seconds: i64,
+    pub earnings: Earnings,
+    pub entries: i64,
+}
+
+impl Bucket {
+    fn tag_list(&self) -> Vec<String> {
+        store::split_tags(&self.tags)
+    }
+}
+
+pub fn load_buckets(conn: &Connection, range: &DateRange) -> anyhow::Result<Vec<Bucket>> {
+    load_rows(conn, range, "GROUP BY task_name, tags, project, billable, day")
+}
+
+/// One bucket per entry, for filters that look at single entries.
+pub fn load_entries(conn: &Connection, range: &DateRange) -> anyhow::Result<Vec<Bucket>> {
+    load_rows(conn, range, "GROUP BY id")
+}
+
+/// The local day of the earliest entry, if there are any.
+pub fn first_tracked_day(conn: &Connection) -> anyhow::Result<Option<NaiveDate>> {
+    let day: Option<String> = conn
+        .query_row(
+            "SELECT date(min(julianday(start_time)), 'localtime') FROM tasks",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    Ok(day.and_then(|day| NaiveDate::parse_from_str(&day, "%F").ok()))
+}
+
+fn load_rows(conn: &Connection, range: &DateRange, group_by: &str) -> anyhow::Result<Vec<Bucket>> {
+    // julianday() understands the stored UTC offsets, and 'localtime'
+    // turns the start back into the user's local day
+    let mut query = conn.prepare(&format!(
+        "SELECT task_name, tags, project, billable, date(start_time, 'localtime') AS day,
+                CAST(round(sum((julianday(stop_time) - julianday(start_time)) * 86400)) AS INTEGER),
+                count(*)
+            FROM tasks
+            WHERE julianday(start_time) >= julianday(?1) AND julianday(start_time) < julianday(?2)
+            {}",
+        group_by
+    ))?;
+
+    let rows = query.query_map(
+        [range.start_time().to_rfc3339(), range.end_time().to_rfc3339()],
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                row.get::<_, String>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(6)?,
+            ))
+        },
+    )?;
+
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for row in rows {
+        let (task_name, tags, project, billable, day, seconds, entries) = row?;
+        buckets.push(Bucket {
+            task_name,
+            tags,
+            project,
+            billable: billing::billable_from_sqlite(&billable),
+            day: NaiveDate::parse_from_str(&day, "%Y-%m-%d")?,
+            seconds,
+            rounded_seconds: seconds,
+            billable_seconds: 0,
+            earnings: Earnings::default(),
+            entries,
+        });
+    }
+
+    Ok(buckets)
+}
+
+fn matches_filters(bucket: &Bucket, filters: &[Filter], expressions: &[Expr]) -> bool {
+    expressions.iter().all(|expr| expr.matches(bucket)) && filters.iter().all(|filter| match filter {
+        Filter::Tasks(names) => names.iter().any(|name| name.to_lowercase() == bucket.task_name.to_lowercase()),
+        Filter::Tags(tags) => {
+            let bucket_tags = bucket.tag_list();
+            tags.iter().any(|tag| bucket_tags.contains(&tag.to_lowercase()))
+        }
+        Filter::Projects(projects) => projects.iter().any(|project| project.to_lowercase() == bucket.project.to_lowercase()),
+        Filter::Expression(_) => true,
+    })
+}
+
+fn keys_for(bucket: &Bucket, group: GroupBy, query: &ReportQuery) -> Vec<String> {
+    match group {
+        GroupBy::Task => vec![bucket.task_name.clone()],
+        GroupBy::Tags => vec![bucket.tag_list().join(" #")],
+        GroupBy::Tag => {
+            let mut tags = bucket.tag_list();
+            // When filtering by tags, only show the chosen ones
+            for filter in &query.filters {
+                if let Filter::Tags(chosen) = filter {
+                    let chosen: Vec<String> = chosen.iter().map(|tag| tag.to_lowercase()).collect();
+                    tags.retain(|tag| chosen.contains(tag));
+                }
+            }
+            if tags.is_empty() {
+                vec![String::new()]
+            } else {
+                tags
+            }
+        }
+        GroupBy::Project => vec![bucket.project.clone()],
+        GroupBy::Day => vec![bucket.day.format("%F").to_string()],
+        GroupBy::Week => vec![week_start(bucket.day, query.week_starts_on).format("%F").to_string()],
+        GroupBy::Month => vec![bucket.day.format("%Y-%m").to_string()],
+    }
+}
+
+fn build_level(buckets: &[&Bucket], levels: &[GroupBy], query: &ReportQuery) -> Vec<ReportNode> {
+    let group = match levels.first() {
+        Some(group) => *group,
+        None => return Vec::new(),
+    };
+
+    let mut order: Vec<String> = Vec::new();
+    let mut grouped: HashMap<String, Vec<&Bucket>> = HashMap::new();
+    for bucket in buckets {
+        for key in keys_for(bucket, group, query) {
+            if !grouped.contains_key(&key) {
+                order.push(key.clone());
+            }
+            grouped.entry(key).or_default().push(bucket);
+        }
+    }
+
+    let mut nodes: Vec<ReportNode> = order
+        .into_iter()
+        .map(|key| {
+            let members = &grouped[&key];
+            let seconds = members.iter().map(|bucket| bucket.seconds).sum();
+            ReportNode {
+                group: Some(group),
+                seconds,
+                rounded_seconds: rounded_sum(members, seconds, query),
+                billable_seconds: members.iter().map(|bucket| bucket.billable_seconds).sum(),
+                earnings: earnings_sum(members),
+                entries: members.iter().map(|bucket| bucket.entries).sum(),
+                previous_seconds: None,
+                estimate: None,
+                children: build_level(members, &levels[1..], query),
+                key,
+            }
+        })
+        .collect();
+
+    // Time periods read best in order, everything else by most time spent
+    if group.is_time() {
+        nodes.sort_by(|a, b| a.key.cmp(&b.key));
+    } else {
+        nodes.sort_by(|a, b| b.seconds.cmp(&a.seconds).then_with(|| a.key.cmp(&b.key)));
+    }
+    nodes
+}
+
+fn rounded_sum(buckets: &[&Bucket], seconds: i64, query: &ReportQuery) -> i64 {
+    match query.rounding {
+        Some(rounding) if rounding.scope == RoundingScope::Total => rounding.round(seconds),
+        _ => buckets.iter().map(|bucket| bucket.rounded_seconds).sum(),
+    }
+}
+
+fn earnings_sum(buckets: &[&Bucket]) -> Earnings {
+    let mut earnings = Earnings::default();
+    for bucket in buckets {
+        earnings.merge(&bucket.earnings);
+    }
+    earnings
+}
+
+/// Adds up single entries into one bucket per task and day, in the order
+/// each task and day first appears.
+fn merge_task_days(entries: Vec<Bucket>) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+    let mut index: HashMap<(String, String, String, Option<bool>, NaiveDate), usize> = HashMap::new();
+    for entry in entries {
+        let key = (
+            entry.task_name.clone(),
+            entry.tags.clone(),
+            entry.project.clone(),
+            entry.billable,
+            entry.day,
+        );
+        match index.get(&key) {
+            Some(&i) => {
+                let bucket = &mut buckets[i];
+                bucket.seconds += entry.seconds;
+                bucket.rounded_seconds += entry.rounded_seconds;
+                bucket.entries += entry.entries;
+            }
+            None => {
+                index.insert(key, buckets.len());
+                buckets.push(entry);
+            }
+        }
+    }
+    buckets
+}
+
+/// Sets `previous_seconds` from the matching nodes of the compared report,
+/// adding what only had time back then. Time periods never match, so
+/// comparing stops at them.
+fn compare_nodes(node: &mut ReportNode, previous: &ReportNode) {
+    node.previous_seconds = Some(previous.seconds);
+    let is_time = |node: &ReportNode| node.group.map_or(false, |group| group.is_time());
+
+    for child in node.children.iter_mut().filter(|child| !is_time(child)) {
+        match previous.children.iter().find(|before| before.key == child.key) {
+            Some(before) => compare_nodes(child, before),
+            None => compare_nodes(child, &ReportNode::default()),
+        }
+    }
+    for before in &previous.children {
+        if !is_time(before) && !node.children.iter().any(|child| child.key == before.key) {
+            let mut gone = ReportNode {
+                key: before.key.clone(),
+                group: before.group,
+                ..ReportNode::default()
+            };
+            compare_nodes(&mut gone, before);
+            node.children.push(gone);
+        }
+    }
+}
+
+/// Runs a report and returns its root node, which holds the overall total.
+pub fn run(conn: &Connection, query: &ReportQuery) -> anyhow::Result<ReportNode> {
+    let mut report = run_range(conn, query)?;
+    if let Some(compare) = query.compare {
+        let previous = run_range(
+            conn,
+            &ReportQuery {
+                range: compare.range_for(&query.range),
+                ..query.clone()
+            },
+        )?;
+        compare_nodes(&mut report, &previous);
+    }
+    let budgets = store::budgets_from(conn)?;
+    set_estimates(&mut report, &budgets, &query.range);
+    Ok(report)
+}
+
+/// Sets the estimate of every line with a budget, for the report's range.
+fn set_estimates(node: &mut ReportNode, budgets: &[Budget], range: &DateRange) {
+    if let Some(kind) = node.group.and_then(|group| group.budget_kind()) {
+        node.estimate = budgets::budget_for(budgets, kind, &node.key).map(|budget| budget.estimate_for(range));
+    }
+    for child in &mut node.children {
+        set_estimates(child, budgets, range);
+    }
+}
+
+fn run_range(conn: &Connection, query: &ReportQuery) -> anyhow::Result<ReportNode> {
+    let expressions = query
+        .filters
+        .iter()
+        .filter_map(|filter| match filter {
+            Filter::Expression(text) => Some(filter::parse(text)),
+            _ => None,
+        })
+        .collect::<Result<Vec<Expr>, _>>()?;
+
+    let scope = query.rounding.map(|rounding| rounding.scope);
+
+    // Expressions can ask about single entries, like their duration, and
+    // rounding per entry needs them too
+    let mut buckets = if expressions.is_empty() && scope != Some(RoundingScope::Entry) {
+        load_buckets(conn, &query.range)?
+    } else {
+        load_entries(conn, &query.range)?
+    };
+    buckets.retain(|bucket| matches_filters(bucket, &query.filters, &expressions));
+
+    if let Some(rounding) = query.rounding {
+        if rounding.scope == RoundingScope::TaskDay && !expressions.is_empty() {
+            buckets = merge_task_days(buckets);
+        }
+        if rounding.scope != RoundingScope::Total {
+            for bucket in &mut buckets {
+                bucket.rounded_seconds = rounding.round(bucket.seconds);
+            }
+        }
+    }
+
+    // Billing goes by the time rounded per entry or per task and day, so
+    // rates apply to what gets invoiced. Rounding only the total leaves the
+    // earnings as they are.
+    let rates = RateBook::new(store::rates_from(conn)?);
+    for bucket in &mut buckets {
+        let rate = rates.rate_for(&bucket.task_name, &bucket.tag_list(), &bucket.project);
+        if billing::is_billable(bucket.billable, rate) {
+            bucket.billable_seconds = bucket.rounded_seconds;
+            if let Some(rate) = rate {
+                bucket.earnings = Earnings::for_time(bucket.rounded_seconds, rate);
+            }
+        }
+    }
+
+    let buckets: Vec<&Bucket> = buckets.iter().collect();
+    let seconds = buckets.iter().map(|bucket| bucket.seconds).sum();
+    Ok(ReportNode {
+        key: String::new(),
+        group: None,
+        seconds,
+        rounded_seconds: rounded_sum(&buckets, seconds, query),
+        billable_seconds: buckets.iter().map(|bucket| bucket.billable_seconds).sum(),
+        earnings: earnings_sum(&buckets),
+        entries: buckets.iter().map(|bucket| bucket.entries).sum(),
+        previous_seconds: None,
+        estimate: None,
+        children: build_level(&buckets, &query.group_by, query),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{self, ChangeOrigin};
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 3, d).unwrap()
+    }
+
+    fn test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        store::create_tasks_table(&conn).unwrap();
+        store::create_changes_table(&conn).unwrap();
+        store::create_rates_table(&conn).unwrap();
+        store::create_budget_table(&conn).unwrap();
+        conn
+    }
+
+    /// Adds an entry starting `hour` hours after midnight on `day`.
+    fn add(conn: &Connection, name: &str, tags: &str, project: &str, day: NaiveDate, hour: i64, minutes: i64) {
+        let start = local_midnight(day) + Duration::hours(hour);
+        let stop = start + Duration::minutes(minutes);
+        store::insert_task(
+            conn,
+            name,
+            &start.to_rfc3339(),
+            &stop.to_rfc3339(),
+            tags,
+            project,
+            ChangeOrigin::Manual,
+        )
+        .unwrap();
+    }
+
+    fn query(range: DateRange, group_by: Vec<GroupBy>) -> ReportQuery {
+        ReportQuery {
+            range,
+            filters: Vec::new(),
+            group_by,
+            week_starts_on: Weekday::Mon,
+            rounding: None,
+            compare: None,
+        }
+    }
+
+    #[test]
+    fn date_ranges() {
+        // March 8th 2023 is a Wednesday
+        assert_eq!(DateRange::week_of(day(8), Weekday::Mon), DateRange::new(day(6), day(12)));
+        assert_eq!(DateRange::week_of(day(8), Weekday::Sun), DateRange::new(day(5), day(11)));
+        assert_eq!(DateRange::month_of(day(8)), DateRange::new(day(1), day(31)));
+        assert_eq!(
+            DateRange::month_of(NaiveDate::from_ymd_opt(2023, 12, 24).unwrap()).end,
+            NaiveDate::from_ymd_opt(2023, 12, 31).unwrap()
+        );
+        assert_eq!(DateRange::last_days(day(8), 7), DateRange::new(day(2), day(8)));
+        assert_eq!(DateRange::new(day(6), day(12)).num_days(), 7);
+        assert_eq!(DateRange::new(day(6), day(6)).end_time(), local_midnight(day(7)));
+        assert_eq!(
+            months_after(NaiveDate::from_ymd_opt(2023, 1, 31).unwrap(), 1),
+            NaiveDate::from_ymd_opt(2023, 2, 28).unwrap()
+        );
+    }
+
+    #[test]
+    fn previous_periods() {
+        let may = |d| NaiveDate::from_ymd_opt(2023, 5, d).unwrap();
+        let april = |d| NaiveDate::from_ymd_opt(2023, 4, d).unwrap();
+        let previous = |start, end| Comparison::PreviousPeriod.range_for(&DateRange::new(start, end));
+
+        // A week starting on the 1st is still a week
+        assert_eq!(previous(may(1), may(7)), DateRange::new(april(24), april(30)));
+        assert_eq!(previous(may(1), may(31)), DateRange::new(april(1), april(30)));
+        assert_eq!(
+            previous(april(1), NaiveDate::from_ymd_opt(2023, 6, 30).unwrap()),
+            DateRange::new(NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), NaiveDate::from_ymd_opt(2023, 3, 31).unwrap())
+        );
+    }
+
+    #[test]
+    fn buckets_sum_per_task_and_day() {
+        let conn = test_db();
+        add(&conn, "Code", "rust", "App", day(6), 9, 60);
+        add(&conn, "Code", "rust", "App", day(6), 13, 30);
+        add(&conn, "Code", "rust", "App", day(7), 9, 45);
+        add(&conn, "Email", "", "", day(6), 8, 15);
+
+        let mut buckets = load_buckets(&conn, &DateRange::new(day(6), day(7))).unwrap();
+        buckets.sort_by(|a, b| (a.day, &a.task_name).cmp(&(b.day, &b.task_name)));
+        let summary: Vec<(NaiveDate, &str, i64, i64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.day, bucket.task_name.as_str(), bucket.seconds, bucket.entries))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (day(6), "Code", 90 * 60, 2),
+                (day(6), "Email", 15 * 60, 1),
+                (day(7), "Code", 45 * 60, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn range_includes_start_and_excludes_next_midnight() {
+        let conn = test_db();
+        add(&conn, "First", "", "", day(6), 0, 10);
+        add(&conn, "Last", "", "", day(7), 23, 10);
+        add(&conn, "Next day", "", "", day(8), 0, 10);
+        add(&conn, "Day before", "", "", day(5), 23, 10);
+
+        let report = run(&conn, &query(DateRange::new(day(6), day(7)), vec![GroupBy::Task])).unwrap();
+        let mut names: Vec<&str> = report.children.iter().map(|node| node.key.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["First", "Last"]);
+        assert_eq!(report.seconds, 20 * 60);
+    }
+
+    #[test]
+    fn group_by_builds_a_tree() {
+        let conn = test_db();
+        add(&conn, "Code", "rust", "App", day(6), 9, 60);
+        add(&conn, "Review", "rust #work", "App", day(6), 11, 30);
+        add(&conn, "Email", "work", "", day(7), 9, 20);
+
+        let report = run(
+            &conn,
+            &query(DateRange::new(day(6), day(12)), vec![GroupBy::Project, GroupBy::Task]),
+        )
+        .unwrap();
+        assert_eq!(report.seconds, 110 * 60);
+        assert_eq!(report.entries, 3);
+
+        let app = &report.children[0];
+        assert_eq!((app.key.as_str(), app.seconds), ("App", 90 * 60));
+        let tasks: Vec<(&str, i64)> = app.children.iter().map(|node| (node.key.as_str(), node.seconds)).collect();
+        assert_eq!(tasks, vec![("Code", 60 * 60), ("Review", 30 * 60)]);
+        let no_project = &report.children[1];
+        assert_eq!((no_project.key.as_str(), no_project.seconds), ("", 20 * 60));
+
+        // Each tag counts the whole time of every task that has it
+        let report = run(&conn, &query(DateRange::new(day(6), day(12)), vec![GroupBy::Tag])).unwrap();
+        let tags: Vec<(&str, i64)> = report.children.iter().map(|node| (node.key.as_str(), node.seconds)).collect();
+        assert_eq!(tags, vec![("rust", 90 * 60), ("work", 50 * 60)]);
+
+        let report = run(&conn, &query(DateRange::new(day(6), day(12)), vec![GroupBy::Day])).unwrap();
+        let days: Vec<(&str, i64)> = report.children.iter().map(|node| (node.key.as_str(), node.seconds)).collect();
+        assert_eq!(days, vec![("2023-03-06", 90 * 60), ("2023-03-07", 20 * 60)]);
+    }
+
+    #[test]
+    fn filters_limit_the_totals() {
+        let conn = test_db();
+        add(&conn, "Code", "rust", "App", day(6), 9, 60);
+        add(&conn, "Email", "work", "", day(6), 11, 20);
+
+        let mut by_tag = query(DateRange::new(day(6), day(6)), vec![GroupBy::Task]);
+        by_tag.filters = vec![Filter::Tags(vec!["Rust".to_string()])];
+        assert_eq!(run(&conn, &by_tag).unwrap().seconds, 60 * 60);
+
+        let mut by_project = query(DateRange::new(day(6), day(6)), vec![GroupBy::Task]);
+        by_project.filters = vec![Filter::Projects(vec!["app".to_string()])];
+        assert_eq!(run(&conn, &by_project).unwrap().seconds, 60 * 60);
+    }
+
+    #[test]
+    fn entries_merge_per_task_and_day_in_order() {
+        let conn = test_db();
+        add(&conn, "Email", "", "", day(6), 8, 15);
+        add(&conn, "Code", "rust", "App", day(6), 9, 60);
+        add(&conn, "Email", "", "", day(6), 12, 10);
+        add(&conn, "Code", "rust", "App", day(6), 13, 30);
+        add(&conn, "Code", "rust", "", day(6), 15, 20);
+
+        let entries = load_entries(&conn, &DateRange::new(day(6), day(6))).unwrap();
+        let merged: Vec<(String, String, i64, i64)> = merge_task_days(entries)
+            .into_iter()
+            .map(|bucket| (bucket.task_name, bucket.project, bucket.seconds, bucket.entries))
+            .collect();
+        assert_eq!(
+            merged,
+            vec![
+                ("Email".to_string(), String::new(), 25 * 60, 2),
+                ("Code".to_string(), "App".to_string(), 90 * 60, 2),
+                ("Code".to_string(), String::new(), 20 * 60, 1),
+            ]
+        );
+    }
+}
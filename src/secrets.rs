@@ -0,0 +1,76 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The sync server's token and encryption key, kept in the keyring through
+//! the Secret Service rather than in GSettings, where anyone who can read
+//! dconf could read them.
+
+use secret_service::blocking::{Collection, SecretService};
+use secret_service::EncryptionType;
+use std::collections::HashMap;
+
+use crate::config;
+
+pub const SYNC_SERVER_TOKEN: &str = "sync-server-token";
+/// The key made from the passphrase, in base64
+pub const SYNC_SERVER_KEY: &str = "sync-server-key";
+
+fn attributes(name: &str) -> HashMap<&str, &str> {
+    HashMap::from([
+        ("application", config::APP_ID.trim_end_matches(".Devel")),
+        ("name", name),
+    ])
+}
+
+/// Runs `f` on the default keyring, unlocking it first, which may ask for
+/// the login password.
+fn with_keyring<T>(f: impl FnOnce(&Collection) -> anyhow::Result<T>) -> anyhow::Result<T> {
+    let service = SecretService::connect(EncryptionType::Dh)?;
+    let collection = service.get_default_collection()?;
+    if collection.is_locked()? {
+        collection.unlock()?;
+    }
+    f(&collection)
+}
+
+/// The secret stored under `name`, or None if there is none.
+pub fn lookup(name: &str) -> anyhow::Result<Option<String>> {
+    with_keyring(|collection| match collection.search_items(attributes(name))?.first() {
+        Some(item) => Ok(Some(String::from_utf8(item.get_secret()?)?)),
+        None => Ok(None),
+    })
+}
+
+/// Stores `value` under `name`, replacing what was there. An empty value
+/// removes it.
+pub fn store(name: &str, value: &str) -> anyhow::Result<()> {
+    with_keyring(|collection| {
+        if value.is_empty() {
+            for item in collection.search_items(attributes(name))? {
+                item.delete()?;
+            }
+        } else {
+            collection.create_item(
+                &format!("rusttimetrack {}", name),
+                attributes(name),
+                value.as_bytes(),
+                true,
+                "text/plain",
+            )?;
+        }
+        Ok(())
+    })
+}
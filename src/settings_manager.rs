@@ -15,6 +15,11 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use crate::config;
+use crate::goals::Goals;
+use crate::reminders::{ReminderSettings, WorkHours};
+use crate::remote_sync::{Server, SyncKey};
+use crate::secrets;
+use crate::sync::SyncSettings;
 use gtk::{gio, gio::prelude::*, glib};
 
 pub fn get_settings() -> gio::Settings {
@@ -48,8 +53,95 @@ pub fn get_string(key: &str) -> String {
     settings.string(key).to_string()
 }
 
+pub fn week_starts_on() -> chrono::Weekday {
+    if get_int("week-starts") == 1 {
+        chrono::Weekday::Sun
+    } else {
+        chrono::Weekday::Mon
+    }
+}
+
+/// The month the fiscal year starts in, from 1 for January to 12.
+pub fn fiscal_year_start() -> u32 {
+    get_int("fiscal-year-start").clamp(0, 11) as u32 + 1
+}
+
+/// The daily and weekly goals, which are stored in minutes.
+pub fn goals() -> Goals {
+    Goals {
+        daily: get_int("daily-goal") as i64 * 60,
+        weekly: get_int("weekly-goal") as i64 * 60,
+        week_starts_on: week_starts_on(),
+        balance_since: chrono::NaiveDate::parse_from_str(&get_string("goal-balance-since"), "%F").ok(),
+    }
+}
+
+/// Working hours and reminders. Days are a bit each, Monday lowest, and
+/// times are minutes after midnight.
+pub fn reminder_settings() -> ReminderSettings {
+    let start_after = get_int("remind-start-after").max(1) as i64;
+    let stop_after = get_int("remind-stop-after").max(1) as i64;
+    ReminderSettings {
+        hours: WorkHours::new(get_int("work-days"), get_int("work-start"), get_int("work-end")),
+        start_after: Some(chrono::Duration::minutes(start_after)).filter(|_| get_bool("remind-to-start")),
+        stop_after: Some(chrono::Duration::hours(stop_after)).filter(|_| get_bool("remind-to-stop")),
+    }
+}
+
+/// This device's id in the sync folder, made up the first time it's needed.
+pub fn sync_device_id() -> anyhow::Result<String> {
+    let device = get_string("sync-device-id");
+    if !device.is_empty() {
+        return Ok(device);
+    }
+    let device = crate::database::new_uuid()?;
+    set_string("sync-device-id", &device);
+    Ok(device)
+}
+
+/// Where to sync to, or None if neither a folder nor a server is set. Only
+/// the server's URL is kept here; its token and key are in the keyring.
+pub fn sync_settings() -> anyhow::Result<Option<SyncSettings>> {
+    let folder = get_string("sync-folder");
+    let url = get_string("sync-server-url");
+
+    let server = if url.is_empty() {
+        None
+    } else {
+        match secrets::lookup(secrets::SYNC_SERVER_KEY)? {
+            Some(key) => Some(Server {
+                url,
+                token: secrets::lookup(secrets::SYNC_SERVER_TOKEN)?.unwrap_or_default(),
+                key: SyncKey::from_base64(&key)?,
+            }),
+            None => None,
+        }
+    };
+    if folder.is_empty() && server.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(SyncSettings {
+        device: sync_device_id()?,
+        folder: Some(std::path::PathBuf::from(folder)).filter(|folder| !folder.as_os_str().is_empty()),
+        server,
+    }))
+}
+
 #[allow(dead_code)]
 pub fn set_int(key: &str, val: i32) {
     let settings = get_settings();
     _ = settings.set_int(key, val);
 }
+
+#[allow(dead_code)]
+pub fn get_double(key: &str) -> f64 {
+    let settings = get_settings();
+    settings.double(key)
+}
+
+#[allow(dead_code)]
+pub fn set_string(key: &str, val: &str) {
+    let settings = get_settings();
+    _ = settings.set_string(key, val);
+}
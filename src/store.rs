@@ -0,0 +1,659 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The tasks table and the writes and queries on it that only need an open
+//! connection. `database` adds where the file lives and the dialogs, and
+//! the GTK-free modules like `reporting` and `sync` use this directly.
+
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, Result};
+
+use crate::billing::{self, Rate, RateKind};
+use crate::budgets::{Budget, BudgetPeriod};
+use crate::reporting::Rounding;
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Task {
+    pub id: i32,
+    pub task_name: String,
+    pub start_time: String,
+    pub stop_time: String,
+    pub tags: String,
+    pub project: String,
+    /// None follows the rates set for its task, tags or project
+    pub billable: Option<bool>,
+    /// Stays the same across databases, unlike the id
+    pub uuid: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl ToString for Task {
+    fn to_string(&self) -> String {
+        if !self.tags.is_empty() {
+            format!("{} #{}", self.task_name, self.tags)
+        } else {
+            self.task_name.to_string()
+        }
+    }
+}
+
+/// Where a write to the tasks table came from, recorded in the change log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOrigin {
+    Timer,
+    Manual,
+    Autosave,
+    Import,
+    Sync,
+    /// Started from the command line, without the window
+    Cli,
+}
+
+impl ChangeOrigin {
+    pub(crate) fn to_sqlite(&self) -> &str {
+        match self {
+            Self::Timer => "timer",
+            Self::Manual => "manual",
+            Self::Autosave => "autosave",
+            Self::Import => "import",
+            Self::Sync => "sync",
+            Self::Cli => "cli",
+        }
+    }
+
+    pub(crate) fn from_sqlite(value: &str) -> Self {
+        match value {
+            "timer" => Self::Timer,
+            "autosave" => Self::Autosave,
+            "import" => Self::Import,
+            "sync" => Self::Sync,
+            "cli" => Self::Cli,
+            _ => Self::Manual,
+        }
+    }
+}
+
+/// One entry of the append-only change log kept for every task. Entries
+/// belong to the task's uuid, so a new task that gets the id of a deleted
+/// one doesn't inherit its history.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub id: i64,
+    pub task_id: i32,
+    pub task_uuid: String,
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+    pub changed_at: String,
+    pub origin: ChangeOrigin,
+}
+
+pub(crate) fn create_tasks_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE tasks (
+                    id integer primary key,
+                    task_name text,
+                    start_time timestamp,
+                    stop_time timestamp,
+                    tags text,
+                    project text DEFAULT '',
+                    billable text DEFAULT '',
+                    uuid text,
+                    created_at timestamp,
+                    updated_at timestamp)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub(crate) fn add_billable_column(conn: &Connection) -> Result<()> {
+    conn.execute("ALTER TABLE tasks ADD COLUMN billable TEXT DEFAULT ''", [])?;
+
+    Ok(())
+}
+
+/// A random version 4 UUID, made by SQLite so existing rows can get one too.
+pub(crate) const NEW_UUID: &str = "lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' ||
+    substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) ||
+    substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))";
+
+/// A new random UUID, like the ones tasks get.
+pub fn new_uuid() -> Result<String> {
+    let conn = Connection::open_in_memory()?;
+    conn.query_row(format!("SELECT {}", NEW_UUID).as_str(), [], |row| row.get(0))
+}
+
+/// Adds the uuid, created_at and updated_at columns if missing, and fills
+/// them in for older rows. The change log is the best guess for when a row
+/// was made and last edited.
+pub(crate) fn add_identity_columns(conn: &Connection) -> Result<()> {
+    // Each fails if the column is already there
+    let _ = conn.execute("ALTER TABLE tasks ADD COLUMN uuid TEXT", []);
+    let _ = conn.execute("ALTER TABLE tasks ADD COLUMN created_at TIMESTAMP", []);
+    let _ = conn.execute("ALTER TABLE tasks ADD COLUMN updated_at TIMESTAMP", []);
+    create_changes_table(conn)?;
+
+    conn.execute(
+        format!("UPDATE tasks SET uuid = {} WHERE uuid IS NULL OR uuid = ''", NEW_UUID).as_str(),
+        [],
+    )?;
+    conn.execute(
+        "UPDATE tasks SET created_at = COALESCE(
+                (SELECT MIN(changed_at) FROM changes WHERE changes.task_id = tasks.id), stop_time)
+            WHERE created_at IS NULL OR created_at = ''",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE tasks SET updated_at = COALESCE(
+                (SELECT MAX(changed_at) FROM changes WHERE changes.task_id = tasks.id), created_at)
+            WHERE updated_at IS NULL OR updated_at = ''",
+        [],
+    )?;
+    conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS tasks_uuid ON tasks (uuid)", [])?;
+    add_change_uuids(conn)?;
+    create_deleted_table(conn)?;
+
+    Ok(())
+}
+
+pub(crate) fn create_changes_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS changes (
+                    id integer primary key,
+                    task_id integer,
+                    task_uuid text,
+                    field text,
+                    old_value text,
+                    new_value text,
+                    changed_at timestamp,
+                    origin text)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// When each deleted task was deleted, by its uuid, so sync can tell an old
+/// deletion from a newer edit made elsewhere. The device is the one that
+/// deleted it, or NULL for this one.
+pub(crate) fn create_deleted_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_deleted (
+                    uuid text primary key,
+                    deleted_at text,
+                    device text)",
+        [],
+    )?;
+    // Older tables lack the device, and fail if it's already there
+    let _ = conn.execute("ALTER TABLE sync_deleted ADD COLUMN device TEXT", []);
+
+    Ok(())
+}
+
+/// Gives the entries of an older change log the uuid of their task. Entries
+/// up to a task id's last deletion belong to tasks that are gone, and stay
+/// without one.
+fn add_change_uuids(conn: &Connection) -> Result<()> {
+    // Fails if the column is already there
+    if conn.execute("ALTER TABLE changes ADD COLUMN task_uuid TEXT", []).is_ok() {
+        conn.execute(
+            "UPDATE changes SET task_uuid = (SELECT uuid FROM tasks WHERE tasks.id = changes.task_id)
+                WHERE id > COALESCE((SELECT MAX(deleted.id) FROM changes AS deleted
+                    WHERE deleted.task_id = changes.task_id AND deleted.field = 'deleted'), 0)",
+            [],
+        )?;
+    }
+    conn.execute("CREATE INDEX IF NOT EXISTS changes_task_uuid ON changes (task_uuid)", [])?;
+
+    Ok(())
+}
+
+pub(crate) fn create_rates_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rates (
+                    id integer primary key,
+                    kind text,
+                    name text,
+                    hourly_rate real,
+                    currency text,
+                    billable integer,
+                    UNIQUE(kind, name))",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub(crate) fn create_budget_table(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS budgets (
+                    id integer primary key,
+                    kind text,
+                    name text,
+                    seconds integer,
+                    period text,
+                    UNIQUE(kind, name))",
+        [],
+    )?;
+
+    Ok(())
+}
+
+pub(crate) fn create_invoice_tables(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS invoices (
+                    id integer primary key,
+                    number text UNIQUE,
+                    client text,
+                    project text,
+                    start_date text,
+                    end_date text,
+                    issued_at timestamp,
+                    currency text,
+                    subtotal real,
+                    tax_rate real,
+                    total real)",
+        [],
+    )?;
+    // Each entry can only be on one invoice, so it's never billed twice.
+    // Entries go by uuid, as SQLite can give a deleted task's id to a new one.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS invoice_entries (
+                    invoice_id integer,
+                    task_uuid text UNIQUE)",
+        [],
+    )?;
+    // Older tables only had the task id, and fail if the column is there
+    if conn.execute("ALTER TABLE invoice_entries ADD COLUMN task_uuid TEXT", []).is_ok() {
+        conn.execute(
+            "UPDATE invoice_entries SET task_uuid =
+                (SELECT uuid FROM tasks WHERE tasks.id = invoice_entries.task_id)",
+            [],
+        )?;
+        conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS invoice_entries_uuid ON invoice_entries (task_uuid)",
+            [],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn log_change(
+    conn: &Connection,
+    task_id: i32,
+    field: &str,
+    old_value: &str,
+    new_value: &str,
+    origin: ChangeOrigin,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+            values (?1, (SELECT uuid FROM tasks WHERE id = ?1), ?2, ?3, ?4, ?5, ?6)",
+        (
+            task_id,
+            field,
+            old_value,
+            new_value,
+            Local::now().to_rfc3339(),
+            origin.to_sqlite(),
+        ),
+    )?;
+
+    Ok(())
+}
+
+fn log_created(
+    conn: &Connection,
+    task_id: i32,
+    task_name: &str,
+    start_time: &str,
+    stop_time: &str,
+    tags: &str,
+    project: &str,
+    origin: ChangeOrigin,
+) -> Result<()> {
+    log_change(conn, task_id, "task_name", "", task_name, origin)?;
+    log_change(conn, task_id, "start_time", "", start_time, origin)?;
+    log_change(conn, task_id, "stop_time", "", stop_time, origin)?;
+    if !tags.trim().is_empty() {
+        log_change(conn, task_id, "tags", "", tags, origin)?;
+    }
+    if !project.trim().is_empty() {
+        log_change(conn, task_id, "project", "", project, origin)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn insert_task(
+    conn: &Connection,
+    task_name: &str,
+    start_time: &str,
+    stop_time: &str,
+    tags: &str,
+    project: &str,
+    origin: ChangeOrigin,
+) -> Result<i32> {
+    insert_task_as(conn, None, task_name, start_time, stop_time, tags, project, origin)
+}
+
+/// Like `insert_task`, but a task from another database keeps the uuid and
+/// times of `identity`.
+pub(crate) fn insert_task_as(
+    conn: &Connection,
+    identity: Option<&Task>,
+    task_name: &str,
+    start_time: &str,
+    stop_time: &str,
+    tags: &str,
+    project: &str,
+    origin: ChangeOrigin,
+) -> Result<i32> {
+    let now = Local::now().to_rfc3339();
+    let (uuid, created_at, updated_at) = match identity {
+        Some(task) if !task.uuid.is_empty() => (task.uuid.as_str(), task.created_at.as_str(), task.updated_at.as_str()),
+        _ => ("", now.as_str(), now.as_str()),
+    };
+    conn.execute(
+        format!(
+            "INSERT INTO tasks (task_name, start_time, stop_time, tags, project, uuid, created_at, updated_at)
+                values (?1, ?2, ?3, ?4, ?5, COALESCE(NULLIF(?6, ''), {}), ?7, ?8)",
+            NEW_UUID
+        )
+        .as_str(),
+        &[&task_name, &start_time, &stop_time, &tags, &project, &uuid, &created_at, &updated_at],
+    )?;
+
+    let id = conn.last_insert_rowid() as i32;
+    log_created(conn, id, task_name, start_time, stop_time, tags, project, origin)?;
+
+    Ok(id)
+}
+
+pub(crate) fn task_from_row(row: &rusqlite::Row) -> Result<Task> {
+    Ok(Task {
+        id: row.get(0)?,
+        task_name: row.get(1)?,
+        start_time: row.get(2)?,
+        stop_time: row.get(3)?,
+        tags: row.get(4)?,
+        project: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+        // Missing in databases that were never upgraded, like fresh imports
+        billable: row
+            .get::<_, Option<String>>(6)
+            .ok()
+            .flatten()
+            .and_then(|billable| billing::billable_from_sqlite(&billable)),
+        uuid: optional_text(row, 7),
+        created_at: optional_text(row, 8),
+        updated_at: optional_text(row, 9),
+    })
+}
+
+/// Every task, oldest first. Columns added in later versions are filled
+/// with their defaults, so databases that were never upgraded can be read.
+pub(crate) fn all_tasks(conn: &Connection) -> Result<Vec<Task>> {
+    let columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('tasks')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_>>()?;
+    let column = |name: &str, default: &str| {
+        if columns.iter().any(|column| column == name) {
+            name.to_string()
+        } else {
+            format!("{} AS {}", default, name)
+        }
+    };
+    let query = format!(
+        "SELECT id, task_name, start_time, stop_time, {}, {}, {}, {}, {}, {} FROM tasks ORDER BY start_time ASC",
+        column("tags", "''"),
+        column("project", "''"),
+        column("billable", "NULL"),
+        column("uuid", "NULL"),
+        column("created_at", "NULL"),
+        column("updated_at", "NULL"),
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let tasks = stmt.query_map([], task_from_row)?.collect();
+    tasks
+}
+
+/// A column that older or imported databases may not have yet.
+fn optional_text(row: &rusqlite::Row, index: usize) -> String {
+    row.get::<_, Option<String>>(index).ok().flatten().unwrap_or_default()
+}
+
+pub(crate) fn update_field_with(
+    conn: &Connection,
+    id: i32,
+    field: &str,
+    value: &str,
+    origin: ChangeOrigin,
+) -> Result<()> {
+    // Field names come from this module, never from user input
+    let old_value: Option<String> = conn.query_row(
+        format!("SELECT {} FROM tasks WHERE id = (?1)", field).as_str(),
+        [id],
+        |row| row.get(0),
+    )?;
+    let old_value = old_value.unwrap_or_default();
+
+    if old_value != value {
+        conn.execute(
+            format!("UPDATE tasks SET {} = (?1), updated_at = (?2) WHERE id = (?3)", field).as_str(),
+            &[&value.to_string(), &Local::now().to_rfc3339(), &id.to_string()],
+        )?;
+        log_change(conn, id, field, &old_value, value, origin)?;
+    }
+
+    Ok(())
+}
+
+/// Splits a stored tag list ("tag #tag 2") into lowercase, unique tags.
+pub fn split_tags(tags: &str) -> Vec<String> {
+    let mut tag_list: Vec<String> = Vec::new();
+    for tag in tags.split('#') {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !tag_list.contains(&tag) {
+            tag_list.push(tag);
+        }
+    }
+    tag_list
+}
+
+/// Writes tasks as the CSV export, with their durations and, when given,
+/// the rounded ones. `importers` reads it back.
+pub fn tasks_to_csv(tasks: Vec<Task>, delimiter: u8, rounding: Option<Rounding>) -> anyhow::Result<String> {
+    #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+    struct CSVTask {
+        pub id: i32,
+        pub task_name: String,
+        pub start_time: String,
+        pub stop_time: String,
+        pub tags: String,
+        pub seconds: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub rounded_seconds: Option<i64>,
+        pub uuid: String,
+        pub created_at: String,
+        pub updated_at: String,
+    }
+
+    let mut csv_writer = csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(vec![]);
+
+    for task in tasks {
+        let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap();
+        let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap();
+        let duration = stop_time - start_time;
+        csv_writer.serialize(CSVTask{
+            id: task.id,
+            task_name: task.task_name,
+            start_time: task.start_time,
+            stop_time: task.stop_time,
+            tags: task.tags,
+            seconds: duration.num_seconds(),
+            rounded_seconds: rounding.map(|rounding| rounding.round(duration.num_seconds())),
+            uuid: task.uuid,
+            created_at: task.created_at,
+            updated_at: task.updated_at,
+        })?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(String::from_utf8(csv_writer.into_inner()?)?)
+}
+
+pub(crate) fn find_overlapping_with(
+    conn: &Connection,
+    start_time: DateTime<Local>,
+    stop_time: DateTime<Local>,
+    exclude: &[i32],
+) -> anyhow::Result<Vec<Task>> {
+    // Times are stored with their UTC offset, so compare them parsed
+    let mut query = conn.prepare("SELECT * FROM tasks")?;
+    let mut overlapping: Vec<Task> = Vec::new();
+    for task in query.query_map([], task_from_row)? {
+        let task = task?;
+        if exclude.contains(&task.id) {
+            continue;
+        }
+        let task_start = DateTime::parse_from_rfc3339(&task.start_time)?;
+        let task_stop = DateTime::parse_from_rfc3339(&task.stop_time)?;
+        if task_start < stop_time && task_stop > start_time {
+            overlapping.push(task);
+        }
+    }
+
+    Ok(overlapping)
+}
+
+pub(crate) fn delete_with(conn: &Connection, id: i32, origin: ChangeOrigin) -> Result<()> {
+    log_deleted(conn, id, origin)?;
+    // Sync sends the deletion with the time it was made, not when it syncs
+    conn.execute(
+        "INSERT OR REPLACE INTO sync_deleted (uuid, deleted_at, device)
+            SELECT uuid, ?1, NULL FROM tasks WHERE id = ?2 AND uuid IS NOT NULL AND uuid != ''",
+        (Local::now().to_rfc3339(), id),
+    )?;
+    conn.execute("delete FROM tasks WHERE id = (?1)", &[&id.to_string()])?;
+
+    Ok(())
+}
+
+fn log_deleted(conn: &Connection, id: i32, origin: ChangeOrigin) -> Result<()> {
+    conn.execute(
+        "INSERT INTO changes (task_id, task_uuid, field, old_value, new_value, changed_at, origin)
+            SELECT id, uuid, 'deleted', task_name, '', ?1, ?2 FROM tasks WHERE id = ?3",
+        (Local::now().to_rfc3339(), origin.to_sqlite(), id),
+    )?;
+
+    Ok(())
+}
+
+/// Reads the rates through an open connection, for reports.
+pub fn rates_from(conn: &Connection) -> Result<Vec<Rate>> {
+    let mut query = conn.prepare(
+        "SELECT kind, name, hourly_rate, currency, billable FROM rates
+            ORDER BY kind, name COLLATE NOCASE",
+    )?;
+    let rate_iter = query.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            Rate {
+                kind: RateKind::Task,
+                name: row.get(1)?,
+                hourly_rate: row.get(2)?,
+                currency: row.get(3)?,
+                billable: row.get(4)?,
+            },
+        ))
+    })?;
+
+    let mut rates: Vec<Rate> = Vec::new();
+    for rate in rate_iter {
+        let (kind, rate) = rate?;
+        if let Some(kind) = RateKind::from_sqlite(&kind) {
+            rates.push(Rate { kind, ..rate });
+        }
+    }
+    Ok(rates)
+}
+
+/// Reads the budgets through an open connection, for reports.
+pub fn budgets_from(conn: &Connection) -> Result<Vec<Budget>> {
+    let mut query = conn.prepare(
+        "SELECT kind, name, seconds, period FROM budgets
+            ORDER BY kind, name COLLATE NOCASE",
+    )?;
+    let budget_iter = query.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, String>(3)?,
+        ))
+    })?;
+
+    let mut budgets: Vec<Budget> = Vec::new();
+    for budget in budget_iter {
+        let (kind, name, seconds, period) = budget?;
+        if let (Some(kind), Some(period)) = (RateKind::from_sqlite(&kind), BudgetPeriod::from_sqlite(&period)) {
+            budgets.push(Budget { kind, name, seconds, period });
+        }
+    }
+    Ok(budgets)
+}
+
+/// Databases and entries for the tests of the modules built on this one.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use super::*;
+
+    /// An in-memory database with the tasks table as it is today.
+    pub(crate) fn empty_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tasks_table(&conn).unwrap();
+        add_identity_columns(&conn).unwrap();
+        conn
+    }
+
+    /// Adds half an hour of `task_name` on March 6th, 2023, from `hour` UTC.
+    pub(crate) fn add(conn: &Connection, task_name: &str, hour: u32) -> i32 {
+        insert_task(
+            conn,
+            task_name,
+            &format!("2023-03-06T{:02}:00:00+00:00", hour),
+            &format!("2023-03-06T{:02}:30:00+00:00", hour),
+            "",
+            "",
+            ChangeOrigin::Manual,
+        )
+        .unwrap()
+    }
+
+    /// The task names, in alphabetical order.
+    pub(crate) fn names(conn: &Connection) -> Vec<String> {
+        let mut names: Vec<String> = all_tasks(conn).unwrap().into_iter().map(|task| task.task_name).collect();
+        names.sort();
+        names
+    }
+}
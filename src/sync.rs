@@ -0,0 +1,638 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Syncing through a folder shared by Syncthing, Nextcloud and the like.
+//! Every device appends its changes to its own journal file in the folder
+//! and replays the journals of the others, so no file is ever written by
+//! two devices. The same changes can also go through a sync server, see
+//! `remote_sync`. Like `reporting`, this stays free of GTK.
+//!
+//! Each place changes are sent to is a target, with its own record of what
+//! was sent, so a change that arrives through one is passed on to the other.
+//!
+//! Conflicts go to the newest change by `updated_at`. On a tie a deletion
+//! wins over an edit, and otherwise the device with the greater id wins, so
+//! every device ends up with the same history.
+
+use chrono::{DateTime, FixedOffset, Local};
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::billing;
+use crate::store::{self, ChangeOrigin, Task};
+use crate::remote_sync::{self, Server};
+
+/// The target name of the sync folder. Servers go by their URL.
+const FOLDER_TARGET: &str = "folder";
+const JOURNAL_PREFIX: &str = "rusttimetrack-";
+const JOURNAL_SUFFIX: &str = ".jsonl";
+
+/// One line of a journal.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    /// Counts up from 1 in each journal
+    pub seq: u64,
+    pub uuid: String,
+    /// When the change was made
+    pub at: String,
+    #[serde(flatten)]
+    pub change: JournalChange,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JournalChange {
+    Upsert {
+        task_name: String,
+        start_time: String,
+        stop_time: String,
+        tags: String,
+        project: String,
+        billable: String,
+        created_at: String,
+    },
+    Delete,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncReport {
+    /// Local changes added to this device's journal
+    pub written: usize,
+    /// Changes from other devices applied here
+    pub applied: usize,
+    /// Changes from other devices that lost to a newer one
+    pub outdated: usize,
+    pub errors: Vec<String>,
+}
+
+impl SyncReport {
+    pub fn add(&mut self, other: SyncReport) {
+        self.written += other.written;
+        self.applied += other.applied;
+        self.outdated += other.outdated;
+        self.errors.extend(other.errors);
+    }
+}
+
+/// Where to sync to, read from the settings beforehand so syncing can run
+/// off the main thread.
+#[derive(Clone, Debug)]
+pub struct SyncSettings {
+    pub device: String,
+    pub folder: Option<PathBuf>,
+    pub server: Option<Server>,
+}
+
+/// Syncs with the folder and the server, whichever are set. A target that
+/// fails doesn't keep the other from syncing, and its error is reported.
+pub fn run(db: &Path, settings: &SyncSettings) -> SyncReport {
+    let mut report = SyncReport::default();
+    let mut conn = match Connection::open(db) {
+        Ok(conn) => conn,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+
+    if let Some(folder) = &settings.folder {
+        match sync(&mut conn, folder, &settings.device) {
+            Ok(folder_report) => report.add(folder_report),
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    }
+    if let Some(server) = &settings.server {
+        match remote_sync::sync(&mut conn, server, &settings.device) {
+            Ok(server_report) => report.add(server_report),
+            Err(e) => report.errors.push(format!("{}: {}", server.url, e)),
+        }
+    }
+    report
+}
+
+pub fn journal_path(folder: &Path, device: &str) -> PathBuf {
+    folder.join(format!("{}{}{}", JOURNAL_PREFIX, device, JOURNAL_SUFFIX))
+}
+
+pub(crate) fn create_sync_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sync_exported (
+                    target text,
+                    uuid text,
+                    updated_at text,
+                    PRIMARY KEY (target, uuid));
+        CREATE TABLE IF NOT EXISTS sync_cursors (
+                    target text primary key,
+                    cursor integer);
+        CREATE TABLE IF NOT EXISTS sync_applied (
+                    device text primary key,
+                    seq integer);
+        CREATE TABLE IF NOT EXISTS sync_received (
+                    uuid text primary key,
+                    device text,
+                    at text);",
+    )?;
+    store::create_deleted_table(conn)
+}
+
+/// Replays the other devices' journals in `folder`, then writes the local
+/// changes since the last sync to this device's journal.
+pub fn sync(conn: &mut Connection, folder: &Path, device: &str) -> anyhow::Result<SyncReport> {
+    if !folder.is_dir() {
+        anyhow::bail!("{} is not a folder", folder.display());
+    }
+    create_sync_tables(conn)?;
+
+    let tx = conn.transaction()?;
+    let mut report = SyncReport::default();
+
+    for (other, path) in journals(folder)? {
+        if other == device {
+            continue;
+        }
+        let applied_seq: u64 = tx
+            .query_row("SELECT seq FROM sync_applied WHERE device = ?1", [&other], |row| row.get::<_, i64>(0))
+            .optional()?
+            .unwrap_or(0) as u64;
+
+        let (entries, error) = read_journal(&path)?;
+        if let Some(error) = error {
+            report.errors.push(format!("{}: {}", path.display(), error));
+        }
+        let mut last_seq = applied_seq;
+        for entry in entries.into_iter().filter(|entry| entry.seq > applied_seq) {
+            if apply(&tx, FOLDER_TARGET, &entry, &other, device)? {
+                report.applied += 1;
+            } else {
+                report.outdated += 1;
+            }
+            last_seq = last_seq.max(entry.seq);
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO sync_applied (device, seq) VALUES (?1, ?2)",
+            (&other, last_seq as i64),
+        )?;
+    }
+
+    // Written before the commit, since replaying a change twice is harmless
+    // but losing one is not
+    let own_path = journal_path(folder, device);
+    let (own_entries, _) = read_journal(&own_path)?;
+    let next_seq = own_entries.iter().map(|entry| entry.seq).max().unwrap_or(0) + 1;
+    let changes = local_changes(&tx, FOLDER_TARGET, next_seq)?;
+    if !changes.is_empty() {
+        let mut file = OpenOptions::new().create(true).append(true).open(&own_path)?;
+        let mut lines = String::new();
+        for entry in &changes {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        file.sync_all()?;
+    }
+    mark_exported(&tx, FOLDER_TARGET, &changes)?;
+    report.written = changes.len();
+
+    tx.commit()?;
+    Ok(report)
+}
+
+/// Every journal in `folder`, by device id.
+fn journals(folder: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    let mut journals: Vec<(String, PathBuf)> = Vec::new();
+    for dir_entry in fs::read_dir(folder)? {
+        let path = dir_entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        // Conflict copies made by the sync tool don't match and are left alone
+        if let Some(device) = name
+            .strip_prefix(JOURNAL_PREFIX)
+            .and_then(|rest| rest.strip_suffix(JOURNAL_SUFFIX))
+        {
+            if !device.is_empty() && !device.contains('.') && !device.contains(' ') {
+                journals.push((device.to_string(), path.clone()));
+            }
+        }
+    }
+    journals.sort();
+    Ok(journals)
+}
+
+/// The complete lines of a journal, up to the first one that can't be read.
+/// A last line without a newline may still be arriving and is skipped.
+fn read_journal(path: &Path) -> anyhow::Result<(Vec<JournalEntry>, Option<String>)> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), None)),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut entries: Vec<JournalEntry> = Vec::new();
+    let complete = match text.rfind('\n') {
+        Some(end) => &text[..end],
+        None => "",
+    };
+    for (i, line) in complete.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<JournalEntry>(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => return Ok((entries, Some(format!("line {}: {}", i + 1, e)))),
+        }
+    }
+    Ok((entries, None))
+}
+
+/// One version of an entry: when it was made, whether it deleted the entry
+/// and which device made it. Versions are ordered the same way on every
+/// device, first by time, then deletions after edits, then by device id,
+/// so every device keeps the same one.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Version<'a> {
+    /// Times that can't be read count as older than any other
+    at: Option<DateTime<FixedOffset>>,
+    deleted: bool,
+    device: &'a str,
+}
+
+impl<'a> Version<'a> {
+    fn new(at: &str, deleted: bool, device: &'a str) -> Self {
+        Version {
+            at: DateTime::parse_from_rfc3339(at).ok(),
+            deleted,
+            device,
+        }
+    }
+}
+
+/// Applies a change from another device that came through `target`,
+/// returning false if the version here wins over it.
+pub(crate) fn apply(
+    conn: &Connection,
+    target: &str,
+    entry: &JournalEntry,
+    device: &str,
+    own_device: &str,
+) -> anyhow::Result<bool> {
+    let local: Option<Task> = conn
+        .query_row("SELECT * FROM tasks WHERE uuid = ?1", [&entry.uuid], store::task_from_row)
+        .optional()?;
+    let deleted: Option<(String, Option<String>)> = conn
+        .query_row(
+            "SELECT deleted_at, device FROM sync_deleted WHERE uuid = ?1",
+            [&entry.uuid],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let received: Option<(String, String)> = conn
+        .query_row(
+            "SELECT device, at FROM sync_received WHERE uuid = ?1",
+            [&entry.uuid],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    // The version here came from the device it was received from, unless
+    // it was edited here since
+    let local_version = match (&local, &deleted) {
+        (Some(local), _) => {
+            let from = match &received {
+                Some((from, at)) if *at == local.updated_at => from.as_str(),
+                _ => own_device,
+            };
+            Some(Version::new(&local.updated_at, false, from))
+        }
+        (None, Some((deleted_at, from))) => Some(Version::new(deleted_at, true, from.as_deref().unwrap_or(own_device))),
+        (None, None) => None,
+    };
+    let version = Version::new(&entry.at, entry.change == JournalChange::Delete, device);
+    if local_version.map_or(false, |local_version| local_version >= version) {
+        return Ok(false);
+    }
+
+    match &entry.change {
+        JournalChange::Delete => {
+            if let Some(local) = &local {
+                store::delete_with(conn, local.id, ChangeOrigin::Sync)?;
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO sync_deleted (uuid, deleted_at, device) VALUES (?1, ?2, ?3)",
+                (&entry.uuid, &entry.at, device),
+            )?;
+            conn.execute(
+                "DELETE FROM sync_exported WHERE target = ?1 AND uuid = ?2",
+                (target, &entry.uuid),
+            )?;
+            conn.execute("DELETE FROM sync_received WHERE uuid = ?1", [&entry.uuid])?;
+            Ok(true)
+        }
+        JournalChange::Upsert {
+            task_name,
+            start_time,
+            stop_time,
+            tags,
+            project,
+            billable,
+            created_at,
+        } => {
+            let id = match &local {
+                Some(local) => {
+                    let fields = [
+                        ("task_name", task_name),
+                        ("start_time", start_time),
+                        ("stop_time", stop_time),
+                        ("tags", tags),
+                        ("project", project),
+                        ("billable", billable),
+                    ];
+                    for (field, value) in fields {
+                        store::update_field_with(conn, local.id, field, value, ChangeOrigin::Sync)?;
+                    }
+                    local.id
+                }
+                None => {
+                    let identity = Task {
+                        id: 0,
+                        task_name: task_name.clone(),
+                        start_time: start_time.clone(),
+                        stop_time: stop_time.clone(),
+                        tags: tags.clone(),
+                        project: project.clone(),
+                        billable: billing::billable_from_sqlite(billable),
+                        uuid: entry.uuid.clone(),
+                        created_at: created_at.clone(),
+                        updated_at: entry.at.clone(),
+                    };
+                    let id = store::insert_task_as(
+                        conn,
+                        Some(&identity),
+                        task_name,
+                        start_time,
+                        stop_time,
+                        tags,
+                        project,
+                        ChangeOrigin::Sync,
+                    )?;
+                    store::update_field_with(conn, id, "billable", billable, ChangeOrigin::Sync)?;
+                    id
+                }
+            };
+
+            // Keeps the other device's time, and doesn't send the change back
+            conn.execute("UPDATE tasks SET updated_at = ?1 WHERE id = ?2", (&entry.at, id))?;
+            conn.execute(
+                "INSERT OR REPLACE INTO sync_exported (target, uuid, updated_at) VALUES (?1, ?2, ?3)",
+                (target, &entry.uuid, &entry.at),
+            )?;
+            conn.execute(
+                "INSERT OR REPLACE INTO sync_received (uuid, device, at) VALUES (?1, ?2, ?3)",
+                (&entry.uuid, device, &entry.at),
+            )?;
+            conn.execute("DELETE FROM sync_deleted WHERE uuid = ?1", [&entry.uuid])?;
+            Ok(true)
+        }
+    }
+}
+
+/// What changed since the last sync with `target`, found by comparing every
+/// task with what was last sent, so edits from every part of the app are
+/// caught.
+pub(crate) fn local_changes(conn: &Connection, target: &str, first_seq: u64) -> anyhow::Result<Vec<JournalEntry>> {
+    let mut exported: HashMap<String, String> = HashMap::new();
+    for row in conn
+        .prepare("SELECT uuid, updated_at FROM sync_exported WHERE target = ?1")?
+        .query_map([target], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?
+    {
+        let (uuid, updated_at) = row?;
+        exported.insert(uuid, updated_at.unwrap_or_default());
+    }
+
+    let tasks: Vec<Task> = conn
+        .prepare("SELECT * FROM tasks ORDER BY id ASC")?
+        .query_map([], store::task_from_row)?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut changes: Vec<JournalEntry> = Vec::new();
+    let mut seq = first_seq;
+    for task in &tasks {
+        if task.uuid.is_empty() || exported.remove(&task.uuid).as_ref() == Some(&task.updated_at) {
+            continue;
+        }
+        changes.push(JournalEntry {
+            seq,
+            uuid: task.uuid.clone(),
+            at: task.updated_at.clone(),
+            change: JournalChange::Upsert {
+                task_name: task.task_name.clone(),
+                start_time: task.start_time.clone(),
+                stop_time: task.stop_time.clone(),
+                tags: task.tags.clone(),
+                project: task.project.clone(),
+                billable: billing::billable_to_sqlite(task.billable).to_string(),
+                created_at: task.created_at.clone(),
+            },
+        });
+        seq += 1;
+    }
+
+    // Whatever was sent before and is gone now was deleted, here or by a
+    // change from another target, and left a tombstone with its time.
+    // Deletions from before tombstones were kept go by the time of sync.
+    let now = Local::now().to_rfc3339();
+    let mut deleted: Vec<String> = exported.into_keys().collect();
+    deleted.sort();
+    for uuid in deleted {
+        let at: Option<String> = conn
+            .query_row("SELECT deleted_at FROM sync_deleted WHERE uuid = ?1", [&uuid], |row| row.get(0))
+            .optional()?;
+        changes.push(JournalEntry {
+            seq,
+            uuid,
+            at: at.unwrap_or_else(|| now.clone()),
+            change: JournalChange::Delete,
+        });
+        seq += 1;
+    }
+    Ok(changes)
+}
+
+/// Records that `changes` were sent to `target`.
+pub(crate) fn mark_exported(conn: &Connection, target: &str, changes: &[JournalEntry]) -> rusqlite::Result<()> {
+    for entry in changes {
+        match entry.change {
+            JournalChange::Upsert { .. } => conn.execute(
+                "INSERT OR REPLACE INTO sync_exported (target, uuid, updated_at) VALUES (?1, ?2, ?3)",
+                (target, &entry.uuid, &entry.at),
+            )?,
+            JournalChange::Delete => conn.execute(
+                "DELETE FROM sync_exported WHERE target = ?1 AND uuid = ?2",
+                (target, &entry.uuid),
+            )?,
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_util::{add, empty_db, names};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FOLDERS: AtomicUsize = AtomicUsize::new(0);
+
+    /// An empty folder of its own for each test.
+    fn shared_folder() -> PathBuf {
+        let folder = std::env::temp_dir().join(format!(
+            "rusttimetrack-sync-test-{}-{}",
+            std::process::id(),
+            FOLDERS.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn id_of(conn: &Connection, task_name: &str) -> i32 {
+        conn.query_row("SELECT id FROM tasks WHERE task_name = ?1", [task_name], |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Renames a task as if it happened at `at`.
+    fn rename_at(conn: &Connection, id: i32, task_name: &str, at: &str) {
+        store::update_field_with(conn, id, "task_name", task_name, ChangeOrigin::Manual).unwrap();
+        conn.execute("UPDATE tasks SET updated_at = ?1 WHERE id = ?2", (at, id)).unwrap();
+    }
+
+    /// Deletes a task as if it happened at `at`.
+    fn delete_at(conn: &Connection, id: i32, at: &str) {
+        let uuid: String = conn.query_row("SELECT uuid FROM tasks WHERE id = ?1", [id], |row| row.get(0)).unwrap();
+        store::delete_with(conn, id, ChangeOrigin::Manual).unwrap();
+        conn.execute("UPDATE sync_deleted SET deleted_at = ?1 WHERE uuid = ?2", (at, uuid)).unwrap();
+    }
+
+    /// Syncs both devices twice, so each has seen the other's changes.
+    fn sync_both(folder: &Path, a: &mut Connection, b: &mut Connection) {
+        for _ in 0..2 {
+            sync(a, folder, "device-a").unwrap();
+            sync(b, folder, "device-b").unwrap();
+        }
+    }
+
+    #[test]
+    fn inserts_reach_the_other_device() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+
+        let report = sync(&mut a, &folder, "device-a").unwrap();
+        assert_eq!(report.written, 1);
+        let report = sync(&mut b, &folder, "device-b").unwrap();
+        assert_eq!(report.applied, 1);
+        assert_eq!(names(&b), vec!["Code"]);
+
+        let uuid = |conn: &Connection| -> String {
+            conn.query_row("SELECT uuid FROM tasks", [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(uuid(&a), uuid(&b));
+
+        // Nothing is sent back or applied twice
+        let report = sync(&mut a, &folder, "device-a").unwrap();
+        assert_eq!((report.written, report.applied), (0, 0));
+    }
+
+    #[test]
+    fn edits_reach_the_other_device() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        sync_both(&folder, &mut a, &mut b);
+
+        let id = id_of(&b, "Code");
+        store::update_field_with(&b, id, "task_name", "Review", ChangeOrigin::Manual).unwrap();
+        sync_both(&folder, &mut a, &mut b);
+        assert_eq!(names(&a), vec!["Review"]);
+    }
+
+    #[test]
+    fn deletes_reach_the_other_device() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        add(&a, "Email", 9);
+        sync_both(&folder, &mut a, &mut b);
+
+        let id = id_of(&a, "Code");
+        store::delete_with(&a, id, ChangeOrigin::Manual).unwrap();
+        sync_both(&folder, &mut a, &mut b);
+        assert_eq!(names(&b), vec!["Email"]);
+        assert_eq!(names(&a), vec!["Email"]);
+    }
+
+    #[test]
+    fn newer_edit_wins_on_both_devices() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        sync_both(&folder, &mut a, &mut b);
+
+        rename_at(&a, id_of(&a, "Code"), "Newer", "2023-03-07T10:00:00+00:00");
+        rename_at(&b, id_of(&b, "Code"), "Older", "2023-03-07T09:00:00+00:00");
+        sync_both(&folder, &mut a, &mut b);
+        assert_eq!(names(&a), vec!["Newer"]);
+        assert_eq!(names(&b), vec!["Newer"]);
+    }
+
+    #[test]
+    fn ties_go_the_same_way_on_both_devices() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        add(&a, "Email", 9);
+        sync_both(&folder, &mut a, &mut b);
+
+        // Between two edits the greater device id wins
+        let at = "2023-03-07T10:00:00+00:00";
+        rename_at(&a, id_of(&a, "Code"), "From A", at);
+        rename_at(&b, id_of(&b, "Code"), "From B", at);
+
+        // A deletion wins over an edit made at the same time
+        rename_at(&a, id_of(&a, "Email"), "Edited", at);
+        delete_at(&b, id_of(&b, "Email"), at);
+
+        sync_both(&folder, &mut a, &mut b);
+        assert_eq!(names(&a), vec!["From B"]);
+        assert_eq!(names(&b), vec!["From B"]);
+    }
+
+    #[test]
+    fn an_older_delete_loses_to_a_newer_edit() {
+        let folder = shared_folder();
+        let (mut a, mut b) = (empty_db(), empty_db());
+        add(&a, "Code", 9);
+        sync_both(&folder, &mut a, &mut b);
+
+        // Deleted on Monday, edited elsewhere on Tuesday, synced on Wednesday
+        delete_at(&a, id_of(&a, "Code"), "2023-03-06T12:00:00+00:00");
+        rename_at(&b, id_of(&b, "Code"), "Edited", "2023-03-07T12:00:00+00:00");
+        sync_both(&folder, &mut a, &mut b);
+        assert_eq!(names(&a), vec!["Edited"]);
+        assert_eq!(names(&b), vec!["Edited"]);
+    }
+}
@@ -15,19 +15,27 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 mod history_box;
+mod import;
+mod invoice;
 mod preferences_window;
 mod report;
 mod task_details;
 mod task_row;
 mod tasks_group;
 mod tasks_page;
+mod timeline;
+mod timesheet;
 pub mod window;
 
 pub use history_box::FurHistoryBox;
+pub use import::FurImport;
+pub use invoice::FurInvoice;
 pub use preferences_window::FurPreferencesWindow;
 pub use report::FurReport;
 pub use task_details::FurTaskDetails;
 pub use task_row::FurTaskRow;
 pub use tasks_group::FurTasksGroup;
 pub use tasks_page::FurTasksPage;
+pub use timeline::FurTimeline;
+pub use timesheet::FurTimesheet;
 pub use window::rusttimetrackWindow;
@@ -19,7 +19,8 @@ use gtk::subclass::prelude::*;
 use gtk::{glib, CompositeTemplate};
 
 use crate::database;
-use crate::ui::{FurTasksPage, rusttimetrackWindow};
+use crate::filter::Expr;
+use crate::ui::{FurTaskRow, FurTasksPage, rusttimetrackWindow};
 use crate::rusttimetrackApplication;
 
 enum View {
@@ -143,6 +144,27 @@ impl FurHistoryBox {
         imp.tasks_page.set_todays_stored_secs(new_time)
     }
 
+    pub fn set_selection_mode(&self, selection_mode: bool) {
+        let imp = imp::FurHistoryBox::from_obj(self);
+        imp.tasks_page.set_selection_mode(selection_mode);
+    }
+
+    pub fn select_row(&self, row: &FurTaskRow, extend: bool) {
+        let imp = imp::FurHistoryBox::from_obj(self);
+        imp.tasks_page.select_row(row, extend);
+    }
+
+    /// Only show tasks matching `filter` after the next rebuild.
+    pub fn set_filter(&self, filter: Option<Expr>) {
+        let imp = imp::FurHistoryBox::from_obj(self);
+        imp.tasks_page.set_filter(filter);
+    }
+
+    pub fn selected_task_ids(&self) -> Vec<i32> {
+        let imp = imp::FurHistoryBox::from_obj(self);
+        imp.tasks_page.selected_task_ids()
+    }
+
     pub fn empty_view(&self) {
         self.set_view(View::Empty);
         let window = rusttimetrackWindow::default();
@@ -0,0 +1,405 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use gettextrs::*;
+use glib::clone;
+use gtk::{glib, CompositeTemplate};
+use std::cell::RefCell;
+use std::path::Path;
+
+use crate::database::{self, SortOrder, TaskSort};
+use crate::dates;
+use crate::goals;
+use crate::importers::{
+    self, CsvOptions, FieldMapping, ImportFormat, ImportPlan, MapTo, Parsed, CSV_DELIMITERS,
+    IMPORT_FORMATS, MAP_TARGETS,
+};
+use crate::ui::rusttimetrackWindow;
+use crate::rusttimetrackApplication;
+
+/// Only this many entries are listed, the summary counts all of them.
+const PREVIEW_ROWS: usize = 200;
+
+mod imp {
+    use super::*;
+    use glib::subclass;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/lakoliu/rusttimetrack/gtk/import.ui")]
+    pub struct FurImport {
+        #[template_child]
+        pub import_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub file_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub format_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub csv_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub delimiter_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub task_name_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub start_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub stop_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub tags_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub project_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub seconds_column_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub project_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub tags_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub notes_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub skip_duplicates_row: TemplateChild<adw::SwitchRow>,
+        #[template_child]
+        pub error_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub preview_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub skipped_group: TemplateChild<adw::PreferencesGroup>,
+
+        pub text: RefCell<String>,
+        pub parsed: RefCell<Parsed>,
+        pub plan: RefCell<ImportPlan>,
+        pub rows: RefCell<Vec<(adw::PreferencesGroup, adw::ActionRow)>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FurImport {
+        const NAME: &'static str = "FurImport";
+        type ParentType = adw::Window;
+        type Type = super::FurImport;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for FurImport {
+        fn constructed(&self) {
+            let obj = self.obj();
+            obj.setup_widgets();
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for FurImport {}
+
+    impl WindowImpl for FurImport {}
+
+    impl AdwWindowImpl for FurImport {}
+}
+
+glib::wrapper! {
+    pub struct FurImport(ObjectSubclass<imp::FurImport>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl FurImport {
+    pub fn new(path: &Path) -> Self {
+        let dialog: Self = glib::Object::new::<FurImport>();
+
+        let window = rusttimetrackWindow::default();
+        dialog.set_transient_for(Some(&window));
+
+        let app = rusttimetrackApplication::default();
+        app.add_window(&window);
+
+        dialog.load_file(path);
+        dialog.setup_signals();
+        dialog
+    }
+
+    fn setup_widgets(&self) {
+        let imp = imp::FurImport::from_obj(self);
+
+        let formats: Vec<String> = IMPORT_FORMATS.iter().map(|format| format.title()).collect();
+        let formats: Vec<&str> = formats.iter().map(String::as_str).collect();
+        imp.format_row.set_model(Some(&gtk::StringList::new(&formats)));
+
+        let delimiters: Vec<String> = CSV_DELIMITERS
+            .iter()
+            .map(|delimiter| importers::delimiter_title(*delimiter))
+            .collect();
+        let delimiters: Vec<&str> = delimiters.iter().map(String::as_str).collect();
+        imp.delimiter_row.set_model(Some(&gtk::StringList::new(&delimiters)));
+
+        let options = CsvOptions::default();
+        imp.task_name_column_row.set_text(&options.task_name);
+        imp.start_column_row.set_text(&options.start_time);
+        imp.stop_column_row.set_text(&options.stop_time);
+        imp.tags_column_row.set_text(&options.tags);
+        imp.project_column_row.set_text(&options.project);
+        imp.seconds_column_row.set_text(&options.seconds);
+
+        let targets: Vec<String> = MAP_TARGETS.iter().map(|target| target.title()).collect();
+        let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+        let mapping = FieldMapping::default();
+        for (row, map_to) in [
+            (&*imp.project_row, mapping.project),
+            (&*imp.tags_row, mapping.tags),
+            (&*imp.notes_row, mapping.notes),
+        ] {
+            row.set_model(Some(&gtk::StringList::new(&targets)));
+            let index = MAP_TARGETS.iter().position(|target| *target == map_to).unwrap_or(0);
+            row.set_selected(index as u32);
+        }
+    }
+
+    fn setup_signals(&self) {
+        let imp = imp::FurImport::from_obj(self);
+
+        imp.format_row.connect_selected_notify(clone!(@weak self as this => move |_| {
+            this.reparse();
+        }));
+
+        imp.delimiter_row.connect_selected_notify(clone!(@weak self as this => move |_| {
+            this.reparse();
+        }));
+
+        for row in [
+            &*imp.task_name_column_row,
+            &*imp.start_column_row,
+            &*imp.stop_column_row,
+            &*imp.tags_column_row,
+            &*imp.project_column_row,
+            &*imp.seconds_column_row,
+        ] {
+            row.connect_apply(clone!(@weak self as this => move |_| {
+                this.reparse();
+            }));
+        }
+
+        for row in [&*imp.project_row, &*imp.tags_row, &*imp.notes_row] {
+            row.connect_selected_notify(clone!(@weak self as this => move |_| {
+                this.refresh();
+            }));
+        }
+
+        imp.skip_duplicates_row.connect_active_notify(clone!(@weak self as this => move |_| {
+            this.refresh();
+        }));
+
+        imp.import_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.import();
+        }));
+    }
+
+    fn load_file(&self, path: &Path) {
+        let imp = imp::FurImport::from_obj(self);
+        imp.file_row.set_subtitle(&glib::markup_escape_text(
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+        ));
+
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let format = ImportFormat::detect(path, &text).unwrap_or(ImportFormat::Toggl);
+                let delimiter = importers::detect_delimiter(&text);
+                let index = CSV_DELIMITERS.iter().position(|known| *known == delimiter).unwrap_or(0);
+                imp.delimiter_row.set_selected(index as u32);
+                *imp.text.borrow_mut() = text;
+                let index = IMPORT_FORMATS.iter().position(|known| *known == format).unwrap_or(0);
+                imp.format_row.set_selected(index as u32);
+                self.reparse();
+            }
+            Err(e) => {
+                log::error!("reading {:?} for import failed, Err {}", path, e);
+                self.show_error(&e.to_string());
+            }
+        }
+    }
+
+    fn format(&self) -> ImportFormat {
+        let imp = imp::FurImport::from_obj(self);
+        IMPORT_FORMATS
+            .get(imp.format_row.selected() as usize)
+            .copied()
+            .unwrap_or(ImportFormat::Toggl)
+    }
+
+    fn csv_options(&self) -> CsvOptions {
+        let imp = imp::FurImport::from_obj(self);
+        CsvOptions {
+            delimiter: CSV_DELIMITERS
+                .get(imp.delimiter_row.selected() as usize)
+                .copied()
+                .unwrap_or(b','),
+            task_name: imp.task_name_column_row.text().to_string(),
+            start_time: imp.start_column_row.text().to_string(),
+            stop_time: imp.stop_column_row.text().to_string(),
+            tags: imp.tags_column_row.text().to_string(),
+            project: imp.project_column_row.text().to_string(),
+            seconds: imp.seconds_column_row.text().to_string(),
+        }
+    }
+
+    fn mapping(&self) -> FieldMapping {
+        let imp = imp::FurImport::from_obj(self);
+        let target = |row: &adw::ComboRow| {
+            MAP_TARGETS
+                .get(row.selected() as usize)
+                .copied()
+                .unwrap_or(MapTo::Ignore)
+        };
+        FieldMapping {
+            project: target(&imp.project_row),
+            tags: target(&imp.tags_row),
+            notes: target(&imp.notes_row),
+        }
+    }
+
+    fn show_error(&self, message: &str) {
+        let imp = imp::FurImport::from_obj(self);
+        imp.error_label.set_text(message);
+        imp.error_label.set_visible(true);
+    }
+
+    /// Reads the file again in the chosen format.
+    fn reparse(&self) {
+        let imp = imp::FurImport::from_obj(self);
+        imp.csv_group.set_visible(self.format() == ImportFormat::Csv);
+        let parsed = importers::parse(self.format(), &imp.text.borrow(), &self.csv_options());
+        match parsed {
+            Ok(parsed) => {
+                imp.error_label.set_visible(false);
+                *imp.parsed.borrow_mut() = parsed;
+            }
+            Err(e) => {
+                self.show_error(
+                    &gettext("*Not a {format} file: {error}")
+                        .replace("{format}", &self.format().title())
+                        .replace("{error}", &e.to_string()),
+                );
+                *imp.parsed.borrow_mut() = Parsed::default();
+            }
+        }
+        self.refresh();
+    }
+
+    /// Works out the import as a dry run, and lists what it would add.
+    fn refresh(&self) {
+        let imp = imp::FurImport::from_obj(self);
+        for (group, row) in imp.rows.borrow_mut().drain(..) {
+            group.remove(&row);
+        }
+
+        // Without the history, duplicates can't be told apart
+        let existing = match database::retrieve(TaskSort::StartTime, SortOrder::Ascending) {
+            Ok(existing) => existing,
+            Err(e) => {
+                log::error!("reading the history to find duplicates failed, Err {}", e);
+                self.show_error(&gettext("Could not read the history: {}").replace("{}", &e.to_string()));
+                imp.import_btn.set_sensitive(false);
+                *imp.plan.borrow_mut() = ImportPlan::default();
+                return;
+            }
+        };
+        let plan = importers::plan(&imp.parsed.borrow(), self.mapping(), &existing);
+        let skip_duplicates = imp.skip_duplicates_row.is_active();
+
+        imp.preview_group.set_description(Some(&plan.summary(skip_duplicates)));
+        for row in plan.rows.iter().take(PREVIEW_ROWS) {
+            let task = &row.task;
+            let mut title = task.task_name.clone();
+            if !task.tags.is_empty() {
+                title = format!("{} #{}", title, task.tags);
+            }
+            let mut subtitle = format!(
+                "{} {}–{}",
+                dates::format_date(task.start.date_naive()),
+                task.start.format("%H:%M"),
+                task.stop.format("%H:%M"),
+            );
+            if !task.project.is_empty() {
+                subtitle = format!("{} · {}", subtitle, task.project);
+            }
+            if row.duplicate {
+                subtitle = format!("{} · {}", gettext("Duplicate"), subtitle);
+            }
+            let added = self.add_row(&imp.preview_group, &title, &subtitle, &goals::format_hours(task.seconds()));
+            if row.duplicate && skip_duplicates {
+                added.add_css_class("dim-label");
+            }
+        }
+        if plan.rows.len() > PREVIEW_ROWS {
+            let more = plan.rows.len() - PREVIEW_ROWS;
+            let added = self.add_row(
+                &imp.preview_group,
+                &ngettext("and {} more entry", "and {} more entries", more as u32).replace("{}", &more.to_string()),
+                "",
+                "",
+            );
+            added.add_css_class("dim-label");
+        }
+
+        imp.skipped_group.set_visible(!plan.skipped.is_empty());
+        for reason in &plan.skipped {
+            self.add_row(&imp.skipped_group, reason, "", "");
+        }
+
+        imp.import_btn
+            .set_sensitive(!plan.new_tasks(skip_duplicates).is_empty());
+        *imp.plan.borrow_mut() = plan;
+    }
+
+    fn add_row(&self, group: &adw::PreferencesGroup, title: &str, subtitle: &str, duration: &str) -> adw::ActionRow {
+        let imp = imp::FurImport::from_obj(self);
+        let row = adw::ActionRow::new();
+        row.set_title(&glib::markup_escape_text(title));
+        row.set_subtitle(&glib::markup_escape_text(subtitle));
+        if !duration.is_empty() {
+            let duration_label = gtk::Label::new(Some(duration));
+            duration_label.add_css_class("numeric");
+            row.add_suffix(&duration_label);
+        }
+        group.add(&row);
+        imp.rows.borrow_mut().push((group.clone(), row.clone()));
+        row
+    }
+
+    fn import(&self) {
+        let imp = imp::FurImport::from_obj(self);
+        let tasks = imp.plan.borrow().new_tasks(imp.skip_duplicates_row.is_active());
+
+        match database::import_tasks(&tasks) {
+            Ok(count) => {
+                let window = rusttimetrackWindow::default();
+                window.reset_history_box();
+                window.display_toast(
+                    &ngettext("Imported {} entry.", "Imported {} entries.", count as u32)
+                        .replace("{}", &count.to_string()),
+                );
+                self.close();
+            }
+            Err(e) => {
+                log::error!("importing {} entries failed, Err {}", tasks.len(), e);
+                self.show_error(&e.to_string());
+            }
+        }
+    }
+}
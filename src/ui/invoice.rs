@@ -0,0 +1,397 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use adw::prelude::*;
+use adw::subclass::prelude::*;
+use chrono::{Datelike, Local};
+use gettextrs::*;
+use glib::clone;
+use gtk::{glib, CompositeTemplate};
+use std::cell::RefCell;
+use std::path::Path;
+
+use crate::database;
+use crate::dates;
+use crate::invoicing::{self, Invoice, InvoiceRequest};
+use crate::reporting::{
+    DateRange, RangePreset, Rounding, RoundingMode, RoundingScope, ROUNDING_INCREMENTS,
+    ROUNDING_MODES, ROUNDING_SCOPES,
+};
+use crate::settings_manager;
+use crate::ui::rusttimetrackWindow;
+use crate::rusttimetrackApplication;
+
+mod imp {
+    use super::*;
+    use glib::subclass;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/lakoliu/rusttimetrack/gtk/invoice.ui")]
+    pub struct FurInvoice {
+        #[template_child]
+        pub save_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+        #[template_child]
+        pub client_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub project_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub start_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub end_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub number_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub tax_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub rounding_increment: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub rounding_mode: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub rounding_scope: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub error_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub lines_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub totals_group: TemplateChild<adw::PreferencesGroup>,
+
+        pub line_rows: RefCell<Vec<(adw::PreferencesGroup, adw::ActionRow)>>,
+        pub invoice: RefCell<Option<Invoice>>,
+        pub filechooser: RefCell<gtk::FileChooserNative>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FurInvoice {
+        const NAME: &'static str = "FurInvoice";
+        type ParentType = adw::Window;
+        type Type = super::FurInvoice;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for FurInvoice {
+        fn constructed(&self) {
+            let obj = self.obj();
+            obj.setup_widgets();
+            obj.setup_signals();
+            obj.refresh();
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for FurInvoice {}
+
+    impl WindowImpl for FurInvoice {}
+
+    impl AdwWindowImpl for FurInvoice {}
+}
+
+glib::wrapper! {
+    pub struct FurInvoice(ObjectSubclass<imp::FurInvoice>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl FurInvoice {
+    pub fn new() -> Self {
+        let dialog: Self = glib::Object::new::<FurInvoice>();
+
+        let window = rusttimetrackWindow::default();
+        dialog.set_transient_for(Some(&window));
+
+        let app = rusttimetrackApplication::default();
+        app.add_window(&window);
+
+        dialog
+    }
+
+    fn setup_widgets(&self) {
+        let imp = imp::FurInvoice::from_obj(self);
+
+        // Invoices usually cover last month
+        let today = Local::now().naive_local().date();
+        let last_month = RangePreset::LastMonth.resolve(
+            today,
+            settings_manager::week_starts_on(),
+            settings_manager::fiscal_year_start(),
+        );
+        let pattern = dates::date_pattern();
+        imp.start_row.set_title(&format!("{} ({})", gettext("From"), pattern));
+        imp.end_row.set_title(&format!("{} ({})", gettext("To"), pattern));
+        imp.start_row.set_text(&dates::format_date(last_month.start));
+        imp.end_row.set_text(&dates::format_date(last_month.end));
+
+        imp.tax_spin.set_value(settings_manager::get_double("invoice-tax-rate"));
+        imp.number_row.set_text(&Self::next_number());
+    }
+
+    fn setup_signals(&self) {
+        let imp = imp::FurInvoice::from_obj(self);
+
+        for row in [&*imp.client_row, &*imp.project_row, &*imp.start_row, &*imp.end_row, &*imp.number_row] {
+            row.connect_changed(clone!(@weak self as this => move |_| {
+                this.refresh();
+            }));
+        }
+
+        imp.tax_spin.connect_value_notify(clone!(@weak self as this => move |_| {
+            this.refresh();
+        }));
+
+        imp.rounding_increment
+            .connect_selected_notify(clone!(@weak self as this => move |row| {
+                let imp = imp::FurInvoice::from_obj(&this);
+                let rounding = row.selected() > 0;
+                imp.rounding_mode.set_sensitive(rounding);
+                imp.rounding_scope.set_sensitive(rounding);
+                this.refresh();
+            }));
+
+        for row in [&*imp.rounding_mode, &*imp.rounding_scope] {
+            row.connect_selected_notify(clone!(@weak self as this => move |_| {
+                this.refresh();
+            }));
+        }
+
+        imp.save_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.open_save_dialog();
+        }));
+    }
+
+    /// The number the next invoice gets, from the format in the preferences.
+    fn next_number() -> String {
+        invoicing::format_number(
+            &settings_manager::get_string("invoice-number-format"),
+            settings_manager::get_int("invoice-next-number"),
+            Local::now().year(),
+        )
+    }
+
+    /// Reads the fields, showing an error and returning None if the dates
+    /// are invalid.
+    fn current_request(&self) -> Option<InvoiceRequest> {
+        let imp = imp::FurInvoice::from_obj(self);
+
+        let start = dates::parse_date(&imp.start_row.text());
+        let end = dates::parse_date(&imp.end_row.text());
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => {
+                self.show_error(&gettext("*Use the format {}").replace("{}", &dates::date_pattern()));
+                return None;
+            }
+        };
+        if end < start {
+            self.show_error(&gettext("*Start date cannot be after end date"));
+            return None;
+        }
+
+        let increment = ROUNDING_INCREMENTS
+            .get(imp.rounding_increment.selected() as usize)
+            .copied()
+            .unwrap_or(0);
+        let rounding = if increment > 0 {
+            Some(Rounding {
+                increment: increment * 60,
+                mode: ROUNDING_MODES
+                    .get(imp.rounding_mode.selected() as usize)
+                    .copied()
+                    .unwrap_or(RoundingMode::Up),
+                scope: ROUNDING_SCOPES
+                    .get(imp.rounding_scope.selected() as usize)
+                    .copied()
+                    .unwrap_or(RoundingScope::Entry),
+            })
+        } else {
+            None
+        };
+
+        Some(InvoiceRequest {
+            number: imp.number_row.text().to_string(),
+            client: imp.client_row.text().to_string(),
+            project: imp.project_row.text().to_string(),
+            range: DateRange::new(start, end),
+            rounding,
+            tax_rate: imp.tax_spin.value(),
+        })
+    }
+
+    fn show_error(&self, message: &str) {
+        let imp = imp::FurInvoice::from_obj(self);
+        imp.error_label.set_text(message);
+        imp.error_label.set_visible(true);
+    }
+
+    /// Rebuilds the invoice from the fields and lists its line items.
+    fn refresh(&self) {
+        let imp = imp::FurInvoice::from_obj(self);
+        imp.error_label.set_visible(false);
+        for (group, row) in imp.line_rows.borrow_mut().drain(..) {
+            group.remove(&row);
+        }
+        *imp.invoice.borrow_mut() = None;
+        imp.save_btn.set_sensitive(false);
+
+        let request = match self.current_request() {
+            Some(request) => request,
+            None => return,
+        };
+        let invoice = match database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| invoicing::build(&conn, &request))
+        {
+            Ok(invoice) => invoice,
+            Err(e) => {
+                self.show_error(&e.to_string());
+                return;
+            }
+        };
+
+        if invoice.is_empty() {
+            self.add_row(&imp.lines_group, &gettext("No billable time left to invoice"), "", "");
+        }
+        for line in &invoice.lines {
+            let detail = gettext("{hours} h at {rate}")
+                .replace("{hours}", &line.hours())
+                .replace("{rate}", &invoice.money(line.hourly_rate));
+            self.add_row(&imp.lines_group, &line.description, &detail, &invoice.money(line.amount));
+        }
+        if invoice.unpriced > 0 {
+            self.show_error(
+                &ngettext(
+                    "{} billable entry has no rate and was left out",
+                    "{} billable entries have no rate and were left out",
+                    invoice.unpriced as u32,
+                )
+                .replace("{}", &invoice.unpriced.to_string()),
+            );
+        }
+        if !invoice.is_empty() {
+            for (label, amount) in invoice.totals() {
+                self.add_row(&imp.totals_group, &label, "", &amount);
+            }
+        }
+
+        imp.save_btn
+            .set_sensitive(!invoice.is_empty() && !invoice.number.is_empty());
+        *imp.invoice.borrow_mut() = Some(invoice);
+    }
+
+    fn add_row(&self, group: &adw::PreferencesGroup, title: &str, subtitle: &str, amount: &str) {
+        let imp = imp::FurInvoice::from_obj(self);
+        let row = adw::ActionRow::new();
+        row.set_title(&glib::markup_escape_text(title));
+        row.set_subtitle(subtitle);
+        if amount.is_empty() {
+            row.add_css_class("dim-label");
+        } else {
+            let amount_label = gtk::Label::new(Some(amount));
+            amount_label.add_css_class("numeric");
+            row.add_suffix(&amount_label);
+        }
+        group.add(&row);
+        imp.line_rows.borrow_mut().push((group.clone(), row));
+    }
+
+    fn open_save_dialog(&self) {
+        let imp = imp::FurInvoice::from_obj(self);
+        let number = match imp.invoice.borrow().as_ref() {
+            Some(invoice) => invoice.number.clone(),
+            None => return,
+        };
+
+        let pdf_filter = gtk::FileFilter::new();
+        pdf_filter.set_name(Some(&gettext("PDF Document")));
+        pdf_filter.add_mime_type("application/pdf");
+        pdf_filter.add_pattern("*.pdf");
+        let html_filter = gtk::FileFilter::new();
+        html_filter.set_name(Some(&gettext("HTML Page")));
+        html_filter.add_mime_type("text/html");
+        html_filter.add_pattern("*.html");
+
+        let filechooser = gtk::FileChooserNative::builder()
+            .title(&gettext("Save Invoice"))
+            .modal(true)
+            .transient_for(self)
+            .action(gtk::FileChooserAction::Save)
+            .accept_label(&gettext("Save"))
+            .cancel_label(&gettext("Cancel"))
+            .select_multiple(false)
+            .build();
+        filechooser.add_filter(&pdf_filter);
+        filechooser.add_filter(&html_filter);
+        filechooser.set_current_name(&format!("{}.pdf", number.replace('/', "-")));
+
+        filechooser.connect_response(clone!(@weak self as this => move |filechooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = filechooser.file().and_then(|file| file.path()) {
+                    this.save_invoice(&path);
+                }
+            }
+        }));
+
+        filechooser.show();
+        *imp.filechooser.borrow_mut() = filechooser;
+    }
+
+    /// Marks the entries as invoiced so they aren't billed again, and writes
+    /// the invoice in the same step.
+    fn save_invoice(&self, path: &Path) {
+        let imp = imp::FurInvoice::from_obj(self);
+        let invoice = match imp.invoice.borrow().clone() {
+            Some(invoice) => invoice,
+            None => return,
+        };
+
+        let mut template = settings_manager::get_string("invoice-template");
+        if template.trim().is_empty() {
+            template = invoicing::default_template();
+        }
+        if let Err(e) = database::record_invoice(&invoice, || invoice.write(path, &template)) {
+            log::error!("saving invoice {} to {:?} failed, Err {}", invoice.number, path, e);
+            let dialog = gtk::MessageDialog::with_markup(
+                Some(self),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Error,
+                gtk::ButtonsType::Ok,
+                Some(&format!("<span size='large'>{}</span>", &gettext("Could not save invoice"))),
+            );
+            dialog.set_secondary_text(Some(&e.to_string()));
+            dialog.connect_response(|dialog, _| dialog.close());
+            dialog.show();
+            return;
+        }
+
+        // Only count up when the suggested number was used
+        if invoice.number == Self::next_number() {
+            settings_manager::set_int(
+                "invoice-next-number",
+                settings_manager::get_int("invoice-next-number") + 1,
+            );
+            imp.number_row.set_text(&Self::next_number());
+        }
+        imp.toast_overlay
+            .add_toast(adw::Toast::new(&gettext("Invoice {} saved").replace("{}", &invoice.number)));
+        self.refresh();
+    }
+}
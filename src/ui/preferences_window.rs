@@ -16,19 +16,34 @@
 
 use adw::prelude::*;
 use adw::subclass::prelude::*;
+use chrono::{Local, Timelike};
 use gettextrs::*;
 use glib::clone;
 use gtk::glib;
 use gtk::CompositeTemplate;
 
+use crate::billing::{Rate, RateKind};
+use crate::budgets::{self, Budget, BUDGET_PERIODS};
+use crate::dates;
+use crate::goals;
+use crate::invoicing;
+use crate::reminders;
+use crate::reporting;
+use crate::remote_sync::SyncKey;
+use crate::secrets;
 use crate::settings_manager;
+use crate::sync;
 use crate::ui::rusttimetrackWindow;
 use crate::rusttimetrackApplication;
 use crate::database;
 
+/// Rate kinds in the order of the dropdown in the rate dialog
+const RATE_KINDS: [RateKind; 3] = [RateKind::Project, RateKind::Tag, RateKind::Task];
+
 mod imp {
     use super::*;
     use glib::subclass;
+    use std::cell::RefCell;
 
     #[derive(Default, Debug, CompositeTemplate)]
     #[template(resource = "/com/lakoliu/rusttimetrack/gtk/preferences_window.ui")]
@@ -58,6 +73,22 @@ mod imp {
         #[template_child]
         pub inclusive_total_switch: TemplateChild<adw::SwitchRow>,
 
+        // Working Hours Group
+        #[template_child]
+        pub work_days_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub work_start_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub work_end_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub remind_to_start_expander: TemplateChild<adw::ExpanderRow>,
+        #[template_child]
+        pub remind_start_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub remind_to_stop_expander: TemplateChild<adw::ExpanderRow>,
+        #[template_child]
+        pub remind_stop_spin: TemplateChild<adw::SpinRow>,
+
         // Tasks Page
         // Task List Group
         #[template_child]
@@ -81,12 +112,68 @@ mod imp {
         // Reports Group
         #[template_child]
         pub week_start_combo: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub fiscal_year_combo: TemplateChild<adw::ComboRow>,
+
+        // Goals Group
+        #[template_child]
+        pub daily_goal_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub weekly_goal_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub notify_of_goal_switch: TemplateChild<adw::SwitchRow>,
+        #[template_child]
+        pub goal_balance_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub goal_balance_btn: TemplateChild<gtk::Button>,
+
+        // Budgets Group
+        #[template_child]
+        pub budgets_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub add_budget_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub notify_of_budget_switch: TemplateChild<adw::SwitchRow>,
+        pub budget_rows: RefCell<Vec<adw::ActionRow>>,
 
         // Database Group
         #[template_child]
         pub database_loc_row: TemplateChild<adw::ActionRow>,
         #[template_child]
         pub database_browse_btn: TemplateChild<gtk::Button>,
+
+        // Sync Group
+        #[template_child]
+        pub sync_folder_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub sync_browse_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub sync_clear_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub sync_server_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub sync_token_row: TemplateChild<adw::PasswordEntryRow>,
+        #[template_child]
+        pub sync_passphrase_row: TemplateChild<adw::PasswordEntryRow>,
+        #[template_child]
+        pub sync_now_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub sync_now_btn: TemplateChild<gtk::Button>,
+
+        // Billing Page
+        #[template_child]
+        pub rates_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub add_rate_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub invoice_number_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub invoice_next_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub invoice_tax_spin: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub invoice_template_btn: TemplateChild<gtk::Button>,
+        pub rate_rows: RefCell<Vec<adw::ActionRow>>,
     }
 
     #[glib::object_subclass]
@@ -149,6 +236,479 @@ impl FurPreferencesWindow {
 
         let db_dir = database::get_directory().to_string_lossy().to_string();
         imp.database_loc_row.set_subtitle(&db_dir);
+        imp.sync_server_row.set_text(&settings_manager::get_string("sync-server-url"));
+        match secrets::lookup(secrets::SYNC_SERVER_TOKEN) {
+            Ok(token) => imp.sync_token_row.set_text(&token.unwrap_or_default()),
+            Err(e) => log::error!("reading the sync token from the keyring failed, Err {}", e),
+        }
+        self.show_sync_settings();
+
+        self.setup_work_hours();
+        self.load_rates();
+        self.load_budgets();
+        self.show_goal_balance();
+    }
+
+    fn show_sync_settings(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        let folder = settings_manager::get_string("sync-folder");
+        if folder.is_empty() {
+            imp.sync_folder_row.set_subtitle(&gettext("Not syncing"));
+        } else {
+            imp.sync_folder_row.set_subtitle(&glib::markup_escape_text(&folder));
+        }
+        imp.sync_clear_btn.set_visible(!folder.is_empty());
+
+        // The passphrase itself isn't kept, only the key made from it
+        let has_key = match secrets::lookup(secrets::SYNC_SERVER_KEY) {
+            Ok(key) => key.is_some(),
+            Err(e) => {
+                log::error!("reading the sync key from the keyring failed, Err {}", e);
+                false
+            }
+        };
+        imp.sync_passphrase_row.set_title(&if has_key {
+            gettext("Encryption Passphrase (set)")
+        } else {
+            gettext("Encryption Passphrase")
+        });
+
+        let has_server = !settings_manager::get_string("sync-server-url").is_empty() && has_key;
+        imp.sync_now_row.set_sensitive(!folder.is_empty() || has_server);
+    }
+
+    fn sync_now(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        imp.sync_now_row.set_subtitle(&gettext("Syncing…"));
+        let window = rusttimetrackWindow::default();
+        let started = window.sync_now(clone!(@weak self as this => move |report| {
+            this.show_sync_report(report);
+        }));
+        if !started {
+            imp.sync_now_row.set_subtitle("");
+        }
+    }
+
+    fn show_sync_report(&self, report: sync::SyncReport) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        let mut parts = vec![
+            ngettext("Sent {} change", "Sent {} changes", report.written as u32)
+                .replace("{}", &report.written.to_string()),
+            ngettext("received {} change", "received {} changes", report.applied as u32)
+                .replace("{}", &report.applied.to_string()),
+        ];
+        if report.outdated > 0 {
+            parts.push(
+                ngettext(
+                    "{} older change was ignored",
+                    "{} older changes were ignored",
+                    report.outdated as u32,
+                )
+                .replace("{}", &report.outdated.to_string()),
+            );
+        }
+        parts.extend(report.errors);
+        imp.sync_now_row.set_subtitle(&glib::markup_escape_text(&parts.join(", ")));
+    }
+
+    fn setup_work_hours(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+
+        // One toggle per day, Monday first, each a bit in "work-days"
+        let days = [
+            gettext("Mon"),
+            gettext("Tue"),
+            gettext("Wed"),
+            gettext("Thu"),
+            gettext("Fri"),
+            gettext("Sat"),
+            gettext("Sun"),
+        ];
+        let day_bits = settings_manager::get_int("work-days");
+        for (i, day) in days.iter().enumerate() {
+            let toggle = gtk::ToggleButton::with_label(day);
+            toggle.set_active(day_bits & (1 << i) != 0);
+            toggle.connect_toggled(move |toggle| {
+                let bits = settings_manager::get_int("work-days");
+                let bits = if toggle.is_active() { bits | (1 << i) } else { bits & !(1 << i) };
+                settings_manager::set_int("work-days", bits);
+            });
+            imp.work_days_box.append(&toggle);
+        }
+
+        for (row, key) in [(&*imp.work_start_row, "work-start"), (&*imp.work_end_row, "work-end")] {
+            let minutes = settings_manager::get_int(key);
+            row.set_text(&format!("{:02}:{:02}", minutes / 60, minutes % 60));
+            row.connect_apply(move |row| match reminders::parse_time(&row.text()) {
+                Some(time) => {
+                    row.remove_css_class("error");
+                    settings_manager::set_int(key, (time.hour() * 60 + time.minute()) as i32);
+                }
+                None => row.add_css_class("error"),
+            });
+        }
+    }
+
+    fn start_goal_balance() {
+        let today = Local::now().date_naive();
+        let week = reporting::week_start(today, settings_manager::week_starts_on());
+        settings_manager::set_string("goal-balance-since", &week.format("%F").to_string());
+    }
+
+    fn show_goal_balance(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        let goals = settings_manager::goals();
+        let today = Local::now().date_naive();
+        let balance = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| goals::status(&conn, &goals, today))
+            .map(|status| status.balance)
+            .unwrap_or(0);
+
+        let subtitle = if goals.weekly == 0 {
+            gettext("Set a weekly goal to carry over- and undertime")
+        } else if let Some(since) = goals.balance_since {
+            gettext("{balance} from the weeks since {date}")
+                .replace("{balance}", &goals::format_balance(balance))
+                .replace("{date}", &dates::format_date(since))
+        } else {
+            gettext("Over- and undertime is carried over once this week is finished")
+        };
+        imp.goal_balance_row.set_subtitle(&subtitle);
+    }
+
+    fn load_rates(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        for row in imp.rate_rows.borrow_mut().drain(..) {
+            imp.rates_group.remove(&row);
+        }
+
+        let rates = database::get_rates().unwrap_or_default();
+        if rates.is_empty() {
+            let row = adw::ActionRow::new();
+            row.set_title(&gettext("No rates yet"));
+            row.add_css_class("dim-label");
+            imp.rates_group.add(&row);
+            imp.rate_rows.borrow_mut().push(row);
+        }
+
+        for rate in rates {
+            let row = adw::ActionRow::new();
+            row.set_title(&glib::markup_escape_text(&format!("{}: {}", rate.kind.title(), rate.name)));
+            let billable = if rate.billable {
+                gettext("Billable")
+            } else {
+                gettext("Not billable")
+            };
+            row.set_subtitle(&format!("{} · {}", rate.describe(), billable));
+            row.set_activatable(true);
+
+            let delete_btn = gtk::Button::from_icon_name("user-trash-symbolic");
+            delete_btn.set_tooltip_text(Some(&gettext("Delete Rate")));
+            delete_btn.set_valign(gtk::Align::Center);
+            delete_btn.add_css_class("flat");
+            row.add_suffix(&delete_btn);
+
+            delete_btn.connect_clicked(clone!(@weak self as this, @strong rate => move |_| {
+                if let Err(e) = database::delete_rate(rate.kind, &rate.name) {
+                    log::error!("deleting rate for {} failed, Err {}", rate.name, e);
+                }
+                this.load_rates();
+            }));
+            row.connect_activated(clone!(@weak self as this, @strong rate => move |_| {
+                this.open_rate_dialog(Some(rate.clone()));
+            }));
+
+            imp.rates_group.add(&row);
+            imp.rate_rows.borrow_mut().push(row);
+        }
+    }
+
+    fn load_budgets(&self) {
+        let imp = imp::FurPreferencesWindow::from_obj(self);
+        for row in imp.budget_rows.borrow_mut().drain(..) {
+            imp.budgets_group.remove(&row);
+        }
+
+        let budgets = database::get_budgets().unwrap_or_default();
+        if budgets.is_empty() {
+            let row = adw::ActionRow::new();
+            row.set_title(&gettext("No budgets yet"));
+            row.add_css_class("dim-label");
+            imp.budgets_group.add(&row);
+            imp.budget_rows.borrow_mut().push(row);
+        }
+
+        let usage = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| {
+                budgets::usage(&conn, &budgets, Local::now().date_naive(), settings_manager::week_starts_on())
+            })
+            .unwrap_or_default();
+        for budget in budgets {
+            let row = adw::ActionRow::new();
+            row.set_title(&glib::markup_escape_text(&format!("{}: {}", budget.kind.title(), budget.name)));
+            let used = usage
+                .iter()
+                .find(|usage| usage.budget == budget)
+                .map(|usage| format!("{:.0}%", usage.fraction() * 100.0))
+                .unwrap_or_default();
+            row.set_subtitle(&format!("{} · {}", budget.describe(), gettext("{} used").replace("{}", &used)));
+            row.set_activatable(true);
+
+            let delete_btn = gtk::Button::from_icon_name("user-trash-symbolic");
+            delete_btn.set_tooltip_text(Some(&gettext("Delete Budget")));
+            delete_btn.set_valign(gtk::Align::Center);
+            delete_btn.add_css_class("flat");
+            row.add_suffix(&delete_btn);
+
+            delete_btn.connect_clicked(clone!(@weak self as this, @strong budget => move |_| {
+                if let Err(e) = database::delete_budget(budget.kind, &budget.name) {
+                    log::error!("deleting budget for {} failed, Err {}", budget.name, e);
+                }
+                this.load_budgets();
+                rusttimetrackWindow::default().refresh_budgets();
+            }));
+            row.connect_activated(clone!(@weak self as this, @strong budget => move |_| {
+                this.open_budget_dialog(Some(budget.clone()));
+            }));
+
+            imp.budgets_group.add(&row);
+            imp.budget_rows.borrow_mut().push(row);
+        }
+    }
+
+    /// Adds a budget, or edits `existing`.
+    fn open_budget_dialog(&self, existing: Option<Budget>) {
+        let heading = if existing.is_some() {
+            gettext("Edit Budget")
+        } else {
+            gettext("Add Budget")
+        };
+        let dialog = gtk::MessageDialog::with_markup(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            Some(&format!("<span size='x-large' weight='bold'>{}</span>", &heading)),
+        );
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let vert_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let kinds: Vec<String> = RATE_KINDS.iter().map(|kind| kind.title()).collect();
+        let kinds: Vec<&str> = kinds.iter().map(|kind| kind.as_str()).collect();
+        let kind_dropdown = gtk::DropDown::from_strings(&kinds);
+        let name_entry = gtk::Entry::new();
+        name_entry.set_placeholder_text(Some(&gettext("Project, tag or task name")));
+        let hours_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let hours_spin = gtk::SpinButton::with_range(0.25, 100000.0, 0.25);
+        hours_spin.set_digits(2);
+        hours_spin.set_value(10.0);
+        hours_spin.set_hexpand(true);
+        hours_spin.set_tooltip_text(Some(&gettext("Hours")));
+        let periods: Vec<String> = BUDGET_PERIODS.iter().map(|period| period.title()).collect();
+        let periods: Vec<&str> = periods.iter().map(|period| period.as_str()).collect();
+        let period_dropdown = gtk::DropDown::from_strings(&periods);
+        let name_error = gtk::Label::new(Some(&gettext("*Enter a name")));
+        name_error.set_visible(false);
+        name_error.add_css_class("error_message");
+
+        if let Some(budget) = &existing {
+            let kind = RATE_KINDS.iter().position(|kind| *kind == budget.kind).unwrap_or(0);
+            kind_dropdown.set_selected(kind as u32);
+            name_entry.set_text(&budget.name);
+            hours_spin.set_value(budget.seconds as f64 / 3600.0);
+            let period = BUDGET_PERIODS.iter().position(|period| *period == budget.period).unwrap_or(0);
+            period_dropdown.set_selected(period as u32);
+        }
+
+        hours_box.append(&hours_spin);
+        hours_box.append(&period_dropdown);
+        vert_box.append(&kind_dropdown);
+        vert_box.append(&name_entry);
+        vert_box.append(&hours_box);
+        vert_box.append(&name_error);
+        message_area.append(&vert_box);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                let name = name_entry.text().trim().trim_start_matches('#').to_string();
+                if name.is_empty() {
+                    name_error.set_visible(true);
+                    return;
+                }
+                let budget = Budget {
+                    kind: RATE_KINDS[kind_dropdown.selected() as usize % RATE_KINDS.len()],
+                    name,
+                    seconds: (hours_spin.value() * 3600.0).round() as i64,
+                    period: BUDGET_PERIODS[period_dropdown.selected() as usize % BUDGET_PERIODS.len()],
+                };
+                // Renaming replaces the old budget
+                if let Some(old) = &existing {
+                    if old.kind != budget.kind || old.name != budget.name {
+                        let _ = database::delete_budget(old.kind, &old.name);
+                    }
+                }
+                if let Err(e) = database::save_budget(&budget) {
+                    log::error!("saving budget for {} failed, Err {}", budget.name, e);
+                }
+                this.load_budgets();
+                rusttimetrackWindow::default().refresh_budgets();
+            }
+            dialog.close();
+        }));
+
+        dialog.show();
+    }
+
+    /// Adds a rate, or edits `existing`.
+    fn open_rate_dialog(&self, existing: Option<Rate>) {
+        let heading = if existing.is_some() {
+            gettext("Edit Rate")
+        } else {
+            gettext("Add Rate")
+        };
+        let dialog = gtk::MessageDialog::with_markup(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            Some(&format!("<span size='x-large' weight='bold'>{}</span>", &heading)),
+        );
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let vert_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let kinds: Vec<String> = RATE_KINDS.iter().map(|kind| kind.title()).collect();
+        let kinds: Vec<&str> = kinds.iter().map(|kind| kind.as_str()).collect();
+        let kind_dropdown = gtk::DropDown::from_strings(&kinds);
+        let name_entry = gtk::Entry::new();
+        name_entry.set_placeholder_text(Some(&gettext("Project, tag or task name")));
+        let rate_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        let rate_spin = gtk::SpinButton::with_range(0.0, 100000.0, 1.0);
+        rate_spin.set_digits(2);
+        rate_spin.set_hexpand(true);
+        let currency_entry = gtk::Entry::new();
+        currency_entry.set_placeholder_text(Some(&gettext("Currency")));
+        currency_entry.set_max_width_chars(6);
+        let billable_check = gtk::CheckButton::with_label(&gettext("Billable by default"));
+        billable_check.set_active(true);
+        let name_error = gtk::Label::new(Some(&gettext("*Enter a name")));
+        name_error.set_visible(false);
+        name_error.add_css_class("error_message");
+
+        match &existing {
+            Some(rate) => {
+                let kind = RATE_KINDS.iter().position(|kind| *kind == rate.kind).unwrap_or(0);
+                kind_dropdown.set_selected(kind as u32);
+                name_entry.set_text(&rate.name);
+                rate_spin.set_value(rate.hourly_rate);
+                currency_entry.set_text(&rate.currency);
+                billable_check.set_active(rate.billable);
+            }
+            None => {
+                // New rates usually share a currency with the others
+                let currency = database::get_rates()
+                    .unwrap_or_default()
+                    .last()
+                    .map(|rate| rate.currency.clone())
+                    .unwrap_or_default();
+                currency_entry.set_text(&currency);
+            }
+        }
+
+        rate_box.append(&rate_spin);
+        rate_box.append(&currency_entry);
+        vert_box.append(&kind_dropdown);
+        vert_box.append(&name_entry);
+        vert_box.append(&rate_box);
+        vert_box.append(&billable_check);
+        vert_box.append(&name_error);
+        message_area.append(&vert_box);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                let name = name_entry.text().trim().trim_start_matches('#').to_string();
+                if name.is_empty() {
+                    name_error.set_visible(true);
+                    return;
+                }
+                let rate = Rate {
+                    kind: RATE_KINDS[kind_dropdown.selected() as usize % RATE_KINDS.len()],
+                    name,
+                    hourly_rate: rate_spin.value(),
+                    currency: currency_entry.text().trim().to_uppercase(),
+                    billable: billable_check.is_active(),
+                };
+                // Renaming replaces the old rate
+                if let Some(old) = &existing {
+                    if old.kind != rate.kind || old.name != rate.name {
+                        let _ = database::delete_rate(old.kind, &old.name);
+                    }
+                }
+                if let Err(e) = database::save_rate(&rate) {
+                    log::error!("saving rate for {} failed, Err {}", rate.name, e);
+                }
+                this.load_rates();
+            }
+            dialog.close();
+        }));
+
+        dialog.show();
+    }
+
+    fn open_invoice_template_dialog(&self) {
+        let dialog = gtk::MessageDialog::new(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("<span size='x-large' weight='bold'>{}</span>", &gettext("Invoice Template")),
+        );
+        dialog.set_use_markup(true);
+        dialog.set_secondary_text(Some(&gettext(
+            "Lines starting with # are headings. {lines} and {totals} on a line of their own place the line items and totals. \
+            Also available: {number}, {date}, {client}, {project}, {period}, {currency}, {subtotal}, {tax} and {total}.",
+        )));
+        dialog.add_buttons(&[
+            (&gettext("Reset"), gtk::ResponseType::Reject),
+            (&gettext("Cancel"), gtk::ResponseType::Cancel),
+            (&gettext("Save"), gtk::ResponseType::Ok)
+        ]);
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let text_view = gtk::TextView::new();
+        text_view.set_monospace(true);
+        text_view.set_top_margin(6);
+        text_view.set_bottom_margin(6);
+        text_view.set_left_margin(6);
+        text_view.set_right_margin(6);
+        let mut template = settings_manager::get_string("invoice-template");
+        if template.trim().is_empty() {
+            template = invoicing::default_template();
+        }
+        text_view.buffer().set_text(&template);
+        let scrolled = gtk::ScrolledWindow::new();
+        scrolled.set_min_content_height(260);
+        scrolled.set_min_content_width(420);
+        scrolled.add_css_class("card");
+        scrolled.set_child(Some(&text_view));
+        message_area.append(&scrolled);
+
+        dialog.connect_response(move |dialog, resp| {
+            match resp {
+                gtk::ResponseType::Ok => {
+                    let buffer = text_view.buffer();
+                    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+                    settings_manager::set_string("invoice-template", &text);
+                }
+                // An empty template means the built-in one
+                gtk::ResponseType::Reject => settings_manager::set_string("invoice-template", ""),
+                _ => (),
+            }
+            dialog.close();
+        });
+
+        dialog.show();
     }
 
     fn setup_signals(&self) {
@@ -196,8 +756,60 @@ impl FurPreferencesWindow {
 
         settings_manager::bind_property("inclusive-total", &*imp.inclusive_total_switch, "active");
 
+        settings_manager::bind_property(
+            "remind-to-start",
+            &*imp.remind_to_start_expander,
+            "enable-expansion",
+        );
+
+        settings_manager::bind_property("remind-start-after", &*imp.remind_start_spin, "value");
+
+        settings_manager::bind_property(
+            "remind-to-stop",
+            &*imp.remind_to_stop_expander,
+            "enable-expansion",
+        );
+
+        settings_manager::bind_property("remind-stop-after", &*imp.remind_stop_spin, "value");
+
         settings_manager::bind_property("week-starts", &*imp.week_start_combo, "selected");
 
+        settings_manager::bind_property("fiscal-year-start", &*imp.fiscal_year_combo, "selected");
+
+        settings_manager::bind_property("daily-goal", &*imp.daily_goal_spin, "value");
+
+        settings_manager::bind_property("weekly-goal", &*imp.weekly_goal_spin, "value");
+
+        settings_manager::bind_property("notify-of-goal", &*imp.notify_of_goal_switch, "active");
+
+        imp.goal_balance_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            // Start counting again from this week
+            FurPreferencesWindow::start_goal_balance();
+            this.show_goal_balance();
+            let window = rusttimetrackWindow::default();
+            window.reset_history_box();
+        }));
+
+        imp.add_rate_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.open_rate_dialog(None);
+        }));
+
+        settings_manager::bind_property("notify-of-budget", &*imp.notify_of_budget_switch, "active");
+
+        imp.add_budget_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.open_budget_dialog(None);
+        }));
+
+        settings_manager::bind_property("invoice-number-format", &*imp.invoice_number_row, "text");
+
+        settings_manager::bind_property("invoice-next-number", &*imp.invoice_next_spin, "value");
+
+        settings_manager::bind_property("invoice-tax-rate", &*imp.invoice_tax_spin, "value");
+
+        imp.invoice_template_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.open_invoice_template_dialog();
+        }));
+
         imp.dark_theme_switch.connect_active_notify(move |_| {
             let app = rusttimetrackApplication::default();
             app.update_light_dark();
@@ -251,6 +863,29 @@ impl FurPreferencesWindow {
             window.reset_history_box();
         });
 
+        imp.daily_goal_spin.connect_value_notify(move |_| {
+            let window = rusttimetrackWindow::default();
+            window.reset_history_box();
+        });
+
+        imp.weekly_goal_spin.connect_value_notify(clone!(@weak self as this => move |spin| {
+            // The balance starts with the week the goal is switched on
+            if spin.value() <= 0.0 {
+                settings_manager::set_string("goal-balance-since", "");
+            } else if settings_manager::goals().balance_since.is_none() {
+                FurPreferencesWindow::start_goal_balance();
+            }
+            this.show_goal_balance();
+            let window = rusttimetrackWindow::default();
+            window.reset_history_box();
+        }));
+
+        imp.week_start_combo.connect_selected_notify(clone!(@weak self as this => move |_| {
+            this.show_goal_balance();
+            let window = rusttimetrackWindow::default();
+            window.reset_history_box();
+        }));
+
         imp.database_browse_btn.connect_clicked(clone!(@weak self as this => move |_| {
             let window = rusttimetrackApplication::default().active_window().unwrap();
             let dialog = gtk::FileChooserDialog::new(
@@ -297,5 +932,83 @@ impl FurPreferencesWindow {
             dialog.show();
 
         }));
+
+        imp.sync_browse_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            let dialog = gtk::FileChooserDialog::new(
+                Some(&gettext("Choose Sync Folder")),
+                Some(&this),
+                gtk::FileChooserAction::SelectFolder,
+                &[
+                    (&gettext("Cancel"), gtk::ResponseType::Reject),
+                    (&gettext("Select"), gtk::ResponseType::Accept),
+                ]
+            );
+            dialog.set_modal(true);
+
+            dialog.connect_response(
+                clone!(@strong dialog, @weak this as this2 => move |filechooser, resp| {
+                    if resp == gtk::ResponseType::Accept {
+                        if let Some(path) = filechooser.file().and_then(|file| file.path()) {
+                            settings_manager::set_string("sync-folder", &path.to_string_lossy());
+                            this2.show_sync_settings();
+                            this2.sync_now();
+                        }
+                    }
+                    dialog.close();
+                }),
+            );
+
+            dialog.show();
+        }));
+
+        imp.sync_clear_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            settings_manager::set_string("sync-folder", "");
+            this.show_sync_settings();
+            let imp2 = imp::FurPreferencesWindow::from_obj(&this);
+            imp2.sync_now_row.set_subtitle("");
+        }));
+
+        imp.sync_server_row.connect_apply(clone!(@weak self as this => move |row| {
+            settings_manager::set_string("sync-server-url", row.text().trim());
+            this.show_sync_settings();
+        }));
+
+        imp.sync_token_row.connect_apply(move |row| {
+            match secrets::store(secrets::SYNC_SERVER_TOKEN, row.text().trim()) {
+                Ok(()) => row.remove_css_class("error"),
+                Err(e) => {
+                    row.add_css_class("error");
+                    log::error!("saving the sync token to the keyring failed, Err {}", e);
+                }
+            }
+        });
+
+        imp.sync_passphrase_row.connect_apply(clone!(@weak self as this => move |row| {
+            let passphrase = row.text();
+            let key = if passphrase.is_empty() {
+                String::new()
+            } else {
+                match SyncKey::from_passphrase(&passphrase) {
+                    Ok(key) => key.to_base64(),
+                    Err(e) => {
+                        row.add_css_class("error");
+                        log::error!("deriving the sync key failed, Err {}", e);
+                        return;
+                    }
+                }
+            };
+            if let Err(e) = secrets::store(secrets::SYNC_SERVER_KEY, &key) {
+                row.add_css_class("error");
+                log::error!("saving the sync key to the keyring failed, Err {}", e);
+                return;
+            }
+            row.remove_css_class("error");
+            row.set_text("");
+            this.show_sync_settings();
+        }));
+
+        imp.sync_now_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.sync_now();
+        }));
     }
 }
@@ -15,17 +15,43 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use adw::subclass::prelude::*;
-use chrono::{offset::TimeZone, Date, DateTime, Datelike, Duration, Local, NaiveDate, Weekday};
+use chrono::{Datelike, Local, NaiveDate};
 use gettextrs::*;
 use glib::clone;
 use gtk::{glib, prelude::*, CompositeTemplate};
 use itertools::Itertools;
-
-use crate::database::{self, SortOrder, TaskSort};
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+
+use crate::charts::{Chart, ChartKind};
+use crate::database;
+use crate::dates;
+use crate::filter;
+use crate::report_export::{ExportFormat, ReportDocument};
+use crate::reporting::{
+    self, Filter, GroupBy, RangePreset, ReportNode, ReportPreset, ReportQuery, Rounding,
+    RoundingMode, RoundingScope, COMPARISONS, ROUNDING_INCREMENTS, ROUNDING_MODES,
+    ROUNDING_SCOPES,
+};
 use crate::settings_manager;
 use crate::ui::rusttimetrackWindow;
 use crate::rusttimetrackApplication;
 
+// In the order of the group by dropdowns
+const GROUP_OPTIONS: [Option<GroupBy>; 8] = [
+    None,
+    Some(GroupBy::Task),
+    Some(GroupBy::Tags),
+    Some(GroupBy::Tag),
+    Some(GroupBy::Project),
+    Some(GroupBy::Day),
+    Some(GroupBy::Week),
+    Some(GroupBy::Month),
+];
+
+const CHART_EXPORT_WIDTH: i32 = 960;
+const CHART_EXPORT_HEIGHT: i32 = 540;
+
 mod imp {
     use super::*;
     use glib::subclass;
@@ -33,6 +59,14 @@ mod imp {
     #[derive(Debug, Default, CompositeTemplate)]
     #[template(resource = "/com/lakoliu/rusttimetrack/gtk/report.ui")]
     pub struct FurReport {
+        #[template_child]
+        pub preset_dropdown: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub preset_list: TemplateChild<gtk::StringList>,
+        #[template_child]
+        pub save_preset_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub delete_preset_btn: TemplateChild<gtk::Button>,
         #[template_child]
         pub range_combo: TemplateChild<gtk::ComboBoxText>,
         #[template_child]
@@ -42,6 +76,14 @@ mod imp {
         #[template_child]
         pub end_date_entry: TemplateChild<gtk::Entry>,
         #[template_child]
+        pub start_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub start_calendar: TemplateChild<gtk::Calendar>,
+        #[template_child]
+        pub end_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub end_calendar: TemplateChild<gtk::Calendar>,
+        #[template_child]
         pub format_error: TemplateChild<gtk::Label>,
         #[template_child]
         pub start_end_error: TemplateChild<gtk::Label>,
@@ -54,15 +96,48 @@ mod imp {
         #[template_child]
         pub filter_entry: TemplateChild<gtk::Entry>,
         #[template_child]
+        pub filter_error: TemplateChild<gtk::Label>,
+        #[template_child]
         pub results_tree: TemplateChild<gtk::TreeView>,
         #[template_child]
-        pub sort_by_box: TemplateChild<gtk::Box>,
+        pub group_by_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub group_first: TemplateChild<gtk::DropDown>,
         #[template_child]
-        pub sort_by_task: TemplateChild<gtk::CheckButton>,
+        pub group_second: TemplateChild<gtk::DropDown>,
         #[template_child]
-        pub sort_by_tag: TemplateChild<gtk::CheckButton>,
+        pub group_third: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub rounding_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub rounding_increment: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub rounding_mode: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub rounding_scope: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub compare_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub compare_dropdown: TemplateChild<gtk::DropDown>,
         #[template_child]
         pub refresh_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub export_report_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub chart_dropdown: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub share_by_dropdown: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub export_chart_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub chart_area: TemplateChild<gtk::DrawingArea>,
+
+        pub presets: RefCell<Vec<ReportPreset>>,
+        /// Set while widgets are changed in code, so they don't each refresh
+        pub applying_preset: Cell<bool>,
+        pub query: RefCell<Option<ReportQuery>>,
+        pub chart: RefCell<Option<Chart>>,
+        pub filechooser: RefCell<gtk::FileChooserNative>,
     }
 
     #[glib::object_subclass]
@@ -119,6 +194,57 @@ impl FurReport {
         imp.range_combo.set_active_id(Some("this_week_item"));
         imp.filter_combo.set_active_id(Some("tasks_item"));
 
+        // Custom dates are typed the way the locale writes them
+        let pattern = dates::date_pattern();
+        imp.start_date_entry.set_placeholder_text(Some(&pattern));
+        imp.end_date_entry.set_placeholder_text(Some(&pattern));
+        imp.format_error
+            .set_text(&gettext("Use the format {}").replace("{}", &pattern));
+        let today = Local::now().naive_local().date();
+        imp.start_date_entry
+            .set_text(&dates::format_date(today.with_day(1).unwrap()));
+        imp.end_date_entry.set_text(&dates::format_date(today));
+
+        for (entry, popover, calendar) in [
+            (&*imp.start_date_entry, &*imp.start_popover, &*imp.start_calendar),
+            (&*imp.end_date_entry, &*imp.end_popover, &*imp.end_calendar),
+        ] {
+            entry.connect_activate(clone!(@weak self as this => move |_|{
+                this.refresh_report();
+            }));
+
+            // Open the calendar on the date that was typed
+            popover.connect_show(clone!(@weak entry, @weak calendar => move |_|{
+                if let Some(day) = dates::parse_date(&entry.text()) {
+                    if let Ok(date) = glib::DateTime::from_local(
+                        day.year(),
+                        day.month() as i32,
+                        day.day() as i32,
+                        0,
+                        0,
+                        0.0,
+                    ) {
+                        calendar.select_day(&date);
+                    }
+                }
+            }));
+
+            calendar.connect_day_selected(clone!(@weak self as this, @weak entry, @weak popover => move |calendar|{
+                let date = calendar.date();
+                let day = NaiveDate::from_ymd_opt(
+                    date.year(),
+                    date.month() as u32,
+                    date.day_of_month() as u32,
+                );
+                // Showing the popover selects the typed date, which isn't a pick
+                if let Some(day) = day.filter(|day| Some(*day) != dates::parse_date(&entry.text())) {
+                    entry.set_text(&dates::format_date(day));
+                    popover.popdown();
+                    this.refresh_report();
+                }
+            }));
+        }
+
         imp.range_combo
             .connect_changed(clone!(@weak self as this => move |combo|{
                 let imp = imp::FurReport::from_obj(&this);
@@ -131,23 +257,20 @@ impl FurReport {
             }));
 
         imp.filter_check
-            .connect_toggled(clone!(@weak self as this => move |_|{
+            .connect_toggled(clone!(@weak self as this => move |check|{
                 let imp = imp::FurReport::from_obj(&this);
-                if imp.filter_box.get_visible() {
-                    imp.filter_box.set_visible(false);
-                } else {
-                    imp.filter_box.set_visible(true);
-                }
+                imp.filter_box.set_visible(check.is_active());
             }));
 
         imp.filter_combo
             .connect_changed(clone!(@weak self as this => move |combo|{
                 let imp = imp::FurReport::from_obj(&this);
-                if combo.active_id().unwrap() == "tasks_item" {
-                    imp.filter_entry.set_placeholder_text(Some(&gettext("Task, Task 2")));
-                } else {
-                    imp.filter_entry.set_placeholder_text(Some(&gettext("tag, tag 2")));
-                }
+                let placeholder = match combo.active_id().unwrap().as_str() {
+                    "tasks_item" => gettext("Task, Task 2"),
+                    "tags_item" => gettext("tag, tag 2"),
+                    _ => gettext("tag:client AND duration>15m"),
+                };
+                imp.filter_entry.set_placeholder_text(Some(&placeholder));
             }));
 
         imp.refresh_btn
@@ -155,6 +278,53 @@ impl FurReport {
                 this.refresh_report();
             }));
 
+        imp.export_report_btn
+            .connect_clicked(clone!(@weak self as this => move |_|{
+                this.open_report_export_dialog();
+            }));
+
+        imp.preset_dropdown
+            .connect_selected_notify(clone!(@weak self as this => move |dropdown|{
+                let imp = imp::FurReport::from_obj(&this);
+                imp.delete_preset_btn.set_sensitive(dropdown.selected() > 0);
+                if imp.applying_preset.get() {
+                    return;
+                }
+                if let Some(preset) = this.selected_preset() {
+                    this.apply_preset(&preset);
+                }
+            }));
+
+        imp.save_preset_btn
+            .connect_clicked(clone!(@weak self as this => move |_|{
+                this.open_save_preset_dialog();
+            }));
+
+        imp.delete_preset_btn
+            .connect_clicked(clone!(@weak self as this => move |_|{
+                this.delete_selected_preset();
+            }));
+
+        for dropdown in [&*imp.group_first, &*imp.group_second, &*imp.group_third] {
+            dropdown.connect_selected_notify(clone!(@weak self as this => move |_|{
+                this.refresh_report();
+            }));
+        }
+
+        imp.rounding_increment
+            .connect_selected_notify(clone!(@weak self as this => move |dropdown|{
+                let imp = imp::FurReport::from_obj(&this);
+                imp.rounding_mode.set_sensitive(dropdown.selected() > 0);
+                imp.rounding_scope.set_sensitive(dropdown.selected() > 0);
+                this.refresh_report();
+            }));
+
+        for dropdown in [&*imp.rounding_mode, &*imp.rounding_scope, &*imp.compare_dropdown] {
+            dropdown.connect_selected_notify(clone!(@weak self as this => move |_|{
+                this.refresh_report();
+            }));
+        }
+
         let renderer = gtk::CellRendererText::new();
         let task_column =
             gtk::TreeViewColumn::with_attributes(&gettext("Task"), &renderer, &[("text", 0)]);
@@ -165,345 +335,671 @@ impl FurReport {
             gtk::TreeViewColumn::with_attributes(&gettext("Duration"), &renderer, &[("text", 1)]);
         duration_column.set_expand(false);
         duration_column.set_resizable(true);
+        let rounded_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Rounded"), &renderer, &[("text", 3)]);
+        rounded_column.set_expand(false);
+        rounded_column.set_resizable(true);
+        rounded_column.set_visible(false);
+        let billable_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Billable"), &renderer, &[("text", 4)]);
+        billable_column.set_expand(false);
+        billable_column.set_resizable(true);
+        billable_column.set_visible(false);
+        let earnings_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Earnings"), &renderer, &[("text", 5)]);
+        earnings_column.set_expand(false);
+        earnings_column.set_resizable(true);
+        earnings_column.set_visible(false);
+        let previous_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Previous"), &renderer, &[("text", 6)]);
+        previous_column.set_expand(false);
+        previous_column.set_resizable(true);
+        previous_column.set_visible(false);
+        let change_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Change"), &renderer, &[("text", 7)]);
+        change_column.set_expand(false);
+        change_column.set_resizable(true);
+        change_column.set_visible(false);
+        let estimate_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Estimate"), &renderer, &[("text", 8)]);
+        estimate_column.set_expand(false);
+        estimate_column.set_resizable(true);
+        estimate_column.set_visible(false);
+        let percent_column =
+            gtk::TreeViewColumn::with_attributes(&gettext("Share"), &renderer, &[("text", 2)]);
+        percent_column.set_expand(false);
+        percent_column.set_resizable(true);
         imp.results_tree.append_column(&task_column);
         imp.results_tree.append_column(&duration_column);
+        imp.results_tree.append_column(&rounded_column);
+        imp.results_tree.append_column(&billable_column);
+        imp.results_tree.append_column(&earnings_column);
+        imp.results_tree.append_column(&previous_column);
+        imp.results_tree.append_column(&change_column);
+        imp.results_tree.append_column(&estimate_column);
+        imp.results_tree.append_column(&percent_column);
         imp.results_tree.set_enable_search(false);
 
+        self.load_presets(None);
+
+        imp.chart_area.set_draw_func(clone!(@weak self as this => move |area, cr, width, height| {
+            let imp = imp::FurReport::from_obj(&this);
+            if let Some(chart) = imp.chart.borrow().as_ref() {
+                let fg = area.style_context().color();
+                chart.draw(cr, width as f64, height as f64, (fg.red() as f64, fg.green() as f64, fg.blue() as f64));
+            }
+        }));
+
+        imp.chart_dropdown
+            .connect_selected_notify(clone!(@weak self as this => move |_|{
+                this.refresh_chart();
+            }));
+
+        imp.share_by_dropdown
+            .connect_selected_notify(clone!(@weak self as this => move |_|{
+                this.refresh_chart();
+            }));
+
+        imp.export_chart_btn
+            .connect_clicked(clone!(@weak self as this => move |_|{
+                this.open_chart_export_dialog();
+            }));
+
         self.refresh_report();
     }
 
     fn refresh_report(&self) {
         let imp = imp::FurReport::from_obj(self);
+        if imp.applying_preset.get() {
+            return;
+        }
         imp.format_error.set_visible(false);
         imp.start_end_error.set_visible(false);
-
-        let results_model = gtk::TreeStore::new(&[String::static_type(), String::static_type()]);
-
-        let task_list = database::retrieve(TaskSort::StartTime, SortOrder::Descending).unwrap();
-
-        // Get date range
-        let active_range = imp.range_combo.active_id().unwrap();
-        let today = Local::now().date();
-        let range_start_date: Date<Local>;
-        let mut range_end_date = today;
-        if active_range == "this_week_item" {
-            let current_week = today.iso_week().week();
-            (range_start_date, range_end_date) = FurReport::week_bounds(today.weekday(), current_week);
-        } else if active_range == "last_week_item" {
-            let last_week = today.iso_week().week() - 1;
-            (range_start_date, range_end_date) = FurReport::week_bounds(today.weekday(), last_week);
-        } else if active_range == "week_item" {
-            range_start_date = today - Duration::days(6);
-        } else if active_range == "month_item" {
-            let days_ago = today.day() - 1;
-            range_start_date = today - Duration::days(days_ago.into());
-        } else if active_range == "30_days_item" {
-            range_start_date = today - Duration::days(29);
-        } else if active_range == "six_months_item" {
-            range_start_date = today - Duration::days(179);
-        } else if active_range == "year_item" {
-            range_start_date = today - Duration::days(364);
-        } else {
-            let input_start_date =
-                NaiveDate::parse_from_str(&imp.start_date_entry.text(), "%m/%d/%Y");
-            let input_end_date = NaiveDate::parse_from_str(&imp.end_date_entry.text(), "%m/%d/%Y");
-            // Check if user entered dates properly
-            if let Err(_) = input_start_date {
-                imp.format_error.set_visible(true);
-                results_model.clear();
-                imp.results_tree.set_model(Some(&results_model));
-                return;
-            }
-            if let Err(_) = input_end_date {
-                imp.format_error.set_visible(true);
-                results_model.clear();
+        imp.filter_error.set_visible(false);
+
+        let results_model = gtk::TreeStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+        ]);
+
+        let query = match self.build_query() {
+            Some(query) => query,
+            None => {
                 imp.results_tree.set_model(Some(&results_model));
+                *imp.query.borrow_mut() = None;
+                self.refresh_chart();
                 return;
             }
-            // Start date cannot be after end date
-            if (input_end_date.unwrap() - input_start_date.unwrap()).num_days() < 0 {
-                imp.start_end_error.set_visible(true);
-                results_model.clear();
-                return;
-            }
-            range_start_date = Local.from_local_date(&input_start_date.unwrap()).unwrap();
-            range_end_date = Local.from_local_date(&input_end_date.unwrap()).unwrap();
-        }
-
-        let mut total_time: i64 = 0;
-        let mut tasks_in_range: Vec<(database::Task, i64)> = Vec::new();
-        let mut user_chosen_tags: Vec<String> = Vec::new();
-        let mut only_this_tag = false;
-        for task in task_list {
-            let start = DateTime::parse_from_rfc3339(&task.start_time)
-                .unwrap()
-                .with_timezone(&Local);
-            let stop = DateTime::parse_from_rfc3339(&task.stop_time)
-                .unwrap()
-                .with_timezone(&Local);
-            // Check if start time is in date range and if not remove it from task_list
-            let start_date = start.date();
-            if start_date >= range_start_date && start_date <= range_end_date {
-                // Sort by only selected tasks or tags if filter is selected
-                if imp.filter_check.is_active() && !imp.filter_entry.text().trim().is_empty() {
-                    if imp.filter_combo.active_id().unwrap() == "tasks_item" {
-                        // Create a vec of tasks to match from written tasks
-                        let chosen_tasks = imp.filter_entry.text();
-                        let mut split_tasks: Vec<&str> = chosen_tasks.trim().split(",").collect();
-                        // Trim whitespace around each task
-                        split_tasks = split_tasks.iter().map(|x| x.trim()).collect();
-                        // Don't allow empty tasks
-                        split_tasks.retain(|&x| !x.trim().is_empty());
-                        // Handle duplicate tasks
-                        split_tasks = split_tasks.into_iter().unique().collect();
-                        // Lowercase tags
-                        let lower_tasks: Vec<String> =
-                            split_tasks.iter().map(|x| x.to_lowercase()).collect();
-
-                        if lower_tasks.contains(&task.task_name.to_lowercase()) {
-                            let duration = stop - start;
-                            let duration = duration.num_seconds();
-                            tasks_in_range.push((task, duration));
-                            total_time += duration;
-                        }
-                    } else if imp.filter_combo.active_id().unwrap() == "tags_item" {
-                        // Split user chosen tags
-                        let chosen_tasgs = imp.filter_entry.text();
-                        let mut split_tags: Vec<&str> = chosen_tasgs.trim().split(",").collect();
-                        // Trim whitespace around each tag
-                        split_tags = split_tags.iter().map(|x| x.trim()).collect();
-                        // Don't allow empty tags
-                        split_tags.retain(|&x| !x.trim().is_empty());
-                        // Handle duplicate tags
-                        split_tags = split_tags.into_iter().unique().collect();
-                        // Lowercase tags
-                        user_chosen_tags = split_tags.iter().map(|x| x.to_lowercase()).collect();
-
-                        // Split task's tags
-                        let mut split_tags: Vec<&str> = task.tags.trim().split("#").collect();
-                        // Trim whitespace around each tag
-                        split_tags = split_tags.iter().map(|x| x.trim()).collect();
-
-                        // Only keep tasks that contain the user's chosen tags
-                        split_tags.retain(|&x| user_chosen_tags.contains(&x.to_string()));
-                        if !split_tags.is_empty() {
-                            let duration = stop - start;
-                            let duration = duration.num_seconds();
-                            tasks_in_range.push((task, duration));
-                            total_time += duration;
-                        }
-
-                        only_this_tag = true;
-                    }
-                } else {
-                    let duration = stop - start;
-                    let duration = duration.num_seconds();
-                    tasks_in_range.push((task, duration));
-                    total_time += duration;
-                }
-            }
-        }
+        };
+        let report = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| reporting::run(&conn, &query))
+            .unwrap_or_default();
 
         let all_tasks_iter: gtk::TreeIter;
-        if tasks_in_range.is_empty() {
+        if report.entries == 0 {
             all_tasks_iter = results_model.insert_with_values(
                 None,
                 None,
-                &[(0, &gettext("No Results")), (1, &"")],
+                &[
+                    (0, &gettext("No Results")),
+                    (1, &""),
+                    (2, &""),
+                    (3, &""),
+                    (4, &""),
+                    (5, &""),
+                    (6, &""),
+                    (7, &""),
+                    (8, &""),
+                ],
             );
         } else {
-            let total_time_str = FurReport::format_duration(total_time);
+            let total_time_str = reporting::format_duration(report.seconds);
+            let rounded_time_str = reporting::format_duration(report.rounded_seconds);
+            let billable_time_str = reporting::format_duration(report.billable_seconds);
             all_tasks_iter = results_model.insert_with_values(
                 None,
                 None,
-                &[(0, &gettext("All Results")), (1, &total_time_str)],
+                &[
+                    (0, &gettext("All Results")),
+                    (1, &total_time_str),
+                    (2, &"100%"),
+                    (3, &rounded_time_str),
+                    (4, &billable_time_str),
+                    (5, &report.earnings.format()),
+                    (6, &FurReport::previous_text(&report)),
+                    (7, &report.change_text()),
+                    (8, &report.estimate_text()),
+                ],
             );
         }
+        FurReport::append_nodes(&results_model, &all_tasks_iter, &report);
 
-        if imp.sort_by_task.is_active() {
-            let mut tasks_by_name: Vec<Vec<(database::Task, i64)>> = Vec::new();
-            for (task, task_duration) in tasks_in_range {
-                let mut unique = true;
-                for i in 0..tasks_by_name.len() {
-                    let (tbn, _) = &tasks_by_name[i][0];
-                    if tbn.task_name == task.task_name {
-                        tasks_by_name[i].push((task.clone(), task_duration));
-                        unique = false;
-                    }
-                }
-                if unique {
-                    // Add unique task to list for group name
-                    let mut new_name_list: Vec<(database::Task, i64)> = Vec::new();
-                    new_name_list.push((task.clone(), task_duration));
-                    tasks_by_name.push(new_name_list);
-                }
+        // The rounded column sits next to the raw duration, only when rounding
+        if let Some(rounded_column) = imp.results_tree.column(2) {
+            rounded_column.set_visible(query.rounding.is_some());
+        }
+        // Billing columns only matter once something is billable
+        for index in [3, 4] {
+            if let Some(column) = imp.results_tree.column(index) {
+                column.set_visible(report.billable_seconds > 0);
+            }
+        }
+        // And the comparison only when comparing
+        for index in [5, 6] {
+            if let Some(column) = imp.results_tree.column(index) {
+                column.set_visible(query.compare.is_some());
             }
+        }
+        // Estimates show once a task, tag or project in the report has a budget
+        if let Some(estimate_column) = imp.results_tree.column(7) {
+            estimate_column.set_visible(report.has_estimates());
+        }
+        let compare_text = query.compare.map(|compare| {
+            let range = compare.range_for(&query.range);
+            format!("{} – {}", range.start.format("%x"), range.end.format("%x"))
+        });
+        imp.compare_box.set_tooltip_text(compare_text.as_deref());
+        let rounding_text = query.rounding.map(|rounding| rounding.describe());
+        imp.rounding_box.set_tooltip_text(rounding_text.as_deref());
 
-            let mut sorted_tasks_by_duration: Vec<(String, i64, Vec<(String, i64)>)> = Vec::new();
-            for tbn in tasks_by_name {
-                let mut total_duration: i64 = 0;
-                let mut tags_dur: Vec<(String, i64)> = Vec::new();
-                let task_name = tbn[0].0.task_name.to_string();
-                for tbn_tuple in tbn {
-                    let (task, task_duration) = tbn_tuple;
-                    total_duration += task_duration;
-
-                    let mut split_tags: Vec<&str> = task.tags.split("#").collect();
-                    split_tags = split_tags.iter().map(|x| x.trim()).collect();
-                    split_tags.retain(|&x| !x.trim().is_empty());
-                    if !split_tags.is_empty() {
-                        let mut formatted_tags = split_tags.join(" #");
-                        formatted_tags = format!("#{}", formatted_tags);
-                        let mut unique = true;
-                        for i in 0..tags_dur.len() {
-                            let (tags, dur) = &tags_dur[i];
-                            if tags == &formatted_tags {
-                                let new_dur = dur + task_duration;
-                                tags_dur[i] = (formatted_tags.clone(), new_dur);
-                                unique = false;
-                            }
-                        }
-                        if unique {
-                            tags_dur.push((formatted_tags, task_duration))
-                        }
-                    }
-                }
+        imp.results_tree.set_model(Some(&results_model));
+        // Automatically expand All Tasks row
+        let all_tasks_path = gtk::TreePath::new_first();
+        imp.results_tree.expand_row(&all_tasks_path, false);
+
+        *imp.query.borrow_mut() = Some(query);
+        self.refresh_chart();
+    }
+
+    fn refresh_chart(&self) {
+        let imp = imp::FurReport::from_obj(self);
+
+        let kind = match imp.chart_dropdown.selected() {
+            1 => ChartKind::Share,
+            2 => ChartKind::Trend,
+            _ => ChartKind::Bars,
+        };
+        let share_by = match imp.share_by_dropdown.selected() {
+            1 => GroupBy::Tag,
+            2 => GroupBy::Project,
+            _ => GroupBy::Task,
+        };
+        // The trend is always the daily total
+        imp.share_by_dropdown.set_sensitive(kind != ChartKind::Trend);
+
+        let daily_goal = settings_manager::goals().daily;
+        let chart = imp.query.borrow().as_ref().and_then(|query| {
+            database::open_db()
+                .map_err(anyhow::Error::from)
+                .and_then(|conn| Chart::load(&conn, kind, query, share_by, daily_goal))
+                .ok()
+        });
+        *imp.chart.borrow_mut() = chart;
+        imp.chart_area.queue_draw();
+    }
 
-                // Sort tasks and tags in descending order by duration
-                tags_dur.sort_by_key(|k| k.1);
-                tags_dur.reverse();
-                sorted_tasks_by_duration.push((task_name, total_duration, tags_dur));
-                sorted_tasks_by_duration.sort_by_key(|k| k.1);
-                sorted_tasks_by_duration.reverse();
+    fn open_report_export_dialog(&self) {
+        let imp = imp::FurReport::from_obj(self);
+
+        let html_filter = gtk::FileFilter::new();
+        html_filter.set_name(Some(&gettext("HTML Page")));
+        html_filter.add_mime_type("text/html");
+        html_filter.add_pattern("*.html");
+        let markdown_filter = gtk::FileFilter::new();
+        markdown_filter.set_name(Some(&gettext("Markdown")));
+        markdown_filter.add_mime_type("text/markdown");
+        markdown_filter.add_pattern("*.md");
+        let pdf_filter = gtk::FileFilter::new();
+        pdf_filter.set_name(Some(&gettext("PDF Document")));
+        pdf_filter.add_mime_type("application/pdf");
+        pdf_filter.add_pattern("*.pdf");
+
+        let filechooser = gtk::FileChooserNative::builder()
+            .title(&gettext("Export Report"))
+            .modal(true)
+            .transient_for(self)
+            .action(gtk::FileChooserAction::Save)
+            .accept_label(&gettext("Export"))
+            .cancel_label(&gettext("Cancel"))
+            .select_multiple(false)
+            .build();
+        filechooser.add_filter(&html_filter);
+        filechooser.add_filter(&markdown_filter);
+        filechooser.add_filter(&pdf_filter);
+        filechooser.set_current_name("report.html");
+
+        filechooser.connect_response(clone!(@weak self as this => move |filechooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = filechooser.file().and_then(|file| file.path()) {
+                    this.export_report(&path);
+                }
             }
+        }));
+
+        filechooser.show();
+        *imp.filechooser.borrow_mut() = filechooser;
+    }
+
+    fn export_report(&self, path: &Path) {
+        let imp = imp::FurReport::from_obj(self);
+        let query = match imp.query.borrow().clone() {
+            Some(query) => query,
+            None => return,
+        };
+
+        let result = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| reporting::run(&conn, &query))
+            .and_then(|report| {
+                ReportDocument::new(&query, &report).write(path, ExportFormat::from_path(path))
+            });
+
+        if let Err(e) = result {
+            log::error!("exporting report to {:?} failed, Err {}", path, e);
+            self.show_export_error(&gettext("Could not export report"), &e);
+        }
+    }
 
-            for stbd in sorted_tasks_by_duration {
-                let header_iter = results_model.append(Some(&all_tasks_iter));
-                for (task, task_duration) in stbd.2 {
-                    let _child_iter = results_model.insert_with_values(
-                        Some(&header_iter),
-                        None,
-                        &[(0, &task), (1, &FurReport::format_duration(task_duration))],
-                    );
+    fn show_export_error(&self, heading: &str, error: &anyhow::Error) {
+        let dialog = gtk::MessageDialog::with_markup(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Error,
+            gtk::ButtonsType::Ok,
+            Some(&format!("<span size='large'>{}</span>", heading)),
+        );
+        dialog.set_secondary_text(Some(&error.to_string()));
+        dialog.connect_response(|dialog, _| dialog.close());
+        dialog.show();
+    }
+
+    fn open_chart_export_dialog(&self) {
+        let imp = imp::FurReport::from_obj(self);
+
+        let png_filter = gtk::FileFilter::new();
+        png_filter.set_name(Some(&gettext("PNG Image")));
+        png_filter.add_mime_type("image/png");
+        png_filter.add_pattern("*.png");
+        let svg_filter = gtk::FileFilter::new();
+        svg_filter.set_name(Some(&gettext("SVG Image")));
+        svg_filter.add_mime_type("image/svg+xml");
+        svg_filter.add_pattern("*.svg");
+
+        let filechooser = gtk::FileChooserNative::builder()
+            .title(&gettext("Export Chart"))
+            .modal(true)
+            .transient_for(self)
+            .action(gtk::FileChooserAction::Save)
+            .accept_label(&gettext("Export"))
+            .cancel_label(&gettext("Cancel"))
+            .select_multiple(false)
+            .build();
+        filechooser.add_filter(&png_filter);
+        filechooser.add_filter(&svg_filter);
+        filechooser.set_current_name("chart.png");
+
+        filechooser.connect_response(clone!(@weak self as this => move |filechooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(path) = filechooser.file().and_then(|file| file.path()) {
+                    this.export_chart(&path);
                 }
-                results_model.set(
-                    &header_iter,
-                    &[(0, &stbd.0), (1, &FurReport::format_duration(stbd.1))],
-                );
             }
-        } else if imp.sort_by_tag.is_active() {
-            let mut tasks_by_tag: Vec<Vec<(String, database::Task, i64)>> = Vec::new();
-            for (task, task_duration) in tasks_in_range {
-                let mut split_tags: Vec<&str> = task.tags.split("#").collect();
-                // Trim whitespace around each tag
-                split_tags = split_tags.iter().map(|x| x.trim()).collect();
-                for tag in split_tags {
-                    let mut unique = true;
-                    for i in 0..tasks_by_tag.len() {
-                        let (tbt_tag, _, _) = &tasks_by_tag[i][0];
-                        if tbt_tag == tag {
-                            tasks_by_tag[i].push((tag.to_string(), task.clone(), task_duration));
-                            unique = false;
-                        }
-                    }
-                    if unique {
-                        // Add unique task to list for group name
-                        let mut new_name_list: Vec<(String, database::Task, i64)> = Vec::new();
-                        new_name_list.push((tag.to_string(), task.clone(), task_duration));
-                        tasks_by_tag.push(new_name_list);
+        }));
+
+        filechooser.show();
+        *imp.filechooser.borrow_mut() = filechooser;
+    }
+
+    fn export_chart(&self, path: &Path) {
+        let imp = imp::FurReport::from_obj(self);
+        let chart = imp.chart.borrow();
+        let chart = match chart.as_ref() {
+            Some(chart) => chart,
+            None => return,
+        };
+
+        // The format follows the file extension, PNG unless it ends in .svg
+        let is_svg = path
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("svg"));
+        let result = if is_svg {
+            chart.write_svg(path, CHART_EXPORT_WIDTH as f64, CHART_EXPORT_HEIGHT as f64)
+        } else {
+            chart.write_png(path, CHART_EXPORT_WIDTH, CHART_EXPORT_HEIGHT)
+        };
+
+        if let Err(e) = result {
+            log::error!("exporting chart to {:?} failed, Err {}", path, e);
+            self.show_export_error(&gettext("Could not export chart"), &e);
+        }
+    }
+
+    fn build_query(&self) -> Option<ReportQuery> {
+        let preset = self.current_preset(String::new())?;
+        let today = Local::now().naive_local().date();
+        Some(preset.query(
+            today,
+            settings_manager::week_starts_on(),
+            settings_manager::fiscal_year_start(),
+        ))
+    }
+
+    /// Reads the report settings from the widgets, showing an error and
+    /// returning None if the custom dates or the filter expression are invalid.
+    fn current_preset(&self, name: String) -> Option<ReportPreset> {
+        let imp = imp::FurReport::from_obj(self);
+
+        // Get date range
+        let range = match imp.range_combo.active_id().unwrap().as_str() {
+            "yesterday_item" => RangePreset::Yesterday,
+            "this_week_item" => RangePreset::ThisWeek,
+            "last_week_item" => RangePreset::LastWeek,
+            "week_item" => RangePreset::PastWeek,
+            "month_item" => RangePreset::ThisMonth,
+            "last_month_item" => RangePreset::LastMonth,
+            "30_days_item" => RangePreset::PastMonth,
+            "quarter_item" => RangePreset::ThisQuarter,
+            "six_months_item" => RangePreset::PastHalfYear,
+            "this_year_item" => RangePreset::ThisYear,
+            "year_to_date_item" => RangePreset::YearToDate,
+            "year_item" => RangePreset::PastYear,
+            "fiscal_year_item" => RangePreset::FiscalYear,
+            _ => {
+                let input_start_date = dates::parse_date(&imp.start_date_entry.text());
+                let input_end_date = dates::parse_date(&imp.end_date_entry.text());
+                // Check if user entered dates properly
+                let (start_date, end_date) = match (input_start_date, input_end_date) {
+                    (Some(start_date), Some(end_date)) => (start_date, end_date),
+                    _ => {
+                        imp.format_error.set_visible(true);
+                        return None;
                     }
+                };
+                // Start date cannot be after end date
+                if (end_date - start_date).num_days() < 0 {
+                    imp.start_end_error.set_visible(true);
+                    return None;
                 }
+                RangePreset::Custom(start_date, end_date)
             }
+        };
+
+        // Sort by only selected tasks or tags if filter is selected
+        let mut filters: Vec<Filter> = Vec::new();
+        if imp.filter_check.is_active()
+            && imp.filter_combo.active_id().unwrap() == "expression_item"
+            && !imp.filter_entry.text().trim().is_empty()
+        {
+            let expression = imp.filter_entry.text().trim().to_string();
+            if let Err(e) = filter::parse(&expression) {
+                imp.filter_error.set_text(&e.to_string());
+                imp.filter_error.set_visible(true);
+                return None;
+            }
+            filters.push(Filter::Expression(expression));
+        } else if imp.filter_check.is_active() && !imp.filter_entry.text().trim().is_empty() {
+            let chosen = imp.filter_entry.text();
+            let chosen: Vec<String> = chosen
+                .split(',')
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .unique()
+                .collect();
+            if imp.filter_combo.active_id().unwrap() == "tasks_item" {
+                filters.push(Filter::Tasks(chosen));
+            } else {
+                filters.push(Filter::Tags(chosen));
+            }
+        }
 
-            let mut sorted_tasks_by_duration: Vec<(String, i64, Vec<(String, i64)>)> = Vec::new();
-            for tbt in tasks_by_tag {
-                let mut total_duration: i64 = 0;
-                let mut tasks_dur: Vec<(String, i64)> = Vec::new();
-                let tag_name = format!("#{}", tbt[0].0);
-                for tbt_tuple in tbt {
-                    let (_, task, tag_duration) = tbt_tuple;
-                    total_duration += tag_duration;
-
-                    let mut unique = true;
-                    for i in 0..tasks_dur.len() {
-                        let (td_task, dur) = &tasks_dur[i];
-                        if td_task == &task.task_name {
-                            let new_dur = dur + tag_duration;
-                            tasks_dur[i] = (task.task_name.clone(), new_dur);
-                            unique = false;
-                        }
-                    }
-                    if unique {
-                        tasks_dur.push((task.task_name.clone(), tag_duration))
-                    }
+        // Skip empty levels and any grouping already used above
+        let mut group_by: Vec<GroupBy> = Vec::new();
+        for dropdown in [&*imp.group_first, &*imp.group_second, &*imp.group_third] {
+            let group = GROUP_OPTIONS
+                .get(dropdown.selected() as usize)
+                .copied()
+                .flatten();
+            if let Some(group) = group {
+                if !group_by.contains(&group) {
+                    group_by.push(group);
                 }
+            }
+        }
+
+        let increment = ROUNDING_INCREMENTS
+            .get(imp.rounding_increment.selected() as usize)
+            .copied()
+            .unwrap_or(0);
+        let rounding = if increment > 0 {
+            Some(Rounding {
+                increment: increment * 60,
+                mode: ROUNDING_MODES
+                    .get(imp.rounding_mode.selected() as usize)
+                    .copied()
+                    .unwrap_or(RoundingMode::Up),
+                scope: ROUNDING_SCOPES
+                    .get(imp.rounding_scope.selected() as usize)
+                    .copied()
+                    .unwrap_or(RoundingScope::Entry),
+            })
+        } else {
+            None
+        };
+
+        let compare = match imp.compare_dropdown.selected() {
+            0 => None,
+            selected => COMPARISONS.get(selected as usize - 1).copied(),
+        };
+
+        Some(ReportPreset {
+            name,
+            range,
+            filters,
+            group_by,
+            rounding,
+            compare,
+        })
+    }
 
-                // Sort tags and tasks in descending order by duration
-                tasks_dur.sort_by_key(|k| k.1);
-                tasks_dur.reverse();
-                sorted_tasks_by_duration.push((tag_name, total_duration, tasks_dur));
-                sorted_tasks_by_duration.sort_by_key(|k| k.1);
-                sorted_tasks_by_duration.reverse();
+    /// Sets the widgets to a saved preset and runs it.
+    fn apply_preset(&self, preset: &ReportPreset) {
+        let imp = imp::FurReport::from_obj(self);
+        imp.applying_preset.set(true);
+
+        let range_id = match preset.range {
+            RangePreset::Yesterday => "yesterday_item",
+            RangePreset::ThisWeek => "this_week_item",
+            RangePreset::LastWeek => "last_week_item",
+            RangePreset::PastWeek => "week_item",
+            RangePreset::ThisMonth => "month_item",
+            RangePreset::LastMonth => "last_month_item",
+            RangePreset::PastMonth => "30_days_item",
+            RangePreset::ThisQuarter => "quarter_item",
+            RangePreset::PastHalfYear => "six_months_item",
+            RangePreset::ThisYear => "this_year_item",
+            RangePreset::YearToDate => "year_to_date_item",
+            RangePreset::PastYear => "year_item",
+            RangePreset::FiscalYear => "fiscal_year_item",
+            RangePreset::Custom(start, end) => {
+                imp.start_date_entry.set_text(&dates::format_date(start));
+                imp.end_date_entry.set_text(&dates::format_date(end));
+                "date_range_item"
             }
+        };
+        imp.range_combo.set_active_id(Some(range_id));
+
+        imp.filter_check.set_active(false);
+        imp.filter_entry.set_text("");
+        for filter in &preset.filters {
+            let (filter_id, text) = match filter {
+                Filter::Tasks(chosen) => ("tasks_item", chosen.join(", ")),
+                Filter::Tags(chosen) => ("tags_item", chosen.join(", ")),
+                Filter::Expression(expression) => ("expression_item", expression.clone()),
+                // Project filters can't be entered here yet, but still apply
+                Filter::Projects(_) => continue,
+            };
+            imp.filter_check.set_active(true);
+            imp.filter_combo.set_active_id(Some(filter_id));
+            imp.filter_entry.set_text(&text);
+        }
 
-            for mut stbd in sorted_tasks_by_duration {
-                if !only_this_tag
-                    || (only_this_tag && user_chosen_tags.contains(&stbd.0[1..].to_string()))
-                {
-                    let header_iter = results_model.append(Some(&all_tasks_iter));
-                    for (task, task_duration) in stbd.2 {
-                        let _child_iter = results_model.insert_with_values(
-                            Some(&header_iter),
-                            None,
-                            &[(0, &task), (1, &FurReport::format_duration(task_duration))],
-                        );
-                    }
-                    if stbd.0 == "#" {
-                        stbd.0 = gettext("no tags").to_string();
-                    }
-                    results_model.set(
-                        &header_iter,
-                        &[(0, &stbd.0), (1, &FurReport::format_duration(stbd.1))],
-                    );
-                }
+        let dropdowns = [&*imp.group_first, &*imp.group_second, &*imp.group_third];
+        for (level, dropdown) in dropdowns.iter().enumerate() {
+            let group = preset.group_by.get(level).copied();
+            let position = GROUP_OPTIONS.iter().position(|option| *option == group).unwrap_or(0);
+            dropdown.set_selected(position as u32);
+        }
+
+        match preset.rounding {
+            Some(rounding) => {
+                let increment = ROUNDING_INCREMENTS
+                    .iter()
+                    .position(|minutes| minutes * 60 == rounding.increment)
+                    .unwrap_or(0);
+                let mode = ROUNDING_MODES.iter().position(|mode| *mode == rounding.mode).unwrap_or(0);
+                let scope = ROUNDING_SCOPES.iter().position(|scope| *scope == rounding.scope).unwrap_or(0);
+                imp.rounding_increment.set_selected(increment as u32);
+                imp.rounding_mode.set_selected(mode as u32);
+                imp.rounding_scope.set_selected(scope as u32);
             }
+            None => imp.rounding_increment.set_selected(0),
         }
 
-        imp.results_tree.set_model(Some(&results_model));
-        // Automatically expand All Tasks row
-        let all_tasks_path = gtk::TreePath::new_first();
-        imp.results_tree.expand_row(&all_tasks_path, false);
+        let compare = preset
+            .compare
+            .and_then(|compare| COMPARISONS.iter().position(|option| *option == compare))
+            .map_or(0, |position| position + 1);
+        imp.compare_dropdown.set_selected(compare as u32);
+
+        imp.applying_preset.set(false);
+        self.refresh_report();
     }
 
-    fn week_bounds(weekday: Weekday, mut week: u32) -> (Date<Local>, Date<Local>) {
-        let mut week_starts_on = Weekday::Mon;
-        let mut week_ends_on = Weekday::Sun;
-        // If user set week to start Sunday
-        if settings_manager::get_int("week-starts") == 1 {
-            week_starts_on = Weekday::Sun;
-            week_ends_on = Weekday::Sat;
+    /// Fills the preset dropdown from the database, selecting `selected`.
+    fn load_presets(&self, selected: Option<&str>) {
+        let imp = imp::FurReport::from_obj(self);
+        let presets = database::get_report_presets().unwrap_or_default();
+
+        imp.applying_preset.set(true);
+        let mut names: Vec<String> = vec![gettext("Unsaved Report")];
+        names.extend(presets.iter().map(|preset| preset.name.clone()));
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        imp.preset_list.splice(0, imp.preset_list.n_items(), &names);
+
+        let position = selected
+            .and_then(|selected| presets.iter().position(|preset| preset.name == selected))
+            .map_or(0, |position| position + 1);
+        imp.preset_dropdown.set_selected(position as u32);
+        imp.delete_preset_btn.set_sensitive(position > 0);
+        imp.applying_preset.set(false);
+
+        *imp.presets.borrow_mut() = presets;
+    }
+
+    /// The preset picked in the dropdown, None for "Unsaved Report".
+    fn selected_preset(&self) -> Option<ReportPreset> {
+        let imp = imp::FurReport::from_obj(self);
+        match imp.preset_dropdown.selected() {
+            0 | gtk::INVALID_LIST_POSITION => None,
+            position => imp.presets.borrow().get(position as usize - 1).cloned(),
         }
+    }
 
-        let current_year = chrono::offset::Local::now().year();
-        let week_start = NaiveDate::from_isoywd(current_year, week, week_starts_on);
+    fn open_save_preset_dialog(&self) {
+        let current_name = self
+            .selected_preset()
+            .map(|preset| preset.name)
+            .unwrap_or_default();
+
+        let dialog = gtk::MessageDialog::new(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &format!("<span size='x-large' weight='bold'>{}</span>", &gettext("Save Report")),
+        );
+        dialog.set_use_markup(true);
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text(Some(&gettext("Name")));
+        entry.set_text(&current_name);
+        entry.set_activates_default(true);
+        let invalid = gtk::Label::new(Some(&gettext("Name cannot be empty.")));
+        invalid.add_css_class("error_message");
+        invalid.hide();
+        message_area.append(&entry);
+        message_area.append(&invalid);
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                let name = entry.text().trim().to_string();
+                if name.is_empty() {
+                    invalid.show();
+                    return;
+                }
+                if let Some(preset) = this.current_preset(name.clone()) {
+                    match database::save_report_preset(&preset) {
+                        Ok(_) => this.load_presets(Some(&name)),
+                        Err(e) => this.show_export_error(&gettext("Could not save report"), &e),
+                    }
+                }
+            }
+            dialog.close();
+        }));
+
+        dialog.show();
+    }
 
-        // If weekday start Sunday, add 1 to the week number only after getting the week start date
-        if settings_manager::get_int("week-starts") == 1 && weekday == Weekday::Sun {
-            week += 1;
+    fn delete_selected_preset(&self) {
+        let name = match self.selected_preset() {
+            Some(preset) => preset.name,
+            None => return,
+        };
+
+        if let Err(e) = database::delete_report_preset(&name) {
+            log::error!("deleting report preset {} failed, Err {}", name, e);
         }
+        self.load_presets(None);
+    }
 
-        let week_end = NaiveDate::from_isoywd(current_year, week, week_ends_on);
-        let week_start_local = Local.from_local_date(&week_start).unwrap();
-        let week_end_local = Local.from_local_date(&week_end).unwrap();
-        (week_start_local, week_end_local)
+    fn previous_text(node: &ReportNode) -> String {
+        node.previous_seconds
+            .map(reporting::format_duration)
+            .unwrap_or_default()
     }
 
-    fn format_duration(total_time: i64) -> String {
-        // Format total time to readable string
-        let h = total_time / 3600;
-        let m = total_time % 3600 / 60;
-        let s = total_time % 60;
-        format!("{:02}:{:02}:{:02}", h, m, s)
+    /// Adds a row for each child of `parent`, with its subtotal and its
+    /// share of the parent's time.
+    fn append_nodes(model: &gtk::TreeStore, iter: &gtk::TreeIter, parent: &ReportNode) {
+        for node in &parent.children {
+            let percent = format!("{:.1}%", node.percent_of(parent.seconds));
+            let child_iter = model.insert_with_values(
+                Some(iter),
+                None,
+                &[
+                    (0, &node.label()),
+                    (1, &reporting::format_duration(node.seconds)),
+                    (2, &percent),
+                    (3, &reporting::format_duration(node.rounded_seconds)),
+                    (4, &reporting::format_duration(node.billable_seconds)),
+                    (5, &node.earnings.format()),
+                    (6, &FurReport::previous_text(node)),
+                    (7, &node.change_text()),
+                    (8, &node.estimate_text()),
+                ],
+            );
+            FurReport::append_nodes(model, &child_iter, node);
+        }
     }
 }
 
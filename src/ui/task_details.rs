@@ -14,13 +14,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use adw::prelude::{ActionRowExt, PreferencesRowExt};
 use adw::subclass::prelude::*;
 use chrono::{offset::TimeZone, DateTime, Local, NaiveDateTime, ParseError, Duration};
 use gettextrs::*;
 use glib::clone;
-use gtk::{glib, prelude::*, CompositeTemplate};
+use gtk::{gio, glib, prelude::*, CompositeTemplate};
 use itertools::Itertools;
 
+use crate::billing;
 use crate::database;
 use crate::settings_manager;
 use crate::ui::rusttimetrackWindow;
@@ -54,10 +56,16 @@ mod imp {
         #[template_child]
         pub delete_all_btn: TemplateChild<gtk::Button>,
 
+        #[template_child]
+        pub changes_section: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub changes_list: TemplateChild<gtk::ListBox>,
+
         pub all_boxes: RefCell<Vec<gtk::Box>>,
         pub all_task_ids: RefCell<Vec<i32>>,
         pub this_task_name: RefCell<String>,
         pub this_task_tags: RefCell<String>,
+        pub this_task_project: RefCell<String>,
         pub this_day: RefCell<String>,
         pub orig_tags: RefCell<String>,
         pub orig_name_with_tags: RefCell<String>,
@@ -120,6 +128,7 @@ impl FurTaskDetails {
         let this_day_str = DateTime::parse_from_rfc3339(&task_group[0].start_time).unwrap();
         *imp.this_task_name.borrow_mut() = task_group[0].task_name.clone();
         *imp.this_task_tags.borrow_mut() = task_group[0].tags.clone();
+        *imp.this_task_project.borrow_mut() = task_group[0].project.clone();
         *imp.this_day.borrow_mut() = this_day_str.format("%F").to_string();
         *imp.orig_tags.borrow_mut() = task_group[0].tags.clone();
         *imp.orig_name_with_tags.borrow_mut() = task_group[0].task_name.clone() + " #" + &task_group[0].tags.clone();
@@ -132,10 +141,20 @@ impl FurTaskDetails {
         let time_formatter = "%F %H:%M:%S";
         let time_formatter_no_secs = "%F %H:%M";
         let task_group_len = task_group.len();
+        // Neighbours in time, for merging adjacent entries
+        let mut ids_by_start: Vec<(i32, DateTime<chrono::FixedOffset>)> = task_group
+            .iter()
+            .map(|task| (task.id, DateTime::parse_from_rfc3339(&task.start_time).unwrap()))
+            .collect();
+        ids_by_start.sort_by_key(|(_, start)| *start);
+        let ids_by_start: Vec<i32> = ids_by_start.into_iter().map(|(id, _)| id).collect();
         task_group.reverse();
         for task in task_group {
+            let entry_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
             let task_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
             task_box.set_homogeneous(true);
+            task_box.set_hexpand(true);
+            entry_box.append(&task_box);
 
             let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap();
             let mut start_time_str = start_time.format("%H:%M:%S").to_string();
@@ -170,8 +189,13 @@ impl FurTaskDetails {
             total.set_hexpand(false);
             task_box.append(&total);
 
-            imp.main_box.append(&task_box);
-            imp.all_boxes.borrow_mut().push(task_box);
+            let position = ids_by_start.iter().position(|id| *id == task.id).unwrap();
+            let previous_id = if position > 0 { Some(ids_by_start[position - 1]) } else { None };
+            let next_id = ids_by_start.get(position + 1).copied();
+            entry_box.append(&self.create_entry_menu(&task, previous_id, next_id));
+
+            imp.main_box.append(&entry_box);
+            imp.all_boxes.borrow_mut().push(entry_box);
 
             start.connect_clicked(clone!(@weak self as this => move |_|{
                 let dialog = gtk::MessageDialog::new(
@@ -195,6 +219,19 @@ impl FurTaskDetails {
                     task_tags = format!("#{}", task.tags);
                     task_tags_edit.set_text(&task_tags);
                 }
+                let task_project_edit = gtk::Entry::new();
+                task_project_edit.set_placeholder_text(Some(&gettext("Project")));
+                task_project_edit.set_text(&task.project);
+                let billable_dropdown = gtk::DropDown::from_strings(&[
+                    &gettext("Billable as Set in Rates"),
+                    &gettext("Billable"),
+                    &gettext("Not Billable"),
+                ]);
+                billable_dropdown.set_selected(match task.billable {
+                    None => 0,
+                    Some(true) => 1,
+                    Some(false) => 2,
+                });
                 let labels_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
                 labels_box.set_homogeneous(true);
                 let start_label = gtk::Label::new(Some(&gettext("Start")));
@@ -235,6 +272,10 @@ impl FurTaskDetails {
                 future_error.set_visible(false);
                 future_error.add_css_class("error_message");
 
+                let save_error = gtk::Label::new(None);
+                save_error.set_visible(false);
+                save_error.add_css_class("error_message");
+
                 let delete_task_btn = gtk::Button::new();
                 delete_task_btn.set_icon_name("user-trash-symbolic");
                 delete_task_btn.set_tooltip_text(Some(&gettext("Delete task")));
@@ -244,6 +285,8 @@ impl FurTaskDetails {
 
                 vert_box.append(&task_name_edit);
                 vert_box.append(&task_tags_edit);
+                vert_box.append(&task_project_edit);
+                vert_box.append(&billable_dropdown);
                 labels_box.append(&start_label);
                 labels_box.append(&stop_label);
                 times_box.append(&start_time_edit);
@@ -253,6 +296,7 @@ impl FurTaskDetails {
                 vert_box.append(&instructions);
                 vert_box.append(&time_error);
                 vert_box.append(&future_error);
+                vert_box.append(&save_error);
                 message_area.append(&delete_task_btn);
                 message_area.append(&vert_box);
 
@@ -303,14 +347,18 @@ impl FurTaskDetails {
                         @strong task.task_name as name,
                         @strong task.start_time as start_time,
                         @strong task.stop_time as stop_time,
-                        @strong task.tags as tags => move |_ , resp| {
+                        @strong task.tags as tags,
+                        @strong task.project as project,
+                        @strong task.billable as billable => move |_ , resp| {
                         if resp == gtk::ResponseType::Ok {
                             instructions.set_visible(false);
                             time_error.set_visible(false);
                             future_error.set_visible(false);
+                            save_error.set_visible(false);
                             let mut start_successful = false;
                             let mut stop_successful = false;
                             let mut do_not_close = false;
+                            let mut results: Vec<anyhow::Result<()>> = Vec::new();
                             let mut new_start_time_edited: String = "".to_string();
                             let mut new_start_time_local = Local::now();
                             let new_stop_time_edited: String;
@@ -359,10 +407,8 @@ impl FurTaskDetails {
                                     new_stop_time_edited = new_stop_time.to_rfc3339();
                                     if start_successful {
                                         if (new_stop_time - new_start_time_local).num_seconds() >= 0 {
-                                            database::update_stop_time(task.id, new_stop_time_edited.clone())
-                                                .expect("Failed to update stop time.");
-                                            database::update_start_time(task.id, new_start_time_edited.clone())
-                                                .expect("Failed to update start time.");
+                                            results.push(database::update_stop_time(task.id, new_stop_time_edited.clone()));
+                                            results.push(database::update_start_time(task.id, new_start_time_edited.clone()));
                                         }
                                     } else {
                                         let old_start_time = DateTime::parse_from_rfc3339(&start_time);
@@ -371,8 +417,7 @@ impl FurTaskDetails {
                                             future_error.set_visible(true);
                                             do_not_close = true;
                                         } else if (new_stop_time - old_start_time).num_seconds() >= 0 {
-                                            database::update_stop_time(task.id, new_stop_time_edited)
-                                                .expect("Failed to update stop time.");
+                                            results.push(database::update_stop_time(task.id, new_stop_time_edited));
                                         } else {
                                             time_error.set_visible(true);
                                             do_not_close = true;
@@ -382,8 +427,7 @@ impl FurTaskDetails {
                                 }
                             }
                             if task_name_edit.text() != name {
-                                database::update_task_name(task.id, task_name_edit.text().to_string())
-                                    .expect("Failed to update task name.");
+                                results.push(database::update_task_name(task.id, task_name_edit.text().to_string()));
                             }
 
                             if task_tags_edit.text() != task_tags {
@@ -397,22 +441,40 @@ impl FurTaskDetails {
 	                            // Lowercase tags
 	                            let lower_tags: Vec<String> = split_tags.iter().map(|x| x.to_lowercase()).collect();
 	                            let new_tag_list = lower_tags.join(" #");
-                                database::update_tags(task.id, new_tag_list)
-                                    .expect("Failed to update tags.");
+                                results.push(database::update_tags(task.id, new_tag_list));
+                            }
+
+                            if task_project_edit.text().trim() != project {
+                                results.push(database::update_project(task.id, task_project_edit.text().trim().to_string()));
+                            }
+
+                            let new_billable = match billable_dropdown.selected() {
+                                1 => Some(true),
+                                2 => Some(false),
+                                _ => None,
+                            };
+                            if new_billable != billable {
+                                results.push(database::update_billable(task.id, new_billable));
                             }
 
                             if start_successful && !stop_successful {
                                 let old_stop_time = DateTime::parse_from_rfc3339(&stop_time);
                                 let old_stop_time = old_stop_time.unwrap().with_timezone(&Local);
                                 if (old_stop_time - new_start_time_local).num_seconds() >= 0 {
-                                    database::update_start_time(task.id, new_start_time_edited)
-                                        .expect("Failed to update start time.");
+                                    results.push(database::update_start_time(task.id, new_start_time_edited));
                                 } else {
                                     time_error.set_visible(true);
                                     do_not_close = true;
                                 }
                             }
 
+                            // Invoiced entries can't be changed
+                            if let Some(Err(e)) = results.into_iter().find(|result| result.is_err()) {
+                                save_error.set_text(&e.to_string());
+                                save_error.set_visible(true);
+                                do_not_close = true;
+                            }
+
                             if !do_not_close {
                                 this.clear_task_list();
                                 dialog.close();
@@ -432,6 +494,262 @@ impl FurTaskDetails {
                 start.emit_clicked();
             });
         }
+
+        self.setup_changes();
+    }
+
+    fn create_entry_menu(
+        &self,
+        task: &database::Task,
+        previous_id: Option<i32>,
+        next_id: Option<i32>,
+    ) -> gtk::MenuButton {
+        let menu = gio::Menu::new();
+        menu.append(Some(&gettext("Split…")), Some("entry.split"));
+        menu.append(Some(&gettext("Merge with Previous")), Some("entry.merge-previous"));
+        menu.append(Some(&gettext("Merge with Next")), Some("entry.merge-next"));
+
+        let menu_button = gtk::MenuButton::new();
+        menu_button.set_icon_name("view-more-symbolic");
+        menu_button.set_tooltip_text(Some(&gettext("More")));
+        menu_button.set_menu_model(Some(&menu));
+        menu_button.add_css_class("flat");
+
+        let actions = gio::SimpleActionGroup::new();
+
+        let split_action = gio::SimpleAction::new("split", None);
+        split_action.connect_activate(clone!(@weak self as this, @strong task => move |_, _| {
+            this.open_split_dialog(task.clone());
+        }));
+        actions.add_action(&split_action);
+
+        let merge_previous_action = gio::SimpleAction::new("merge-previous", None);
+        merge_previous_action.set_enabled(previous_id.is_some());
+        merge_previous_action.connect_activate(clone!(@weak self as this, @strong task.id as id => move |_, _| {
+            if let Some(previous_id) = previous_id {
+                this.merge_entries(vec![previous_id, id]);
+            }
+        }));
+        actions.add_action(&merge_previous_action);
+
+        let merge_next_action = gio::SimpleAction::new("merge-next", None);
+        merge_next_action.set_enabled(next_id.is_some());
+        merge_next_action.connect_activate(clone!(@weak self as this, @strong task.id as id => move |_, _| {
+            if let Some(next_id) = next_id {
+                this.merge_entries(vec![id, next_id]);
+            }
+        }));
+        actions.add_action(&merge_next_action);
+
+        menu_button.insert_action_group("entry", Some(&actions));
+        menu_button
+    }
+
+    fn open_split_dialog(&self, task: database::Task) {
+        // Using ISO 8601 format until a localized option is possible
+        let time_formatter = if settings_manager::get_bool("show-seconds") {
+            "%F %H:%M:%S"
+        } else {
+            "%F %H:%M"
+        };
+        let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap();
+        let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap();
+
+        let dialog = gtk::MessageDialog::new(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("<span size='x-large' weight='bold'>{}</span>", &gettext("Split Task")),
+        );
+        dialog.set_use_markup(true);
+        dialog.add_buttons(&[
+            (&gettext("Cancel"), gtk::ResponseType::Cancel),
+            (&gettext("Split"), gtk::ResponseType::Ok)
+        ]);
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let vert_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+
+        let split_label = gtk::Label::new(Some(&gettext("Split at")));
+        split_label.add_css_class("title-4");
+        let split_time_edit = gtk::Entry::new();
+        let middle = start_time + (stop_time - start_time) / 2;
+        split_time_edit.set_text(&middle.format(time_formatter).to_string());
+
+        let second_label = gtk::Label::new(Some(&gettext("Second part")));
+        second_label.add_css_class("title-4");
+        let second_name_edit = gtk::Entry::new();
+        second_name_edit.set_placeholder_text(Some(&gettext("Task Name #tags")));
+        if task.tags.trim().is_empty() {
+            second_name_edit.set_text(&task.task_name);
+        } else {
+            second_name_edit.set_text(&format!("{} #{}", task.task_name, task.tags));
+        }
+
+        let instructions = gtk::Label::new(Some(
+            &gettext("*Use the format YYYY-MM-DD HH:MM:SS")));
+        if !settings_manager::get_bool("show-seconds") {
+            instructions.set_text(&gettext("*Use the format YYYY-MM-DD HH:MM"));
+        }
+        instructions.set_visible(false);
+        instructions.add_css_class("error_message");
+
+        let name_error = gtk::Label::new(Some(
+            &gettext("*Task name cannot be blank.")));
+        name_error.set_visible(false);
+        name_error.add_css_class("error_message");
+
+        let split_error = gtk::Label::new(None);
+        split_error.set_visible(false);
+        split_error.add_css_class("error_message");
+
+        vert_box.append(&split_label);
+        vert_box.append(&split_time_edit);
+        vert_box.append(&second_label);
+        vert_box.append(&second_name_edit);
+        vert_box.append(&instructions);
+        vert_box.append(&name_error);
+        vert_box.append(&split_error);
+        message_area.append(&vert_box);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                instructions.set_visible(false);
+                name_error.set_visible(false);
+                split_error.set_visible(false);
+
+                // Times skipped by a DST change don't exist
+                let split_time = NaiveDateTime::parse_from_str(&split_time_edit.text(), time_formatter)
+                    .ok()
+                    .and_then(|time| Local.from_local_datetime(&time).earliest());
+                let split_time = match split_time {
+                    Some(time) => time,
+                    None => {
+                        instructions.set_visible(true);
+                        return;
+                    }
+                };
+
+                let second_name = second_name_edit.text();
+                let (task_name, tags) = second_name.split_once('#').unwrap_or((second_name.as_str(), ""));
+                if task_name.trim().is_empty() {
+                    name_error.set_visible(true);
+                    return;
+                }
+                let tag_list = database::split_tags(tags).join(" #");
+
+                match database::split_task(task.id, split_time, task_name.trim(), &tag_list) {
+                    Ok(_) => {
+                        // The second part may have a name or tags of its own,
+                        // so it's only shown here if it still belongs
+                        let imp = imp::FurTaskDetails::from_obj(&this);
+                        let group = database::get_group(
+                            &imp.this_task_name.borrow(),
+                            &imp.orig_tags.borrow(),
+                            &imp.this_day.borrow(),
+                        );
+                        if let Ok(group) = group {
+                            *imp.all_task_ids.borrow_mut() = group.iter().map(|task| task.id).collect();
+                        }
+                        dialog.close();
+                        this.clear_task_list();
+                    }
+                    Err(e) => {
+                        split_error.set_text(&e.to_string());
+                        split_error.set_visible(true);
+                    }
+                }
+            } else {
+                dialog.close();
+            }
+        }));
+
+        dialog.show();
+    }
+
+    fn merge_entries(&self, id_list: Vec<i32>) {
+        if let Err(e) = database::merge_tasks(id_list) {
+            let dialog = gtk::MessageDialog::with_markup(
+                Some(self),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Error,
+                gtk::ButtonsType::Ok,
+                Some(&format!("<span size='large'>{}</span>", &gettext("Could not merge tasks"))),
+            );
+            dialog.set_secondary_text(Some(&e.to_string()));
+            dialog.connect_response(|dialog, _| dialog.close());
+            dialog.show();
+        } else {
+            self.clear_task_list();
+        }
+    }
+
+    fn setup_changes(&self) {
+        let imp = imp::FurTaskDetails::from_obj(self);
+
+        let changes = database::get_changes_by_ids(imp.all_task_ids.borrow().to_vec())
+            .unwrap_or_default();
+        imp.changes_section.set_visible(!changes.is_empty());
+
+        // Newest changes first
+        for change in changes.iter().rev() {
+            let changed_at = match DateTime::parse_from_rfc3339(&change.changed_at) {
+                Ok(time) => time.format("%F %H:%M:%S").to_string(),
+                Err(_) => change.changed_at.clone(),
+            };
+            let origin = match change.origin {
+                database::ChangeOrigin::Timer => gettext("Timer"),
+                database::ChangeOrigin::Manual => gettext("Manual edit"),
+                database::ChangeOrigin::Autosave => gettext("Autosave"),
+                database::ChangeOrigin::Import => gettext("Import"),
+                database::ChangeOrigin::Sync => gettext("Sync"),
+                database::ChangeOrigin::Cli => gettext("Command line"),
+            };
+
+            let row = adw::ActionRow::new();
+            row.set_title(&glib::markup_escape_text(&FurTaskDetails::describe_change(change)));
+            row.set_subtitle(&format!("{} · {}", changed_at, origin));
+            imp.changes_list.append(&row);
+        }
+    }
+
+    fn describe_change(change: &database::Change) -> String {
+        let field = match change.field.as_str() {
+            "task_name" => gettext("Name"),
+            "start_time" => gettext("Start"),
+            "stop_time" => gettext("Stop"),
+            "tags" => gettext("Tags"),
+            "project" => gettext("Project"),
+            "billable" => gettext("Billable"),
+            "deleted" => return gettext("Deleted"),
+            "imported" => return gettext("Imported"),
+            _ => change.field.clone(),
+        };
+
+        let old_value = FurTaskDetails::format_change_value(&change.field, &change.old_value);
+        let new_value = FurTaskDetails::format_change_value(&change.field, &change.new_value);
+        if change.old_value.is_empty() {
+            format!("{}: {}", field, new_value)
+        } else {
+            format!("{}: {} → {}", field, old_value, new_value)
+        }
+    }
+
+    fn format_change_value(field: &str, value: &str) -> String {
+        match field {
+            "start_time" | "stop_time" => match DateTime::parse_from_rfc3339(value) {
+                Ok(time) => time.format("%F %H:%M:%S").to_string(),
+                Err(_) => value.to_string(),
+            },
+            "tags" if !value.trim().is_empty() => format!("#{}", value),
+            "billable" => match billing::billable_from_sqlite(value) {
+                Some(true) => gettext("Yes"),
+                Some(false) => gettext("No"),
+                None => gettext("As set in rates"),
+            },
+            _ => value.to_string(),
+        }
     }
 
     fn clear_task_list(&self) {
@@ -442,6 +760,10 @@ impl FurTaskDetails {
         }
 
         imp.all_boxes.borrow_mut().clear();
+        while let Some(row) = imp.changes_list.row_at_index(0) {
+            imp.changes_list.remove(&row);
+        }
+
         // Get list from database by a vec of IDs
         let updated_list = database::get_list_by_id(imp.all_task_ids.clone().borrow().to_vec());
         let mut updated_list = updated_list.unwrap();
@@ -547,11 +869,11 @@ impl FurTaskDetails {
                     if !new_task_name.is_empty() {
                         // Change all task names & tags
                         let imp2 = imp::FurTaskDetails::from_obj(&this);
-                        for id in &*imp2.all_task_ids.borrow() {
-                            database::update_task_name(*id, new_task_name.trim().to_string())
-                                .expect("Could not update group of task names");
-                            database::update_tags(*id, tag_list.clone())
-                                .expect("Could not update tags of group");
+                        let ids = imp2.all_task_ids.borrow().to_vec();
+                        if let Err(e) = database::rename_tasks(&ids, new_task_name.trim(), &tag_list) {
+                            cant_be_empty.set_text(&e.to_string());
+                            cant_be_empty.show();
+                            return;
                         }
                         imp2.all_task_ids.borrow_mut().clear();
                         window.reset_history_box();
@@ -560,6 +882,7 @@ impl FurTaskDetails {
                     } else {
                         // Clear any spaces from entry
                         new_name_entry.set_text("");
+                        cant_be_empty.set_text(&gettext("Task name cannot be empty."));
                         cant_be_empty.show();
                     }
                 } else if resp == gtk::ResponseType::Cancel {
@@ -636,6 +959,7 @@ impl FurTaskDetails {
             let tags_placeholder = format!("#{}", imp2.this_task_tags.borrow());
             task_tags_edit.set_placeholder_text(Some(&tags_placeholder));
             task_tags_edit.set_text(&tags_placeholder);
+            let task_project = imp2.this_task_project.borrow().to_string();
 
             let labels_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
             labels_box.set_homogeneous(true);
@@ -789,7 +1113,9 @@ impl FurTaskDetails {
                         let _ = database::db_write(task_name_edit.text().trim(),
                                                     new_start_time_local,
                                                     new_stop_time_local,
-                                                    new_tag_list);
+                                                    new_tag_list,
+                                                    task_project.clone(),
+                                                    database::ChangeOrigin::Manual);
                         let window = rusttimetrackWindow::default();
                         window.reset_history_box();
                         dialog.close();
@@ -36,6 +36,8 @@ mod imp {
         #[template_child]
         pub row_box: TemplateChild<gtk::Box>,
         #[template_child]
+        pub selected_check: TemplateChild<gtk::CheckButton>,
+        #[template_child]
         pub task_name_label: TemplateChild<gtk::Label>,
         #[template_child]
         pub task_tags_label: TemplateChild<gtk::Label>,
@@ -93,14 +95,60 @@ impl FurTaskRow {
         let open_details_action = gio::SimpleAction::new("open-details", None);
 
         open_details_action.connect_activate(clone!(@strong self as this => move |_, _| {
-            let dialog = FurTaskDetails::new();
-            dialog.setup_widgets(this.get_tasks());
-            dialog.show();
+            let imp = imp::FurTaskRow::from_obj(&this);
+            // While selecting, activating a row toggles it instead
+            if imp.selected_check.is_visible() {
+                this.set_selected(!this.is_selected());
+            } else {
+                let dialog = FurTaskDetails::new();
+                dialog.setup_widgets(this.get_tasks());
+                dialog.show();
+            }
         }));
 
         let actions = gio::SimpleActionGroup::new();
         self.insert_action_group("task-row", Some(&actions));
         actions.add_action(&open_details_action);
+
+        // Ctrl or Shift click selects rows instead of opening them
+        let select_gesture = gtk::GestureClick::new();
+        select_gesture.set_button(gtk::gdk::ffi::GDK_BUTTON_PRIMARY as u32);
+        select_gesture.set_propagation_phase(gtk::PropagationPhase::Capture);
+        select_gesture.connect_pressed(clone!(@weak self as this => move |gesture, _, _, _| {
+            let state = gesture.current_event_state();
+            let extend = state.contains(gtk::gdk::ModifierType::SHIFT_MASK);
+            if extend || state.contains(gtk::gdk::ModifierType::CONTROL_MASK) {
+                gesture.set_state(gtk::EventSequenceState::Claimed);
+                let window = rusttimetrackWindow::default();
+                window.select_task_row(&this, extend);
+            }
+        }));
+        self.add_controller(select_gesture);
+
+        let imp = imp::FurTaskRow::from_obj(self);
+        imp.selected_check.connect_toggled(|_| {
+            let window = rusttimetrackWindow::default();
+            window.update_selection_count();
+        });
+    }
+
+    pub fn set_selection_mode(&self, selection_mode: bool) {
+        let imp = imp::FurTaskRow::from_obj(self);
+        imp.selected_check.set_visible(selection_mode);
+        imp.restart_task_btn.set_visible(!selection_mode);
+        if !selection_mode {
+            imp.selected_check.set_active(false);
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        let imp = imp::FurTaskRow::from_obj(self);
+        imp.selected_check.is_active()
+    }
+
+    pub fn set_selected(&self, selected: bool) {
+        let imp = imp::FurTaskRow::from_obj(self);
+        imp.selected_check.set_active(selected);
     }
 
     pub fn set_row_labels(&self, task_list: Vec<Task>) {
@@ -113,12 +161,18 @@ impl FurTaskRow {
         imp.task_name_label
             .set_text(&imp.tasks.lock().unwrap()[0].task_name);
 
-        // Display task's tags
-        if task_list[0].tags.trim().is_empty() || !settings_manager::get_bool("show-tags") {
+        // Display task's project and tags
+        let mut subtitle: Vec<String> = Vec::new();
+        if !task_list[0].project.trim().is_empty() {
+            subtitle.push(task_list[0].project.clone());
+        }
+        if !task_list[0].tags.trim().is_empty() && settings_manager::get_bool("show-tags") {
+            subtitle.push(format!("#{}", task_list[0].tags));
+        }
+        if subtitle.is_empty() {
             imp.task_tags_label.hide();
         } else {
-            let task_tags = format!("#{}", task_list[0].tags);
-            imp.task_tags_label.set_text(&task_tags);
+            imp.task_tags_label.set_text(&subtitle.join(" · "));
         }
 
         // Create right-click gesture
@@ -161,6 +215,11 @@ impl FurTaskRow {
         imp.total_time_label.set_text(&total_time_str);
     }
 
+    pub fn get_task_ids(&self) -> Vec<i32> {
+        let imp = imp::FurTaskRow::from_obj(&self);
+        imp.tasks.lock().unwrap().iter().map(|task| task.id).collect()
+    }
+
     pub fn get_tasks(&self) -> Vec<Task> {
         let imp = imp::FurTaskRow::from_obj(&self);
         imp.tasks.lock().unwrap().to_vec()
@@ -14,10 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use adw::prelude::PreferencesGroupExt;
 use adw::subclass::prelude::*;
 use gtk::{glib, prelude::*};
 
 use crate::database;
+use crate::goals::Progress;
 use crate::settings_manager;
 use crate::ui::FurTaskRow;
 
@@ -35,6 +37,7 @@ mod imp {
         pub listbox_box: TemplateChild<gtk::Box>,
 
         pub models: RefCell<Vec<gtk::SortListModel>>,
+        pub rows: RefCell<Vec<FurTaskRow>>,
         pub day_total_time: RefCell<i64>,
     }
 
@@ -106,6 +109,7 @@ impl FurTasksGroup {
             listbox_row.set_row_labels(same_name);
             *imp.day_total_time.borrow_mut() += listbox_row.get_total_time();
             listbox.append(&listbox_row);
+            imp.rows.borrow_mut().push(listbox_row);
         }
 
         listbox.connect_row_activated(move |_, row| {
@@ -113,8 +117,43 @@ impl FurTasksGroup {
         });
     }
 
+    pub fn get_rows(&self) -> Vec<FurTaskRow> {
+        let imp = imp::FurTasksGroup::from_obj(&self);
+        imp.rows.borrow().to_vec()
+    }
+
     pub fn get_total_day_time(&self) -> i64 {
         let imp = imp::FurTasksGroup::from_obj(&self);
         *imp.day_total_time.borrow()
     }
+
+    /// Shows the day's total in the header, with progress towards the daily
+    /// goal if there is one.
+    pub fn set_day_total(&self, total_time: i64) {
+        let h = total_time / 3600;
+        let m = total_time % 3600 / 60;
+        let s = total_time % 60;
+
+        let mut total_time_str = format!("{:02}:{:02}:{:02}", h, m, s);
+        if !settings_manager::get_bool("show-seconds") {
+            total_time_str = format!("{:02}:{:02}", h, m);
+        }
+
+        let suffix = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+        let daily_goal = settings_manager::goals().daily;
+        if daily_goal > 0 {
+            let progress = Progress::new(total_time, daily_goal);
+            let bar = gtk::ProgressBar::new();
+            bar.set_fraction(progress.fraction());
+            bar.set_valign(gtk::Align::Center);
+            bar.set_width_request(60);
+            bar.set_tooltip_text(Some(&format!("{} · {}", progress.text(), progress.remaining_text())));
+            if progress.is_reached() {
+                bar.add_css_class("goal-reached");
+            }
+            suffix.append(&bar);
+        }
+        suffix.append(&gtk::Label::new(Some(&total_time_str)));
+        self.set_header_suffix(Some(&suffix));
+    }
 }
@@ -24,8 +24,9 @@ use std::env;
 use std::sync::Mutex;
 
 use crate::database::{self, SortOrder, TaskSort};
+use crate::filter::Expr;
 use crate::settings_manager;
-use crate::ui::FurTasksGroup;
+use crate::ui::{FurTaskRow, FurTasksGroup};
 
 mod imp {
     use super::*;
@@ -37,7 +38,11 @@ mod imp {
     #[template(resource = "/com/lakoliu/rusttimetrack/gtk/tasks_page.ui")]
     pub struct FurTasksPage {
         pub all_groups: RefCell<Vec<FurTasksGroup>>,
+        pub all_rows: RefCell<Vec<FurTaskRow>>,
+        pub selection_mode: RefCell<bool>,
+        pub selection_anchor: RefCell<Option<usize>>,
         pub today_stored_secs: Mutex<i32>,
+        pub filter: RefCell<Option<Expr>>,
     }
 
     #[glib::object_subclass]
@@ -85,18 +90,7 @@ impl FurTasksPage {
             if today_group.title() == "Today" {
                 // Add the time to the thing here
                 let new_total_time = added_time + *imp.today_stored_secs.lock().unwrap();
-
-                let h = new_total_time / 3600;
-                let m = new_total_time % 3600 / 60;
-                let s = new_total_time % 60;
-
-                let mut total_time_str = format!("{:02}:{:02}:{:02}", h, m, s);
-                if !settings_manager::get_bool("show-seconds") {
-                    total_time_str = format!("{:02}:{:02}", h, m);
-                }
-
-                let total_time_label = gtk::Label::new(Some(&total_time_str));
-                today_group.set_header_suffix(Some(&total_time_label));
+                today_group.set_day_total(new_total_time as i64);
             }
         }
     }
@@ -115,9 +109,60 @@ impl FurTasksPage {
         }
 
         imp.all_groups.borrow_mut().clear();
+        imp.all_rows.borrow_mut().clear();
+        *imp.selection_anchor.borrow_mut() = None;
         *imp.today_stored_secs.lock().unwrap() = 0;
     }
 
+    pub fn set_selection_mode(&self, selection_mode: bool) {
+        let imp = imp::FurTasksPage::from_obj(&self);
+        *imp.selection_mode.borrow_mut() = selection_mode;
+        *imp.selection_anchor.borrow_mut() = None;
+        for row in &*imp.all_rows.borrow() {
+            row.set_selection_mode(selection_mode);
+        }
+    }
+
+    pub fn select_row(&self, row: &FurTaskRow, extend: bool) {
+        let imp = imp::FurTasksPage::from_obj(&self);
+        let rows = imp.all_rows.borrow();
+        let index = match rows.iter().position(|r| r == row) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let anchor = *imp.selection_anchor.borrow();
+        match anchor {
+            // Shift selects everything between the last clicked row and this one
+            Some(anchor) if extend => {
+                let (from, to) = if anchor < index { (anchor, index) } else { (index, anchor) };
+                for r in &rows[from..=to] {
+                    r.set_selected(true);
+                }
+            }
+            _ => {
+                row.set_selected(!row.is_selected());
+                *imp.selection_anchor.borrow_mut() = Some(index);
+            }
+        }
+    }
+
+    pub fn set_filter(&self, filter: Option<Expr>) {
+        let imp = imp::FurTasksPage::from_obj(&self);
+        *imp.filter.borrow_mut() = filter;
+    }
+
+    pub fn selected_task_ids(&self) -> Vec<i32> {
+        let imp = imp::FurTasksPage::from_obj(&self);
+        let mut ids: Vec<i32> = Vec::new();
+        for row in &*imp.all_rows.borrow() {
+            if row.is_selected() {
+                ids.extend(row.get_task_ids());
+            }
+        }
+        ids
+    }
+
     pub fn build_task_list(&self) {
         let imp = imp::FurTasksPage::from_obj(&self);
 
@@ -132,7 +177,10 @@ impl FurTasksPage {
             user_locale = split_locale[0].to_string();
         }
 
-        let tasks_list = database::retrieve(TaskSort::StartTime, SortOrder::Descending).unwrap();
+        let mut tasks_list = database::retrieve(TaskSort::StartTime, SortOrder::Descending).unwrap();
+        if let Some(filter) = &*imp.filter.borrow() {
+            tasks_list.retain(|task| filter.matches(task));
+        }
 
         let mut uniq_date_list: Vec<String> = Vec::new();
         let mut same_date_list: Vec<database::Task> = Vec::new();
@@ -194,6 +242,10 @@ impl FurTasksPage {
 
             self.add(&group);
             group.add_task_model(tasks_sorted_by_day[i].clone());
+            for row in group.get_rows() {
+                row.set_selection_mode(*imp.selection_mode.borrow());
+                imp.all_rows.borrow_mut().push(row);
+            }
 
             // Set total time for each day
             if settings_manager::get_bool("show-daily-sums") {
@@ -201,19 +253,7 @@ impl FurTasksPage {
                 if uniq_date_list[i] == today {
                     *imp.today_stored_secs.lock().unwrap() = day_total_time as i32;
                 }
-
-                // Format total time to readable string
-                let h = day_total_time / 3600;
-                let m = day_total_time % 3600 / 60;
-                let s = day_total_time % 60;
-
-                let mut total_time_str = format!("{:02}:{:02}:{:02}", h, m, s);
-                if !settings_manager::get_bool("show-seconds") {
-                    total_time_str = format!("{:02}:{:02}", h, m);
-                }
-
-                let total_time_label = gtk::Label::new(Some(&total_time_str));
-                group.set_header_suffix(Some(&total_time_label));
+                group.set_day_total(day_total_time);
             }
 
             imp.all_groups.borrow_mut().push(group);
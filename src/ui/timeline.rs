@@ -0,0 +1,558 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use adw::subclass::prelude::*;
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use gettextrs::*;
+use glib::clone;
+use gtk::{cairo, glib, prelude::*, CompositeTemplate};
+use std::cell::RefCell;
+
+use crate::charts;
+use crate::database::{self, Task};
+use crate::reporting;
+use crate::settings_manager;
+use crate::ui::{FurTaskDetails, rusttimetrackWindow};
+use crate::rusttimetrackApplication;
+
+const HOUR_HEIGHT: f64 = 48.0;
+const HEADER_HEIGHT: f64 = 28.0;
+const GUTTER_WIDTH: f64 = 52.0;
+const EDGE_SIZE: f64 = 6.0;
+const SNAP_MINUTES: i64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DragMode {
+    Move,
+    ResizeStart,
+    ResizeStop,
+}
+
+#[derive(Clone, Debug)]
+pub struct DragState {
+    task_id: i32,
+    mode: DragMode,
+    start_time: DateTime<Local>,
+    stop_time: DateTime<Local>,
+    preview_start: DateTime<Local>,
+    preview_stop: DateTime<Local>,
+}
+
+// A task's visible part on one day of the view
+struct Block {
+    task: Task,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+mod imp {
+    use super::*;
+    use glib::subclass;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/lakoliu/rusttimetrack/gtk/timeline.ui")]
+    pub struct FurTimeline {
+        #[template_child]
+        pub prev_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub today_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub next_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub range_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub day_btn: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub week_btn: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+        #[template_child]
+        pub drawing_area: TemplateChild<gtk::DrawingArea>,
+
+        pub first_day: RefCell<NaiveDate>,
+        pub tasks: RefCell<Vec<Task>>,
+        pub drag: RefCell<Option<DragState>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FurTimeline {
+        const NAME: &'static str = "FurTimeline";
+        type ParentType = adw::Window;
+        type Type = super::FurTimeline;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for FurTimeline {
+        fn constructed(&self) {
+            let obj = self.obj();
+            obj.setup_widgets();
+            obj.setup_signals();
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for FurTimeline {}
+
+    impl WindowImpl for FurTimeline {}
+
+    impl AdwWindowImpl for FurTimeline {}
+}
+
+glib::wrapper! {
+    pub struct FurTimeline(ObjectSubclass<imp::FurTimeline>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl FurTimeline {
+    pub fn new() -> Self {
+        let dialog: Self = glib::Object::new::<FurTimeline>();
+
+        let window = rusttimetrackWindow::default();
+        dialog.set_transient_for(Some(&window));
+
+        let app = rusttimetrackApplication::default();
+        app.add_window(&dialog);
+
+        dialog
+    }
+
+    fn setup_widgets(&self) {
+        let imp = imp::FurTimeline::from_obj(self);
+
+        *imp.first_day.borrow_mut() = Local::now().naive_local().date();
+        imp.drawing_area.set_content_height((HEADER_HEIGHT + 24.0 * HOUR_HEIGHT) as i32);
+        imp.drawing_area.set_content_width(300);
+        imp.drawing_area.set_draw_func(clone!(@weak self as this => move |_, cr, width, height| {
+            this.draw(cr, width as f64, height as f64);
+        }));
+
+        self.refresh();
+    }
+
+    fn setup_signals(&self) {
+        let imp = imp::FurTimeline::from_obj(self);
+
+        imp.prev_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.move_by(-this.days_shown());
+        }));
+
+        imp.next_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.move_by(this.days_shown());
+        }));
+
+        imp.today_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            let imp = imp::FurTimeline::from_obj(&this);
+            *imp.first_day.borrow_mut() = this.view_start(Local::now().naive_local().date());
+            this.refresh();
+        }));
+
+        imp.week_btn.connect_toggled(clone!(@weak self as this => move |_| {
+            let imp = imp::FurTimeline::from_obj(&this);
+            let first_day = *imp.first_day.borrow();
+            *imp.first_day.borrow_mut() = this.view_start(first_day);
+            this.refresh();
+        }));
+
+        let drag = gtk::GestureDrag::new();
+        drag.set_button(gtk::gdk::ffi::GDK_BUTTON_PRIMARY as u32);
+        drag.connect_drag_begin(clone!(@weak self as this => move |_, x, y| {
+            this.drag_begin(x, y);
+        }));
+        drag.connect_drag_update(clone!(@weak self as this => move |_, dx, dy| {
+            this.drag_update(dx, dy);
+        }));
+        drag.connect_drag_end(clone!(@weak self as this => move |gesture, dx, dy| {
+            if let Some((x, y)) = gesture.start_point() {
+                this.drag_end(x, y, dx, dy);
+            }
+        }));
+        imp.drawing_area.add_controller(drag);
+
+        // Hint at what dragging will do
+        let motion = gtk::EventControllerMotion::new();
+        motion.connect_motion(clone!(@weak self as this => move |_, x, y| {
+            let imp = imp::FurTimeline::from_obj(&this);
+            let cursor = match this.hit_test(x, y) {
+                Some((_, DragMode::Move)) => "pointer",
+                Some(_) => "ns-resize",
+                None => "default",
+            };
+            imp.drawing_area.set_cursor_from_name(Some(cursor));
+        }));
+        imp.drawing_area.add_controller(motion);
+    }
+
+    pub fn refresh(&self) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let first_day = *imp.first_day.borrow();
+        let last_day = first_day + Duration::days(self.days_shown() - 1);
+
+        if self.days_shown() == 1 {
+            imp.range_label.set_text(&first_day.format("%A %F").to_string());
+        } else {
+            imp.range_label.set_text(&format!("{} – {}", first_day.format("%F"), last_day.format("%F")));
+        }
+
+        let start_time = reporting::local_midnight(first_day);
+        let stop_time = reporting::local_midnight(last_day + Duration::days(1));
+        *imp.tasks.borrow_mut() = database::find_overlapping(start_time, stop_time, &[])
+            .unwrap_or_default();
+        imp.drawing_area.queue_draw();
+    }
+
+    fn days_shown(&self) -> i64 {
+        let imp = imp::FurTimeline::from_obj(self);
+        if imp.week_btn.is_active() {
+            7
+        } else {
+            1
+        }
+    }
+
+    fn move_by(&self, days: i64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let first_day = *imp.first_day.borrow() + Duration::days(days);
+        *imp.first_day.borrow_mut() = first_day;
+        self.refresh();
+    }
+
+    fn view_start(&self, day: NaiveDate) -> NaiveDate {
+        if self.days_shown() == 1 {
+            day
+        } else {
+            reporting::week_start(day, settings_manager::week_starts_on())
+        }
+    }
+
+    fn column_width(&self) -> f64 {
+        let imp = imp::FurTimeline::from_obj(self);
+        let width = imp.drawing_area.width() as f64;
+        ((width - GUTTER_WIDTH) / self.days_shown() as f64).max(1.0)
+    }
+
+    fn time_at(&self, x: f64, y: f64) -> Option<(usize, DateTime<Local>)> {
+        let imp = imp::FurTimeline::from_obj(self);
+        if x < GUTTER_WIDTH || y < HEADER_HEIGHT {
+            return None;
+        }
+        let day = ((x - GUTTER_WIDTH) / self.column_width()).floor() as i64;
+        if day >= self.days_shown() {
+            return None;
+        }
+        let minutes = ((y - HEADER_HEIGHT) / HOUR_HEIGHT * 60.0) as i64;
+        let midnight = reporting::local_midnight(*imp.first_day.borrow() + Duration::days(day));
+        Some((day as usize, midnight + Duration::minutes(minutes.min(24 * 60))))
+    }
+
+    fn blocks(&self) -> Vec<Block> {
+        let imp = imp::FurTimeline::from_obj(self);
+        let first_day = *imp.first_day.borrow();
+        let column_width = self.column_width();
+        let drag = imp.drag.borrow().clone();
+        let mut blocks: Vec<Block> = Vec::new();
+
+        for task in imp.tasks.borrow().iter() {
+            let (start_time, stop_time) = match &drag {
+                Some(drag) if drag.task_id == task.id => (drag.preview_start, drag.preview_stop),
+                _ => (
+                    DateTime::parse_from_rfc3339(&task.start_time).unwrap().with_timezone(&Local),
+                    DateTime::parse_from_rfc3339(&task.stop_time).unwrap().with_timezone(&Local),
+                ),
+            };
+
+            // Tasks that pass midnight get a block on each day
+            for day in 0..self.days_shown() {
+                let day_start = reporting::local_midnight(first_day + Duration::days(day));
+                let day_end = reporting::local_midnight(first_day + Duration::days(day + 1));
+                let block_start = start_time.max(day_start);
+                let block_stop = stop_time.min(day_end);
+                if block_start >= block_stop {
+                    continue;
+                }
+
+                let top = (block_start - day_start).num_seconds() as f64 / 3600.0 * HOUR_HEIGHT;
+                let bottom = (block_stop - day_start).num_seconds() as f64 / 3600.0 * HOUR_HEIGHT;
+                blocks.push(Block {
+                    task: task.clone(),
+                    x: GUTTER_WIDTH + day as f64 * column_width + 2.0,
+                    y: HEADER_HEIGHT + top,
+                    width: column_width - 4.0,
+                    height: (bottom - top).max(3.0),
+                });
+            }
+        }
+
+        blocks
+    }
+
+    fn hit_test(&self, x: f64, y: f64) -> Option<(Task, DragMode)> {
+        for block in self.blocks().into_iter().rev() {
+            if x < block.x || x > block.x + block.width || y < block.y || y > block.y + block.height {
+                continue;
+            }
+            let mode = if block.height > EDGE_SIZE * 3.0 && y - block.y < EDGE_SIZE {
+                DragMode::ResizeStart
+            } else if block.height > EDGE_SIZE * 3.0 && block.y + block.height - y < EDGE_SIZE {
+                DragMode::ResizeStop
+            } else {
+                DragMode::Move
+            };
+            return Some((block.task, mode));
+        }
+        None
+    }
+
+    fn color_for(task: &Task) -> (f64, f64, f64) {
+        let key = if !task.project.trim().is_empty() {
+            task.project.trim().to_lowercase()
+        } else if !task.tags.trim().is_empty() {
+            task.tags.split(" #").next().unwrap_or_default().to_string()
+        } else {
+            task.task_name.to_lowercase()
+        };
+        charts::color_for_key(&key)
+    }
+
+    fn draw(&self, cr: &cairo::Context, width: f64, height: f64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let first_day = *imp.first_day.borrow();
+        let column_width = self.column_width();
+        let fg = imp.drawing_area.style_context().color();
+        let (fg_r, fg_g, fg_b) = (fg.red() as f64, fg.green() as f64, fg.blue() as f64);
+
+        cr.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        cr.set_font_size(11.0);
+        cr.set_line_width(1.0);
+
+        // Hour lines and labels
+        for hour in 0..=24 {
+            let y = HEADER_HEIGHT + hour as f64 * HOUR_HEIGHT + 0.5;
+            cr.set_source_rgba(fg_r, fg_g, fg_b, 0.12);
+            cr.move_to(GUTTER_WIDTH, y);
+            cr.line_to(width, y);
+            let _ = cr.stroke();
+
+            if hour < 24 {
+                cr.set_source_rgba(fg_r, fg_g, fg_b, 0.6);
+                cr.move_to(6.0, y + 12.0);
+                let _ = cr.show_text(&format!("{:02}:00", hour));
+            }
+        }
+
+        // Day headers and separators
+        for day in 0..self.days_shown() {
+            let x = GUTTER_WIDTH + day as f64 * column_width;
+            cr.set_source_rgba(fg_r, fg_g, fg_b, 0.12);
+            cr.move_to(x + 0.5, HEADER_HEIGHT);
+            cr.line_to(x + 0.5, height);
+            let _ = cr.stroke();
+
+            if self.days_shown() > 1 {
+                let date = first_day + Duration::days(day);
+                cr.set_source_rgba(fg_r, fg_g, fg_b, 0.8);
+                cr.move_to(x + 6.0, HEADER_HEIGHT - 9.0);
+                let _ = cr.show_text(&date.format("%a %d").to_string());
+            }
+        }
+
+        // Entries
+        for block in self.blocks() {
+            let (r, g, b) = FurTimeline::color_for(&block.task);
+            cr.set_source_rgba(r, g, b, 0.85);
+            cr.rectangle(block.x, block.y, block.width, block.height);
+            let _ = cr.fill();
+
+            let _ = cr.save();
+            cr.rectangle(block.x, block.y, block.width, block.height);
+            cr.clip();
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+            cr.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+            cr.move_to(block.x + 4.0, block.y + 13.0);
+            let _ = cr.show_text(&block.task.task_name);
+            if block.height > 30.0 {
+                cr.select_font_face("Sans", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+                let start_time = DateTime::parse_from_rfc3339(&block.task.start_time).unwrap();
+                let stop_time = DateTime::parse_from_rfc3339(&block.task.stop_time).unwrap();
+                cr.move_to(block.x + 4.0, block.y + 27.0);
+                let _ = cr.show_text(&format!(
+                    "{} – {}",
+                    start_time.format("%H:%M"),
+                    stop_time.format("%H:%M")
+                ));
+            }
+            let _ = cr.restore();
+        }
+
+        // Current time
+        let now = Local::now();
+        let offset = (now.naive_local().date() - first_day).num_days();
+        if offset >= 0 && offset < self.days_shown() {
+            let midnight = reporting::local_midnight(now.naive_local().date());
+            let y = HEADER_HEIGHT + (now - midnight).num_seconds() as f64 / 3600.0 * HOUR_HEIGHT;
+            let x = GUTTER_WIDTH + offset as f64 * column_width;
+            cr.set_source_rgb(0.88, 0.11, 0.14);
+            cr.set_line_width(2.0);
+            cr.move_to(x, y);
+            cr.line_to(x + column_width, y);
+            let _ = cr.stroke();
+        }
+    }
+
+    fn drag_begin(&self, x: f64, y: f64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        *imp.drag.borrow_mut() = match self.hit_test(x, y) {
+            Some((task, mode)) => {
+                let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap().with_timezone(&Local);
+                let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap().with_timezone(&Local);
+                Some(DragState {
+                    task_id: task.id,
+                    mode,
+                    start_time,
+                    stop_time,
+                    preview_start: start_time,
+                    preview_stop: stop_time,
+                })
+            }
+            None => None,
+        };
+    }
+
+    fn drag_update(&self, dx: f64, dy: f64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let mut drag = imp.drag.borrow_mut();
+        let drag = match drag.as_mut() {
+            Some(drag) => drag,
+            None => return,
+        };
+
+        let minutes = (dy / HOUR_HEIGHT * 60.0 / SNAP_MINUTES as f64).round() as i64 * SNAP_MINUTES;
+        let offset = Duration::minutes(minutes);
+        match drag.mode {
+            DragMode::Move => {
+                let days = (dx / self.column_width()).round() as i64;
+                let offset = offset + Duration::days(days);
+                drag.preview_start = drag.start_time + offset;
+                drag.preview_stop = drag.stop_time + offset;
+            }
+            DragMode::ResizeStart => {
+                drag.preview_start = (drag.start_time + offset).min(drag.stop_time - Duration::minutes(1));
+            }
+            DragMode::ResizeStop => {
+                drag.preview_stop = (drag.stop_time + offset).max(drag.start_time + Duration::minutes(1));
+            }
+        }
+        imp.drawing_area.queue_draw();
+    }
+
+    fn drag_end(&self, x: f64, y: f64, dx: f64, dy: f64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let drag = imp.drag.borrow_mut().take();
+        let is_click = dx.abs() < 4.0 && dy.abs() < 4.0;
+
+        match drag {
+            Some(drag) if is_click => {
+                let task = imp.tasks.borrow().iter().find(|task| task.id == drag.task_id).cloned();
+                if let Some(task) = task {
+                    let dialog = FurTaskDetails::new();
+                    dialog.setup_widgets(vec![task]);
+                    dialog.show();
+                }
+            }
+            Some(drag) => {
+                if drag.preview_start == drag.start_time && drag.preview_stop == drag.stop_time {
+                    return;
+                }
+                self.save_drag(&drag);
+            }
+            None if is_click => {
+                self.create_in_gap(x, y);
+            }
+            None => {}
+        }
+        imp.drawing_area.queue_draw();
+    }
+
+    fn save_drag(&self, drag: &DragState) {
+        let imp = imp::FurTimeline::from_obj(self);
+
+        if drag.preview_stop > Local::now() {
+            imp.toast_overlay.add_toast(adw::Toast::new(&gettext("Time cannot be in the future.")));
+            return;
+        }
+        let overlapping = database::find_overlapping(drag.preview_start, drag.preview_stop, &[drag.task_id])
+            .unwrap_or_default();
+        if let Some(other) = overlapping.first() {
+            imp.toast_overlay.add_toast(adw::Toast::new(
+                &gettext("Overlaps with {}.").replace("{}", &other.task_name)));
+            return;
+        }
+
+        match database::update_times(
+            drag.task_id,
+            drag.preview_start.to_rfc3339(),
+            drag.preview_stop.to_rfc3339(),
+        ) {
+            Ok(_) => {
+                self.refresh();
+                let window = rusttimetrackWindow::default();
+                window.reset_history_box();
+            }
+            Err(e) => imp.toast_overlay.add_toast(adw::Toast::new(&e.to_string())),
+        }
+    }
+
+    fn create_in_gap(&self, x: f64, y: f64) {
+        let imp = imp::FurTimeline::from_obj(self);
+        let (day, clicked) = match self.time_at(x, y) {
+            Some(time) => time,
+            None => return,
+        };
+        let now = Local::now();
+        if clicked > now {
+            return;
+        }
+
+        // Fill the empty slot between the neighbouring entries
+        let day_start = reporting::local_midnight(*imp.first_day.borrow() + Duration::days(day as i64));
+        let mut gap_start = day_start;
+        let mut gap_stop = reporting::local_midnight(day_start.naive_local().date() + Duration::days(1)).min(now);
+        for task in imp.tasks.borrow().iter() {
+            let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap().with_timezone(&Local);
+            let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap().with_timezone(&Local);
+            if stop_time <= clicked && stop_time > gap_start {
+                gap_start = stop_time;
+            }
+            if start_time >= clicked && start_time < gap_stop {
+                gap_stop = start_time;
+            }
+        }
+        if gap_stop - gap_start < Duration::minutes(1) {
+            return;
+        }
+
+        let window = rusttimetrackWindow::default();
+        window.open_new_task_dialog(gap_start, gap_stop);
+    }
+}
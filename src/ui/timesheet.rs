@@ -0,0 +1,501 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use adw::subclass::prelude::*;
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use gettextrs::*;
+use glib::clone;
+use gtk::{glib, prelude::*, CompositeTemplate};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::billing::{self, Earnings, RateBook};
+use crate::database::{self, Task};
+use crate::reporting;
+use crate::settings_manager;
+use crate::ui::rusttimetrackWindow;
+use crate::rusttimetrackApplication;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetRow {
+    label: String,
+    task_name: String,
+    tags: String,
+    project: String,
+}
+
+mod imp {
+    use super::*;
+    use glib::subclass;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/com/lakoliu/rusttimetrack/gtk/timesheet.ui")]
+    pub struct FurTimesheet {
+        #[template_child]
+        pub prev_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub this_week_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub next_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub range_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub group_dropdown: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+        #[template_child]
+        pub sheet_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        pub new_row_entry: TemplateChild<gtk::Entry>,
+
+        pub week_start: RefCell<NaiveDate>,
+        pub extra_rows: RefCell<Vec<SheetRow>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FurTimesheet {
+        const NAME: &'static str = "FurTimesheet";
+        type ParentType = adw::Window;
+        type Type = super::FurTimesheet;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for FurTimesheet {
+        fn constructed(&self) {
+            let obj = self.obj();
+            obj.setup_signals();
+            obj.this_week();
+            self.parent_constructed();
+        }
+    }
+
+    impl WidgetImpl for FurTimesheet {}
+
+    impl WindowImpl for FurTimesheet {}
+
+    impl AdwWindowImpl for FurTimesheet {}
+}
+
+glib::wrapper! {
+    pub struct FurTimesheet(ObjectSubclass<imp::FurTimesheet>)
+        @extends gtk::Widget, gtk::Window, adw::Window;
+}
+
+impl FurTimesheet {
+    pub fn new() -> Self {
+        let dialog: Self = glib::Object::new::<FurTimesheet>();
+
+        let window = rusttimetrackWindow::default();
+        dialog.set_transient_for(Some(&window));
+
+        let app = rusttimetrackApplication::default();
+        app.add_window(&dialog);
+
+        dialog
+    }
+
+    fn setup_signals(&self) {
+        let imp = imp::FurTimesheet::from_obj(self);
+
+        imp.prev_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.move_by_weeks(-1);
+        }));
+
+        imp.next_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.move_by_weeks(1);
+        }));
+
+        imp.this_week_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.this_week();
+        }));
+
+        imp.group_dropdown.connect_selected_notify(clone!(@weak self as this => move |_| {
+            let imp = imp::FurTimesheet::from_obj(&this);
+            imp.extra_rows.borrow_mut().clear();
+            if this.by_project() {
+                imp.new_row_entry.set_placeholder_text(Some(&gettext("Add a row: Project")));
+            } else {
+                imp.new_row_entry.set_placeholder_text(Some(&gettext("Add a row: Task Name #tags")));
+            }
+            this.refresh();
+        }));
+
+        imp.new_row_entry.connect_activate(clone!(@weak self as this => move |entry| {
+            let imp = imp::FurTimesheet::from_obj(&this);
+            let text = entry.text();
+            let row = if this.by_project() {
+                let project = text.trim().to_string();
+                SheetRow {
+                    label: project.clone(),
+                    task_name: project.clone(),
+                    tags: String::new(),
+                    project,
+                }
+            } else {
+                let (task_name, tags) = text.split_once('#').unwrap_or((text.as_str(), ""));
+                let tags = database::split_tags(tags).join(" #");
+                SheetRow {
+                    label: FurTimesheet::task_label(task_name.trim(), &tags),
+                    task_name: task_name.trim().to_string(),
+                    tags,
+                    project: String::new(),
+                }
+            };
+            if row.task_name.is_empty() {
+                return;
+            }
+            imp.extra_rows.borrow_mut().push(row);
+            entry.set_text("");
+            this.refresh();
+        }));
+    }
+
+    fn this_week(&self) {
+        let imp = imp::FurTimesheet::from_obj(self);
+        let today = Local::now().naive_local().date();
+        *imp.week_start.borrow_mut() = reporting::week_start(today, settings_manager::week_starts_on());
+        self.refresh();
+    }
+
+    fn move_by_weeks(&self, weeks: i64) {
+        let imp = imp::FurTimesheet::from_obj(self);
+        let week_start = *imp.week_start.borrow() + Duration::weeks(weeks);
+        *imp.week_start.borrow_mut() = week_start;
+        imp.extra_rows.borrow_mut().clear();
+        self.refresh();
+    }
+
+    fn by_project(&self) -> bool {
+        let imp = imp::FurTimesheet::from_obj(self);
+        imp.group_dropdown.selected() == 1
+    }
+
+    fn task_label(task_name: &str, tags: &str) -> String {
+        if tags.trim().is_empty() {
+            task_name.to_string()
+        } else {
+            format!("{} #{}", task_name, tags)
+        }
+    }
+
+    fn row_for(&self, task: &Task) -> SheetRow {
+        if self.by_project() {
+            let label = if task.project.trim().is_empty() {
+                gettext("No project")
+            } else {
+                task.project.clone()
+            };
+            SheetRow {
+                label,
+                task_name: task.project.clone(),
+                tags: String::new(),
+                project: task.project.clone(),
+            }
+        } else {
+            SheetRow {
+                label: FurTimesheet::task_label(&task.task_name, &task.tags),
+                task_name: task.task_name.clone(),
+                tags: task.tags.clone(),
+                project: task.project.clone(),
+            }
+        }
+    }
+
+    /// In project mode, time added to a day goes on as the project's latest
+    /// entry of that day, rather than as an entry named after the project.
+    fn day_row(&self, row: &SheetRow, tasks: &[Task]) -> SheetRow {
+        let latest = tasks
+            .iter()
+            .max_by_key(|task| DateTime::parse_from_rfc3339(&task.start_time).ok());
+        match latest {
+            Some(task) if self.by_project() => SheetRow {
+                task_name: task.task_name.clone(),
+                tags: task.tags.clone(),
+                ..row.clone()
+            },
+            _ => row.clone(),
+        }
+    }
+
+    pub fn refresh(&self) {
+        let imp = imp::FurTimesheet::from_obj(self);
+        let week_start = *imp.week_start.borrow();
+        let week_end = week_start + Duration::days(6);
+        imp.range_label.set_text(&format!("{} – {}", week_start.format("%F"), week_end.format("%F")));
+
+        // Tasks count toward the day they started on, like in the history
+        let tasks = database::find_overlapping(
+            reporting::local_midnight(week_start),
+            reporting::local_midnight(week_end + Duration::days(1)),
+            &[],
+        ).unwrap_or_default();
+
+        let mut rows: Vec<(SheetRow, Vec<Vec<Task>>)> = Vec::new();
+        for task in tasks {
+            let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap().with_timezone(&Local);
+            let day = (start_time.naive_local().date() - week_start).num_days();
+            if day < 0 || day > 6 {
+                continue;
+            }
+            let row = self.row_for(&task);
+            let index = match rows.iter().position(|(r, _)| r.label == row.label) {
+                Some(index) => index,
+                None => {
+                    rows.push((row, vec![Vec::new(); 7]));
+                    rows.len() - 1
+                }
+            };
+            rows[index].1[day as usize].push(task);
+        }
+        for row in imp.extra_rows.borrow().iter() {
+            if !rows.iter().any(|(r, _)| r.label == row.label) {
+                rows.push((row.clone(), vec![Vec::new(); 7]));
+            }
+        }
+        rows.sort_by(|a, b| a.0.label.to_lowercase().cmp(&b.0.label.to_lowercase()));
+
+        let rates = RateBook::new(database::get_rates().unwrap_or_default());
+        self.build_grid(week_start, rows, &rates);
+    }
+
+    fn build_grid(&self, week_start: NaiveDate, rows: Vec<(SheetRow, Vec<Vec<Task>>)>, rates: &RateBook) {
+        let imp = imp::FurTimesheet::from_obj(self);
+        let grid = &imp.sheet_grid;
+        while let Some(child) = grid.first_child() {
+            grid.remove(&child);
+        }
+
+        // Header
+        for day in 0..7 {
+            let date = week_start + Duration::days(day);
+            let label = gtk::Label::new(Some(&date.format("%a %d").to_string()));
+            label.add_css_class("heading");
+            grid.attach(&label, day as i32 + 1, 0, 1, 1);
+        }
+        let total_header = gtk::Label::new(Some(&gettext("Total")));
+        total_header.add_css_class("heading");
+        grid.attach(&total_header, 8, 0, 1, 1);
+        // Earnings only show once rates are set up
+        if !rates.is_empty() {
+            let earnings_header = gtk::Label::new(Some(&gettext("Earnings")));
+            earnings_header.add_css_class("heading");
+            grid.attach(&earnings_header, 9, 0, 1, 1);
+        }
+
+        let mut column_totals = [0i64; 7];
+        let mut week_earnings = Earnings::default();
+        let row_count = rows.len() as i32;
+        for (i, (row, days)) in rows.into_iter().enumerate() {
+            let grid_row = i as i32 + 1;
+            let name_label = gtk::Label::new(Some(&row.label));
+            name_label.set_xalign(0.0);
+            name_label.set_hexpand(true);
+            name_label.set_ellipsize(gtk::pango::EllipsizeMode::End);
+            name_label.set_tooltip_text(Some(&row.label));
+            grid.attach(&name_label, 0, grid_row, 1, 1);
+
+            let mut row_total = 0i64;
+            let mut row_earnings = Earnings::default();
+            for (day, tasks) in days.into_iter().enumerate() {
+                let secs: i64 = tasks.iter().map(FurTimesheet::task_secs).sum();
+                for task in &tasks {
+                    row_earnings.merge(&FurTimesheet::task_earnings(task, rates));
+                }
+                row_total += secs;
+                column_totals[day] += secs;
+
+                let cell = gtk::Entry::new();
+                cell.set_width_chars(5);
+                cell.set_max_width_chars(6);
+                cell.set_alignment(1.0);
+                cell.set_text(&FurTimesheet::format_cell(secs));
+                // Rows without a project have nothing to name new time after
+                if self.by_project() && row.project.trim().is_empty() {
+                    cell.set_editable(false);
+                    cell.set_can_focus(false);
+                }
+                grid.attach(&cell, day as i32 + 1, grid_row, 1, 1);
+
+                let ids: Vec<i32> = tasks.iter().map(|task| task.id).collect();
+                let date = week_start + Duration::days(day as i64);
+                let row = self.day_row(&row, &tasks);
+                let current = Rc::new(Cell::new(secs));
+
+                cell.connect_activate(clone!(@weak self as this, @strong row, @strong ids, @strong current => move |cell| {
+                    this.commit_cell(cell, &row, &ids, date, &current);
+                }));
+                let focus = gtk::EventControllerFocus::new();
+                focus.connect_leave(clone!(@weak self as this, @weak cell, @strong row, @strong ids, @strong current => move |_| {
+                    this.commit_cell(&cell, &row, &ids, date, &current);
+                }));
+                cell.add_controller(focus);
+            }
+
+            let total_label = gtk::Label::new(Some(&FurTimesheet::format_total(row_total)));
+            total_label.set_xalign(1.0);
+            total_label.add_css_class("numeric");
+            grid.attach(&total_label, 8, grid_row, 1, 1);
+
+            if !rates.is_empty() {
+                let earnings_label = gtk::Label::new(Some(&row_earnings.format()));
+                earnings_label.set_xalign(1.0);
+                earnings_label.add_css_class("numeric");
+                grid.attach(&earnings_label, 9, grid_row, 1, 1);
+            }
+            week_earnings.merge(&row_earnings);
+        }
+
+        // Footer
+        let footer_row = row_count + 1;
+        let total_label = gtk::Label::new(Some(&gettext("Total")));
+        total_label.set_xalign(0.0);
+        total_label.add_css_class("heading");
+        grid.attach(&total_label, 0, footer_row, 1, 1);
+        for (day, secs) in column_totals.iter().enumerate() {
+            let label = gtk::Label::new(Some(&FurTimesheet::format_total(*secs)));
+            label.set_xalign(1.0);
+            label.add_css_class("numeric");
+            grid.attach(&label, day as i32 + 1, footer_row, 1, 1);
+        }
+        let week_total = gtk::Label::new(Some(&FurTimesheet::format_total(column_totals.iter().sum())));
+        week_total.set_xalign(1.0);
+        week_total.add_css_class("heading");
+        week_total.add_css_class("numeric");
+        grid.attach(&week_total, 8, footer_row, 1, 1);
+        if !rates.is_empty() {
+            let earnings_total = gtk::Label::new(Some(&week_earnings.format()));
+            earnings_total.set_xalign(1.0);
+            earnings_total.add_css_class("heading");
+            earnings_total.add_css_class("numeric");
+            grid.attach(&earnings_total, 9, footer_row, 1, 1);
+        }
+    }
+
+    fn commit_cell(&self, cell: &gtk::Entry, row: &SheetRow, ids: &[i32], day: NaiveDate, current: &Cell<i64>) {
+        let imp = imp::FurTimesheet::from_obj(self);
+        if !cell.is_editable() {
+            return;
+        }
+
+        let secs = match FurTimesheet::parse_cell(&cell.text()) {
+            Some(secs) => secs,
+            None => {
+                cell.add_css_class("error");
+                imp.toast_overlay.add_toast(adw::Toast::new(&gettext("Use H:MM or decimal hours.")));
+                return;
+            }
+        };
+        cell.remove_css_class("error");
+        // Cells only show minutes, so ignore leftover seconds
+        if secs / 60 == current.get() / 60 {
+            return;
+        }
+        // A project without entries that day continues its latest entry,
+        // and only a project never tracked before names the entry itself
+        let (mut task_name, mut tags) = (row.task_name.clone(), row.tags.clone());
+        if self.by_project() && ids.is_empty() {
+            match database::last_task_in_project(&row.project) {
+                Ok(Some(latest)) => (task_name, tags) = latest,
+                Ok(None) => {}
+                Err(e) => {
+                    cell.set_text(&FurTimesheet::format_cell(current.get()));
+                    imp.toast_overlay.add_toast(adw::Toast::new(&e.to_string()));
+                    return;
+                }
+            }
+        }
+        let previous = current.replace(secs);
+
+        match database::set_day_total(
+            ids.to_vec(),
+            reporting::local_midnight(day),
+            &task_name,
+            &tags,
+            &row.project,
+            Duration::seconds(secs),
+        ) {
+            Ok(_) => {
+                let window = rusttimetrackWindow::default();
+                window.reset_history_box();
+            }
+            Err(e) => {
+                current.set(previous);
+                cell.set_text(&FurTimesheet::format_cell(previous));
+                imp.toast_overlay.add_toast(adw::Toast::new(&e.to_string()));
+            }
+        }
+    }
+
+    fn task_secs(task: &Task) -> i64 {
+        let start_time = DateTime::parse_from_rfc3339(&task.start_time).unwrap();
+        let stop_time = DateTime::parse_from_rfc3339(&task.stop_time).unwrap();
+        (stop_time - start_time).num_seconds()
+    }
+
+    fn task_earnings(task: &Task, rates: &RateBook) -> Earnings {
+        let rate = rates.rate_for(&task.task_name, &database::split_tags(&task.tags), &task.project);
+        match rate {
+            Some(rate) if billing::is_billable(task.billable, Some(rate)) => {
+                Earnings::for_time(FurTimesheet::task_secs(task), rate)
+            }
+            _ => Earnings::default(),
+        }
+    }
+
+    fn parse_cell(text: &str) -> Option<i64> {
+        // Accepts "1:30" as well as decimal hours like "1.5"
+        let text = text.trim();
+        if text.is_empty() {
+            return Some(0);
+        }
+        match text.split_once(':') {
+            Some((hours, mins)) => {
+                let hours: i64 = hours.trim().parse().ok()?;
+                let mins: i64 = mins.trim().parse().ok()?;
+                if hours < 0 || !(0..60).contains(&mins) || hours * 60 + mins > 24 * 60 {
+                    return None;
+                }
+                Some(hours * 3600 + mins * 60)
+            }
+            None => {
+                let hours: f64 = text.replace(',', ".").parse().ok()?;
+                if hours < 0.0 || hours > 24.0 {
+                    return None;
+                }
+                Some((hours * 60.0).round() as i64 * 60)
+            }
+        }
+    }
+
+    fn format_cell(secs: i64) -> String {
+        if secs == 0 {
+            String::new()
+        } else {
+            FurTimesheet::format_total(secs)
+        }
+    }
+
+    fn format_total(secs: i64) -> String {
+        format!("{}:{:02}", secs / 3600, secs % 3600 / 60)
+    }
+}
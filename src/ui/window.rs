@@ -16,7 +16,7 @@
 
 use adw::prelude::*;
 use adw::subclass::prelude::AdwApplicationWindowImpl;
-use chrono::{offset::TimeZone, DateTime, Duration as ChronDur, Local, NaiveDateTime, ParseError};
+use chrono::{offset::TimeZone, DateTime, Duration as ChronDur, Local, NaiveDate, NaiveDateTime, ParseError};
 use dbus::blocking::Connection;
 use directories::ProjectDirs;
 use gettextrs::*;
@@ -24,7 +24,8 @@ use glib::{clone, timeout_add_local, ControlFlow};
 use gtk::subclass::prelude::*;
 use gtk::{Application, gio, glib, CompositeTemplate};
 use itertools::Itertools;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::{create_dir_all, remove_file, File};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
@@ -33,10 +34,16 @@ use std::rc::Rc;
 use std::sync::Mutex;
 use std::time::Duration;
 
+use crate::budgets::{self, BudgetUsage};
 use crate::config;
-use crate::database::{self, SortOrder, TaskSort};
+use crate::database::{self, BulkEdit, ChangeOrigin, SortOrder, TaskSort};
+use crate::filter;
+use crate::goals::{self, GoalStatus};
+use crate::reminders::{self, Reminder};
+use crate::reporting::{Rounding, RoundingMode, RoundingScope, ROUNDING_INCREMENTS, ROUNDING_MODES};
 use crate::settings_manager;
-use crate::ui::FurHistoryBox;
+use crate::sync;
+use crate::ui::{FurHistoryBox, FurTaskRow, FurTimeline, FurTimesheet};
 use crate::rusttimetrackApplication;
 
 mod imp {
@@ -60,9 +67,35 @@ mod imp {
         #[template_child]
         pub start_button: TemplateChild<gtk::Button>,
         #[template_child]
+        pub budget_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub goal_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub day_progress: TemplateChild<gtk::ProgressBar>,
+        #[template_child]
+        pub week_progress: TemplateChild<gtk::ProgressBar>,
+        #[template_child]
+        pub balance_label: TemplateChild<gtk::Label>,
+        #[template_child]
         pub history_box: TemplateChild<FurHistoryBox>,
         #[template_child]
         pub toast_overlay: TemplateChild<adw::ToastOverlay>,
+        #[template_child]
+        pub search_button: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub search_bar: TemplateChild<gtk::SearchBar>,
+        #[template_child]
+        pub search_entry: TemplateChild<gtk::SearchEntry>,
+        #[template_child]
+        pub select_button: TemplateChild<gtk::ToggleButton>,
+        #[template_child]
+        pub selection_bar: TemplateChild<gtk::ActionBar>,
+        #[template_child]
+        pub selection_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub bulk_delete_btn: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub bulk_export_btn: TemplateChild<gtk::Button>,
 
         pub stored_idle: Mutex<u64>,
         pub idle_notified: Mutex<bool>,
@@ -72,6 +105,22 @@ mod imp {
         pub running: Mutex<bool>,
         pub pomodoro_continue: Mutex<bool>,
         pub idle_dialog: Mutex<gtk::MessageDialog>,
+        pub goal_status: RefCell<Option<GoalStatus>>,
+        /// The day last shown and whether the daily and weekly goals were reached
+        pub goals_reached: RefCell<Option<(NaiveDate, bool, bool)>>,
+        pub budget_usage: RefCell<Vec<BudgetUsage>>,
+        /// The running task's name and the project it gets from earlier entries
+        pub running_project: RefCell<Option<(String, String)>>,
+        pub running_since: RefCell<Option<DateTime<Local>>>,
+        /// Set to stop the timer at an earlier time than now
+        pub stop_at: RefCell<Option<DateTime<Local>>>,
+        /// Reminders already shown, so each is only shown once
+        pub reminded: RefCell<Vec<Reminder>>,
+        pub stop_suggestion: RefCell<Option<DateTime<Local>>>,
+        /// The last alert threshold reached per budget
+        pub budget_alerts: RefCell<HashMap<String, Option<u32>>>,
+        /// Set while a sync runs in the background
+        pub syncing: Cell<bool>,
 
         // We have to keep a reference to the current popped up filechooser dialog
         pub filechooser: RefCell<gtk::FileChooserNative>,
@@ -98,6 +147,8 @@ mod imp {
             let obj = self.obj();
             obj.setup_widgets();
             obj.setup_signals();
+            obj.setup_selection();
+            obj.setup_search();
             obj.setup_settings();
             self.parent_constructed();
         }
@@ -152,15 +203,187 @@ impl rusttimetrackWindow {
         }
 
         let (task_name, tag_list) = self.split_tags_and_task();
-        let _ = database::db_write(&task_name, start_time, stop_time, tag_list);
+        let _ = database::db_write(
+            &task_name,
+            start_time,
+            stop_time,
+            tag_list,
+            self.running_project(&task_name),
+            ChangeOrigin::Timer,
+        );
         imp.task_input.set_text("");
-        imp.history_box.create_tasks_page();
+        self.reset_history_box();
         self.reset_idle();
     }
 
     pub fn reset_history_box(&self) {
         let imp = imp::rusttimetrackWindow::from_obj(self);
         imp.history_box.create_tasks_page();
+        self.refresh_goals();
+        self.refresh_budgets();
+
+        // Keep other open views in sync with the database
+        for window in rusttimetrackApplication::default().windows() {
+            if let Some(timeline) = window.downcast_ref::<FurTimeline>() {
+                timeline.refresh();
+            } else if let Some(timesheet) = window.downcast_ref::<FurTimesheet>() {
+                timesheet.refresh();
+            }
+        }
+    }
+
+    /// Reloads today's and this week's time for the goal progress bars.
+    pub fn refresh_goals(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let goals = settings_manager::goals();
+        let status = if goals.is_set() {
+            database::open_db()
+                .map_err(anyhow::Error::from)
+                .and_then(|conn| goals::status(&conn, &goals, Local::now().date_naive()))
+                .ok()
+        } else {
+            None
+        };
+        *imp.goal_status.borrow_mut() = status;
+        self.show_goal_progress(0);
+    }
+
+    /// Shows progress towards the goals, counting `running_secs` from the
+    /// timer, and notifies once when a goal is reached.
+    fn show_goal_progress(&self, running_secs: i64) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let status = match *imp.goal_status.borrow() {
+            Some(status) => status.with_running(running_secs),
+            None => {
+                imp.goal_box.set_visible(false);
+                return;
+            }
+        };
+        imp.goal_box.set_visible(true);
+
+        let bars = [
+            (&*imp.day_progress, status.day, gettext("Today")),
+            (&*imp.week_progress, status.week, gettext("This Week")),
+        ];
+        for (bar, progress, title) in bars {
+            bar.set_visible(progress.goal > 0);
+            bar.set_fraction(progress.fraction());
+            bar.set_text(Some(&format!("{}: {} · {}", title, progress.text(), progress.remaining_text())));
+            if progress.is_reached() {
+                bar.add_css_class("goal-reached");
+            } else {
+                bar.remove_css_class("goal-reached");
+            }
+        }
+
+        // Time over or under the goal in this week counts towards the
+        // balance once the week is over
+        imp.balance_label.set_visible(status.week.goal > 0);
+        imp.balance_label.set_text(
+            &gettext("Balance from earlier weeks: {}").replace("{}", &goals::format_balance(status.balance)),
+        );
+
+        let reached = (status.today, status.day.is_reached(), status.week.is_reached());
+        let before = imp.goals_reached.replace(Some(reached));
+        // Only notify when a goal is reached while the app is open, not when
+        // it opens with the goal already met
+        if let Some((day, day_before, week_before)) = before {
+            if day == status.today && settings_manager::get_bool("notify-of-goal") {
+                let app = rusttimetrackApplication::default();
+                if reached.2 && !week_before {
+                    app.system_goal_notification(
+                        &gettext("Weekly goal reached"),
+                        &gettext("You tracked {} this week.").replace("{}", &goals::format_hours(status.week.seconds)),
+                    );
+                } else if reached.1 && !day_before {
+                    app.system_goal_notification(
+                        &gettext("Daily goal reached"),
+                        &gettext("You tracked {} today.").replace("{}", &goals::format_hours(status.day.seconds)),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reloads how much of each budget is used.
+    pub fn refresh_budgets(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let usage = database::open_db()
+            .map_err(anyhow::Error::from)
+            .and_then(|conn| {
+                let budgets = database::budgets_from(&conn).unwrap_or_default();
+                budgets::usage(&conn, &budgets, Local::now().date_naive(), settings_manager::week_starts_on())
+            })
+            .unwrap_or_default();
+        *imp.budget_usage.borrow_mut() = usage;
+        // Entries may have been edited, so look the project up again
+        imp.running_project.replace(None);
+        self.show_budget_progress(0);
+    }
+
+    /// The project the running task will be saved with, looked up once per
+    /// task name rather than every second.
+    fn running_project(&self, task_name: &str) -> String {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let mut cached = imp.running_project.borrow_mut();
+        match &*cached {
+            Some((name, project)) if name == task_name => project.clone(),
+            _ => {
+                let project = database::last_project(task_name).unwrap_or_default();
+                *cached = Some((task_name.to_string(), project.clone()));
+                project
+            }
+        }
+    }
+
+    /// Shows the time left in the tightest budget the running task counts
+    /// toward, and notifies when a budget reaches 80% or 100%.
+    fn show_budget_progress(&self, running_secs: i64) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let running = *imp.running.lock().unwrap();
+        let (task_name, tags) = self.split_tags_and_task();
+        let tags = database::split_tags(&tags);
+        let project = if running { self.running_project(&task_name) } else { String::new() };
+
+        let usage: Vec<BudgetUsage> = imp
+            .budget_usage
+            .borrow()
+            .iter()
+            .map(|usage| {
+                if running && usage.budget.matches(&task_name, &tags, &project) {
+                    usage.with_running(running_secs)
+                } else {
+                    usage.clone()
+                }
+            })
+            .collect();
+
+        let tightest = usage
+            .iter()
+            .filter(|usage| running && usage.budget.matches(&task_name, &tags, &project))
+            .min_by_key(|usage| usage.remaining());
+        imp.budget_label.set_visible(tightest.is_some());
+        if let Some(tightest) = tightest {
+            imp.budget_label.set_text(&tightest.status_text());
+        }
+
+        // Budgets already over a threshold when first loaded don't alert
+        let mut alerts = imp.budget_alerts.borrow_mut();
+        for usage in &usage {
+            let key = format!("{}:{}", usage.budget.kind.to_sqlite(), usage.budget.name.to_lowercase());
+            let percent = usage.alert_percent();
+            match alerts.insert(key, percent) {
+                Some(before) if percent > before && settings_manager::get_bool("notify-of-budget") => {
+                    let title = if percent == Some(100) {
+                        gettext("Budget used up")
+                    } else {
+                        gettext("{}% of budget used").replace("{}", &percent.unwrap_or(0).to_string())
+                    };
+                    rusttimetrackApplication::default().system_budget_notification(&title, &usage.status_text());
+                }
+                _ => (),
+            }
+        }
     }
 
     pub fn reset_autocomplete(&self) {
@@ -193,6 +416,8 @@ impl rusttimetrackWindow {
         imp.start_button.set_sensitive(false);
         imp.start_button.add_css_class("suggested-action");
         self.refresh_timer();
+        self.refresh_goals();
+        self.refresh_budgets();
 
         if settings_manager::get_bool("autocomplete") {
             imp.task_input.set_completion(Some(&rusttimetrackWindow::create_autocomplete()));
@@ -203,6 +428,196 @@ impl rusttimetrackWindow {
         if settings_manager::get_bool("autosave") {
             self.check_for_autosave();
         }
+
+        // Reminders count in minutes, so checking once a minute is enough
+        timeout_add_local(Duration::new(60, 0), clone!(@weak self as this => @default-return ControlFlow::Break, move || {
+            this.check_reminders();
+            ControlFlow::Continue
+        }));
+
+        self.sync_quietly();
+        timeout_add_local(Duration::new(300, 0), clone!(@weak self as this => @default-return ControlFlow::Break, move || {
+            this.sync_quietly();
+            ControlFlow::Continue
+        }));
+    }
+
+    /// Syncs with the sync folder and server in the background, and calls
+    /// `done` with the result. Returns false without syncing if neither is
+    /// set or a sync is already running.
+    pub fn sync_now<F: FnOnce(sync::SyncReport) + 'static>(&self, done: F) -> bool {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let settings = match settings_manager::sync_settings() {
+            Ok(Some(settings)) => settings,
+            Ok(None) => return false,
+            Err(e) => {
+                done(sync::SyncReport {
+                    errors: vec![e.to_string()],
+                    ..Default::default()
+                });
+                return true;
+            }
+        };
+        if imp.syncing.replace(true) {
+            return false;
+        }
+
+        // The server may take a while to answer, so this stays off the main
+        // thread
+        let db = database::get_directory();
+        glib::MainContext::default().spawn_local(clone!(@strong self as window => async move {
+            let report = gio::spawn_blocking(move || sync::run(&db, &settings))
+                .await
+                .unwrap_or_else(|_| sync::SyncReport {
+                    errors: vec![gettext("Sync stopped unexpectedly.")],
+                    ..Default::default()
+                });
+
+            let imp = imp::rusttimetrackWindow::from_obj(&window);
+            imp.syncing.set(false);
+            if report.applied > 0 {
+                window.reset_history_box();
+            }
+            done(report);
+        }));
+        true
+    }
+
+    fn sync_quietly(&self) {
+        self.sync_now(|report| {
+            for error in report.errors {
+                log::error!("sync failed: {}", error);
+            }
+        });
+    }
+
+    fn check_reminders(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let settings = settings_manager::reminder_settings();
+        if settings.start_after.is_none() && settings.stop_after.is_none() {
+            return;
+        }
+
+        let running_since = if *imp.running.lock().unwrap() {
+            imp.running_since.borrow().map(|since| since.naive_local())
+        } else {
+            None
+        };
+        let last_stop = if running_since.is_none() {
+            database::last_task()
+                .ok()
+                .flatten()
+                .and_then(|task| DateTime::parse_from_rfc3339(&task.stop_time).ok())
+                .map(|stop| stop.with_timezone(&Local).naive_local())
+        } else {
+            None
+        };
+
+        let app = rusttimetrackApplication::default();
+        let now = Local::now().naive_local();
+        for reminder in reminders::due(&settings, now, running_since, last_stop) {
+            if imp.reminded.borrow().contains(&reminder) {
+                continue;
+            }
+            imp.reminded.borrow_mut().push(reminder);
+
+            match reminder {
+                Reminder::Start(since) => {
+                    app.system_start_reminder(
+                        &gettext("Nothing has been tracked since {}.").replace("{}", &since.format("%H:%M").to_string()),
+                    );
+                }
+                Reminder::RunningLong(since) => {
+                    *imp.stop_suggestion.borrow_mut() = reminder.suggested_stop(settings.stop_after)
+                        .and_then(|stop| Local.from_local_datetime(&stop).earliest());
+                    app.system_stop_reminder(
+                        &gettext("The timer has been running since {}.").replace("{}", &since.format("%H:%M").to_string()),
+                    );
+                }
+                Reminder::PastWorkday(end) => {
+                    *imp.stop_suggestion.borrow_mut() = Local.from_local_datetime(&end).earliest();
+                    app.system_stop_reminder(
+                        &gettext("The working day ended at {}.").replace("{}", &end.format("%H:%M").to_string()),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Starts the timer again on whatever was tracked last.
+    pub fn start_last_task(&self) {
+        match database::last_task() {
+            Ok(Some(task)) => self.duplicate_task(task),
+            _ => self.display_toast(&gettext("Nothing has been tracked yet.")),
+        }
+    }
+
+    /// Asks when the running timer should have stopped, and stops it then.
+    pub fn open_stop_at_dialog(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let running_since = match *imp.running_since.borrow() {
+            Some(since) if *imp.running.lock().unwrap() => since,
+            _ => return,
+        };
+
+        let dialog = gtk::MessageDialog::with_markup(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            Some(&format!("<span size='x-large' weight='bold'>{}</span>", &gettext("Stop Timer At"))),
+        );
+        dialog.set_secondary_text(Some(
+            &gettext("The timer started at {}.").replace("{}", &running_since.format("%H:%M").to_string()),
+        ));
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let time_entry = gtk::Entry::new();
+        time_entry.set_placeholder_text(Some(&gettext("HH:MM")));
+        time_entry.set_activates_default(true);
+        let suggestion = imp.stop_suggestion.borrow().unwrap_or_else(Local::now);
+        time_entry.set_text(&suggestion.format("%H:%M").to_string());
+        let time_error = gtk::Label::new(None);
+        time_error.set_visible(false);
+        time_error.add_css_class("error_message");
+        message_area.append(&time_entry);
+        message_area.append(&time_error);
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                let now = Local::now();
+                let stop = match reminders::parse_time(&time_entry.text()) {
+                    Some(time) => reminders::latest_time_between(time, running_since.naive_local(), now.naive_local())
+                        .and_then(|stop| Local.from_local_datetime(&stop).earliest()),
+                    None => {
+                        time_error.set_text(&gettext("*Use the format HH:MM"));
+                        time_error.set_visible(true);
+                        return;
+                    }
+                };
+                match stop {
+                    Some(stop) => this.stop_timer_at(stop),
+                    None => {
+                        time_error.set_text(&gettext("*Stop time must be after the start time"));
+                        time_error.set_visible(true);
+                        return;
+                    }
+                }
+            }
+            dialog.close();
+        }));
+
+        dialog.show();
+    }
+
+    /// Stops the running timer as if it had been stopped at `stop`.
+    pub fn stop_timer_at(&self, stop: DateTime<Local>) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        if *imp.running.lock().unwrap() {
+            *imp.stop_at.borrow_mut() = Some(stop);
+            imp.start_button.emit_clicked();
+        }
     }
 
     fn setup_signals(&self) {
@@ -251,6 +666,7 @@ impl rusttimetrackWindow {
                     *imp2.running.lock().unwrap() = true;
                     *start_time.borrow_mut() = Local::now();
                     let timer_start = *start_time.borrow();
+                    *imp2.running_since.borrow_mut() = Some(timer_start);
                     imp2.task_input.set_sensitive(false);
                     let duration = Duration::new(1,0);
                     timeout_add_local(duration, clone!(@strong this as this_clone => move || {
@@ -267,6 +683,8 @@ impl rusttimetrackWindow {
                             }
                             let watch_text: &str = &format!("{:02}:{:02}:{:02}", hrs, mins, secs).to_string();
                             this_clone.set_watch_time(watch_text);
+                            this_clone.show_goal_progress((Local::now() - timer_start).num_seconds());
+                            this_clone.show_budget_progress((Local::now() - timer_start).num_seconds());
 
                             if settings_manager::get_bool("inclusive-total") {
                                 secs_only += 1;
@@ -320,6 +738,7 @@ impl rusttimetrackWindow {
                     *imp2.running.lock().unwrap() = true;
                     imp2.task_input.set_sensitive(false);
                     let autosave_start = *start_time.borrow();
+                    *imp2.running_since.borrow_mut() = Some(autosave_start);
                     let duration = Duration::new(1,0);
                     timeout_add_local(duration, clone!(@strong this as this_clone => move || {
                         let imp3 = imp::rusttimetrackWindow::from_obj(&this_clone);
@@ -335,6 +754,8 @@ impl rusttimetrackWindow {
                             }
                             let watch_text: &str = &format!("{:02}:{:02}:{:02}", hrs, mins, secs).to_string();
                             this_clone.set_watch_time(watch_text);
+                            this_clone.show_goal_progress((Local::now() - autosave_start).num_seconds());
+                            this_clone.show_budget_progress((Local::now() - autosave_start).num_seconds());
 
                             if settings_manager::get_bool("inclusive-total") {
                                 secs_only +=1;
@@ -358,8 +779,9 @@ impl rusttimetrackWindow {
                 }
                 button.set_icon_name("media-playback-stop-symbolic");
             } else {
-                *stop_time.borrow_mut() = Local::now();
+                *stop_time.borrow_mut() = imp2.stop_at.borrow_mut().take().unwrap_or_else(Local::now);
                 *imp2.running.lock().unwrap() = false;
+                *imp2.running_since.borrow_mut() = None;
                 button.set_icon_name("media-playback-start-symbolic");
                 this.refresh_timer();
                 imp2.task_input.set_sensitive(true);
@@ -375,191 +797,544 @@ impl rusttimetrackWindow {
         }));
 
         imp.add_task.connect_clicked(clone!(@weak self as this => move |_| {
-            let dialog = gtk::MessageDialog::new(
+            let stop_time = Local::now();
+            let start_time = stop_time - ChronDur::seconds(1);
+            this.open_new_task_dialog(start_time, stop_time);
+        }));
+    }
+
+    pub fn open_new_task_dialog(&self, start_time: DateTime<Local>, stop_time: DateTime<Local>) {
+        let dialog = gtk::MessageDialog::new(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            &format!("<span size='x-large' weight='bold'>{}</span>", &gettext("New Task")),
+        );
+        dialog.set_use_markup(true);
+        dialog.add_buttons(&[
+            (&gettext("Cancel"), gtk::ResponseType::Cancel),
+            (&gettext("Add"), gtk::ResponseType::Ok)
+        ]);
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let vert_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
+        let task_name_edit = gtk::Entry::new();
+        task_name_edit.set_placeholder_text(Some(&gettext("Task Name")));
+        let task_tags_edit = gtk::Entry::new();
+        let tags_placeholder = format!("#{}", &gettext("tags"));
+        task_tags_edit.set_placeholder_text(Some(&tags_placeholder));
+
+        let labels_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        labels_box.set_homogeneous(true);
+        let start_label = gtk::Label::new(Some(&gettext("Start")));
+        start_label.add_css_class("title-4");
+        let stop_label = gtk::Label::new(Some(&gettext("Stop")));
+        stop_label.add_css_class("title-4");
+        let times_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        times_box.set_homogeneous(true);
+
+        let time_formatter = "%F %H:%M:%S";
+        let time_formatter_no_secs = "%F %H:%M";
+
+        let mut start_time_w_year = start_time.format(time_formatter).to_string();
+        if !settings_manager::get_bool("show-seconds") {
+            start_time_w_year = start_time.format(time_formatter_no_secs).to_string();
+        }
+        let mut stop_time_w_year = stop_time.format(time_formatter).to_string();
+        if !settings_manager::get_bool("show-seconds") {
+            stop_time_w_year = stop_time.format(time_formatter_no_secs).to_string();
+        }
+        let start_time_edit = gtk::Entry::new();
+        start_time_edit.set_text(&start_time_w_year);
+        let stop_time_edit = gtk::Entry::new();
+        stop_time_edit.set_text(&stop_time_w_year);
+
+        let instructions = gtk::Label::new(Some(
+            &gettext("*Use the format YYYY-MM-DD HH:MM:SS")));
+        if !settings_manager::get_bool("show-seconds") {
+            instructions.set_text(&gettext("*Use the format YYYY-MM-DD HH:MM"));
+        }
+        instructions.set_visible(false);
+        instructions.add_css_class("error_message");
+
+        let time_error = gtk::Label::new(Some(
+            &gettext("*Start time cannot be later than stop time.")));
+        time_error.set_visible(false);
+        time_error.add_css_class("error_message");
+
+        let future_error = gtk::Label::new(Some(
+            &gettext("*Time cannot be in the future.")));
+        future_error.set_visible(false);
+        future_error.add_css_class("error_message");
+
+        let name_error = gtk::Label::new(Some(
+            &gettext("*Task name cannot be blank.")));
+        name_error.set_visible(false);
+        name_error.add_css_class("error_message");
+
+        vert_box.append(&task_name_edit);
+        vert_box.append(&task_tags_edit);
+        labels_box.append(&start_label);
+        labels_box.append(&stop_label);
+        times_box.append(&start_time_edit);
+        times_box.append(&stop_time_edit);
+        vert_box.append(&labels_box);
+        vert_box.append(&times_box);
+        vert_box.append(&instructions);
+        vert_box.append(&time_error);
+        vert_box.append(&future_error);
+        vert_box.append(&name_error);
+        message_area.append(&vert_box);
+
+        dialog.connect_response(clone!(@weak self as this, @strong dialog => move |_ , resp| {
+            if resp == gtk::ResponseType::Ok {
+                instructions.set_visible(false);
+                time_error.set_visible(false);
+                future_error.set_visible(false);
+                name_error.set_visible(false);
+                let mut do_not_close = false;
+                let mut new_start_time_local = Local::now();
+                let mut new_stop_time_local = Local::now();
+
+                // Task Name
+                if task_name_edit.text().trim().is_empty() {
+                    name_error.set_visible(true);
+                    do_not_close = true;
+                }
+
+                // Start Time
+                let new_start_time_str = start_time_edit.text();
+                let new_start_time: Result<NaiveDateTime, ParseError>;
+                if settings_manager::get_bool("show-seconds") {
+                    new_start_time = NaiveDateTime::parse_from_str(
+                                        &new_start_time_str,
+                                        time_formatter);
+                } else {
+                    new_start_time = NaiveDateTime::parse_from_str(
+                                            &new_start_time_str,
+                                            time_formatter_no_secs);
+                }
+                if let Err(_) = new_start_time {
+                    instructions.set_visible(true);
+                    do_not_close = true;
+                } else {
+                    new_start_time_local = Local.from_local_datetime(&new_start_time.unwrap()).unwrap();
+                    if (Local::now() - new_start_time_local).num_seconds() < 0 {
+                        future_error.set_visible(true);
+                        do_not_close = true;
+                    }
+                }
+
+                // Stop Time
+                let new_stop_time_str = stop_time_edit.text();
+                let new_stop_time: Result<NaiveDateTime, ParseError>;
+                if settings_manager::get_bool("show-seconds") {
+                    new_stop_time = NaiveDateTime::parse_from_str(
+                                        &new_stop_time_str,
+                                        time_formatter);
+                } else {
+                    new_stop_time = NaiveDateTime::parse_from_str(
+                                            &new_stop_time_str,
+                                            time_formatter_no_secs);
+                }
+                if let Err(_) = new_stop_time {
+                    instructions.set_visible(true);
+                    do_not_close = true;
+                } else {
+                    new_stop_time_local = Local.from_local_datetime(&new_stop_time.unwrap()).unwrap();
+                    if (Local::now() - new_stop_time_local).num_seconds() < 0 {
+                        future_error.set_visible(true);
+                        do_not_close = true;
+                    }
+                }
+
+                // Start time can't be later than stop time
+                if !do_not_close && (new_stop_time_local - new_start_time_local).num_seconds() < 0 {
+                    time_error.set_visible(true);
+                    do_not_close = true;
+                }
+
+                // Tags
+                let mut new_tag_list = "".to_string();
+                if !task_tags_edit.text().trim().is_empty() {
+                    let new_tags = task_tags_edit.text();
+                    let mut split_tags: Vec<&str> = new_tags.trim().split("#").collect();
+                    split_tags = split_tags.iter().map(|x| x.trim()).collect();
+                    // Don't allow empty tags
+                    split_tags.retain(|&x| !x.trim().is_empty());
+                    // Handle duplicate tags before they are saved
+                    split_tags = split_tags.into_iter().unique().collect();
+                    // Lowercase tags
+                    let lower_tags: Vec<String> = split_tags.iter().map(|x| x.to_lowercase()).collect();
+                    new_tag_list = lower_tags.join(" #");
+                }
+
+                if !do_not_close {
+                    let _ = database::db_write(task_name_edit.text().trim(),
+                                                new_start_time_local,
+                                                new_stop_time_local,
+                                                new_tag_list,
+                                                String::new(),
+                                                ChangeOrigin::Manual);
+                    this.reset_history_box();
+                    dialog.close();
+                }
+
+            } else if resp == gtk::ResponseType::Cancel {
+                dialog.close();
+            }
+        }));
+
+        dialog.show();
+    }
+
+    fn setup_search(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        imp.search_bar.connect_entry(&*imp.search_entry);
+
+        imp.search_entry.connect_search_changed(clone!(@weak self as this => move |entry| {
+            let imp2 = imp::rusttimetrackWindow::from_obj(&this);
+            let text = entry.text();
+            if text.trim().is_empty() {
+                entry.remove_css_class("error");
+                entry.set_tooltip_text(None);
+                imp2.history_box.set_filter(None);
+                this.reset_history_box();
+                return;
+            }
+            // Keep showing the last good results while the expression is unfinished
+            match filter::parse(&text) {
+                Ok(expr) => {
+                    entry.remove_css_class("error");
+                    entry.set_tooltip_text(None);
+                    imp2.history_box.set_filter(Some(expr));
+                    this.reset_history_box();
+                }
+                Err(e) => {
+                    entry.add_css_class("error");
+                    entry.set_tooltip_text(Some(&e.to_string()));
+                }
+            }
+        }));
+
+        // Closing the search bar shows all tasks again
+        imp.search_button.connect_toggled(clone!(@weak self as this => move |button| {
+            if !button.is_active() {
+                let imp2 = imp::rusttimetrackWindow::from_obj(&this);
+                imp2.search_entry.set_text("");
+            }
+        }));
+    }
+
+    fn setup_selection(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+
+        imp.select_button.connect_toggled(clone!(@weak self as this => move |button| {
+            let imp2 = imp::rusttimetrackWindow::from_obj(&this);
+            imp2.selection_bar.set_revealed(button.is_active());
+            imp2.history_box.set_selection_mode(button.is_active());
+            this.update_selection_count();
+        }));
+
+        imp.bulk_delete_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            let imp2 = imp::rusttimetrackWindow::from_obj(&this);
+            let selected = imp2.history_box.selected_task_ids();
+            if selected.is_empty() {
+                return;
+            }
+
+            let dialog = gtk::MessageDialog::with_markup(
                 Some(&this),
                 gtk::DialogFlags::MODAL,
-                gtk::MessageType::Question,
+                gtk::MessageType::Warning,
                 gtk::ButtonsType::None,
-                &format!("<span size='x-large' weight='bold'>{}</span>", &gettext("New Task")),
+                Some(&format!("<span size='large'>{}</span>", &gettext("Delete Selected?"))),
             );
-            dialog.set_use_markup(true);
+            dialog.set_secondary_text(Some(&gettext("This will delete all selected tasks.")));
             dialog.add_buttons(&[
-                (&gettext("Cancel"), gtk::ResponseType::Cancel),
-                (&gettext("Add"), gtk::ResponseType::Ok)
+                (&gettext("Cancel"), gtk::ResponseType::Reject),
+                (&gettext("Delete"), gtk::ResponseType::Accept)
             ]);
+            let delete_btn = dialog.widget_for_response(gtk::ResponseType::Accept).unwrap();
+            delete_btn.add_css_class("destructive-action");
 
-            let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
-            let vert_box = gtk::Box::new(gtk::Orientation::Vertical, 5);
-            let task_name_edit = gtk::Entry::new();
-            task_name_edit.set_placeholder_text(Some(&gettext("Task Name")));
-            let task_tags_edit = gtk::Entry::new();
-            let tags_placeholder = format!("#{}", &gettext("tags"));
-            task_tags_edit.set_placeholder_text(Some(&tags_placeholder));
-
-            let labels_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
-            labels_box.set_homogeneous(true);
-            let start_label = gtk::Label::new(Some(&gettext("Start")));
-            start_label.add_css_class("title-4");
-            let stop_label = gtk::Label::new(Some(&gettext("Stop")));
-            stop_label.add_css_class("title-4");
-            let times_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
-            times_box.set_homogeneous(true);
+            dialog.connect_response(clone!(@weak this => move |dialog, resp| {
+                if resp == gtk::ResponseType::Accept {
+                    this.apply_bulk_edit(selected.clone(), BulkEdit::Delete);
+                }
+                dialog.close();
+            }));
 
-            let stop_time = Local::now();
-            let start_time = stop_time - ChronDur::seconds(1);
+            if settings_manager::get_bool("delete-confirmation") {
+                dialog.show();
+            } else {
+                dialog.response(gtk::ResponseType::Accept);
+            }
+        }));
+
+        imp.bulk_export_btn.connect_clicked(clone!(@weak self as this => move |_| {
+            this.open_selection_export_dialog();
+        }));
+
+        let rename_action = gio::SimpleAction::new("bulk-rename", None);
+        rename_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            this.open_bulk_entry_dialog(&gettext("Rename Tasks"), &gettext("New Name"), |text| {
+                if text.is_empty() {
+                    None
+                } else {
+                    Some(BulkEdit::Rename(text.to_string()))
+                }
+            });
+        }));
+        self.add_action(&rename_action);
+
+        let add_tags_action = gio::SimpleAction::new("bulk-add-tags", None);
+        add_tags_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            this.open_bulk_entry_dialog(&gettext("Add Tags"), &gettext("#tags"), |text| {
+                let tags = database::split_tags(text);
+                if tags.is_empty() {
+                    None
+                } else {
+                    Some(BulkEdit::AddTags(tags))
+                }
+            });
+        }));
+        self.add_action(&add_tags_action);
+
+        let remove_tags_action = gio::SimpleAction::new("bulk-remove-tags", None);
+        remove_tags_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            this.open_bulk_entry_dialog(&gettext("Remove Tags"), &gettext("#tags"), |text| {
+                let tags = database::split_tags(text);
+                if tags.is_empty() {
+                    None
+                } else {
+                    Some(BulkEdit::RemoveTags(tags))
+                }
+            });
+        }));
+        self.add_action(&remove_tags_action);
+
+        let project_action = gio::SimpleAction::new("bulk-project", None);
+        project_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            // An empty project clears it
+            this.open_bulk_entry_dialog(&gettext("Change Project"), &gettext("Project"), |text| {
+                Some(BulkEdit::SetProject(text.to_string()))
+            });
+        }));
+        self.add_action(&project_action);
 
-            let time_formatter = "%F %H:%M:%S";
-            let time_formatter_no_secs = "%F %H:%M";
+        let shift_action = gio::SimpleAction::new("bulk-shift", None);
+        shift_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            this.open_bulk_entry_dialog(&gettext("Shift Times"), &gettext("Minutes or ±H:MM"), |text| {
+                rusttimetrackWindow::parse_shift(text).map(BulkEdit::ShiftTimes)
+            });
+        }));
+        self.add_action(&shift_action);
 
-            let mut start_time_w_year = start_time.format(time_formatter).to_string();
-            if !settings_manager::get_bool("show-seconds") {
-                start_time_w_year = start_time.format(time_formatter_no_secs).to_string();
+        let merge_action = gio::SimpleAction::new("bulk-merge", None);
+        merge_action.connect_activate(clone!(@weak self as this => move |_, _| {
+            let imp2 = imp::rusttimetrackWindow::from_obj(&this);
+            let selected = imp2.history_box.selected_task_ids();
+            if selected.len() < 2 {
+                return;
             }
-            let mut stop_time_w_year = stop_time.format(time_formatter).to_string();
-            if !settings_manager::get_bool("show-seconds") {
-                stop_time_w_year = stop_time.format(time_formatter_no_secs).to_string();
+
+            // The time between the entries becomes tracked time
+            let dialog = gtk::MessageDialog::with_markup(
+                Some(&this),
+                gtk::DialogFlags::MODAL,
+                gtk::MessageType::Warning,
+                gtk::ButtonsType::None,
+                Some(&format!("<span size='large'>{}</span>", &gettext("Merge Selected?"))),
+            );
+            dialog.set_secondary_text(Some(&gettext(
+                "The selected entries will become one entry from the first start to the last stop, \
+                including the time between them.")));
+            dialog.add_buttons(&[
+                (&gettext("Cancel"), gtk::ResponseType::Reject),
+                (&gettext("Merge"), gtk::ResponseType::Accept)
+            ]);
+            let merge_btn = dialog.widget_for_response(gtk::ResponseType::Accept).unwrap();
+            merge_btn.add_css_class("destructive-action");
+
+            dialog.connect_response(clone!(@weak this => move |dialog, resp| {
+                if resp == gtk::ResponseType::Accept {
+                    this.apply_bulk_edit(selected.clone(), BulkEdit::Merge);
+                }
+                dialog.close();
+            }));
+
+            dialog.show();
+        }));
+        self.add_action(&merge_action);
+
+        self.update_selection_count();
+    }
+
+    pub fn select_task_row(&self, row: &FurTaskRow, extend: bool) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        if !imp.select_button.is_active() {
+            imp.select_button.set_active(true);
+        }
+        imp.history_box.select_row(row, extend);
+    }
+
+    pub fn update_selection_count(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let count = imp.history_box.selected_task_ids().len();
+        if count == 0 {
+            imp.selection_label.set_text(&gettext("No tasks selected"));
+        } else {
+            imp.selection_label.set_text(
+                &ngettext("{} task selected", "{} tasks selected", count as u32)
+                    .replace("{}", &count.to_string()));
+        }
+        imp.bulk_delete_btn.set_sensitive(count > 0);
+        imp.bulk_export_btn.set_sensitive(count > 0);
+
+        let enabled = count > 0;
+        for name in ["bulk-rename", "bulk-add-tags", "bulk-remove-tags", "bulk-project", "bulk-shift"] {
+            if let Some(action) = self.lookup_action(name) {
+                action.downcast::<gio::SimpleAction>().unwrap().set_enabled(enabled);
             }
-            let start_time_edit = gtk::Entry::new();
-            start_time_edit.set_text(&start_time_w_year);
-            let stop_time_edit = gtk::Entry::new();
-            stop_time_edit.set_text(&stop_time_w_year);
-
-            let instructions = gtk::Label::new(Some(
-                &gettext("*Use the format YYYY-MM-DD HH:MM:SS")));
-            if !settings_manager::get_bool("show-seconds") {
-                instructions.set_text(&gettext("*Use the format YYYY-MM-DD HH:MM"));
+        }
+        if let Some(action) = self.lookup_action("bulk-merge") {
+            action.downcast::<gio::SimpleAction>().unwrap().set_enabled(count > 1);
+        }
+    }
+
+    fn parse_shift(text: &str) -> Option<ChronDur> {
+        // Accepts whole minutes ("-15") or hours and minutes ("+1:30")
+        let text = text.trim();
+        let (negative, rest) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let minutes = match rest.split_once(':') {
+            Some((hours, mins)) => {
+                let hours: i64 = hours.trim().parse().ok()?;
+                let mins: i64 = mins.trim().parse().ok()?;
+                if mins > 59 {
+                    return None;
+                }
+                hours * 60 + mins
             }
-            instructions.set_visible(false);
-            instructions.add_css_class("error_message");
-
-            let time_error = gtk::Label::new(Some(
-                &gettext("*Start time cannot be later than stop time.")));
-            time_error.set_visible(false);
-            time_error.add_css_class("error_message");
-
-            let future_error = gtk::Label::new(Some(
-                &gettext("*Time cannot be in the future.")));
-            future_error.set_visible(false);
-            future_error.add_css_class("error_message");
-
-            let name_error = gtk::Label::new(Some(
-                &gettext("*Task name cannot be blank.")));
-            name_error.set_visible(false);
-            name_error.add_css_class("error_message");
-
-            vert_box.append(&task_name_edit);
-            vert_box.append(&task_tags_edit);
-            labels_box.append(&start_label);
-            labels_box.append(&stop_label);
-            times_box.append(&start_time_edit);
-            times_box.append(&stop_time_edit);
-            vert_box.append(&labels_box);
-            vert_box.append(&times_box);
-            vert_box.append(&instructions);
-            vert_box.append(&time_error);
-            vert_box.append(&future_error);
-            vert_box.append(&name_error);
-            message_area.append(&vert_box);
-
-            dialog.connect_response(clone!(@strong dialog => move |_ , resp| {
-                if resp == gtk::ResponseType::Ok {
-                    instructions.set_visible(false);
-                    time_error.set_visible(false);
-                    future_error.set_visible(false);
-                    name_error.set_visible(false);
-                    let mut do_not_close = false;
-                    let mut new_start_time_local = Local::now();
-                    let mut new_stop_time_local = Local::now();
-
-                    // Task Name
-                    if task_name_edit.text().trim().is_empty() {
-                        name_error.set_visible(true);
-                        do_not_close = true;
-                    }
+            None => rest.parse().ok()?,
+        };
+        if minutes == 0 {
+            return None;
+        }
+        Some(ChronDur::minutes(if negative { -minutes } else { minutes }))
+    }
 
-                    // Start Time
-                    let new_start_time_str = start_time_edit.text();
-                    let new_start_time: Result<NaiveDateTime, ParseError>;
-                    if settings_manager::get_bool("show-seconds") {
-                        new_start_time = NaiveDateTime::parse_from_str(
-                                            &new_start_time_str,
-                                            time_formatter);
-                    } else {
-                        new_start_time = NaiveDateTime::parse_from_str(
-                                                &new_start_time_str,
-                                                time_formatter_no_secs);
-                    }
-                    if let Err(_) = new_start_time {
-                        instructions.set_visible(true);
-                        do_not_close = true;
-                    } else {
-                        new_start_time_local = Local.from_local_datetime(&new_start_time.unwrap()).unwrap();
-                        if (Local::now() - new_start_time_local).num_seconds() < 0 {
-                            future_error.set_visible(true);
-                            do_not_close = true;
-                        }
-                    }
+    fn open_bulk_entry_dialog<F>(&self, title: &str, placeholder: &str, parse: F)
+    where
+        F: Fn(&str) -> Option<BulkEdit> + 'static,
+    {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let selected = imp.history_box.selected_task_ids();
+        if selected.is_empty() {
+            return;
+        }
 
-                    // Stop Time
-                    let new_stop_time_str = stop_time_edit.text();
-                    let new_stop_time: Result<NaiveDateTime, ParseError>;
-                    if settings_manager::get_bool("show-seconds") {
-                        new_stop_time = NaiveDateTime::parse_from_str(
-                                            &new_stop_time_str,
-                                            time_formatter);
-                    } else {
-                        new_stop_time = NaiveDateTime::parse_from_str(
-                                                &new_stop_time_str,
-                                                time_formatter_no_secs);
-                    }
-                    if let Err(_) = new_stop_time {
-                        instructions.set_visible(true);
-                        do_not_close = true;
-                    } else {
-                        new_stop_time_local = Local.from_local_datetime(&new_stop_time.unwrap()).unwrap();
-                        if (Local::now() - new_stop_time_local).num_seconds() < 0 {
-                            future_error.set_visible(true);
-                            do_not_close = true;
-                        }
+        let dialog = gtk::MessageDialog::new(
+            Some(self),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::OkCancel,
+            &format!("<span size='x-large' weight='bold'>{}</span>", title),
+        );
+        dialog.set_use_markup(true);
+
+        let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+        let entry = gtk::Entry::new();
+        entry.set_placeholder_text(Some(placeholder));
+        entry.set_activates_default(true);
+        let invalid = gtk::Label::new(Some(&gettext("Please enter a valid value.")));
+        invalid.add_css_class("error_message");
+        invalid.hide();
+        message_area.append(&entry);
+        message_area.append(&invalid);
+        dialog.set_default_response(gtk::ResponseType::Ok);
+
+        dialog.connect_response(clone!(@weak self as this => move |dialog, resp| {
+            if resp == gtk::ResponseType::Ok {
+                match parse(entry.text().trim()) {
+                    Some(edit) => {
+                        this.apply_bulk_edit(selected.clone(), edit);
+                        dialog.close();
                     }
+                    None => invalid.show(),
+                }
+            } else {
+                dialog.close();
+            }
+        }));
 
-                    // Start time can't be later than stop time
-                    if !do_not_close && (new_stop_time_local - new_start_time_local).num_seconds() < 0 {
-                        time_error.set_visible(true);
-                        do_not_close = true;
-                    }
+        dialog.show();
+    }
 
-                    // Tags
-                    let mut new_tag_list = "".to_string();
-                    if !task_tags_edit.text().trim().is_empty() {
-                        let new_tags = task_tags_edit.text();
-                        let mut split_tags: Vec<&str> = new_tags.trim().split("#").collect();
-                        split_tags = split_tags.iter().map(|x| x.trim()).collect();
-                        // Don't allow empty tags
-                        split_tags.retain(|&x| !x.trim().is_empty());
-                        // Handle duplicate tags before they are saved
-                        split_tags = split_tags.into_iter().unique().collect();
-                        // Lowercase tags
-                        let lower_tags: Vec<String> = split_tags.iter().map(|x| x.to_lowercase()).collect();
-                        new_tag_list = lower_tags.join(" #");
-                    }
+    fn apply_bulk_edit(&self, id_list: Vec<i32>, edit: BulkEdit) {
+        let count = id_list.len();
+        match database::bulk_edit(id_list, edit) {
+            Ok(_) => {
+                self.reset_history_box();
+                self.update_selection_count();
+                self.display_toast(
+                    &ngettext("Updated {} task.", "Updated {} tasks.", count as u32)
+                        .replace("{}", &count.to_string()));
+            }
+            Err(e) => self.display_toast(&e.to_string()),
+        }
+    }
 
-                    if !do_not_close {
-                        let _ = database::db_write(task_name_edit.text().trim(),
-                                                    new_start_time_local,
-                                                    new_stop_time_local,
-                                                    new_tag_list);
-                        this.reset_history_box();
-                        dialog.close();
-                    }
+    fn open_selection_export_dialog(&self) {
+        let imp = imp::rusttimetrackWindow::from_obj(self);
+        let selected = imp.history_box.selected_task_ids();
+        if selected.is_empty() {
+            return;
+        }
 
-                } else if resp == gtk::ResponseType::Cancel {
-                    dialog.close();
-                }
-            }));
+        let filefilter = gtk::FileFilter::new();
+        filefilter.add_mime_type("text/csv");
+        filefilter.add_pattern("*.csv");
 
-            dialog.show();
+        let filechooser = gtk::FileChooserNative::builder()
+            .title(&gettext("Create or choose a CSV file"))
+            .modal(true)
+            .transient_for(self)
+            .action(gtk::FileChooserAction::Save)
+            .accept_label(&gettext("Accept"))
+            .cancel_label(&gettext("Cancel"))
+            .select_multiple(false)
+            .filter(&filefilter)
+            .build();
+
+        filechooser.set_current_name("selection.csv");
+
+        filechooser.connect_response(clone!(@weak self as window => move |filechooser, response| {
+            if response == gtk::ResponseType::Accept {
+                if let Some(file) = filechooser.file() {
+                    let selected = selected.clone();
+                    glib::MainContext::default().spawn_local(clone!(@strong window, @strong file => async move {
+                        let result = match database::export_ids_as_csv(selected, b',') {
+                            Ok(csv) => overwrite_file_future(&file, csv.into_bytes()).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            log::error!("replace file {:?} failed, Err {}", file, e);
+                            window.display_toast(&gettext("Exporting as CSV failed."));
+                        } else {
+                            window.display_toast(&gettext("Exported as CSV successfully."));
+                        };
+                    }));
+                }
+            }
         }));
+
+        filechooser.show();
+        *self.imp().filechooser.borrow_mut() = filechooser;
     }
 
     fn setup_settings(&self) {
@@ -885,28 +1660,11 @@ impl rusttimetrackWindow {
     pub async fn export_csv_to_file(
         sort: TaskSort,
         order: SortOrder,
+        filter: Option<filter::Expr>,
+        rounding: Option<Rounding>,
         file: &gio::File,
     ) -> anyhow::Result<()> {
-        async fn overwrite_file_future(file: &gio::File, bytes: Vec<u8>) -> anyhow::Result<()> {
-            let output_stream = file
-                .replace_future(
-                    None,
-                    false,
-                    gio::FileCreateFlags::REPLACE_DESTINATION,
-                    glib::source::Priority::DEFAULT,
-                )
-                .await?;
-
-            output_stream
-                .write_all_future(bytes, glib::source::Priority::DEFAULT)
-                .await
-                .map_err(|e| anyhow::anyhow!(e.1))?;
-            output_stream.close_future(glib::source::Priority::DEFAULT).await?;
-
-            Ok(())
-        }
-
-        let csv = database::export_as_csv(sort, order, b',')?;
+        let csv = database::export_as_csv(sort, order, b',', filter.as_ref(), rounding)?;
         overwrite_file_future(file, csv.into_bytes()).await
     }
 
@@ -925,6 +1683,19 @@ impl rusttimetrackWindow {
         let chosenfile_label = builder
             .object::<gtk::Label>("csv_export_chosenfile_label")
             .unwrap();
+        let rounding_row = builder
+            .object::<adw::ComboRow>("csv_export_rounding_row")
+            .unwrap();
+        let rounding_mode_row = builder
+            .object::<adw::ComboRow>("csv_export_rounding_mode_row")
+            .unwrap();
+        let filter_row = builder
+            .object::<adw::EntryRow>("csv_export_filter_row")
+            .unwrap();
+
+        rounding_row.connect_selected_notify(clone!(@weak rounding_mode_row => move |row| {
+            rounding_mode_row.set_sensitive(row.selected() > 0);
+        }));
 
         dialog.set_transient_for(Some(self));
 
@@ -966,15 +1737,52 @@ impl rusttimetrackWindow {
             }),
         );
 
-        dialog.connect_response(clone!(@weak self as window, @weak filechooser, @weak tasksort_row, @weak sortorder_row => move |dialog, response| {
+        filter_row.connect_changed(|row| {
+            row.remove_css_class("error");
+            row.set_tooltip_text(None);
+        });
+
+        dialog.connect_response(clone!(@weak self as window, @weak filechooser, @weak tasksort_row, @weak sortorder_row, @weak rounding_row, @weak rounding_mode_row, @weak filter_row => move |dialog, response| {
             match response {
                 gtk::ResponseType::Apply => {
                     let sort = TaskSort::try_from(tasksort_row.selected()).unwrap_or_default();
                     let order = SortOrder::try_from(sortorder_row.selected()).unwrap_or_default();
 
+                    let increment = ROUNDING_INCREMENTS
+                        .get(rounding_row.selected() as usize)
+                        .copied()
+                        .unwrap_or(0);
+                    let rounding = if increment > 0 {
+                        Some(Rounding {
+                            increment: increment * 60,
+                            mode: ROUNDING_MODES
+                                .get(rounding_mode_row.selected() as usize)
+                                .copied()
+                                .unwrap_or(RoundingMode::Up),
+                            scope: RoundingScope::Entry,
+                        })
+                    } else {
+                        None
+                    };
+
+                    // Keep the dialog open so the expression can be fixed
+                    let text = filter_row.text();
+                    let filter = if text.trim().is_empty() {
+                        None
+                    } else {
+                        match filter::parse(&text) {
+                            Ok(expr) => Some(expr),
+                            Err(e) => {
+                                filter_row.add_css_class("error");
+                                filter_row.set_tooltip_text(Some(&e.to_string()));
+                                return;
+                            }
+                        }
+                    };
+
                     if let Some(file) = filechooser.file() {
                         glib::MainContext::default().spawn_local(clone!(@strong window, @strong file => async move {
-                            if let Err(e) = rusttimetrackWindow::export_csv_to_file(sort, order, &file).await {
+                            if let Err(e) = rusttimetrackWindow::export_csv_to_file(sort, order, filter, rounding, &file).await {
                                 log::error!("replace file {:?} failed, Err {}", file, e);
                                 window.display_toast(&gettext("Exporting as CSV failed."));
                             } else {
@@ -1010,3 +1818,21 @@ impl Default for rusttimetrackWindow {
     }
 }
 
+async fn overwrite_file_future(file: &gio::File, bytes: Vec<u8>) -> anyhow::Result<()> {
+    let output_stream = file
+        .replace_future(
+            None,
+            false,
+            gio::FileCreateFlags::REPLACE_DESTINATION,
+            glib::source::Priority::DEFAULT,
+        )
+        .await?;
+
+    output_stream
+        .write_all_future(bytes, glib::source::Priority::DEFAULT)
+        .await
+        .map_err(|e| anyhow::anyhow!(e.1))?;
+    output_stream.close_future(glib::source::Priority::DEFAULT).await?;
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The request handling of the reference sync server, kept apart from the
+//! command line so it can be started in tests.
+
+pub mod store;
+
+use std::io::{Cursor, Read};
+use tiny_http::{Header, Method, Request, Response};
+
+use store::Store;
+
+/// Largest request body accepted
+const MAX_BODY: u64 = 16 * 1024 * 1024;
+const DEFAULT_LIMIT: usize = 500;
+const MAX_LIMIT: usize = 1000;
+const MAX_DEVICE_LEN: usize = 200;
+
+/// Answers requests until the server is unblocked or dropped.
+pub fn serve(server: &tiny_http::Server, store: &mut Store, token: &str) {
+    // One request at a time keeps the store simple, and is plenty for a team
+    for mut request in server.incoming_requests() {
+        let response = handle(store, token, &mut request);
+        if let Err(e) = request.respond(response) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct PushRequest {
+    device: String,
+    changes: Vec<String>,
+}
+
+fn handle(store: &mut Store, token: &str, request: &mut Request) -> Response<Cursor<Vec<u8>>> {
+    let expected = format!("Bearer {}", token);
+    let authorized = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .is_some_and(|header| same_secret(header.value.as_str(), &expected));
+    if !authorized {
+        return error(401, "Missing or wrong token");
+    }
+
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    if path != "/v1/changes" {
+        return error(404, "Not found");
+    }
+
+    match request.method() {
+        Method::Get => {
+            let mut since: i64 = 0;
+            let mut limit = DEFAULT_LIMIT;
+            for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+                match key {
+                    "since" => match value.parse() {
+                        Ok(value) => since = value,
+                        Err(_) => return error(400, "since must be a number"),
+                    },
+                    "limit" => match value.parse::<usize>() {
+                        Ok(value) => limit = value.clamp(1, MAX_LIMIT),
+                        Err(_) => return error(400, "limit must be a number"),
+                    },
+                    _ => {}
+                }
+            }
+            match store.pull(since, limit) {
+                Ok((changes, more)) => json(200, &serde_json::json!({ "changes": changes, "more": more })),
+                Err(e) => error(500, &e.to_string()),
+            }
+        }
+        Method::Post => {
+            let mut body = Vec::new();
+            if let Err(e) = request.as_reader().take(MAX_BODY + 1).read_to_end(&mut body) {
+                return error(400, &e.to_string());
+            }
+            if body.len() as u64 > MAX_BODY {
+                return error(413, "Too many changes at once");
+            }
+            let push: PushRequest = match serde_json::from_slice(&body) {
+                Ok(push) => push,
+                Err(e) => return error(400, &e.to_string()),
+            };
+            if push.device.is_empty() || push.device.len() > MAX_DEVICE_LEN {
+                return error(400, "device must be between 1 and 200 characters");
+            }
+            if push.changes.is_empty() || push.changes.iter().any(|change| change.is_empty()) {
+                return error(400, "changes must be a list of payloads");
+            }
+            match store.push(&push.device, &push.changes) {
+                Ok(cursor) => json(200, &serde_json::json!({ "cursor": cursor })),
+                Err(e) => error(500, &e.to_string()),
+            }
+        }
+        _ => error(405, "Only GET and POST are allowed"),
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken
+/// doesn't give the token away.
+fn same_secret(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn json(status: u16, body: &serde_json::Value) -> Response<Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_data(body.to_string().into_bytes())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    json(status, &serde_json::json!({ "error": message }))
+}
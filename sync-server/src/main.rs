@@ -0,0 +1,91 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The reference sync server, speaking the protocol in `PROTOCOL.md`. It
+//! keeps encrypted changes in order and hands them out again, and nothing
+//! more. Put it behind a reverse proxy for HTTPS.
+
+use rusttimetrack_sync_server::store::Store;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: rusttimetrack-sync-server [--listen ADDRESS] [--db FILE] [--token TOKEN]
+
+  --listen ADDRESS  Where to listen, 127.0.0.1:8765 by default
+  --db FILE         The database to keep changes in, rusttimetrack-sync.db by default
+  --token TOKEN     The token devices must send. Can also be set with
+                    RUSTTIMETRACK_SYNC_TOKEN, and is required.";
+
+struct Options {
+    listen: String,
+    db: PathBuf,
+    token: String,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        listen: String::from("127.0.0.1:8765"),
+        db: PathBuf::from("rusttimetrack-sync.db"),
+        token: std::env::var("RUSTTIMETRACK_SYNC_TOKEN").unwrap_or_default(),
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--listen" => options.listen = value()?,
+            "--db" => options.db = PathBuf::from(value()?),
+            "--token" => options.token = value()?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+
+    if options.token.is_empty() {
+        return Err(String::from("A token is required"));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut store = match Store::open(&options.db) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Can't open {}: {}", options.db.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let server = match tiny_http::Server::http(&options.listen) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Can't listen on {}: {}", options.listen, e);
+            std::process::exit(1);
+        }
+    };
+    println!("Listening on {}", options.listen);
+
+    rusttimetrack_sync_server::serve(&server, &mut store, &options.token);
+}
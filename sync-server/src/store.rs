@@ -0,0 +1,81 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! The changes the server keeps. Payloads are stored as they arrive, since
+//! only the devices can read them.
+
+use rusqlite::Connection;
+use std::path::Path;
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Change {
+    pub cursor: i64,
+    pub device: String,
+    pub payload: String,
+}
+
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // AUTOINCREMENT so a cursor is never handed out twice
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS changes (
+                        cursor integer primary key autoincrement,
+                        device text not null,
+                        payload text not null,
+                        received_at text not null);",
+        )?;
+        Ok(Store { conn })
+    }
+
+    /// Stores changes in the order given, returning the cursor of the last.
+    pub fn push(&mut self, device: &str, payloads: &[String]) -> rusqlite::Result<i64> {
+        let tx = self.conn.transaction()?;
+        for payload in payloads {
+            tx.execute(
+                "INSERT INTO changes (device, payload, received_at) VALUES (?1, ?2, datetime('now'))",
+                (device, payload),
+            )?;
+        }
+        let cursor = tx.last_insert_rowid();
+        tx.commit()?;
+        Ok(cursor)
+    }
+
+    /// Up to `limit` changes after `since`, and whether there are more.
+    pub fn pull(&self, since: i64, limit: usize) -> rusqlite::Result<(Vec<Change>, bool)> {
+        let mut stmt = self.conn.prepare(
+            "SELECT cursor, device, payload FROM changes WHERE cursor > ?1 ORDER BY cursor ASC LIMIT ?2",
+        )?;
+        let mut changes = stmt
+            .query_map((since, limit as i64 + 1), |row| {
+                Ok(Change {
+                    cursor: row.get(0)?,
+                    device: row.get(1)?,
+                    payload: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<Change>>>()?;
+
+        let more = changes.len() > limit;
+        changes.truncate(limit);
+        Ok((changes, more))
+    }
+}
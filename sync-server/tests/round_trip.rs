@@ -0,0 +1,138 @@
+// rusttimetrack - Track your time without being tracked
+// Copyright (C) 2022  Ricky Kresslein <rk@lakoliu.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runs the server on localhost and passes changes between two devices,
+//! each keeping what it sent and received in a database of its own.
+
+use rusqlite::Connection;
+use rusttimetrack_sync_server::store::Store;
+use serde_json::{json, Value};
+use std::path::Path;
+
+const TOKEN: &str = "test token";
+
+/// Starts a server with an empty store, returning its address.
+fn start_server() -> String {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/v1/changes", server.server_addr().to_ip().unwrap());
+    std::thread::spawn(move || {
+        let mut store = Store::open(Path::new(":memory:")).unwrap();
+        rusttimetrack_sync_server::serve(&server, &mut store, TOKEN);
+    });
+    url
+}
+
+fn device_db(payloads: &[&str]) -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE changes (payload text not null);
+         CREATE TABLE cursor (cursor integer not null);
+         INSERT INTO cursor VALUES (0);",
+    )
+    .unwrap();
+    for payload in payloads {
+        conn.execute("INSERT INTO changes VALUES (?1)", [payload]).unwrap();
+    }
+    conn
+}
+
+fn payloads(conn: &Connection) -> Vec<String> {
+    conn.prepare("SELECT payload FROM changes ORDER BY rowid")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap()
+}
+
+fn push(url: &str, device: &str, conn: &Connection) -> i64 {
+    let response: Value = ureq::post(url)
+        .set("Authorization", &format!("Bearer {}", TOKEN))
+        .send_json(json!({ "device": device, "changes": payloads(conn) }))
+        .unwrap()
+        .into_json()
+        .unwrap();
+    response["cursor"].as_i64().unwrap()
+}
+
+/// Pulls a page at a time like the app does, keeping the changes of other
+/// devices and remembering the last cursor.
+fn pull(url: &str, device: &str, conn: &Connection) {
+    loop {
+        let since: i64 = conn.query_row("SELECT cursor FROM cursor", [], |row| row.get(0)).unwrap();
+        let page: Value = ureq::get(url)
+            .query("since", &since.to_string())
+            .query("limit", "2")
+            .set("Authorization", &format!("Bearer {}", TOKEN))
+            .call()
+            .unwrap()
+            .into_json()
+            .unwrap();
+
+        let changes = page["changes"].as_array().unwrap();
+        for change in changes {
+            if change["device"] != device {
+                conn.execute("INSERT INTO changes VALUES (?1)", [change["payload"].as_str().unwrap()])
+                    .unwrap();
+            }
+            conn.execute("UPDATE cursor SET cursor = ?1", [change["cursor"].as_i64().unwrap()])
+                .unwrap();
+        }
+        if !page["more"].as_bool().unwrap() || changes.is_empty() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn changes_reach_the_other_device_in_order() {
+    let url = start_server();
+    let a = device_db(&["a1", "a2", "a3"]);
+    let b = device_db(&["b1"]);
+
+    assert_eq!(push(&url, "device-a", &a), 3);
+    assert_eq!(push(&url, "device-b", &b), 4);
+    pull(&url, "device-a", &a);
+    pull(&url, "device-b", &b);
+
+    assert_eq!(payloads(&a), vec!["a1", "a2", "a3", "b1"]);
+    assert_eq!(payloads(&b), vec!["b1", "a1", "a2", "a3"]);
+
+    // Pulling again brings nothing new
+    pull(&url, "device-b", &b);
+    assert_eq!(payloads(&b).len(), 4);
+}
+
+#[test]
+fn wrong_token_is_turned_down() {
+    let url = start_server();
+    match ureq::get(&url).set("Authorization", "Bearer wrong").call() {
+        Err(ureq::Error::Status(code, _)) => assert_eq!(code, 401),
+        other => panic!("expected 401, got {:?}", other.map(|response| response.status())),
+    }
+}
+
+#[test]
+fn empty_pushes_are_turned_down() {
+    let url = start_server();
+    let result = ureq::post(&url)
+        .set("Authorization", &format!("Bearer {}", TOKEN))
+        .send_json(json!({ "device": "device-a", "changes": [] }));
+    match result {
+        Err(ureq::Error::Status(code, _)) => assert_eq!(code, 400),
+        other => panic!("expected 400, got {:?}", other.map(|response| response.status())),
+    }
+}